@@ -1,4 +1,9 @@
-import type { WasmBundle, WasmTonkCore } from './tonk_core.js';
+import type {
+  TonkErrorCode,
+  TonkErrorInfo,
+  WasmBundle,
+  WasmTonkCore,
+} from './tonk_core.js';
 
 /**
  * Entry in a directory listing
@@ -129,16 +134,54 @@ export interface BundleConfig {
   vendorMetadata?: any;
 }
 
+/**
+ * Details of an error from tonk-core, such as the `path` it concerns
+ */
+export type TonkErrorContext = TonkErrorInfo['context'];
+
+/**
+ * Options for constructing a {@link TonkError}
+ */
+export interface TonkErrorOptions {
+  /** Error that caused this one, such as a rejection from tonk-core */
+  cause?: unknown;
+  /** Details of the error, in place of those of `cause` */
+  context?: TonkErrorContext;
+}
+
+/**
+ * The code and context of a rejection from tonk-core, if `error` is one
+ */
+function codedRejection(
+  error: unknown
+): { code: string; context: TonkErrorContext } | undefined {
+  if (typeof error !== 'object' || error === null) return undefined;
+  const { code, context } = error as Partial<TonkErrorInfo>;
+  if (typeof code !== 'string') return undefined;
+  return { code, context: context ?? {} };
+}
+
 /**
  * Base error class for all Tonk-related errors
+ *
+ * When tonk-core rejected the call, `code` is its stable {@link TonkErrorCode},
+ * `context` its details and `cause` the original rejection. Otherwise `code`
+ * is the one given, or that of the subclass.
  */
 export class TonkError extends Error {
-  constructor(
-    message: string,
-    public code?: string
-  ) {
+  code?: TonkErrorCode | string;
+  context: TonkErrorContext;
+  cause?: unknown;
+
+  constructor(message: string, code?: string, options: TonkErrorOptions = {}) {
     super(message);
     this.name = 'TonkError';
+    const coded = codedRejection(options.cause);
+    this.code = coded?.code ?? code;
+    this.context = options.context ?? coded?.context ?? {};
+    if ('cause' in options) {
+      this.cause = options.cause;
+    }
   }
 }
 
@@ -146,8 +189,8 @@ export class TonkError extends Error {
  * Error thrown when connection operations fail
  */
 export class ConnectionError extends TonkError {
-  constructor(message: string) {
-    super(message, 'CONNECTION_ERROR');
+  constructor(message: string, options?: TonkErrorOptions) {
+    super(message, 'CONNECTION_ERROR', options);
     this.name = 'ConnectionError';
   }
 }
@@ -156,8 +199,8 @@ export class ConnectionError extends TonkError {
  * Error thrown when file system operations fail
  */
 export class FileSystemError extends TonkError {
-  constructor(message: string) {
-    super(message, 'FILESYSTEM_ERROR');
+  constructor(message: string, options?: TonkErrorOptions) {
+    super(message, 'FILESYSTEM_ERROR', options);
     this.name = 'FileSystemError';
   }
 }
@@ -166,8 +209,8 @@ export class FileSystemError extends TonkError {
  * Error thrown when bundle operations fail
  */
 export class BundleError extends TonkError {
-  constructor(message: string) {
-    super(message, 'BUNDLE_ERROR');
+  constructor(message: string, options?: TonkErrorOptions) {
+    super(message, 'BUNDLE_ERROR', options);
     this.name = 'BundleError';
   }
 }
@@ -221,7 +264,9 @@ export class Bundle {
         wasmModule || (await import('./tonk_core.js'));
       return new Bundle(create_bundle_from_bytes(data));
     } catch (error) {
-      throw new BundleError(`Failed to create bundle from bytes: ${error}`);
+      throw new BundleError(`Failed to create bundle from bytes: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -236,7 +281,9 @@ export class Bundle {
       const rootId = await this.#wasm.getRootId();
       return rootId;
     } catch (error) {
-      throw new BundleError(`Failed to get root ID: ${error}`);
+      throw new BundleError(`Failed to get root ID: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -252,7 +299,9 @@ export class Bundle {
       const result = await this.#wasm.get(key);
       return result === null ? null : result;
     } catch (error) {
-      throw new BundleError(`Failed to get key ${key}: ${error}`);
+      throw new BundleError(`Failed to get key ${key}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -280,7 +329,9 @@ export class Bundle {
         value: entry.value,
       }));
     } catch (error) {
-      throw new BundleError(`Failed to get prefix ${prefix}: ${error}`);
+      throw new BundleError(`Failed to get prefix ${prefix}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -294,7 +345,7 @@ export class Bundle {
     try {
       return await this.#wasm.listKeys();
     } catch (error) {
-      throw new BundleError(`Failed to list keys: ${error}`);
+      throw new BundleError(`Failed to list keys: ${error}`, { cause: error });
     }
   }
 
@@ -308,7 +359,9 @@ export class Bundle {
     try {
       return await this.#wasm.getManifest();
     } catch (error) {
-      throw new BundleError(`Failed to retrieve manifest: ${error}`);
+      throw new BundleError(`Failed to retrieve manifest: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -322,7 +375,9 @@ export class Bundle {
     try {
       await (this.#wasm as any).setManifest(config);
     } catch (error) {
-      throw new BundleError(`Failed to set manifest: ${error}`);
+      throw new BundleError(`Failed to set manifest: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -336,7 +391,9 @@ export class Bundle {
     try {
       return await this.#wasm.toBytes();
     } catch (error) {
-      throw new BundleError(`Failed to serialize bundle: ${error}`);
+      throw new BundleError(`Failed to serialize bundle: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -556,7 +613,9 @@ export class TonkCore {
     try {
       await this.#wasm.connectWebsocket(url);
     } catch (error) {
-      throw new ConnectionError(`Failed to connect to ${url}: ${error}`);
+      throw new ConnectionError(`Failed to connect to ${url}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -601,7 +660,11 @@ export class TonkCore {
     try {
       return await this.#wasm.forkToBytes(config);
     } catch (error) {
-      throw new TonkError(`Failed to serialize to bundle data: ${error}`);
+      throw new TonkError(
+        `Failed to serialize to bundle data: ${error}`,
+        undefined,
+        { cause: error }
+      );
     }
   }
 
@@ -616,7 +679,11 @@ export class TonkCore {
     try {
       return await this.#wasm.toBytes(config);
     } catch (error) {
-      throw new TonkError(`Failed to serialize to bundle data: ${error}`);
+      throw new TonkError(
+        `Failed to serialize to bundle data: ${error}`,
+        undefined,
+        { cause: error }
+      );
     }
   }
 
@@ -641,7 +708,9 @@ export class TonkCore {
     try {
       await this.#wasm.createFile(path, content);
     } catch (error) {
-      throw new FileSystemError(`Failed to create file at ${path}: ${error}`);
+      throw new FileSystemError(`Failed to create file at ${path}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -670,7 +739,9 @@ export class TonkCore {
 
       await this.#wasm.createFileWithBytes(path, content, normalizedBytes);
     } catch (error) {
-      throw new FileSystemError(`Failed to create file at ${path}: ${error}`);
+      throw new FileSystemError(`Failed to create file at ${path}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -715,7 +786,9 @@ export class TonkCore {
       };
     } catch (error) {
       if (error instanceof FileSystemError) throw error;
-      throw new FileSystemError(`Failed to read file at ${path}: ${error}`);
+      throw new FileSystemError(`Failed to read file at ${path}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -743,7 +816,9 @@ export class TonkCore {
     try {
       return await this.#wasm.setFile(path, content);
     } catch (error) {
-      throw new FileSystemError(`Failed to set file at ${path}: ${error}`);
+      throw new FileSystemError(`Failed to set file at ${path}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -775,7 +850,9 @@ export class TonkCore {
         typeof bytes === 'string' ? extractBytes(bytes) : bytes;
       return await this.#wasm.setFileWithBytes(path, content, normalizedBytes);
     } catch (error) {
-      throw new FileSystemError(`Failed to set file at ${path}: ${error}`);
+      throw new FileSystemError(`Failed to set file at ${path}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -825,7 +902,9 @@ export class TonkCore {
     try {
       return await (this.#wasm as any).updateFile(path, content);
     } catch (error) {
-      throw new FileSystemError(`Failed to update file at ${path}: ${error}`);
+      throw new FileSystemError(`Failed to update file at ${path}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -858,7 +937,9 @@ export class TonkCore {
     try {
       return await (this.#wasm as any).patchFile(path, jsonPath, value);
     } catch (error) {
-      throw new FileSystemError(`Failed to patch file at ${path}: ${error}`);
+      throw new FileSystemError(`Failed to patch file at ${path}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -901,7 +982,9 @@ export class TonkCore {
         insert
       );
     } catch (error) {
-      throw new FileSystemError(`Failed to splice text at ${path}: ${error}`);
+      throw new FileSystemError(`Failed to splice text at ${path}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -916,7 +999,9 @@ export class TonkCore {
     try {
       return await this.#wasm.deleteFile(path);
     } catch (error) {
-      throw new FileSystemError(`Failed to delete file at ${path}: ${error}`);
+      throw new FileSystemError(`Failed to delete file at ${path}: ${error}`, {
+        cause: error,
+      });
     }
   }
 
@@ -936,7 +1021,8 @@ export class TonkCore {
       await this.#wasm.createDirectory(path);
     } catch (error) {
       throw new FileSystemError(
-        `Failed to create directory at ${path}: ${error}`
+        `Failed to create directory at ${path}: ${error}`,
+        { cause: error }
       );
     }
   }
@@ -967,7 +1053,8 @@ export class TonkCore {
       }));
     } catch (error) {
       throw new FileSystemError(
-        `Failed to list directory at ${path}: ${error}`
+        `Failed to list directory at ${path}: ${error}`,
+        { cause: error }
       );
     }
   }
@@ -983,7 +1070,8 @@ export class TonkCore {
       return await this.#wasm.exists(path);
     } catch (error) {
       throw new FileSystemError(
-        `Failed to check existence of ${path}: ${error}`
+        `Failed to check existence of ${path}: ${error}`,
+        { cause: error }
       );
     }
   }
@@ -1010,7 +1098,8 @@ export class TonkCore {
       return await this.#wasm.rename(oldPath, newPath);
     } catch (error) {
       throw new FileSystemError(
-        `Failed to rename ${oldPath} to ${newPath}: ${error}`
+        `Failed to rename ${oldPath} to ${newPath}: ${error}`,
+        { cause: error }
       );
     }
   }
@@ -1046,7 +1135,10 @@ export class TonkCore {
       return result;
     } catch (error) {
       if (error instanceof FileSystemError) throw error;
-      throw new FileSystemError(`Failed to get metadata for ${path}: ${error}`);
+      throw new FileSystemError(
+        `Failed to get metadata for ${path}: ${error}`,
+        { cause: error }
+      );
     }
   }
 
@@ -1091,7 +1183,8 @@ export class TonkCore {
     } catch (error) {
       if (error instanceof FileSystemError) throw error;
       throw new FileSystemError(
-        `Failed to watch file at path ${path}: ${error}`
+        `Failed to watch file at path ${path}: ${error}`,
+        { cause: error }
       );
     }
   }
@@ -1126,7 +1219,8 @@ export class TonkCore {
     } catch (error) {
       if (error instanceof FileSystemError) throw error;
       throw new FileSystemError(
-        `Failed to watch directory at path ${path}: ${error}`
+        `Failed to watch directory at path ${path}: ${error}`,
+        { cause: error }
       );
     }
   }
@@ -41,7 +41,10 @@ export {
   TonkCore,
   // Error classes
   TonkError,
+  type TonkErrorContext,
+  type TonkErrorOptions,
 } from './core.js';
+export type { TonkErrorCode } from './tonk_core.js';
 // Export initialization functions
 export { initializeTonk, isInitialized } from './init.js';
 
@@ -19,10 +19,14 @@ import {
   TonkCore,
   // Error classes
   TonkError,
+  type TonkErrorContext,
+  type TonkErrorOptions,
 } from './core.js';
 import { WASM_BASE64 } from './generated/wasm-data.js';
 import { initializeTonkWithEmbeddedWasm, isInitialized } from './init.js';
 
+export type { TonkErrorCode } from './tonk_core.js';
+
 // Auto-initialize on import for convenience
 await initializeTonkWithEmbeddedWasm();
 
@@ -77,6 +81,8 @@ export {
   type JsonValue,
   // Error classes
   TonkError,
+  type TonkErrorContext,
+  type TonkErrorOptions,
   ConnectionError,
   FileSystemError,
   BundleError,
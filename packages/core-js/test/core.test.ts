@@ -657,6 +657,39 @@ describe('TonkCore', () => {
 });
 
 describe('Error Classes', () => {
+  test('should keep the code and context of tonk-core rejections', async () => {
+    const tonk = await TonkCore.create();
+    await tonk.createFile('/taken.json', { value: 1 });
+
+    try {
+      await tonk.createFile('/taken.json', { value: 2 });
+      throw new Error('Should throw error for existing file');
+    } catch (error) {
+      expect(error instanceof FileSystemError).toBeTruthy();
+      const fsError = error as FileSystemError;
+      expect(fsError.code).toBe('DOCUMENT_EXISTS');
+      expect(fsError.context.path).toBe('/taken.json');
+      expect((fsError.cause as TonkError).code).toBe('DOCUMENT_EXISTS');
+    }
+
+    tonk.free();
+  });
+
+  test('should keep the code of a coded cause', () => {
+    const cause = Object.assign(new Error('gone'), {
+      code: 'PATH_NOT_FOUND',
+      context: { path: '/gone' },
+    });
+    const fsError = new FileSystemError('wrapped', { cause });
+    expect(fsError.code).toBe('PATH_NOT_FOUND');
+    expect(fsError.context).toEqual({ path: '/gone' });
+    expect(fsError.cause).toBe(cause);
+
+    const uncoded = new BundleError('wrapped', { cause: 'a string' });
+    expect(uncoded.code).toBe('BUNDLE_ERROR');
+    expect(uncoded.context).toEqual({});
+  });
+
   test('should create custom error instances', () => {
     const tonkError = new TonkError('test message', 'TEST_CODE');
     expect(tonkError instanceof TonkError).toBeTruthy();
@@ -26,7 +26,7 @@ docker run --rm \
   -v "$SCRIPT_DIR:/build" \
   -w /build \
   "$IMAGE_NAME" \
-  sh -c 'rm -rf target && wasm-pack build --target web --out-dir pkg-browser --features wasm-browser'
+  sh -c 'rm -rf target && wasm-pack build --target web --out-dir pkg-browser --no-default-features --features wasm-browser,console_error_panic_hook'
 
 echo ""
 echo "✅ Docker WASM build completed successfully!"
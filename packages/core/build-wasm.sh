@@ -20,12 +20,12 @@ rm -rf pkg pkg-node pkg-browser
 echo "Building for web target (wasm-browser)..."
 RUSTFLAGS="--cfg getrandom_backend=\"wasm_js\"" \
   wasm-pack build --target web --out-dir pkg-browser \
-  --features wasm-browser
+  --no-default-features --features wasm-browser,console_error_panic_hook
 
 # Build for Node.js
 echo "Building for Node.js target..."
 RUSTFLAGS="--cfg getrandom_backend=\"wasm_js\"" \
-  wasm-pack build --target nodejs --out-dir pkg-node -- --features wasm-node
+  wasm-pack build --target nodejs --out-dir pkg-node -- --no-default-features --features wasm-node,console_error_panic_hook
 
 echo "WASM build completed successfully!"
 echo ""
@@ -0,0 +1,68 @@
+use crate::error::{Result, VfsError};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::StorageManager;
+
+/// How much browser storage the origin uses, and whether it can be evicted
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageEstimate {
+    /// Bytes used by the origin, across IndexedDB and other storage
+    pub usage: Option<u64>,
+    /// Bytes the browser will let the origin use
+    pub quota: Option<u64>,
+    /// Whether the browser has agreed not to evict the origin's storage
+    pub persisted: bool,
+    /// Whether the stored replica was found evicted when the instance started
+    pub evicted: bool,
+}
+
+/// The `navigator.storage` of the page or worker, if the browser has one
+fn storage_manager() -> Result<StorageManager> {
+    let navigator = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("navigator"))
+        .ok()
+        .filter(|navigator| !navigator.is_undefined());
+    navigator
+        .and_then(|navigator| js_sys::Reflect::get(&navigator, &JsValue::from_str("storage")).ok())
+        .filter(|storage| !storage.is_undefined())
+        .map(JsCast::unchecked_into)
+        .ok_or_else(|| VfsError::NotImplemented("navigator.storage".to_string()))
+}
+
+async fn resolve(promise: std::result::Result<js_sys::Promise, JsValue>) -> Result<JsValue> {
+    let failed = |e: JsValue| VfsError::Other(anyhow::anyhow!("Storage API failed: {:?}", e));
+    JsFuture::from(promise.map_err(failed)?)
+        .await
+        .map_err(failed)
+}
+
+/// Ask the browser to exempt the origin's storage from eviction
+///
+/// Browsers may grant this silently, prompt the user, or refuse, for example
+/// for sites the user rarely visits. Returns whether storage is now persistent.
+pub async fn request_persistence() -> Result<bool> {
+    let manager = storage_manager()?;
+    Ok(resolve(manager.persist()).await?.as_bool().unwrap_or(false))
+}
+
+/// Whether the origin's storage is exempt from eviction
+pub async fn is_persisted() -> Result<bool> {
+    let manager = storage_manager()?;
+    Ok(resolve(manager.persisted())
+        .await?
+        .as_bool()
+        .unwrap_or(false))
+}
+
+/// The origin's storage usage and quota, as estimated by the browser
+pub async fn estimate() -> Result<StorageEstimate> {
+    let manager = storage_manager()?;
+    let estimate: web_sys::StorageEstimate = resolve(manager.estimate()).await?.unchecked_into();
+    Ok(StorageEstimate {
+        usage: estimate.get_usage().map(|bytes| bytes as u64),
+        quota: estimate.get_quota().map(|bytes| bytes as u64),
+        persisted: is_persisted().await?,
+        evicted: false,
+    })
+}
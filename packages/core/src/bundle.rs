@@ -1,14 +1,56 @@
+pub mod container;
+pub mod diff;
+pub mod integrity;
 pub mod path;
+pub mod reader;
+pub mod stream;
+pub mod verify;
+pub use container::{
+    convert_to_container, ContainerWriter, CONTAINER_FORMAT_VERSION, CONTAINER_MAGIC,
+};
+pub use diff::{BundleDiff, DocumentChange, DocumentDiff, ManifestChange};
+pub use integrity::{Integrity, IntegritySignature, VerifyingKey};
 pub use path::BundlePath;
+pub use reader::BundleEntryReader;
+pub use stream::BundleStreamReader;
+pub use verify::{VerifyCheck, VerifyIssue, VerifyReport};
 
+use crate::error::VfsError;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::{Read, Seek, SeekFrom, Write};
 use zip::ZipArchive;
 
+/// Manifest format version written to and accepted from `manifest.json`
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Tonk format version written to new bundles
+pub const FORMAT_VERSION: Version = Version { major: 1, minor: 0 };
+
+/// Most memory reserved up front for an entry, whatever size the index records
+///
+/// Sizes come from the bundle, so a crafted one could otherwise ask for more
+/// than can be allocated before a byte is read. Larger entries grow as they're read.
+const MAX_PREALLOCATION: u64 = 1024 * 1024;
+
+/// An empty buffer for an entry the index says holds `size` bytes
+pub(crate) fn entry_buffer(size: u64) -> Vec<u8> {
+    Vec::with_capacity(size.min(MAX_PREALLOCATION) as usize)
+}
+
+/// How a bundle's entries are laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BundleFormat {
+    /// A ZIP archive (format v1)
+    Zip,
+    /// Length-prefixed records with an index footer (format v2), see `ContainerWriter`
+    Container,
+}
+
 /// Version information for the bundle
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Version {
     pub major: u32,
     pub minor: u32,
@@ -30,6 +72,42 @@ pub struct Manifest {
     pub x_notes: Option<String>,
     #[serde(default, rename = "xVendor")]
     pub x_vendor: Option<serde_json::Value>,
+    /// Additional named document trees carried alongside the one at `rootId`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub roots: BTreeMap<String, NamedRoot>,
+    /// Checksums of the bundle's entries, verified when the bundle is loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<Integrity>,
+}
+
+/// A named document tree in a multi-root bundle
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NamedRoot {
+    /// Document ID of the tree's root PathIndex
+    pub root_id: String,
+    /// Trees with a higher priority are listed, and should be synced, first
+    #[serde(default)]
+    pub sync_priority: i32,
+}
+
+impl Manifest {
+    /// Parse and validate the contents of a `manifest.json` entry
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let manifest: Manifest =
+            serde_json::from_slice(data).context("Failed to parse manifest.json")?;
+
+        // Validate manifest version
+        if manifest.manifest_version != MANIFEST_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported manifest version: {}. Expected version {}.",
+                manifest.manifest_version,
+                MANIFEST_VERSION
+            ));
+        }
+
+        Ok(manifest)
+    }
 }
 
 /// Configuration for bundle export
@@ -43,6 +121,19 @@ pub struct BundleConfig {
     pub notes: Option<String>,
     /// Custom vendor-specific metadata
     pub vendor_metadata: Option<serde_json::Value>,
+    /// Key used to sign the manifest's content index
+    #[serde(skip)]
+    pub signing_key: Option<ed25519_dalek::SigningKey>,
+    /// Write byte-identical bundles for identical content
+    ///
+    /// Entries are written in path order and every timestamp is fixed: to
+    /// `created_at` if set, else to an `xTonk.createdAt` already in
+    /// `vendor_metadata`, else to the ZIP epoch of 1980-01-01.
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Time recorded as `xTonk.createdAt`, instead of the time of export
+    #[serde(default)]
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 /// Trait for random access to data sources with read and write capabilities.
@@ -116,12 +207,12 @@ pub trait RandomAccess: Read + Write + Seek + Send + std::fmt::Debug {
 // Blanket implementation for types that implement the required traits
 impl<T> RandomAccess for T where T: Read + Write + Seek + Send + std::fmt::Debug {}
 
-/// Metadata for a ZIP entry stored in our index
+/// Metadata for a bundle entry stored in our index
 #[derive(Debug, Clone)]
 pub struct EntryMetadata {
-    /// Path within the ZIP file
+    /// Path within the bundle
     pub path: String,
-    /// Offset of local file header in ZIP
+    /// Offset of the local file header in a ZIP, or of the entry data in a container
     pub local_header_offset: u64,
     /// Compressed size
     pub compressed_size: u64,
@@ -300,24 +391,76 @@ pub struct Bundle<R: RandomAccess> {
     index: BundleIndex,
     /// Parsed manifest data
     manifest: Manifest,
+    /// Whether the source was opened without write access
+    read_only: bool,
+    format: BundleFormat,
+    /// Keys the content index must be signed by, if any
+    trusted_keys: Vec<VerifyingKey>,
+    /// Whether entries are checked against the content index as they're read
+    verify_entries: bool,
 }
 
 impl<R: RandomAccess> Bundle<R> {
     /// Create a new bundle from a random access source
-    pub fn from_source(mut data_source: R) -> Result<Self> {
-        // Read the central directory and build our index
-        let index = Self::build_index(&mut data_source)?;
+    ///
+    /// If the manifest has a content index, its signature is verified and it must
+    /// list every entry in the bundle. Each entry is then checked against its
+    /// SHA-256 checksum when it's read, failing the read on a mismatch.
+    pub fn from_source(data_source: R) -> Result<Self> {
+        Self::from_source_trusting(data_source, &[])
+    }
+
+    /// Create a new bundle whose content index must be signed by one of `trusted_keys`
+    ///
+    /// Bundles without a content index, or not signed by a trusted key, are
+    /// rejected. With no keys this is the same as `from_source`.
+    pub fn from_source_trusting(data_source: R, trusted_keys: &[VerifyingKey]) -> Result<Self> {
+        let mut bundle = Self::from_source_unverified(data_source)?;
+        bundle.trusted_keys = trusted_keys.to_vec();
+
+        // Refuse tampered bundles up front; entries are checked as they're read
+        if bundle.manifest.integrity.is_none() && !trusted_keys.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Bundle has no content index to check its signature against"
+            ));
+        }
+        let report = bundle.check_content_index();
+        if !report.is_ok() {
+            return Err(anyhow::anyhow!("Bundle failed integrity checks: {report}"));
+        }
+        bundle.verify_entries = bundle.manifest.integrity.is_some();
+
+        Ok(bundle)
+    }
+
+    /// Create a new bundle without checking it against its content index
+    ///
+    /// Use this to inspect a damaged bundle with `verify`.
+    pub fn from_source_unverified(mut data_source: R) -> Result<Self> {
+        let format = if container::is_container(&mut data_source)? {
+            BundleFormat::Container
+        } else {
+            BundleFormat::Zip
+        };
+
+        // Read the central directory or index footer and build our index
+        let index = match format {
+            BundleFormat::Zip => Self::build_index(&mut data_source)?,
+            BundleFormat::Container => container::build_index(&mut data_source)?,
+        };
 
         // Read and parse the manifest
-        let manifest = Self::read_manifest(&mut data_source, &index)?;
+        let manifest = Self::read_manifest(&mut data_source, format, &index)?;
 
-        let bundle = Bundle {
+        Ok(Bundle {
             data_source,
             index,
             manifest,
-        };
-
-        Ok(bundle)
+            read_only: false,
+            format,
+            trusted_keys: Vec::new(),
+            verify_entries: false,
+        })
     }
 
     /// Helper function to create a ZipArchive from the data source
@@ -400,15 +543,67 @@ impl<R: RandomAccess> Bundle<R> {
         }
     }
 
-    /// Read the actual data for a ZIP entry
+    /// Stream a value by key instead of reading it into memory
+    ///
+    /// The reader borrows the bundle until it's dropped. Its size and CRC32 are
+    /// checked against the index as it's read; see `BundleEntryReader`.
+    pub fn get_reader(&mut self, key: &BundlePath) -> Result<Option<BundleEntryReader<'_, R>>> {
+        let Some(metadata) = self.index.entry(&key.to_string()).cloned() else {
+            return Ok(None);
+        };
+        let sha256 = match self.manifest.integrity.as_ref() {
+            Some(integrity) if self.verify_entries && metadata.path != "manifest.json" => {
+                let expected = integrity.entries.get(&metadata.path).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Entry {} is not listed in the manifest's content index",
+                        metadata.path
+                    )
+                })?;
+                Some(expected.clone())
+            }
+            _ => None,
+        };
+        BundleEntryReader::open(&mut self.data_source, self.format, metadata, sha256).map(Some)
+    }
+
+    /// Stream a value by key to code reading through `futures::io::AsyncRead`
+    ///
+    /// Checked as [`Self::get_reader`] is. Reads go straight to the data source
+    /// and complete immediately, so a bundle read from a file blocks the task
+    /// for each read.
+    pub fn get_async_reader(
+        &mut self,
+        key: &BundlePath,
+    ) -> Result<Option<impl futures::io::AsyncRead + Unpin + '_>> {
+        self.get_reader(key)
+    }
+
+    /// Read the data for an entry, checking it against the content index
     fn read_entry_data(&mut self, metadata: &EntryMetadata) -> Result<Option<Vec<u8>>> {
+        let data = self.read_entry_raw(metadata)?;
+        if let (Some(data), Some(integrity)) = (&data, &self.manifest.integrity) {
+            if self.verify_entries && metadata.path != "manifest.json" {
+                if let Some(message) = integrity.check_entry(&metadata.path, data) {
+                    return Err(anyhow::anyhow!("Entry {}: {message}", metadata.path));
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    /// Read the data for an entry as stored
+    fn read_entry_raw(&mut self, metadata: &EntryMetadata) -> Result<Option<Vec<u8>>> {
+        if self.format == BundleFormat::Container {
+            return container::read_entry(&mut self.data_source, metadata).map(Some);
+        }
+
         let mut archive = self.create_archive()?;
 
         let mut file = archive
             .by_name(&metadata.path)
             .context("Failed to find entry in zip")?;
 
-        let mut buffer = Vec::with_capacity(metadata.uncompressed_size as usize);
+        let mut buffer = entry_buffer(metadata.uncompressed_size);
         file.read_to_end(&mut buffer)
             .context("Failed to read entry data")?;
 
@@ -442,6 +637,15 @@ impl<R: RandomAccess> Bundle<R> {
         Ok(results)
     }
 
+    /// List the entries that match a key prefix without reading their data
+    pub fn prefix_entries(&self, prefix: &BundlePath) -> Vec<EntryMetadata> {
+        self.index
+            .prefix_entries(&prefix.to_string())
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
     /// Get all keys in the bundle
     pub fn list_keys(&self) -> Vec<BundlePath> {
         self.index
@@ -456,13 +660,32 @@ impl<R: RandomAccess> Bundle<R> {
         &self.manifest
     }
 
+    /// Whether the bundle was opened read-only, e.g. because another process had it open
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Whether the bundle is a ZIP archive or a v2 container
+    pub fn format(&self) -> BundleFormat {
+        self.format
+    }
+
     /// Read and parse the manifest.json file from the bundle
-    fn read_manifest(data_source: &mut R, index: &BundleIndex) -> Result<Manifest> {
+    fn read_manifest(
+        data_source: &mut R,
+        format: BundleFormat,
+        index: &BundleIndex,
+    ) -> Result<Manifest> {
         // Check that manifest.json exists in the bundle
-        index
+        let metadata = index
             .entry("manifest.json")
             .ok_or_else(|| anyhow::anyhow!("manifest.json not found in bundle"))?;
 
+        if format == BundleFormat::Container {
+            let data = container::read_entry(data_source, metadata)?;
+            return Manifest::parse(&data);
+        }
+
         // Reset to the beginning to ensure ZipArchive can read the central directory
         data_source.seek_to(0)?;
 
@@ -479,19 +702,7 @@ impl<R: RandomAccess> Bundle<R> {
             .read_to_string(&mut manifest_content)
             .context("Failed to read manifest.json content")?;
 
-        // Parse the JSON
-        let manifest: Manifest =
-            serde_json::from_str(&manifest_content).context("Failed to parse manifest.json")?;
-
-        // Validate manifest version
-        if manifest.manifest_version != 1 {
-            return Err(anyhow::anyhow!(
-                "Unsupported manifest version: {}. Expected version 1.",
-                manifest.manifest_version
-            ));
-        }
-
-        Ok(manifest)
+        Manifest::parse(manifest_content.as_bytes())
     }
 }
 
@@ -503,6 +714,11 @@ impl Bundle<std::io::Cursor<Vec<u8>>> {
         Self::from_source(cursor)
     }
 
+    /// Load a bundle from a byte array, requiring it to be signed by a trusted key
+    pub fn from_bytes_trusting(data: Vec<u8>, trusted_keys: &[VerifyingKey]) -> Result<Self> {
+        Self::from_source_trusting(std::io::Cursor::new(data), trusted_keys)
+    }
+
     /// Get the bundle data as bytes (for serialization)
     pub fn to_bytes(&mut self) -> Result<Vec<u8>> {
         // Read all data from our cursor
@@ -517,16 +733,65 @@ impl Bundle<std::io::Cursor<Vec<u8>>> {
 
 impl Bundle<std::fs::File> {
     /// Load a bundle from a file path
+    ///
+    /// Takes an exclusive advisory lock on the file, so other processes can't open
+    /// it while it's held. If another process is only reading the file, it is
+    /// opened read-only with a shared lock instead. If another process is writing
+    /// it, this fails with `VfsError::BundleLocked`.
     pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
         use std::fs::OpenOptions;
 
+        let path = path.as_ref();
         // Open the file with read+write permissions to support both reading and writing operations
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .open(path)
             .context("Failed to open bundle file with read+write permissions")?;
-        Self::from_source(file)
+
+        if try_lock_file(&file, true).context("Failed to lock bundle file")? {
+            return Self::from_source(file);
+        }
+        Self::from_file_read_only(path)
+    }
+
+    /// Load a bundle from a file path for reading only
+    ///
+    /// Takes a shared advisory lock, which any number of readers can hold at once.
+    /// Fails with `VfsError::BundleLocked` if another process is writing the file.
+    pub fn from_file_read_only<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).context("Failed to open bundle file")?;
+
+        if !try_lock_file(&file, false).context("Failed to lock bundle file")? {
+            return Err(VfsError::BundleLocked(path.display().to_string()).into());
+        }
+
+        let mut bundle = Self::from_source(file)?;
+        bundle.read_only = true;
+        Ok(bundle)
+    }
+}
+
+/// Take an advisory lock on a file without waiting
+///
+/// Returns false if another process holds a conflicting lock. Writers take an
+/// exclusive lock and readers a shared one. The lock is released when the file
+/// is closed. On platforms without file locking, no lock is taken.
+pub(crate) fn try_lock_file(file: &std::fs::File, exclusive: bool) -> std::io::Result<bool> {
+    let result = if exclusive {
+        file.try_lock()
+    } else {
+        file.try_lock_shared()
+    };
+    match result {
+        Ok(()) => Ok(true),
+        Err(std::fs::TryLockError::WouldBlock) => Ok(false),
+        Err(std::fs::TryLockError::Error(e)) if e.kind() == std::io::ErrorKind::Unsupported => {
+            tracing::debug!("File locking is not supported here, continuing without a lock");
+            Ok(true)
+        }
+        Err(std::fs::TryLockError::Error(e)) => Err(e),
     }
 }
 
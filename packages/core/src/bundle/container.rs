@@ -0,0 +1,536 @@
+use super::{Bundle, BundleIndex, EntryMetadata, RandomAccess, Version};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+
+/// First bytes of every v2 container
+pub const CONTAINER_MAGIC: &[u8; 8] = b"TONKBND2";
+
+/// Tonk format version recorded in the manifest of v2 containers
+pub const CONTAINER_FORMAT_VERSION: Version = Version { major: 2, minor: 0 };
+
+/// Last bytes of a container whose index footer was written completely
+const TRAILER_MAGIC: &[u8; 8] = b"TONKIDX2";
+/// Kind, path length, data length and CRC32 of the data
+const RECORD_HEADER_LEN: u64 = 1 + 4 + 8 + 4;
+/// Offset of the index record, then `TRAILER_MAGIC`
+const TRAILER_LEN: u64 = 8 + 8;
+
+/// What a record in a v2 container holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordKind {
+    /// The data of the entry at the record's path, replacing any earlier one
+    Entry = 1,
+    /// Marks the entry at the record's path as deleted
+    Tombstone = 2,
+    /// The JSON index of every live entry; always followed by the trailer
+    Index = 3,
+}
+
+impl RecordKind {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(Self::Entry),
+            2 => Some(Self::Tombstone),
+            3 => Some(Self::Index),
+            _ => None,
+        }
+    }
+}
+
+/// Where an entry's data lives in the container
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexedEntry {
+    /// Offset of the first byte of data, after the record header and path
+    offset: u64,
+    len: u64,
+    crc32: u32,
+}
+
+impl IndexedEntry {
+    fn metadata(&self, path: &str) -> EntryMetadata {
+        EntryMetadata {
+            path: path.to_string(),
+            local_header_offset: self.offset,
+            compressed_size: self.len,
+            uncompressed_size: self.len,
+            crc32: self.crc32,
+            compression_method: 0,
+        }
+    }
+}
+
+/// Contents of the index record
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ContainerIndex {
+    entries: BTreeMap<String, IndexedEntry>,
+}
+
+/// Header of a record, read back from the container
+struct RecordHeader {
+    kind: RecordKind,
+    path_len: u64,
+    data_len: u64,
+    crc32: u32,
+}
+
+impl RecordHeader {
+    fn read<R: RandomAccess>(source: &mut R) -> Result<Self> {
+        let mut buf = [0u8; RECORD_HEADER_LEN as usize];
+        source.read_exact_at(&mut buf)?;
+        let kind = RecordKind::from_byte(buf[0])
+            .ok_or_else(|| anyhow::anyhow!("Unknown record kind {}", buf[0]))?;
+        Ok(Self {
+            kind,
+            path_len: u32::from_le_bytes(buf[1..5].try_into().unwrap()) as u64,
+            data_len: u64::from_le_bytes(buf[5..13].try_into().unwrap()),
+            crc32: u32::from_le_bytes(buf[13..17].try_into().unwrap()),
+        })
+    }
+
+    /// Length of the whole record, or `None` if a corrupt length overflows
+    fn len(&self) -> Option<u64> {
+        RECORD_HEADER_LEN
+            .checked_add(self.path_len)?
+            .checked_add(self.data_len)
+    }
+}
+
+/// Whether `source` holds a v2 container rather than a ZIP bundle
+pub(crate) fn is_container<R: RandomAccess>(source: &mut R) -> Result<bool> {
+    let mut magic = [0u8; 8];
+    source.seek_to(0)?;
+    let is_container = match source.read_exact(&mut magic) {
+        Ok(()) => &magic == CONTAINER_MAGIC,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e).context("Failed to read bundle header"),
+    };
+    source.seek_to(0)?;
+    Ok(is_container)
+}
+
+/// Build the index of a v2 container
+pub(crate) fn build_index<R: RandomAccess>(source: &mut R) -> Result<BundleIndex> {
+    let (entries, _) = read_entries(source)?;
+    let mut index = BundleIndex::new();
+    for (path, entry) in &entries {
+        index.add_entry(entry.metadata(path));
+    }
+    Ok(index)
+}
+
+/// Read an entry's data by its offset and check its CRC32
+///
+/// The data is read through a reader limited to the entry's length, so a
+/// corrupt length can't make it allocate more than the container holds.
+pub(crate) fn read_entry<R: RandomAccess>(
+    source: &mut R,
+    metadata: &EntryMetadata,
+) -> Result<Vec<u8>> {
+    source.seek_to(metadata.local_header_offset)?;
+    let mut data = Vec::new();
+    Read::take(&mut *source, metadata.uncompressed_size)
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to read {}", metadata.path))?;
+    if data.len() as u64 != metadata.uncompressed_size {
+        return Err(anyhow::anyhow!("{} is truncated", metadata.path));
+    }
+    if crc32fast::hash(&data) != metadata.crc32 {
+        return Err(anyhow::anyhow!("CRC32 mismatch in {}", metadata.path));
+    }
+    Ok(data)
+}
+
+/// Read the live entries of a container and where the next record can be appended
+///
+/// The index footer is used when it's intact. A container whose last write was
+/// interrupted has no valid footer, so its records are scanned instead, applying
+/// entries and tombstones in order, up to the last complete record.
+fn read_entries<R: RandomAccess>(source: &mut R) -> Result<(BTreeMap<String, IndexedEntry>, u64)> {
+    let size = source
+        .size()?
+        .ok_or_else(|| anyhow::anyhow!("Container size is unknown"))?;
+    match read_footer(source, size).and_then(|index| check_bounds(index, size)) {
+        Ok(index) => Ok((index.entries, size)),
+        Err(e) => {
+            tracing::warn!("Container index footer unusable ({e:#}), scanning records");
+            scan_records(source, size)
+        }
+    }
+}
+
+/// Check that every indexed entry lies within the container's records
+fn check_bounds(index: ContainerIndex, size: u64) -> Result<ContainerIndex> {
+    for (path, entry) in &index.entries {
+        let end = entry.offset.checked_add(entry.len);
+        if entry.offset < CONTAINER_MAGIC.len() as u64 || end.is_none_or(|end| end > size) {
+            return Err(anyhow::anyhow!(
+                "Index entry {path} lies outside the container"
+            ));
+        }
+    }
+    Ok(index)
+}
+
+fn read_footer<R: RandomAccess>(source: &mut R, size: u64) -> Result<ContainerIndex> {
+    if size < CONTAINER_MAGIC.len() as u64 + RECORD_HEADER_LEN + TRAILER_LEN {
+        return Err(anyhow::anyhow!("Container is too short for an index"));
+    }
+    let mut trailer = [0u8; TRAILER_LEN as usize];
+    source.seek_to(size - TRAILER_LEN)?;
+    source.read_exact_at(&mut trailer)?;
+    if &trailer[8..] != TRAILER_MAGIC {
+        return Err(anyhow::anyhow!("Missing index trailer"));
+    }
+
+    let index_offset = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+    source.seek_to(index_offset)?;
+    let header = RecordHeader::read(source)?;
+    let end = header
+        .len()
+        .and_then(|len| index_offset.checked_add(len)?.checked_add(TRAILER_LEN));
+    if header.kind != RecordKind::Index || end != Some(size) {
+        return Err(anyhow::anyhow!("Trailer doesn't point at an index record"));
+    }
+    source.seek_to(index_offset + RECORD_HEADER_LEN + header.path_len)?;
+    let mut data = vec![0u8; header.data_len as usize];
+    source.read_exact_at(&mut data)?;
+    if crc32fast::hash(&data) != header.crc32 {
+        return Err(anyhow::anyhow!("CRC32 mismatch in index"));
+    }
+    serde_json::from_slice(&data).context("Failed to parse container index")
+}
+
+fn scan_records<R: RandomAccess>(
+    source: &mut R,
+    size: u64,
+) -> Result<(BTreeMap<String, IndexedEntry>, u64)> {
+    let mut entries = BTreeMap::new();
+    let mut offset = CONTAINER_MAGIC.len() as u64;
+
+    while offset + RECORD_HEADER_LEN <= size {
+        source.seek_to(offset)?;
+        let Ok(header) = RecordHeader::read(source) else {
+            break;
+        };
+        let trailer = if header.kind == RecordKind::Index {
+            TRAILER_LEN
+        } else {
+            0
+        };
+        let end = header
+            .len()
+            .and_then(|len| offset.checked_add(len)?.checked_add(trailer));
+        let Some(end) = end.filter(|end| *end <= size) else {
+            break;
+        };
+
+        let mut path = vec![0u8; header.path_len as usize];
+        source.read_exact_at(&mut path)?;
+        let path = String::from_utf8(path).context("Record path is not UTF-8")?;
+        let entry = IndexedEntry {
+            offset: offset + RECORD_HEADER_LEN + header.path_len,
+            len: header.data_len,
+            crc32: header.crc32,
+        };
+        match header.kind {
+            RecordKind::Entry => {
+                entries.insert(path, entry);
+            }
+            RecordKind::Tombstone => {
+                entries.remove(&path);
+            }
+            RecordKind::Index => {}
+        }
+        offset = end;
+    }
+
+    Ok((entries, offset))
+}
+
+/// Writes a v2 container: length-prefixed records followed by an index footer
+///
+/// Records are only ever appended. Writing an entry that already exists adds a
+/// new record that supersedes the old one, and deleting an entry adds a
+/// tombstone, so neither rewrites earlier data. `finish` appends a fresh index
+/// and trailer; until it's called, readers see the container as it was before.
+///
+/// ```
+/// # use tonk_core::bundle::ContainerWriter;
+/// let mut writer = ContainerWriter::new(std::io::Cursor::new(Vec::new())).unwrap();
+/// writer.put("storage/doc", b"data").unwrap();
+/// let container = writer.finish().unwrap().into_inner();
+/// # assert!(container.starts_with(tonk_core::bundle::CONTAINER_MAGIC));
+/// ```
+#[derive(Debug)]
+pub struct ContainerWriter<W: RandomAccess> {
+    sink: W,
+    entries: BTreeMap<String, IndexedEntry>,
+    /// Where the next record goes
+    offset: u64,
+}
+
+impl<W: RandomAccess> ContainerWriter<W> {
+    /// Start a new, empty container at the beginning of `sink`
+    pub fn new(mut sink: W) -> Result<Self> {
+        sink.seek_to(0)?;
+        sink.write_at(CONTAINER_MAGIC)?;
+        Ok(Self {
+            sink,
+            entries: BTreeMap::new(),
+            offset: CONTAINER_MAGIC.len() as u64,
+        })
+    }
+
+    /// Reopen an existing container to append to it
+    ///
+    /// Anything after the last complete record of an interrupted write is
+    /// overwritten.
+    pub fn append(mut sink: W) -> Result<Self> {
+        if !is_container(&mut sink)? {
+            return Err(anyhow::anyhow!("Not a v2 container"));
+        }
+        let (entries, offset) = read_entries(&mut sink)?;
+        Ok(Self {
+            sink,
+            entries,
+            offset,
+        })
+    }
+
+    /// Write the data of the entry at `path`
+    pub fn put(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        let entry = self.write_record(RecordKind::Entry, path, data)?;
+        self.entries.insert(path.to_string(), entry);
+        Ok(())
+    }
+
+    /// Delete the entry at `path`, returning whether there was one
+    pub fn delete(&mut self, path: &str) -> Result<bool> {
+        if !self.entries.contains_key(path) {
+            return Ok(false);
+        }
+        self.write_record(RecordKind::Tombstone, path, &[])?;
+        self.entries.remove(path);
+        Ok(true)
+    }
+
+    /// Paths of the live entries
+    pub fn paths(&self) -> impl Iterator<Item = &String> {
+        self.entries.keys()
+    }
+
+    /// Write the index footer and return the sink
+    pub fn finish(mut self) -> Result<W> {
+        let index = ContainerIndex {
+            entries: std::mem::take(&mut self.entries),
+        };
+        let data = serde_json::to_vec(&index).context("Failed to serialize container index")?;
+        let index_offset = self.offset;
+        self.write_record(RecordKind::Index, "", &data)?;
+        self.sink.write_at(&index_offset.to_le_bytes())?;
+        self.sink.write_at(TRAILER_MAGIC)?;
+        RandomAccess::flush(&mut self.sink)?;
+        Ok(self.sink)
+    }
+
+    fn write_record(&mut self, kind: RecordKind, path: &str, data: &[u8]) -> Result<IndexedEntry> {
+        let path_len = u32::try_from(path.len()).context("Entry path is too long")?;
+        let crc32 = crc32fast::hash(data);
+
+        let mut header = Vec::with_capacity(RECORD_HEADER_LEN as usize + path.len());
+        header.push(kind as u8);
+        header.extend_from_slice(&path_len.to_le_bytes());
+        header.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        header.extend_from_slice(&crc32.to_le_bytes());
+        header.extend_from_slice(path.as_bytes());
+
+        self.sink.seek_to(self.offset)?;
+        self.sink.write_at(&header)?;
+        self.sink.write_at(data)?;
+
+        let entry = IndexedEntry {
+            offset: self.offset + header.len() as u64,
+            len: data.len() as u64,
+            crc32,
+        };
+        self.offset = entry.offset + entry.len;
+        Ok(entry)
+    }
+}
+
+impl<R: RandomAccess> Bundle<R> {
+    /// Write the bundle's manifest and entries to a new v2 container
+    ///
+    /// The manifest records `CONTAINER_FORMAT_VERSION`; everything else,
+    /// including the content index, is copied unchanged.
+    pub fn write_container<W: RandomAccess>(&mut self, sink: W) -> Result<W> {
+        let mut manifest = self.manifest.clone();
+        manifest.version = CONTAINER_FORMAT_VERSION;
+
+        let mut writer = ContainerWriter::new(sink)?;
+        writer.put(
+            "manifest.json",
+            &serde_json::to_vec_pretty(&manifest).context("Failed to serialize manifest")?,
+        )?;
+
+        let mut paths: Vec<String> = self.index.all_paths().into_iter().cloned().collect();
+        paths.sort();
+        for path in paths.iter().filter(|path| *path != "manifest.json") {
+            let metadata = self.index.entry(path).cloned().unwrap();
+            if let Some(data) = self.read_entry_data(&metadata)? {
+                writer.put(path, &data)?;
+            }
+        }
+        writer.finish()
+    }
+}
+
+/// Convert a v1 ZIP bundle to a v2 container
+///
+/// Bundles that are already v2 containers are rewritten, which drops superseded
+/// records and tombstones.
+pub fn convert_to_container(data: Vec<u8>) -> Result<Vec<u8>> {
+    let mut bundle = Bundle::from_bytes(data)?;
+    Ok(bundle
+        .write_container(Cursor::new(Vec::new()))?
+        .into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::{BundleFormat, BundlePath};
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    const MANIFEST: &str = r#"{
+        "manifestVersion": 1,
+        "version": { "major": 1, "minor": 0 },
+        "rootId": "test-root-id",
+        "entrypoints": [],
+        "networkUris": []
+    }"#;
+
+    fn zip_bundle() -> Vec<u8> {
+        let mut zip_data = Vec::new();
+        let mut zip_writer = ZipWriter::new(Cursor::new(&mut zip_data));
+        for (path, data) in [
+            ("manifest.json", MANIFEST.as_bytes()),
+            ("storage/ab/cdef/snapshot/bundle_export", b"snapshot"),
+            ("app/index.html", b"<html></html>"),
+        ] {
+            zip_writer
+                .start_file(path, SimpleFileOptions::default())
+                .unwrap();
+            zip_writer.write_all(data).unwrap();
+        }
+        zip_writer.finish().unwrap();
+        zip_data
+    }
+
+    #[test]
+    fn test_convert_zip_bundle() {
+        let container = convert_to_container(zip_bundle()).unwrap();
+        assert!(container.starts_with(CONTAINER_MAGIC));
+
+        let mut bundle = Bundle::from_bytes(container).unwrap();
+        assert_eq!(bundle.format(), BundleFormat::Container);
+        assert_eq!(bundle.manifest().root_id, "test-root-id");
+        assert_eq!(bundle.manifest().version, CONTAINER_FORMAT_VERSION);
+        assert_eq!(
+            bundle.get(&BundlePath::from("app/index.html")).unwrap(),
+            Some(b"<html></html>".to_vec())
+        );
+        assert_eq!(
+            bundle.prefix(&BundlePath::from("storage")).unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_append_and_tombstones() {
+        let container = convert_to_container(zip_bundle()).unwrap();
+        let mut writer = ContainerWriter::append(Cursor::new(container)).unwrap();
+        writer.put("app/index.html", b"<html>v2</html>").unwrap();
+        assert!(writer
+            .delete("storage/ab/cdef/snapshot/bundle_export")
+            .unwrap());
+        assert!(!writer.delete("missing").unwrap());
+        let container = writer.finish().unwrap().into_inner();
+
+        let mut bundle = Bundle::from_bytes(container.clone()).unwrap();
+        assert_eq!(
+            bundle.get(&BundlePath::from("app/index.html")).unwrap(),
+            Some(b"<html>v2</html>".to_vec())
+        );
+        assert!(bundle
+            .prefix(&BundlePath::from("storage"))
+            .unwrap()
+            .is_empty());
+
+        // Rewriting drops the superseded record and the tombstone
+        let compacted = convert_to_container(container.clone()).unwrap();
+        assert!(compacted.len() < container.len());
+    }
+
+    #[test]
+    fn test_recover_interrupted_append() {
+        let container = convert_to_container(zip_bundle()).unwrap();
+        let mut writer = ContainerWriter::append(Cursor::new(container)).unwrap();
+        writer.put("app/new.html", b"new").unwrap();
+        writer.put("app/partial.html", b"cut off").unwrap();
+        // Lose the footer and the end of the last record
+        let mut container = writer.sink.into_inner();
+        container.truncate(container.len() - 2);
+
+        let mut bundle = Bundle::from_bytes(container).unwrap();
+        assert_eq!(
+            bundle.get(&BundlePath::from("app/new.html")).unwrap(),
+            Some(b"new".to_vec())
+        );
+        assert_eq!(
+            bundle.get(&BundlePath::from("app/partial.html")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_index_entries_outside_container_are_rejected() {
+        let mut writer = ContainerWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.put("app/a.txt", b"data").unwrap();
+        // An index whose entry runs past the end, with a valid checksum
+        writer.entries.get_mut("app/a.txt").unwrap().len = u64::MAX - 1;
+        let container = writer.finish().unwrap().into_inner();
+
+        // The footer is ignored and the records scanned instead
+        let (entries, _) = read_entries(&mut Cursor::new(container)).unwrap();
+        assert_eq!(entries["app/a.txt"].len, 4);
+    }
+
+    #[test]
+    fn test_scan_survives_overflowing_record_length() {
+        let mut container = CONTAINER_MAGIC.to_vec();
+        container.push(RecordKind::Entry as u8);
+        container.extend_from_slice(&1u32.to_le_bytes());
+        container.extend_from_slice(&u64::MAX.to_le_bytes());
+        container.extend_from_slice(&0u32.to_le_bytes());
+        container.push(b'x');
+
+        let index = build_index(&mut Cursor::new(container.clone())).unwrap();
+        assert!(index.all_paths().is_empty());
+
+        // Reading an entry whose length exceeds the container fails without allocating it
+        let metadata = IndexedEntry {
+            offset: CONTAINER_MAGIC.len() as u64,
+            len: u64::MAX,
+            crc32: 0,
+        }
+        .metadata("x");
+        let err = read_entry(&mut Cursor::new(container), &metadata).unwrap_err();
+        assert!(err.to_string().contains("truncated"));
+    }
+}
@@ -0,0 +1,330 @@
+use super::verify::storage_document_id;
+use super::{Bundle, BundlePath, RandomAccess};
+use anyhow::{Context, Result};
+use automerge::{Automerge, ChangeHash, ReadDoc};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// How a stored document differs between two bundles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DocumentChange {
+    /// Only the other bundle has the document
+    Added,
+    /// Only this bundle has the document
+    Removed,
+    /// This bundle has every change of the other one, plus more
+    Ahead,
+    /// The other bundle has every change of this one, plus more
+    Behind,
+    /// Each bundle has changes the other is missing
+    Diverged,
+}
+
+impl fmt::Display for DocumentChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentChange::Added => write!(f, "added"),
+            DocumentChange::Removed => write!(f, "removed"),
+            DocumentChange::Ahead => write!(f, "ahead"),
+            DocumentChange::Behind => write!(f, "behind"),
+            DocumentChange::Diverged => write!(f, "diverged"),
+        }
+    }
+}
+
+/// A stored document whose heads differ between two bundles
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentDiff {
+    pub document_id: String,
+    pub change: DocumentChange,
+    /// Heads of the document in this bundle, empty if it's missing
+    pub heads: Vec<String>,
+    /// Heads of the document in the other bundle, empty if it's missing
+    pub other_heads: Vec<String>,
+}
+
+/// A top-level manifest field that differs between two bundles
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestChange {
+    /// Field name as written in `manifest.json`
+    pub field: String,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+/// Differences between two bundles, as seen from the first one
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleDiff {
+    /// Entries only in the other bundle
+    pub added: Vec<String>,
+    /// Entries only in this bundle
+    pub removed: Vec<String>,
+    /// Entries in both bundles with different contents
+    pub changed: Vec<String>,
+    pub manifest: Vec<ManifestChange>,
+    pub documents: Vec<DocumentDiff>,
+}
+
+impl BundleDiff {
+    /// Whether the two bundles have the same entries, manifest and documents
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.manifest.is_empty()
+            && self.documents.is_empty()
+    }
+}
+
+impl fmt::Display for BundleDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "Bundles are identical");
+        }
+        write!(
+            f,
+            "{} added, {} removed, {} changed entries; {} manifest field(s) and {} document(s) differ",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.manifest.len(),
+            self.documents.len()
+        )?;
+        for path in &self.added {
+            write!(f, "\n  + {path}")?;
+        }
+        for path in &self.removed {
+            write!(f, "\n  - {path}")?;
+        }
+        for path in &self.changed {
+            write!(f, "\n  ~ {path}")?;
+        }
+        for change in &self.manifest {
+            let show = |value: &Option<serde_json::Value>| match value {
+                Some(value) => value.to_string(),
+                None => "(unset)".to_string(),
+            };
+            write!(
+                f,
+                "\n  [manifest] {}: {} -> {}",
+                change.field,
+                show(&change.before),
+                show(&change.after)
+            )?;
+        }
+        for document in &self.documents {
+            write!(f, "\n  [{}] {}", document.change, document.document_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the top-level fields of two manifests
+fn diff_manifests(
+    before: &super::Manifest,
+    after: &super::Manifest,
+) -> Result<Vec<ManifestChange>> {
+    let to_map =
+        |manifest: &super::Manifest| -> Result<serde_json::Map<String, serde_json::Value>> {
+            match serde_json::to_value(manifest)? {
+                serde_json::Value::Object(map) => Ok(map),
+                _ => Err(anyhow::anyhow!("Manifest did not serialize to an object")),
+            }
+        };
+    let before = to_map(before)?;
+    let after = to_map(after)?;
+
+    let fields: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    Ok(fields
+        .into_iter()
+        .filter(|field| before.get(*field) != after.get(*field))
+        .map(|field| ManifestChange {
+            field: field.clone(),
+            before: before.get(field).cloned(),
+            after: after.get(field).cloned(),
+        })
+        .collect())
+}
+
+/// Whether `doc` contains every change in `heads`
+fn contains_all(doc: &Automerge, heads: &[ChangeHash]) -> bool {
+    heads
+        .iter()
+        .all(|hash| doc.get_change_by_hash(hash).is_some())
+}
+
+fn hex_heads(heads: &[ChangeHash]) -> Vec<String> {
+    heads.iter().map(|hash| hash.to_string()).collect()
+}
+
+impl<R: RandomAccess> Bundle<R> {
+    /// Compare this bundle with another one
+    ///
+    /// Entries are compared by their CRC32 and size, so no entry data is read
+    /// for that part. Every stored document is loaded from both bundles to
+    /// compare its heads; documents with the same heads are left out of the
+    /// report. The manifest is compared field by field rather than as an entry.
+    pub fn diff<S: RandomAccess>(&mut self, other: &mut Bundle<S>) -> Result<BundleDiff> {
+        let mut diff = BundleDiff {
+            manifest: diff_manifests(&self.manifest, &other.manifest)?,
+            ..Default::default()
+        };
+
+        let ours = self.sorted_paths();
+        let theirs = other.sorted_paths();
+        for path in ours.into_iter().filter(|path| path != "manifest.json") {
+            let (Some(a), Some(b)) = (self.index.entry(&path), other.index.entry(&path)) else {
+                diff.removed.push(path);
+                continue;
+            };
+            if a.crc32 != b.crc32 || a.uncompressed_size != b.uncompressed_size {
+                diff.changed.push(path);
+            }
+        }
+        diff.added = theirs
+            .into_iter()
+            .filter(|path| path != "manifest.json" && self.index.entry(path).is_none())
+            .collect();
+
+        let ours = self.load_documents()?;
+        let mut theirs = other.load_documents()?;
+        for (document_id, doc) in ours {
+            let heads = doc.get_heads();
+            let Some(other_doc) = theirs.remove(&document_id) else {
+                diff.documents.push(DocumentDiff {
+                    document_id,
+                    change: DocumentChange::Removed,
+                    heads: hex_heads(&heads),
+                    other_heads: Vec::new(),
+                });
+                continue;
+            };
+
+            let other_heads = other_doc.get_heads();
+            if heads == other_heads {
+                continue;
+            }
+            let change = if contains_all(&doc, &other_heads) {
+                DocumentChange::Ahead
+            } else if contains_all(&other_doc, &heads) {
+                DocumentChange::Behind
+            } else {
+                DocumentChange::Diverged
+            };
+            diff.documents.push(DocumentDiff {
+                document_id,
+                change,
+                heads: hex_heads(&heads),
+                other_heads: hex_heads(&other_heads),
+            });
+        }
+        for (document_id, doc) in theirs {
+            diff.documents.push(DocumentDiff {
+                document_id,
+                change: DocumentChange::Added,
+                heads: Vec::new(),
+                other_heads: hex_heads(&doc.get_heads()),
+            });
+        }
+        diff.documents
+            .sort_by(|a, b| a.document_id.cmp(&b.document_id));
+
+        Ok(diff)
+    }
+
+    /// Load every stored document, keyed by document ID
+    fn load_documents(&mut self) -> Result<BTreeMap<String, Automerge>> {
+        let mut chunks: BTreeMap<String, (Vec<u8>, Vec<u8>)> = BTreeMap::new();
+        for (key, data) in self.prefix(&BundlePath::from("storage"))? {
+            let path = key.to_string();
+            let Some((document_id, kind)) = storage_document_id(&path) else {
+                continue;
+            };
+            // Snapshots go first so incremental changes apply on top of them
+            let (snapshots, incrementals) = chunks.entry(document_id).or_default();
+            if kind == "snapshot" {
+                snapshots.extend(data);
+            } else {
+                incrementals.extend(data);
+            }
+        }
+
+        chunks
+            .into_iter()
+            .map(|(document_id, (mut data, incrementals))| {
+                data.extend(incrementals);
+                let doc = Automerge::load(&data)
+                    .with_context(|| format!("Failed to load document {document_id}"))?;
+                Ok((document_id, doc))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::BundleConfig;
+    use crate::TonkCore;
+
+    #[tokio::test]
+    async fn test_diff_identical_bundles() {
+        let tonk = TonkCore::new().await.unwrap();
+        tonk.vfs()
+            .create_document("/a.txt", "hello".to_string())
+            .await
+            .unwrap();
+
+        let bytes = tonk.to_bytes(None).await.unwrap();
+        let mut a = Bundle::from_bytes(bytes.clone()).unwrap();
+        let mut b = Bundle::from_bytes(bytes).unwrap();
+        let diff = a.diff(&mut b).unwrap();
+
+        assert!(diff.is_empty(), "{diff}");
+    }
+
+    #[tokio::test]
+    async fn test_diff_reports_changes() {
+        let tonk = TonkCore::new().await.unwrap();
+        tonk.vfs()
+            .create_document("/a.txt", "hello".to_string())
+            .await
+            .unwrap();
+        let before = tonk.to_bytes(None).await.unwrap();
+
+        tonk.vfs()
+            .create_document("/b.txt", "world".to_string())
+            .await
+            .unwrap();
+        let config = BundleConfig {
+            notes: Some("second release".to_string()),
+            ..Default::default()
+        };
+        let after = tonk.to_bytes(Some(config)).await.unwrap();
+
+        let mut a = Bundle::from_bytes(before).unwrap();
+        let mut b = Bundle::from_bytes(after).unwrap();
+        let diff = a.diff(&mut b).unwrap();
+
+        assert!(!diff.added.is_empty());
+        let notes = diff.manifest.iter().find(|c| c.field == "xNotes").unwrap();
+        assert_eq!(notes.after, Some(serde_json::json!("second release")));
+
+        let changes: Vec<DocumentChange> = diff.documents.iter().map(|d| d.change).collect();
+        assert!(changes.contains(&DocumentChange::Added));
+        assert!(changes.contains(&DocumentChange::Behind));
+        assert!(!changes.contains(&DocumentChange::Diverged));
+
+        // The reverse comparison mirrors the forward one
+        let reverse = b.diff(&mut a).unwrap();
+        assert_eq!(reverse.removed, diff.added);
+        let changes: Vec<DocumentChange> = reverse.documents.iter().map(|d| d.change).collect();
+        assert!(changes.contains(&DocumentChange::Removed));
+        assert!(changes.contains(&DocumentChange::Ahead));
+    }
+}
@@ -0,0 +1,204 @@
+use super::Manifest;
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+pub use ed25519_dalek::VerifyingKey;
+
+/// Content index of a bundle: a SHA-256 checksum per entry, optionally signed
+///
+/// `manifest.json` itself is not listed. The signature covers the rest of the
+/// manifest together with `entries`, so any change to the manifest, to an entry,
+/// or to the set of entries, invalidates it.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Integrity {
+    /// Hex-encoded SHA-256 of each entry, keyed by bundle path
+    pub entries: BTreeMap<String, String>,
+    /// Ed25519 signature over the manifest and `entries`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<IntegritySignature>,
+}
+
+/// An Ed25519 signature and the key that made it, both base64-encoded
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegritySignature {
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// Hex-encoded SHA-256 of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+impl Integrity {
+    /// Record the checksum of an entry
+    pub fn add_entry(&mut self, path: &str, data: &[u8]) {
+        self.entries.insert(path.to_string(), sha256_hex(data));
+    }
+
+    /// Describe how `data` differs from the recorded checksum of `path`, if it does
+    pub fn check_entry(&self, path: &str, data: &[u8]) -> Option<String> {
+        let actual = sha256_hex(data);
+        match self.entries.get(path) {
+            Some(expected) if *expected == actual => None,
+            Some(expected) => Some(format!(
+                "SHA-256 mismatch: expected {}, found {}",
+                expected, actual
+            )),
+            None => Some("Entry is not listed in the manifest's content index".to_string()),
+        }
+    }
+
+    /// Sign the content index and `manifest`, replacing any existing signature
+    pub fn sign(&mut self, manifest: &Manifest, key: &SigningKey) {
+        let signature = key.sign(&self.signed_payload(manifest));
+        self.signature = Some(IntegritySignature {
+            public_key: STANDARD.encode(key.verifying_key().as_bytes()),
+            signature: STANDARD.encode(signature.to_bytes()),
+        });
+    }
+
+    /// Check the signature against the content index and `manifest`
+    ///
+    /// With `trusted` keys, the index must be signed by one of them. Without any,
+    /// the signature is checked against the key the bundle carries, which shows
+    /// the bundle is intact but not who made it. Returns the signing key, or
+    /// `None` if the index is unsigned and no key is trusted.
+    pub fn verify_signature(
+        &self,
+        manifest: &Manifest,
+        trusted: &[VerifyingKey],
+    ) -> Result<Option<VerifyingKey>> {
+        let Some(signed) = &self.signature else {
+            if trusted.is_empty() {
+                return Ok(None);
+            }
+            return Err(anyhow::anyhow!("Bundle is not signed by a trusted key"));
+        };
+
+        let key = parse_public_key(&signed.public_key)?;
+        if !trusted.is_empty() && !trusted.contains(&key) {
+            return Err(anyhow::anyhow!(
+                "Bundle is signed by an untrusted key {}",
+                signed.public_key
+            ));
+        }
+
+        let signature: [u8; 64] = STANDARD
+            .decode(&signed.signature)
+            .context("Invalid signature encoding")?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Signature must be 64 bytes"))?;
+
+        key.verify(
+            &self.signed_payload(manifest),
+            &Signature::from_bytes(&signature),
+        )
+        .context("Signature does not match the manifest and content index")?;
+        Ok(Some(key))
+    }
+
+    fn signed_payload(&self, manifest: &Manifest) -> Vec<u8> {
+        // Struct fields and BTreeMaps serialize in a fixed order, so this is
+        // deterministic
+        let manifest = Manifest {
+            integrity: None,
+            ..manifest.clone()
+        };
+        serde_json::to_vec(&(manifest, &self.entries)).unwrap_or_default()
+    }
+}
+
+/// Parse a base64-encoded Ed25519 public key, as carried in `IntegritySignature`
+pub fn parse_public_key(encoded: &str) -> Result<VerifyingKey> {
+    let public_key: [u8; 32] = STANDARD
+        .decode(encoded.trim())
+        .context("Invalid public key encoding")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Public key must be 32 bytes"))?;
+    VerifyingKey::from_bytes(&public_key).context("Invalid public key")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> Manifest {
+        Manifest::parse(
+            br#"{
+                "manifestVersion": 1,
+                "version": { "major": 1, "minor": 0 },
+                "rootId": "test-root-id",
+                "entrypoints": [],
+                "networkUris": []
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let manifest = manifest();
+        let mut integrity = Integrity::default();
+        integrity.add_entry("storage/ab/cdef/snapshot/x", b"data");
+        assert!(integrity
+            .verify_signature(&manifest, &[])
+            .unwrap()
+            .is_none());
+
+        integrity.sign(&manifest, &key);
+        assert_eq!(
+            integrity.verify_signature(&manifest, &[]).unwrap(),
+            Some(key.verifying_key())
+        );
+        assert!(integrity
+            .check_entry("storage/ab/cdef/snapshot/x", b"data")
+            .is_none());
+        assert!(integrity
+            .check_entry("storage/ab/cdef/snapshot/x", b"tampered")
+            .is_some());
+
+        // The manifest is covered as well as the entries
+        let mut renamed = manifest.clone();
+        renamed.root_id = "other-root-id".to_string();
+        assert!(integrity.verify_signature(&renamed, &[]).is_err());
+
+        integrity.add_entry("extra", b"data");
+        assert!(integrity.verify_signature(&manifest, &[]).is_err());
+    }
+
+    #[test]
+    fn test_verify_against_trusted_keys() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let manifest = manifest();
+        let mut integrity = Integrity::default();
+        integrity.add_entry("storage/ab/cdef/snapshot/x", b"data");
+
+        let trusted = [key.verifying_key()];
+        let error = integrity.verify_signature(&manifest, &trusted).unwrap_err();
+        assert!(error.to_string().contains("not signed"));
+
+        // A bundle signed by anyone else carries their key, which isn't trusted
+        integrity.sign(&manifest, &other);
+        let error = integrity.verify_signature(&manifest, &trusted).unwrap_err();
+        assert!(error.to_string().contains("untrusted key"));
+
+        integrity.sign(&manifest, &key);
+        assert_eq!(
+            integrity.verify_signature(&manifest, &trusted).unwrap(),
+            Some(key.verifying_key())
+        );
+
+        let encoded = STANDARD.encode(key.verifying_key().as_bytes());
+        assert_eq!(parse_public_key(&encoded).unwrap(), key.verifying_key());
+        assert!(parse_public_key("not a key").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use super::{BundleFormat, EntryMetadata, RandomAccess};
+use anyhow::{Context, Result};
+use flate2::read::DeflateDecoder;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, SeekFrom, Take};
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const LOCAL_HEADER_LEN: usize = 30;
+
+/// An entry's data as stored, limited to its compressed size
+enum Body<'a, R> {
+    Stored(Take<&'a mut R>),
+    Deflated(DeflateDecoder<Take<&'a mut R>>),
+}
+
+/// Streams one entry of a bundle from its data source
+///
+/// Data is decompressed as it's read, so only the caller's buffer is held in memory.
+/// Reads are bounded by the sizes in the bundle's index: an entry that decompresses
+/// to more than its recorded size fails as soon as it does, and once the last byte
+/// is read the size and CRC32 are checked, along with the SHA-256 recorded in the
+/// manifest's content index for bundles that have one, failing the final read
+/// with `InvalidData` on a mismatch.
+///
+/// The reader also implements `futures::io::AsyncRead`. Reads go straight to the
+/// data source and complete immediately, which suits in-memory bundles; reads from
+/// a file block the task for the length of each read.
+pub struct BundleEntryReader<'a, R> {
+    body: Body<'a, R>,
+    metadata: EntryMetadata,
+    hasher: crc32fast::Hasher,
+    /// Running digest and the expected hex-encoded SHA-256, if the entry has one
+    sha256: Option<(Sha256, String)>,
+    /// Decompressed bytes read so far
+    read: u64,
+}
+
+impl<'a, R: RandomAccess> BundleEntryReader<'a, R> {
+    /// Position `source` at the start of an entry's data
+    pub(crate) fn open(
+        source: &'a mut R,
+        format: BundleFormat,
+        metadata: EntryMetadata,
+        sha256: Option<String>,
+    ) -> Result<Self> {
+        source.seek_to(metadata.local_header_offset)?;
+
+        // Container records hold their data uncompressed at the indexed offset
+        let compression_method = match format {
+            BundleFormat::Container => 0,
+            BundleFormat::Zip => skip_local_header(source, &metadata)?,
+        };
+
+        let data = Read::take(source, metadata.compressed_size);
+        let body = match compression_method {
+            0 => Body::Stored(data),
+            8 => Body::Deflated(DeflateDecoder::new(data)),
+            method => {
+                return Err(anyhow::anyhow!(
+                    "Entry {} uses unsupported compression method {method}",
+                    metadata.path
+                ))
+            }
+        };
+
+        Ok(Self {
+            body,
+            metadata,
+            hasher: crc32fast::Hasher::new(),
+            sha256: sha256.map(|expected| (Sha256::new(), expected)),
+            read: 0,
+        })
+    }
+}
+
+impl<R> BundleEntryReader<'_, R> {
+    /// Index metadata of the entry being read
+    pub fn metadata(&self) -> &EntryMetadata {
+        &self.metadata
+    }
+
+    /// Check the entry's size and CRC32 once all of its data has been read
+    fn check(&self) -> io::Result<()> {
+        if self.read != self.metadata.uncompressed_size {
+            return Err(invalid_data(format!(
+                "Entry {} ended after {} of {} bytes",
+                self.metadata.path, self.read, self.metadata.uncompressed_size
+            )));
+        }
+        if self.hasher.clone().finalize() != self.metadata.crc32 {
+            return Err(invalid_data(format!(
+                "CRC32 mismatch in {}",
+                self.metadata.path
+            )));
+        }
+        if let Some((digest, expected)) = &self.sha256 {
+            let actual = format!("{:x}", digest.clone().finalize());
+            if actual != *expected {
+                return Err(invalid_data(format!(
+                    "SHA-256 mismatch in {}: expected {}, found {}",
+                    self.metadata.path, expected, actual
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BundleEntryReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match &mut self.body {
+            Body::Stored(data) => data.read(buf)?,
+            Body::Deflated(data) => data.read(buf)?,
+        };
+        if n == 0 {
+            if !buf.is_empty() {
+                self.check()?;
+            }
+            return Ok(0);
+        }
+
+        self.read += n as u64;
+        if self.read > self.metadata.uncompressed_size {
+            return Err(invalid_data(format!(
+                "Entry {} is larger than its recorded size of {} bytes",
+                self.metadata.path, self.metadata.uncompressed_size
+            )));
+        }
+        self.hasher.update(&buf[..n]);
+        if let Some((digest, _)) = &mut self.sha256 {
+            digest.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Read> futures::io::AsyncRead for BundleEntryReader<'_, R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().read(buf))
+    }
+}
+
+/// Read the local header at the current position, leaving the source at the entry's
+/// data, and return its compression method
+///
+/// Sizes are taken from the index rather than the local header, which may defer
+/// them to a data descriptor.
+fn skip_local_header<R: RandomAccess>(source: &mut R, metadata: &EntryMetadata) -> Result<u16> {
+    let mut header = [0u8; LOCAL_HEADER_LEN];
+    source
+        .read_exact_at(&mut header)
+        .with_context(|| format!("Failed to read the local header of {}", metadata.path))?;
+
+    let signature = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    if signature != LOCAL_FILE_HEADER_SIGNATURE {
+        return Err(anyhow::anyhow!(
+            "Entry {} has no local header at offset {}",
+            metadata.path,
+            metadata.local_header_offset
+        ));
+    }
+    let flags = u16::from_le_bytes([header[6], header[7]]);
+    if flags & 0x0001 != 0 {
+        return Err(anyhow::anyhow!("Entry {} is encrypted", metadata.path));
+    }
+
+    let compression_method = u16::from_le_bytes([header[8], header[9]]);
+    let name_len = u16::from_le_bytes([header[26], header[27]]) as i64;
+    let extra_len = u16::from_le_bytes([header[28], header[29]]) as i64;
+    source
+        .seek(SeekFrom::Current(name_len + extra_len))
+        .with_context(|| format!("Failed to seek to the data of {}", metadata.path))?;
+    Ok(compression_method)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::convert_to_container;
+    use crate::{Bundle, BundlePath};
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    const MANIFEST: &str = r#"{
+        "manifestVersion": 1,
+        "version": { "major": 1, "minor": 0 },
+        "rootId": "test-root-id",
+        "entrypoints": [],
+        "networkUris": []
+    }"#;
+
+    /// A bundle with one large entry of each compression method
+    fn zip_bundle(asset: &[u8]) -> Vec<u8> {
+        let mut zip_data = Vec::new();
+        let mut zip_writer = ZipWriter::new(Cursor::new(&mut zip_data));
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        for (path, options, data) in [
+            ("manifest.json", stored, MANIFEST.as_bytes()),
+            ("app/stored.bin", stored, asset),
+            ("app/deflated.bin", SimpleFileOptions::default(), asset),
+        ] {
+            zip_writer.start_file(path, options).unwrap();
+            zip_writer.write_all(data).unwrap();
+        }
+        zip_writer.finish().unwrap();
+        zip_data
+    }
+
+    fn asset() -> Vec<u8> {
+        (0..200_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_reader_matches_get() {
+        let asset = asset();
+        let zip = zip_bundle(&asset);
+        for bytes in [zip.clone(), convert_to_container(zip).unwrap()] {
+            let mut bundle = Bundle::from_bytes(bytes).unwrap();
+            for path in ["manifest.json", "app/stored.bin", "app/deflated.bin"] {
+                let key = BundlePath::from(path);
+                let expected = bundle.get(&key).unwrap().unwrap();
+
+                let mut reader = bundle.get_reader(&key).unwrap().unwrap();
+                assert_eq!(reader.metadata().path, path);
+                // Small reads exercise the decoder across many calls
+                let mut data = Vec::new();
+                let mut chunk = [0u8; 1000];
+                loop {
+                    let n = reader.read(&mut chunk).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    data.extend_from_slice(&chunk[..n]);
+                }
+                assert_eq!(data, expected, "{path}");
+            }
+
+            assert!(bundle
+                .get_reader(&BundlePath::from("app/missing.bin"))
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_async_reader() {
+        let asset = asset();
+        let mut bundle = Bundle::from_bytes(zip_bundle(&asset)).unwrap();
+        let mut reader = bundle
+            .get_async_reader(&BundlePath::from("app/deflated.bin"))
+            .unwrap()
+            .unwrap();
+        let mut data = Vec::new();
+        futures::executor::block_on(futures::io::AsyncReadExt::read_to_end(
+            &mut reader,
+            &mut data,
+        ))
+        .unwrap();
+        assert_eq!(data, asset);
+    }
+
+    #[test]
+    fn test_reader_rejects_corrupt_data() {
+        let asset = asset();
+        let mut zip = zip_bundle(&asset);
+        // Flip a byte in the middle of the stored copy of the asset
+        let at = zip.windows(4).position(|w| w == [0, 1, 2, 3]).unwrap() + 1000;
+        zip[at] ^= 0xff;
+
+        let mut bundle = Bundle::from_source_unverified(Cursor::new(zip)).unwrap();
+        let mut reader = bundle
+            .get_reader(&BundlePath::from("app/stored.bin"))
+            .unwrap()
+            .unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("CRC32 mismatch"));
+    }
+}
@@ -0,0 +1,353 @@
+use super::EntryMetadata;
+use anyhow::{Context, Result};
+use std::io::{Cursor, Read};
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+const LOCAL_HEADER_LEN: usize = 30;
+
+/// Incremental reader for a bundle that arrives in chunks, such as a network download
+///
+/// Entries are parsed from their local headers as soon as they have fully arrived, so
+/// only the entry being read (plus any partial chunk) is held in memory. The central
+/// directory is never consulted, which means entry sizes must be recorded in the local
+/// headers; archives written with trailing data descriptors are rejected, as are
+/// v2 containers.
+#[derive(Debug, Default)]
+pub struct BundleStreamReader {
+    /// Bytes received but not yet consumed
+    buffer: Vec<u8>,
+    /// Archive offset of the first byte in `buffer`
+    offset: u64,
+    /// Set once the central directory is reached
+    finished: bool,
+}
+
+impl BundleStreamReader {
+    /// Create a reader with nothing buffered
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the next chunk of archive data
+    pub fn push(&mut self, chunk: &[u8]) {
+        if !self.finished {
+            self.buffer.extend_from_slice(chunk);
+        }
+    }
+
+    /// Whether every entry has been read and the rest of the archive can be ignored
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Metadata for the next file entry, available as soon as its header has arrived
+    ///
+    /// This lets callers reject an entry before its data is buffered. Returns `None`
+    /// if more data is needed or the last entry has been read.
+    pub fn peek_entry(&mut self) -> Result<Option<EntryMetadata>> {
+        Ok(self.next_header()?.map(|(metadata, _)| metadata))
+    }
+
+    /// Read the next file entry once all of its data has arrived
+    ///
+    /// The entry is decompressed and its CRC32 checked. Returns `None` if more data is
+    /// needed or the last entry has been read.
+    pub fn next_entry(&mut self) -> Result<Option<(EntryMetadata, Vec<u8>)>> {
+        let Some((metadata, len)) = self.next_header()? else {
+            return Ok(None);
+        };
+        if self.buffer.len() < len {
+            return Ok(None);
+        }
+
+        let data = Self::read_entry(&self.buffer[..len], &metadata)?;
+        self.consume(len);
+        Ok(Some((metadata, data)))
+    }
+
+    /// Check that the archive ended cleanly once the input is exhausted
+    pub fn finish(&self) -> Result<()> {
+        if self.finished {
+            Ok(())
+        } else if self.buffer.is_empty() {
+            Err(anyhow::anyhow!(
+                "Bundle stream ended after {} bytes without a central directory",
+                self.offset
+            ))
+        } else {
+            Err(anyhow::anyhow!(
+                "Bundle stream ended part way through the entry at offset {}",
+                self.offset
+            ))
+        }
+    }
+
+    /// Parse the header of the next file entry, skipping any directory entries
+    fn next_header(&mut self) -> Result<Option<(EntryMetadata, usize)>> {
+        loop {
+            if self.finished || self.buffer.len() < 4 {
+                return Ok(None);
+            }
+
+            match read_u32(&self.buffer, 0) {
+                LOCAL_FILE_HEADER_SIGNATURE => {}
+                CENTRAL_DIRECTORY_HEADER_SIGNATURE
+                | END_OF_CENTRAL_DIRECTORY_SIGNATURE
+                | ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE => {
+                    self.finished = true;
+                    self.buffer = Vec::new();
+                    return Ok(None);
+                }
+                _ if self.offset == 0 && super::CONTAINER_MAGIC.starts_with(&self.buffer[..4]) => {
+                    return Err(anyhow::anyhow!(
+                        "v2 containers can't be streamed; load the whole bundle instead"
+                    ));
+                }
+                signature => {
+                    return Err(anyhow::anyhow!(
+                        "Unexpected signature {signature:#010x} at offset {}",
+                        self.offset
+                    ));
+                }
+            }
+
+            let Some((metadata, len)) = self.parse_local_header()? else {
+                return Ok(None);
+            };
+
+            if !metadata.path.ends_with('/') {
+                return Ok(Some((metadata, len)));
+            }
+            if self.buffer.len() < len {
+                return Ok(None);
+            }
+            self.consume(len);
+        }
+    }
+
+    /// Parse the local header at the start of the buffer into metadata and the
+    /// total length of the entry, or `None` if the header hasn't fully arrived
+    fn parse_local_header(&self) -> Result<Option<(EntryMetadata, usize)>> {
+        let buf = &self.buffer;
+        if buf.len() < LOCAL_HEADER_LEN {
+            return Ok(None);
+        }
+
+        let flags = read_u16(buf, 6);
+        let compression_method = read_u16(buf, 8);
+        let crc32 = read_u32(buf, 14);
+        let mut compressed_size = read_u32(buf, 18) as u64;
+        let mut uncompressed_size = read_u32(buf, 22) as u64;
+        let name_len = read_u16(buf, 26) as usize;
+        let extra_len = read_u16(buf, 28) as usize;
+
+        let header_len = LOCAL_HEADER_LEN + name_len + extra_len;
+        if buf.len() < header_len {
+            return Ok(None);
+        }
+
+        let path = String::from_utf8_lossy(&buf[LOCAL_HEADER_LEN..LOCAL_HEADER_LEN + name_len])
+            .into_owned();
+
+        if flags & 0x0001 != 0 {
+            return Err(anyhow::anyhow!("Entry {path} is encrypted"));
+        }
+        if flags & 0x0008 != 0 {
+            return Err(anyhow::anyhow!(
+                "Entry {path} uses a data descriptor, so its size is unknown until it has been read"
+            ));
+        }
+
+        // Sizes that don't fit in 32 bits are moved to the zip64 extra field
+        if compressed_size == u32::MAX as u64 || uncompressed_size == u32::MAX as u64 {
+            let extra = &buf[LOCAL_HEADER_LEN + name_len..header_len];
+            let mut fields = zip64_sizes(extra)
+                .with_context(|| format!("Entry {path} is missing its zip64 sizes"))?
+                .into_iter();
+            if uncompressed_size == u32::MAX as u64 {
+                uncompressed_size = fields
+                    .next()
+                    .with_context(|| format!("Entry {path} is missing its zip64 size"))?;
+            }
+            if compressed_size == u32::MAX as u64 {
+                compressed_size = fields
+                    .next()
+                    .with_context(|| format!("Entry {path} is missing its zip64 size"))?;
+            }
+        }
+
+        let len = usize::try_from(header_len as u64 + compressed_size)
+            .with_context(|| format!("Entry {path} is too large to buffer"))?;
+
+        Ok(Some((
+            EntryMetadata {
+                path,
+                local_header_offset: self.offset,
+                compressed_size,
+                uncompressed_size,
+                crc32,
+                compression_method,
+            },
+            len,
+        )))
+    }
+
+    /// Decompress a fully buffered entry, checking its CRC32
+    fn read_entry(entry: &[u8], metadata: &EntryMetadata) -> Result<Vec<u8>> {
+        let mut cursor = Cursor::new(entry);
+        let mut file = zip::read::read_zipfile_from_stream(&mut cursor)
+            .with_context(|| format!("Failed to read entry {}", metadata.path))?
+            .with_context(|| format!("Entry {} has no local header", metadata.path))?;
+
+        let mut data = super::entry_buffer(metadata.uncompressed_size);
+        file.read_to_end(&mut data)
+            .with_context(|| format!("Failed to read entry data for {}", metadata.path))?;
+        Ok(data)
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.buffer.drain(..len);
+        self.offset += len as u64;
+    }
+}
+
+/// Values of the zip64 extended information extra field, in header order
+fn zip64_sizes(mut extra: &[u8]) -> Option<Vec<u64>> {
+    while extra.len() >= 4 {
+        let id = read_u16(extra, 0);
+        let len = read_u16(extra, 2) as usize;
+        let data = extra.get(4..4 + len)?;
+        if id == ZIP64_EXTRA_FIELD_ID {
+            return Some(data.chunks_exact(8).map(|v| read_u64(v, 0)).collect());
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+fn read_u16(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([buf[at], buf[at + 1]])
+}
+
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bundle, BundlePath, TonkCore};
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    /// Feed `data` to a reader in `chunk_size` pieces, collecting every entry
+    fn read_in_chunks(data: &[u8], chunk_size: usize) -> Result<Vec<(EntryMetadata, Vec<u8>)>> {
+        let mut reader = BundleStreamReader::new();
+        let mut entries = Vec::new();
+        for chunk in data.chunks(chunk_size) {
+            reader.push(chunk);
+            while let Some(entry) = reader.next_entry()? {
+                entries.push(entry);
+            }
+        }
+        reader.finish()?;
+        Ok(entries)
+    }
+
+    #[tokio::test]
+    async fn test_stream_matches_bundle() {
+        let tonk = TonkCore::new().await.unwrap();
+        tonk.vfs()
+            .create_document("/notes/a.txt", "hello".to_string())
+            .await
+            .unwrap();
+        let bytes = tonk.to_bytes(None).await.unwrap();
+        let mut bundle = Bundle::from_bytes(bytes.clone()).unwrap();
+
+        for chunk_size in [1, 7, 4096, bytes.len()] {
+            let entries = read_in_chunks(&bytes, chunk_size).unwrap();
+            assert_eq!(entries.len(), bundle.list_keys().len());
+
+            for (metadata, data) in entries {
+                let expected = bundle
+                    .get(&BundlePath::from(metadata.path.as_str()))
+                    .unwrap();
+                assert_eq!(Some(data), expected, "{}", metadata.path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_peek_before_data() {
+        let mut zip_data = Vec::new();
+        {
+            let mut zip_writer = ZipWriter::new(Cursor::new(&mut zip_data));
+            zip_writer
+                .add_directory("storage/", SimpleFileOptions::default())
+                .unwrap();
+            zip_writer
+                .start_file("storage/big", SimpleFileOptions::default())
+                .unwrap();
+            zip_writer.write_all(&[7u8; 1000]).unwrap();
+            zip_writer.finish().unwrap();
+        }
+
+        // The header (after the skipped directory entry) is visible before the data
+        let mut reader = BundleStreamReader::new();
+        let header_end = zip_data
+            .windows(11)
+            .position(|w| w == b"storage/big")
+            .unwrap()
+            + 11;
+        reader.push(&zip_data[..header_end]);
+        let metadata = reader.peek_entry().unwrap().unwrap();
+        assert_eq!(metadata.path, "storage/big");
+        assert_eq!(metadata.uncompressed_size, 1000);
+        assert!(reader.next_entry().unwrap().is_none());
+
+        let entries = read_in_chunks(&zip_data, 16).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].1, vec![7u8; 1000]);
+    }
+
+    #[test]
+    fn test_stream_rejects_bad_input() {
+        let mut zip_data = Vec::new();
+        {
+            let mut zip_writer = ZipWriter::new(Cursor::new(&mut zip_data));
+            zip_writer
+                .start_file("manifest.json", SimpleFileOptions::default())
+                .unwrap();
+            zip_writer.write_all(b"{}").unwrap();
+            zip_writer.finish().unwrap();
+        }
+
+        // Archives truncated before the central directory fail once the input runs out
+        let err = read_in_chunks(&zip_data[..LOCAL_HEADER_LEN + 4], 8).unwrap_err();
+        assert!(err.to_string().contains("ended"));
+
+        // Archives that defer sizes to a data descriptor can't be streamed
+        let mut streamed = Vec::new();
+        {
+            let mut zip_writer = ZipWriter::new_stream(&mut streamed);
+            zip_writer
+                .start_file("manifest.json", SimpleFileOptions::default())
+                .unwrap();
+            zip_writer.write_all(b"{}").unwrap();
+            zip_writer.finish().unwrap();
+        }
+        let err = read_in_chunks(&streamed, 64).unwrap_err();
+        assert!(err.to_string().contains("data descriptor"));
+
+        assert!(read_in_chunks(b"not a zip archive", 4).is_err());
+    }
+}
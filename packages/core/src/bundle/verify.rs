@@ -0,0 +1,420 @@
+use super::{Bundle, Integrity, Manifest, RandomAccess, VerifyingKey};
+use anyhow::Result;
+use automerge::Automerge;
+use samod::DocumentId;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The kind of check that produced a verification issue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VerifyCheck {
+    /// An entry could not be read back or failed its CRC32 checksum
+    Crc,
+    /// The manifest is inconsistent with the bundle contents
+    Manifest,
+    /// A stored document failed to load as Automerge
+    Document,
+    /// An entry doesn't match the manifest's content index
+    Checksum,
+    /// The content index's signature is invalid
+    Signature,
+}
+
+impl fmt::Display for VerifyCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyCheck::Crc => write!(f, "crc"),
+            VerifyCheck::Manifest => write!(f, "manifest"),
+            VerifyCheck::Document => write!(f, "document"),
+            VerifyCheck::Checksum => write!(f, "checksum"),
+            VerifyCheck::Signature => write!(f, "signature"),
+        }
+    }
+}
+
+/// A single problem found while verifying a bundle
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyIssue {
+    pub check: VerifyCheck,
+    /// Bundle path or document ID the issue relates to, if any
+    pub subject: Option<String>,
+    pub message: String,
+}
+
+/// Summary of a bundle integrity check
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyReport {
+    pub entries_checked: usize,
+    pub documents_checked: usize,
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl VerifyReport {
+    /// Whether every check passed
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn push(&mut self, check: VerifyCheck, subject: Option<String>, message: String) {
+        self.issues.push(VerifyIssue {
+            check,
+            subject,
+            message,
+        });
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Checked {} entries and {} documents: ",
+            self.entries_checked, self.documents_checked
+        )?;
+        if self.is_ok() {
+            return write!(f, "OK");
+        }
+        write!(f, "{} issue(s)", self.issues.len())?;
+        for issue in &self.issues {
+            match &issue.subject {
+                Some(subject) => write!(f, "\n  [{}] {}: {}", issue.check, subject, issue.message)?,
+                None => write!(f, "\n  [{}] {}", issue.check, issue.message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Chunks of one stored document, split by kind so snapshots load first
+#[derive(Default)]
+struct DocumentChunks {
+    snapshots: Vec<Vec<u8>>,
+    incrementals: Vec<Vec<u8>>,
+}
+
+/// Extract the document ID from a `storage/...` bundle path, joining splayed IDs
+pub(super) fn storage_document_id(path: &str) -> Option<(String, &str)> {
+    let parts: Vec<&str> = path.strip_prefix("storage/")?.split('/').collect();
+    if parts.len() >= 3 && parts[0].len() == 2 {
+        Some((format!("{}{}", parts[0], parts[1]), parts[2]))
+    } else if parts.len() >= 2 {
+        Some((parts[0].to_string(), parts[1]))
+    } else {
+        None
+    }
+}
+
+/// Check the content index's signature and that it lists every one of `paths`
+fn check_index(
+    integrity: &Integrity,
+    manifest: &Manifest,
+    trusted_keys: &[VerifyingKey],
+    paths: &[String],
+    report: &mut VerifyReport,
+) {
+    if let Err(e) = integrity.verify_signature(manifest, trusted_keys) {
+        report.push(VerifyCheck::Signature, None, format!("{e:#}"));
+    }
+    for path in integrity.entries.keys() {
+        if paths.binary_search(path).is_err() {
+            report.push(
+                VerifyCheck::Checksum,
+                Some(path.clone()),
+                "Entry listed in the content index is missing".to_string(),
+            );
+        }
+    }
+}
+
+impl<R: RandomAccess> Bundle<R> {
+    /// Verify the integrity of every entry in the bundle
+    ///
+    /// Reads each entry back (which checks its CRC32), validates that the manifest
+    /// root document is present, and loads every stored document with Automerge.
+    /// If the manifest has a content index, entries are also checked against their
+    /// SHA-256 checksums and the index's signature is verified. Problems are
+    /// collected into the report rather than returned as errors; an error is only
+    /// returned if the archive itself cannot be opened.
+    pub fn verify(&mut self) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        let mut documents: BTreeMap<String, DocumentChunks> = BTreeMap::new();
+        let integrity = self.manifest.integrity.clone();
+
+        let paths = self.sorted_paths();
+        if let Some(integrity) = &integrity {
+            check_index(
+                integrity,
+                &self.manifest,
+                &self.trusted_keys,
+                &paths,
+                &mut report,
+            );
+        }
+
+        for path in paths {
+            let Some(metadata) = self.index.entry(&path).cloned() else {
+                continue;
+            };
+            report.entries_checked += 1;
+
+            let data = match self.read_entry_raw(&metadata) {
+                Ok(Some(data)) => data,
+                Ok(None) => continue,
+                Err(e) => {
+                    report.push(VerifyCheck::Crc, Some(path), format!("{e:#}"));
+                    continue;
+                }
+            };
+
+            if let Some(message) = integrity
+                .as_ref()
+                .filter(|_| path != "manifest.json")
+                .and_then(|integrity| integrity.check_entry(&path, &data))
+            {
+                report.push(VerifyCheck::Checksum, Some(path.clone()), message);
+            }
+
+            if let Some((doc_id, kind)) = storage_document_id(&path) {
+                let chunks = documents.entry(doc_id).or_default();
+                if kind == "snapshot" {
+                    chunks.snapshots.push(data);
+                } else {
+                    chunks.incrementals.push(data);
+                }
+            }
+        }
+
+        let root_id = self.manifest.root_id.clone();
+        if root_id.is_empty() {
+            report.push(
+                VerifyCheck::Manifest,
+                None,
+                "Manifest has an empty rootId".to_string(),
+            );
+        } else if root_id.parse::<DocumentId>().is_err() {
+            report.push(
+                VerifyCheck::Manifest,
+                Some(root_id),
+                "Manifest rootId is not a valid document ID".to_string(),
+            );
+        } else if !documents.contains_key(&root_id) {
+            report.push(
+                VerifyCheck::Manifest,
+                Some(root_id),
+                "Root document is missing from storage".to_string(),
+            );
+        }
+
+        for (doc_id, chunks) in documents {
+            report.documents_checked += 1;
+
+            if doc_id.parse::<DocumentId>().is_err() {
+                report.push(
+                    VerifyCheck::Document,
+                    Some(doc_id),
+                    "Storage key is not a valid document ID".to_string(),
+                );
+                continue;
+            }
+
+            let data: Vec<u8> = chunks
+                .snapshots
+                .into_iter()
+                .chain(chunks.incrementals)
+                .flatten()
+                .collect();
+            if let Err(e) = Automerge::load(&data) {
+                report.push(
+                    VerifyCheck::Document,
+                    Some(doc_id),
+                    format!("Failed to load document: {e}"),
+                );
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Check the content index's signature and that it lists every entry
+    ///
+    /// Entries aren't read; their checksums are checked as they're read.
+    pub(super) fn check_content_index(&self) -> VerifyReport {
+        let mut report = VerifyReport::default();
+        if let Some(integrity) = &self.manifest.integrity {
+            let paths = self.sorted_paths();
+            check_index(
+                integrity,
+                &self.manifest,
+                &self.trusted_keys,
+                &paths,
+                &mut report,
+            );
+            for path in paths.into_iter().filter(|path| path != "manifest.json") {
+                if !integrity.entries.contains_key(&path) {
+                    report.push(
+                        VerifyCheck::Checksum,
+                        Some(path),
+                        "Entry is not listed in the manifest's content index".to_string(),
+                    );
+                }
+            }
+        }
+        report
+    }
+
+    pub(super) fn sorted_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.index.all_paths().into_iter().cloned().collect();
+        paths.sort();
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BundlePath, TonkCore};
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    const MANIFEST: &str = r#"{
+        "manifestVersion": 1,
+        "version": { "major": 1, "minor": 0 },
+        "rootId": "test-root-id",
+        "entrypoints": [],
+        "networkUris": []
+    }"#;
+
+    #[tokio::test]
+    async fn test_verify_exported_bundle() {
+        let tonk = TonkCore::new().await.unwrap();
+        tonk.vfs()
+            .create_document("/notes/a.txt", "hello".to_string())
+            .await
+            .unwrap();
+
+        let bytes = tonk.to_bytes(None).await.unwrap();
+        let mut bundle = Bundle::from_bytes(bytes).unwrap();
+        let report = bundle.verify().unwrap();
+
+        assert!(report.is_ok(), "{report}");
+        assert!(report.documents_checked >= 3);
+        assert!(report.entries_checked > report.documents_checked);
+    }
+
+    #[test]
+    fn test_verify_reports_corruption() {
+        let mut zip_data = Vec::new();
+        {
+            let mut zip_writer = ZipWriter::new(std::io::Cursor::new(&mut zip_data));
+            let stored =
+                SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+            zip_writer.start_file("manifest.json", stored).unwrap();
+            zip_writer.write_all(MANIFEST.as_bytes()).unwrap();
+            zip_writer
+                .start_file("storage/not-a-doc/snapshot/abc", stored)
+                .unwrap();
+            zip_writer.write_all(b"definitely not automerge").unwrap();
+            zip_writer.start_file("payload.bin", stored).unwrap();
+            zip_writer.write_all(b"ORIGINAL-PAYLOAD").unwrap();
+            zip_writer.finish().unwrap();
+        }
+
+        // Flip the stored payload without updating its checksum
+        let offset = zip_data
+            .windows(16)
+            .position(|w| w == b"ORIGINAL-PAYLOAD")
+            .unwrap();
+        zip_data[offset] = b'X';
+
+        let mut bundle = Bundle::from_bytes(zip_data).unwrap();
+        let report = bundle.verify().unwrap();
+
+        assert!(!report.is_ok());
+        assert_eq!(report.entries_checked, 3);
+        assert_eq!(report.documents_checked, 1);
+
+        let checks: Vec<VerifyCheck> = report.issues.iter().map(|i| i.check).collect();
+        assert!(checks.contains(&VerifyCheck::Crc));
+        assert!(checks.contains(&VerifyCheck::Manifest));
+        assert!(checks.contains(&VerifyCheck::Document));
+        assert!(report.to_string().contains("payload.bin"));
+    }
+
+    #[tokio::test]
+    async fn test_verify_content_index() {
+        let tonk = TonkCore::new().await.unwrap();
+        tonk.vfs()
+            .create_document("/a.txt", "ORIGINAL-CONTENT".to_string())
+            .await
+            .unwrap();
+
+        let key = ed25519_dalek::SigningKey::from_bytes(&[3; 32]);
+        let config = crate::bundle::BundleConfig {
+            signing_key: Some(key.clone()),
+            ..Default::default()
+        };
+        let bytes = tonk.to_bytes(Some(config)).await.unwrap();
+
+        let mut bundle = Bundle::from_bytes(bytes.clone()).unwrap();
+        let integrity = bundle.manifest().integrity.clone().unwrap();
+        assert_eq!(integrity.entries.len(), bundle.list_keys().len() - 1);
+        assert_eq!(
+            integrity
+                .verify_signature(bundle.manifest(), &[key.verifying_key()])
+                .unwrap(),
+            Some(key.verifying_key())
+        );
+        assert!(bundle.verify().unwrap().is_ok());
+        assert!(Bundle::from_bytes_trusting(bytes.clone(), &[key.verifying_key()]).is_ok());
+        let stranger = ed25519_dalek::SigningKey::from_bytes(&[4; 32]).verifying_key();
+        assert!(Bundle::from_bytes_trusting(bytes.clone(), &[stranger]).is_err());
+
+        // Rewrite the archive with one entry changed but the same manifest
+        let mut source = Bundle::from_bytes(bytes).unwrap();
+        let tampered_path = integrity.entries.keys().next().unwrap().clone();
+        let mut zip_data = Vec::new();
+        {
+            let mut zip_writer = ZipWriter::new(std::io::Cursor::new(&mut zip_data));
+            for key in source.list_keys() {
+                let path = key.to_string();
+                let mut data = source.get(&key).unwrap().unwrap();
+                if path == tampered_path {
+                    data.push(0);
+                }
+                zip_writer
+                    .start_file(&path, SimpleFileOptions::default())
+                    .unwrap();
+                zip_writer.write_all(&data).unwrap();
+            }
+            zip_writer.finish().unwrap();
+        }
+
+        // Opening checks only the index; the tampered entry fails when it's read
+        let mut bundle = Bundle::from_bytes(zip_data.clone()).unwrap();
+        let error = bundle
+            .get(&BundlePath::from(tampered_path.as_str()))
+            .unwrap_err();
+        assert!(error.to_string().contains(&tampered_path));
+        let mut reader = bundle
+            .get_reader(&BundlePath::from(tampered_path.as_str()))
+            .unwrap()
+            .unwrap();
+        let error = std::io::Read::read_to_end(&mut reader, &mut Vec::new()).unwrap_err();
+        assert!(error.to_string().contains("SHA-256 mismatch"));
+
+        let mut bundle = Bundle::from_source_unverified(std::io::Cursor::new(zip_data)).unwrap();
+        let report = bundle.verify().unwrap();
+        let mismatched: Vec<_> = report
+            .issues
+            .iter()
+            .filter(|issue| issue.check == VerifyCheck::Checksum)
+            .filter_map(|issue| issue.subject.clone())
+            .collect();
+        assert_eq!(mismatched, vec![tampered_path]);
+    }
+}
@@ -0,0 +1,169 @@
+#[cfg(feature = "bundle")]
+use crate::bundle::{Version, FORMAT_VERSION, MANIFEST_VERSION};
+use crate::vfs::NODE_SCHEMA_VERSION;
+use serde::{Deserialize, Serialize};
+
+/// Optional features compiled into every build of this version
+const FEATURES: &[&str] = &[
+    "conflicts",
+    "deltaEvents",
+    "history",
+    "journal",
+    "mounts",
+    "quota",
+    "traversalLimits",
+    "trash",
+];
+
+/// Features that come with the `bundle` cargo feature
+const BUNDLE_FEATURES: &[&str] = &[
+    "bundleContainer",
+    "bundleVerify",
+    "deterministicExport",
+    "importLimits",
+    "streamingImport",
+];
+
+/// Features that need a WebSocket transport
+const WEBSOCKET_FEATURES: &[&str] = &["shareLinks"];
+
+/// Description of what this build of tonk-core supports
+///
+/// Lets embedders feature-detect rather than compare version numbers. Features
+/// that aren't compiled in (such as encryption, presence or a blob store) are
+/// simply absent from `features`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    /// Version of the tonk-core crate
+    pub version: String,
+    /// Platform the build targets, `native` or `wasm`
+    pub platform: String,
+    /// Storage backends accepted by `StorageConfig`
+    pub storage_backends: Vec<String>,
+    /// Transports available for syncing with peers
+    pub transports: Vec<String>,
+    /// Versions of the formats read and written
+    pub protocols: ProtocolVersions,
+    /// Names of optional features available in this build
+    pub features: Vec<String>,
+}
+
+/// Versions of the on-disk and wire formats supported by a build
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProtocolVersions {
+    /// `manifestVersion` of bundle manifests
+    #[cfg(feature = "bundle")]
+    pub manifest_version: u32,
+    /// Tonk format version written to new bundles
+    #[cfg(feature = "bundle")]
+    pub bundle_format: Version,
+    /// Schema version of VFS node documents
+    pub node_schema: u32,
+}
+
+impl Capabilities {
+    /// Describe the current build
+    pub fn current() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let (platform, storage_backends) = ("native", ["inMemory", "filesystem"]);
+        #[cfg(target_arch = "wasm32")]
+        let (platform, storage_backends) = ("wasm", ["inMemory", "indexedDb"]);
+
+        let websocket = cfg!(any(target_arch = "wasm32", feature = "websocket"));
+        let transports: &[&str] = if websocket { &["websocket"] } else { &[] };
+        let mut features = FEATURES.to_vec();
+        if websocket {
+            features.extend(WEBSOCKET_FEATURES);
+        }
+        if cfg!(feature = "bundle") {
+            features.extend(BUNDLE_FEATURES);
+        }
+        features.sort_unstable();
+
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            platform: platform.to_string(),
+            storage_backends: storage_backends.iter().map(|s| s.to_string()).collect(),
+            transports: transports.iter().map(|s| s.to_string()).collect(),
+            protocols: ProtocolVersions {
+                #[cfg(feature = "bundle")]
+                manifest_version: MANIFEST_VERSION,
+                #[cfg(feature = "bundle")]
+                bundle_format: FORMAT_VERSION,
+                node_schema: NODE_SCHEMA_VERSION,
+            },
+            features: features.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Whether the named optional feature is available
+    pub fn has_feature(&self, name: &str) -> bool {
+        self.features.iter().any(|feature| feature == name)
+    }
+}
+
+/// What one end of a sync connection reports about itself when the connection opens
+///
+/// Clients send this to the relay as query parameters of the sync URL, and the
+/// relay answers with response headers; see `websocket::client_url`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerInfo {
+    /// Software at that end, e.g. `tonk-core` or `tonk-relay`
+    pub name: String,
+    pub version: String,
+    /// Platform it runs on, `native` or `wasm`
+    pub platform: String,
+    /// Names of optional features it supports
+    pub features: Vec<String>,
+}
+
+impl PeerInfo {
+    /// Describe the current build, as it introduces itself to relays
+    pub fn current() -> Self {
+        let capabilities = Capabilities::current();
+        Self {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: capabilities.version,
+            platform: capabilities.platform,
+            features: capabilities.features,
+        }
+    }
+
+    /// Whether the peer reported the named optional feature
+    pub fn has_feature(&self, name: &str) -> bool {
+        self.features.iter().any(|feature| feature == name)
+    }
+}
+
+impl std::fmt::Display for PeerInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{} ({})", self.name, self.version, self.platform)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_capabilities() {
+        let capabilities = Capabilities::current();
+        assert_eq!(capabilities.platform, "native");
+        assert!(capabilities.has_feature("mounts"));
+        assert!(!capabilities.has_feature("encryption"));
+
+        let json = serde_json::to_value(&capabilities).unwrap();
+        assert_eq!(json["storageBackends"][1], "filesystem");
+        #[cfg(feature = "bundle")]
+        {
+            assert!(capabilities.has_feature("bundleVerify"));
+            assert_eq!(json["protocols"]["manifestVersion"], MANIFEST_VERSION);
+            assert_eq!(json["protocols"]["bundleFormat"]["major"], 1);
+        }
+        #[cfg(not(feature = "bundle"))]
+        assert!(!capabilities.has_feature("bundleVerify"));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::gc::RepoStorage;
+use automerge::ChangeHash;
+use samod::storage::StorageKey;
+use samod::DocHandle;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// When scheduled compaction runs, and which documents it rewrites
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CompactionPolicy {
+    /// Time between compaction passes
+    pub interval_ms: u64,
+    /// Documents stored in fewer chunks than this are left as they are
+    pub min_chunks: usize,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        Self {
+            interval_ms: 5 * 60 * 1_000,
+            min_chunks: 8,
+        }
+    }
+}
+
+impl CompactionPolicy {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+}
+
+/// Space reclaimed by compacting one or more documents
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompactionReport {
+    /// Documents rewritten as a single snapshot
+    pub documents: usize,
+    /// Snapshot and incremental chunks deleted because the new snapshot covers them
+    pub chunks_removed: usize,
+    /// Stored size of the compacted documents before and after
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    /// How much smaller the compacted documents are now
+    pub bytes_reclaimed: u64,
+}
+
+impl CompactionReport {
+    pub(crate) fn add(&mut self, other: &CompactionReport) {
+        self.documents += other.documents;
+        self.chunks_removed += other.chunks_removed;
+        self.bytes_before += other.bytes_before;
+        self.bytes_after += other.bytes_after;
+        self.bytes_reclaimed += other.bytes_reclaimed;
+    }
+}
+
+/// Totals across every compaction since the instance was created
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompactionStats {
+    /// Compaction passes, manual or scheduled
+    pub runs: u64,
+    pub documents_compacted: u64,
+    pub chunks_removed: u64,
+    pub bytes_reclaimed: u64,
+    /// Unix time in milliseconds of the last pass
+    pub last_run_at: Option<i64>,
+}
+
+/// Running totals and the scheduler generation, shared by clones of a `TonkCore`
+#[derive(Debug, Default)]
+pub(crate) struct Compaction {
+    stats: Mutex<CompactionStats>,
+    /// Bumped whenever the schedule changes, so a superseded scheduler task stops
+    schedule: AtomicU64,
+}
+
+impl Compaction {
+    pub(crate) fn record(&self, report: &CompactionReport) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.runs += 1;
+        stats.documents_compacted += report.documents as u64;
+        stats.chunks_removed += report.chunks_removed as u64;
+        stats.bytes_reclaimed += report.bytes_reclaimed;
+        stats.last_run_at = Some(chrono::Utc::now().timestamp_millis());
+    }
+
+    pub(crate) fn stats(&self) -> CompactionStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Start a new schedule, returning its generation
+    pub(crate) fn reschedule(&self) -> u64 {
+        self.schedule.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub(crate) fn is_current(&self, generation: u64) -> bool {
+        self.schedule.load(Ordering::Relaxed) == generation
+    }
+}
+
+/// Storage key of the snapshot of a document at `heads`
+fn snapshot_key(handle: &DocHandle, heads: &[ChangeHash]) -> Option<StorageKey> {
+    let mut hasher = Sha256::new();
+    for head in heads {
+        hasher.update(head.0);
+    }
+    let hash: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    StorageKey::from_parts([
+        handle.document_id().to_string(),
+        "snapshot".to_string(),
+        hash,
+    ])
+    .ok()
+}
+
+/// Replace a document's stored chunks with one snapshot of its current state
+///
+/// Chunks are listed before the document is saved, and only those are deleted,
+/// so anything written while compacting survives. Documents already stored in
+/// fewer than `min_chunks` chunks, or in just one, are skipped.
+pub(crate) async fn compact_document(
+    storage: &RepoStorage,
+    handle: &DocHandle,
+    min_chunks: usize,
+) -> CompactionReport {
+    let chunks = storage
+        .document_chunks(Some(handle.document_id()))
+        .await
+        .remove(handle.document_id())
+        .unwrap_or_default();
+    if chunks.len() < min_chunks.max(2) {
+        return CompactionReport::default();
+    }
+
+    let (heads, data) = handle.with_document(|doc| (doc.get_heads(), doc.save()));
+    let Some(key) = snapshot_key(handle, &heads) else {
+        return CompactionReport::default();
+    };
+    let mut report = CompactionReport {
+        documents: 1,
+        bytes_after: data.len() as u64,
+        ..Default::default()
+    };
+    storage.put(key.clone(), data).await;
+
+    for chunk in chunks {
+        report.bytes_before += chunk.len;
+        if chunk.key != key {
+            storage.delete(chunk.key).await;
+            report.chunks_removed += 1;
+        }
+    }
+    report.bytes_reclaimed = report.bytes_before.saturating_sub(report.bytes_after);
+    report
+}
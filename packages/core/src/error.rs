@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -17,6 +18,12 @@ pub enum VfsError {
     #[error("Cannot move directory into itself or its subdirectory: {0}")]
     CircularMove(String),
 
+    #[error("Symbolic link loop at: {0}")]
+    LinkLoop(String),
+
+    #[error("Directory is not empty: {0}")]
+    DirectoryNotEmpty(String),
+
     #[error("Node type mismatch: expected {expected}, got {actual}")]
     NodeTypeMismatch { expected: String, actual: String },
 
@@ -47,6 +54,33 @@ pub enum VfsError {
     #[error("Transaction failed: {0}")]
     TransactionFailed(String),
 
+    #[error("Import limit exceeded: {0}")]
+    ImportLimitExceeded(String),
+
+    #[error("Traversal limit exceeded: {0}")]
+    TraversalLimitExceeded(String),
+
+    #[error("Mount already exists: {0}")]
+    MountExists(String),
+
+    #[error("Bundle file is locked by another process: {0}")]
+    BundleLocked(String),
+
+    #[error("Quota exceeded: {used} bytes used of {quota} allowed")]
+    QuotaExceeded { used: u64, quota: u64 },
+
+    #[error("Timed out waiting for sync: {0}")]
+    SyncTimeout(String),
+
+    #[error("Validation failed: {0}")]
+    ValidationFailed(String),
+
+    #[error("Invalid schema: {0}")]
+    InvalidSchema(String),
+
+    #[error("Lease is held by another client: {0}")]
+    LeaseUnavailable(String),
+
     #[error("Not implemented: {0}")]
     NotImplemented(String),
 
@@ -56,9 +90,183 @@ pub enum VfsError {
 
 pub type Result<T> = std::result::Result<T, VfsError>;
 
-#[cfg(not(target_arch = "wasm32"))]
+/// Stable identifier for a kind of error, thrown to JavaScript as `error.code`
+///
+/// Codes are never renamed or reused, so callers can match on them instead of
+/// on messages. Each `VfsError` variant has its own code; `InvalidArgument` is
+/// only raised by the bindings when a JavaScript value can't be converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    PathNotFound,
+    DocumentExists,
+    InvalidPath,
+    RootPath,
+    CircularMove,
+    LinkLoop,
+    DirectoryNotEmpty,
+    NodeTypeMismatch,
+    Automerge,
+    Samod,
+    WebSocket,
+    Serialization,
+    Io,
+    DocumentNotFound,
+    InvalidDocumentStructure,
+    ConcurrentModification,
+    TransactionFailed,
+    ImportLimitExceeded,
+    TraversalLimitExceeded,
+    MountExists,
+    BundleLocked,
+    QuotaExceeded,
+    SyncTimeout,
+    ValidationFailed,
+    InvalidSchema,
+    LeaseUnavailable,
+    NotImplemented,
+    InvalidArgument,
+    Other,
+}
+
+impl ErrorCode {
+    /// Returns the string representation of the code, as serialized
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::PathNotFound => "PATH_NOT_FOUND",
+            ErrorCode::DocumentExists => "DOCUMENT_EXISTS",
+            ErrorCode::InvalidPath => "INVALID_PATH",
+            ErrorCode::RootPath => "ROOT_PATH",
+            ErrorCode::CircularMove => "CIRCULAR_MOVE",
+            ErrorCode::LinkLoop => "LINK_LOOP",
+            ErrorCode::DirectoryNotEmpty => "DIRECTORY_NOT_EMPTY",
+            ErrorCode::NodeTypeMismatch => "NODE_TYPE_MISMATCH",
+            ErrorCode::Automerge => "AUTOMERGE",
+            ErrorCode::Samod => "SAMOD",
+            ErrorCode::WebSocket => "WEB_SOCKET",
+            ErrorCode::Serialization => "SERIALIZATION",
+            ErrorCode::Io => "IO",
+            ErrorCode::DocumentNotFound => "DOCUMENT_NOT_FOUND",
+            ErrorCode::InvalidDocumentStructure => "INVALID_DOCUMENT_STRUCTURE",
+            ErrorCode::ConcurrentModification => "CONCURRENT_MODIFICATION",
+            ErrorCode::TransactionFailed => "TRANSACTION_FAILED",
+            ErrorCode::ImportLimitExceeded => "IMPORT_LIMIT_EXCEEDED",
+            ErrorCode::TraversalLimitExceeded => "TRAVERSAL_LIMIT_EXCEEDED",
+            ErrorCode::MountExists => "MOUNT_EXISTS",
+            ErrorCode::BundleLocked => "BUNDLE_LOCKED",
+            ErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorCode::SyncTimeout => "SYNC_TIMEOUT",
+            ErrorCode::ValidationFailed => "VALIDATION_FAILED",
+            ErrorCode::InvalidSchema => "INVALID_SCHEMA",
+            ErrorCode::LeaseUnavailable => "LEASE_UNAVAILABLE",
+            ErrorCode::NotImplemented => "NOT_IMPLEMENTED",
+            ErrorCode::InvalidArgument => "INVALID_ARGUMENT",
+            ErrorCode::Other => "OTHER",
+        }
+    }
+}
+
+impl VfsError {
+    /// The stable code for this kind of error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            VfsError::PathNotFound(_) => ErrorCode::PathNotFound,
+            VfsError::DocumentExists(_) => ErrorCode::DocumentExists,
+            VfsError::InvalidPath(_) => ErrorCode::InvalidPath,
+            VfsError::RootPathError => ErrorCode::RootPath,
+            VfsError::CircularMove(_) => ErrorCode::CircularMove,
+            VfsError::LinkLoop(_) => ErrorCode::LinkLoop,
+            VfsError::DirectoryNotEmpty(_) => ErrorCode::DirectoryNotEmpty,
+            VfsError::NodeTypeMismatch { .. } => ErrorCode::NodeTypeMismatch,
+            VfsError::AutomergeError(_) => ErrorCode::Automerge,
+            VfsError::SamodError(_) => ErrorCode::Samod,
+            VfsError::WebSocketError(_) => ErrorCode::WebSocket,
+            VfsError::SerializationError(_) => ErrorCode::Serialization,
+            VfsError::IoError(_) => ErrorCode::Io,
+            VfsError::DocumentNotFound(_) => ErrorCode::DocumentNotFound,
+            VfsError::InvalidDocumentStructure => ErrorCode::InvalidDocumentStructure,
+            VfsError::ConcurrentModification => ErrorCode::ConcurrentModification,
+            VfsError::TransactionFailed(_) => ErrorCode::TransactionFailed,
+            VfsError::ImportLimitExceeded(_) => ErrorCode::ImportLimitExceeded,
+            VfsError::TraversalLimitExceeded(_) => ErrorCode::TraversalLimitExceeded,
+            VfsError::MountExists(_) => ErrorCode::MountExists,
+            VfsError::BundleLocked(_) => ErrorCode::BundleLocked,
+            VfsError::QuotaExceeded { .. } => ErrorCode::QuotaExceeded,
+            VfsError::SyncTimeout(_) => ErrorCode::SyncTimeout,
+            VfsError::ValidationFailed(_) => ErrorCode::ValidationFailed,
+            VfsError::InvalidSchema(_) => ErrorCode::InvalidSchema,
+            VfsError::LeaseUnavailable(_) => ErrorCode::LeaseUnavailable,
+            VfsError::NotImplemented(_) => ErrorCode::NotImplemented,
+            VfsError::Other(_) => ErrorCode::Other,
+        }
+    }
+
+    /// Structured details of the error, such as the path it concerns
+    ///
+    /// Empty for variants that carry nothing beyond their message.
+    pub fn context(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut context = serde_json::Map::new();
+        match self {
+            VfsError::PathNotFound(path)
+            | VfsError::DocumentExists(path)
+            | VfsError::LinkLoop(path)
+            | VfsError::DirectoryNotEmpty(path)
+            | VfsError::MountExists(path)
+            | VfsError::BundleLocked(path) => {
+                context.insert("path".into(), path.clone().into());
+            }
+            VfsError::DocumentNotFound(document) => {
+                context.insert("document".into(), document.clone().into());
+            }
+            VfsError::NodeTypeMismatch { expected, actual } => {
+                context.insert("expected".into(), expected.clone().into());
+                context.insert("actual".into(), actual.clone().into());
+            }
+            VfsError::QuotaExceeded { used, quota } => {
+                context.insert("used".into(), (*used).into());
+                context.insert("quota".into(), (*quota).into());
+            }
+            _ => {}
+        }
+        context
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
 impl From<tokio_tungstenite::tungstenite::Error> for VfsError {
     fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
         VfsError::WebSocketError(err.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes_and_context() {
+        let err = VfsError::PathNotFound("/missing.txt".to_string());
+        assert_eq!(err.code(), ErrorCode::PathNotFound);
+        assert_eq!(
+            serde_json::to_value(err.code()).unwrap(),
+            serde_json::json!(err.code().as_str())
+        );
+        assert_eq!(
+            serde_json::Value::Object(err.context()),
+            serde_json::json!({ "path": "/missing.txt" })
+        );
+
+        let err = VfsError::QuotaExceeded { used: 10, quota: 8 };
+        assert_eq!(err.code().as_str(), "QUOTA_EXCEEDED");
+        assert_eq!(
+            serde_json::Value::Object(err.context()),
+            serde_json::json!({ "used": 10, "quota": 8 })
+        );
+
+        assert_eq!(
+            VfsError::Other(anyhow::anyhow!("boom")).code(),
+            ErrorCode::Other
+        );
+        assert!(VfsError::ConcurrentModification.context().is_empty());
+    }
+}
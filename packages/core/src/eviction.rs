@@ -0,0 +1,162 @@
+use crate::gc::RepoStorage;
+use samod::DocumentId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// How the local replica was found to have lost the documents of its space
+///
+/// Browsers evict an origin's storage under pressure, all of it at once or,
+/// when the manifest was written after the documents, leaving the manifest
+/// behind. Either way the app should sync the space again from its relay
+/// rather than carry on with an empty one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "camelCase")]
+pub enum StorageEviction {
+    /// The stored manifest names a root document that storage no longer holds
+    #[serde(rename_all = "camelCase")]
+    RootMissing { root_id: String },
+    /// The instance started without a stored root and made a new one, while the
+    /// relay it connected to hosts another
+    ///
+    /// This is what a wiped origin looks like from the inside, since the marker
+    /// of which space was stored is wiped with it. An app that never loaded the
+    /// relay's space sees it too.
+    #[serde(rename_all = "camelCase")]
+    RootMismatch {
+        local_root: String,
+        relay_root: String,
+    },
+}
+
+/// Reports evictions of the local replica to subscribers
+pub(crate) struct EvictionMonitor {
+    /// Root created when the instance started, rather than restored from storage
+    fresh_root: Option<DocumentId>,
+    eviction: watch::Sender<Option<StorageEviction>>,
+}
+
+impl EvictionMonitor {
+    /// Monitor an instance whose root was restored from storage or a bundle
+    pub(crate) fn restored() -> Self {
+        Self {
+            fresh_root: None,
+            eviction: watch::Sender::new(None),
+        }
+    }
+
+    /// Monitor an instance that made the new root `root_id` when it started
+    pub(crate) fn fresh(root_id: DocumentId) -> Self {
+        Self {
+            fresh_root: Some(root_id),
+            ..Self::restored()
+        }
+    }
+
+    /// The eviction found so far, if any
+    pub(crate) fn current(&self) -> Option<StorageEviction> {
+        self.eviction.borrow().clone()
+    }
+
+    /// Subscribe to evictions, starting with one already found
+    pub(crate) fn subscribe(&self) -> watch::Receiver<Option<StorageEviction>> {
+        let mut rx = self.eviction.subscribe();
+        if rx.borrow().is_some() {
+            rx.mark_changed();
+        }
+        rx
+    }
+
+    fn report(&self, eviction: StorageEviction) {
+        tracing::warn!(
+            "Local replica lost its documents ({:?}); sync them again from the relay",
+            eviction
+        );
+        self.eviction.send_replace(Some(eviction));
+    }
+
+    /// Check that `storage` still holds the root document a manifest named
+    ///
+    /// Returns whether the root was found.
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub(crate) async fn check_stored_root(
+        &self,
+        storage: &RepoStorage,
+        root_id: &DocumentId,
+    ) -> bool {
+        let found = !storage.document_chunks(Some(root_id)).await.is_empty();
+        if !found {
+            self.report(StorageEviction::RootMissing {
+                root_id: root_id.to_string(),
+            });
+        }
+        found
+    }
+
+    /// Compare the root a relay hosts with the one this instance made at start
+    ///
+    /// Returns whether they match; a restored root always does, as it was
+    /// stored by an earlier sync or bundle load.
+    pub(crate) fn check_relay_root(&self, relay_root: &str) -> bool {
+        let Some(local_root) = &self.fresh_root else {
+            return true;
+        };
+        if local_root.to_string() == relay_root {
+            return true;
+        }
+        self.report(StorageEviction::RootMismatch {
+            local_root: local_root.to_string(),
+            relay_root: relay_root.to_string(),
+        });
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use samod::storage::InMemoryStorage;
+
+    #[tokio::test]
+    async fn test_missing_root_is_reported_to_late_subscribers() {
+        let tonk = crate::TonkCore::new().await.unwrap();
+        let root_id = tonk.vfs().root_id();
+        let monitor = EvictionMonitor::restored();
+
+        let storage = RepoStorage::InMemory(InMemoryStorage::new());
+        assert!(!monitor.check_stored_root(&storage, &root_id).await);
+
+        let mut rx = monitor.subscribe();
+        rx.changed().await.unwrap();
+        assert_eq!(
+            *rx.borrow_and_update(),
+            Some(StorageEviction::RootMissing {
+                root_id: root_id.to_string()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_relay_root_is_compared_with_a_fresh_root_only() {
+        let local = crate::TonkCore::new().await.unwrap().vfs().root_id();
+        let relay = crate::TonkCore::new().await.unwrap().vfs().root_id();
+
+        let restored = EvictionMonitor::restored();
+        assert!(restored.check_relay_root(&relay.to_string()));
+        assert_eq!(restored.current(), None);
+
+        let fresh = EvictionMonitor::fresh(local.clone());
+        assert!(fresh.check_relay_root(&local.to_string()));
+        assert_eq!(fresh.current(), None);
+
+        let mut rx = fresh.subscribe();
+        assert!(!fresh.check_relay_root(&relay.to_string()));
+        rx.changed().await.unwrap();
+        assert_eq!(
+            *rx.borrow(),
+            Some(StorageEviction::RootMismatch {
+                local_root: local.to_string(),
+                relay_root: relay.to_string(),
+            })
+        );
+    }
+}
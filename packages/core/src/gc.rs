@@ -0,0 +1,348 @@
+use crate::outbox::OutboxRecord;
+use crate::vfs::mount::MountRecord;
+#[cfg(target_arch = "wasm32")]
+use samod::storage::IndexedDbStorage;
+#[cfg(not(target_arch = "wasm32"))]
+use samod::storage::TokioFilesystemStorage as FilesystemStorage;
+use samod::storage::{InMemoryStorage, Storage, StorageKey};
+use samod::DocumentId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long a document has to have gone unchanged and unreachable before `gc` deletes it
+pub const DEFAULT_GC_GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// Storage key prefix of the mounts recorded for each space
+const MOUNTS_KEY: &str = "__tonk_mounts__";
+
+/// Storage key prefix of the writes each space's outbox holds, see [`crate::outbox::Outbox`]
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+const OUTBOX_KEY: &str = "__tonk_outbox__";
+
+/// Storage key of this install's random ID, see [`crate::identity::Identity::actor_id`]
+const INSTALL_KEY: &str = "__tonk_install__";
+
+/// Storage key prefix of when each space's garbage collection first found documents unreachable
+const GC_LEDGER_KEY: &str = "__tonk_gc__";
+
+/// Outcome of a garbage collection pass
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GcReport {
+    /// Whether the pass only reported what it would delete
+    pub dry_run: bool,
+    /// Documents in storage that no tree reaches, sorted by ID
+    pub unreachable_documents: Vec<String>,
+    /// Storage chunks belonging to the unreachable documents
+    pub chunks: usize,
+    /// Total size of those chunks in bytes
+    pub reclaimable_bytes: u64,
+    /// Unreachable documents younger than the grace period, left for a later pass
+    pub deferred_documents: Vec<String>,
+}
+
+/// A stored chunk of a document, as listed by [`RepoStorage::document_chunks`]
+#[derive(Debug, Clone)]
+pub(crate) struct StoredChunk {
+    pub key: StorageKey,
+    /// Size in bytes
+    pub len: u64,
+    /// When the chunk was last written, where the storage records it
+    pub modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// The storage a repo was built on, kept so it can be scanned and pruned
+#[derive(Clone)]
+pub(crate) enum RepoStorage {
+    InMemory(InMemoryStorage),
+    /// Filesystem storage and the directory it writes to
+    #[cfg(not(target_arch = "wasm32"))]
+    Filesystem(FilesystemStorage, PathBuf),
+    #[cfg(target_arch = "wasm32")]
+    IndexedDb(IndexedDbStorage),
+}
+
+impl RepoStorage {
+    pub(crate) async fn load_range(&self, prefix: StorageKey) -> HashMap<StorageKey, Vec<u8>> {
+        match self {
+            Self::InMemory(storage) => storage.load_range(prefix).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Filesystem(storage, _) => storage.load_range(prefix).await,
+            #[cfg(target_arch = "wasm32")]
+            Self::IndexedDb(storage) => storage.load_range(prefix).await,
+        }
+    }
+
+    pub(crate) async fn load(&self, key: StorageKey) -> Option<Vec<u8>> {
+        match self {
+            Self::InMemory(storage) => storage.load(key).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Filesystem(storage, _) => storage.load(key).await,
+            #[cfg(target_arch = "wasm32")]
+            Self::IndexedDb(storage) => storage.load(key).await,
+        }
+    }
+
+    pub(crate) async fn put(&self, key: StorageKey, data: Vec<u8>) {
+        match self {
+            Self::InMemory(storage) => storage.put(key, data).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Filesystem(storage, _) => storage.put(key, data).await,
+            #[cfg(target_arch = "wasm32")]
+            Self::IndexedDb(storage) => storage.put(key, data).await,
+        }
+    }
+
+    pub(crate) async fn delete(&self, key: StorageKey) {
+        match self {
+            Self::InMemory(storage) => storage.delete(key).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Filesystem(storage, _) => storage.delete(key).await,
+            #[cfg(target_arch = "wasm32")]
+            Self::IndexedDb(storage) => storage.delete(key).await,
+        }
+    }
+
+    /// List the stored chunks of every document, or of just `document`, with their sizes
+    ///
+    /// Only keys laid out as `<document id>/<snapshot|incremental>/...` count as
+    /// document chunks; anything else in storage is left out. Filesystem storage
+    /// is listed from file metadata without reading the chunks; the other
+    /// storages have no listing, so their chunks are loaded to be measured.
+    pub(crate) async fn document_chunks(
+        &self,
+        document: Option<&DocumentId>,
+    ) -> HashMap<DocumentId, Vec<StoredChunk>> {
+        let parts: Vec<String> = document.map(|id| id.to_string()).into_iter().collect();
+        let Ok(prefix) = StorageKey::from_parts(parts) else {
+            return HashMap::new();
+        };
+
+        let chunks: Vec<StoredChunk> = match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Filesystem(_, root) => list_files(root, &prefix).await,
+            _ => self
+                .load_range(prefix)
+                .await
+                .into_iter()
+                .map(|(key, data)| StoredChunk {
+                    key,
+                    len: data.len() as u64,
+                    modified: None,
+                })
+                .collect(),
+        };
+
+        let mut documents: HashMap<DocumentId, Vec<StoredChunk>> = HashMap::new();
+        for chunk in chunks {
+            let parts: Vec<&String> = (&chunk.key).into_iter().collect();
+            let [doc_id, kind, ..] = parts.as_slice() else {
+                continue;
+            };
+            if *kind != "snapshot" && *kind != "incremental" {
+                continue;
+            }
+            let Ok(doc_id) = doc_id.parse::<DocumentId>() else {
+                continue;
+            };
+            documents.entry(doc_id).or_default().push(chunk);
+        }
+        documents
+    }
+
+    /// The random ID of the install this storage belongs to, created on first use
+    pub(crate) async fn install_id(&self) -> Vec<u8> {
+        let Ok(key) = StorageKey::from_parts([INSTALL_KEY]) else {
+            return rand::random::<[u8; 16]>().to_vec();
+        };
+        if let Some(id) = self.load(key.clone()).await.filter(|id| id.len() == 16) {
+            return id;
+        }
+        let id = rand::random::<[u8; 16]>().to_vec();
+        self.put(key, id.clone()).await;
+        id
+    }
+
+    /// The mounts recorded for the space rooted at `root`
+    pub(crate) async fn mount_records(&self, root: &DocumentId) -> Vec<MountRecord> {
+        let Ok(key) = StorageKey::from_parts([MOUNTS_KEY.to_string(), root.to_string()]) else {
+            return Vec::new();
+        };
+        self.load(key)
+            .await
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// Record the mounts of the space rooted at `root`, replacing any recorded before
+    pub(crate) async fn put_mount_records(&self, root: &DocumentId, records: &[MountRecord]) {
+        let Ok(key) = StorageKey::from_parts([MOUNTS_KEY.to_string(), root.to_string()]) else {
+            return;
+        };
+        if records.is_empty() {
+            self.delete(key).await;
+        } else if let Ok(data) = serde_json::to_vec(records) {
+            self.put(key, data).await;
+        }
+    }
+
+    /// The outbox stored for the space rooted at `root`
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub(crate) async fn outbox_record(&self, root: &DocumentId) -> OutboxRecord {
+        let Ok(key) = StorageKey::from_parts([OUTBOX_KEY.to_string(), root.to_string()]) else {
+            return OutboxRecord::default();
+        };
+        self.load(key)
+            .await
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// Store the outbox of the space rooted at `root`, replacing any stored before
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub(crate) async fn put_outbox_record(&self, root: &DocumentId, record: &OutboxRecord) {
+        let Ok(key) = StorageKey::from_parts([OUTBOX_KEY.to_string(), root.to_string()]) else {
+            return;
+        };
+        if let Ok(data) = serde_json::to_vec(record) {
+            self.put(key, data).await;
+        }
+    }
+
+    /// Delete the chunks of every stored document not in `reachable`
+    ///
+    /// A document is only deleted once it is older than `grace`: since its
+    /// newest chunk was written where the storage records that, and otherwise
+    /// since a pass for the space rooted at `root` first found it unreachable.
+    /// Younger documents are reported as deferred.
+    pub(crate) async fn collect_garbage(
+        &self,
+        root: &DocumentId,
+        reachable: &HashSet<DocumentId>,
+        grace: Duration,
+        dry_run: bool,
+    ) -> GcReport {
+        let unreachable: BTreeMap<String, Vec<StoredChunk>> = self
+            .document_chunks(None)
+            .await
+            .into_iter()
+            .filter(|(doc_id, _)| !reachable.contains(doc_id))
+            .map(|(doc_id, chunks)| (doc_id.to_string(), chunks))
+            .collect();
+
+        let ledger_key = StorageKey::from_parts([GC_LEDGER_KEY.to_string(), root.to_string()]).ok();
+        let first_seen: HashMap<String, i64> = match &ledger_key {
+            Some(key) => self
+                .load(key.clone())
+                .await
+                .and_then(|data| serde_json::from_slice(&data).ok())
+                .unwrap_or_default(),
+            None => HashMap::new(),
+        };
+
+        let now = chrono::Utc::now();
+        let grace = chrono::Duration::from_std(grace).unwrap_or(chrono::Duration::MAX);
+        let mut ledger = HashMap::new();
+        let mut report = GcReport {
+            dry_run,
+            ..Default::default()
+        };
+        for (doc_id, chunks) in unreachable {
+            let written = chunks
+                .iter()
+                .map(|chunk| chunk.modified)
+                .collect::<Option<Vec<_>>>()
+                .and_then(|times| times.into_iter().max());
+            let since = written.unwrap_or_else(|| {
+                first_seen
+                    .get(&doc_id)
+                    .and_then(|ms| chrono::DateTime::from_timestamp_millis(*ms))
+                    .unwrap_or(now)
+            });
+            if now.signed_duration_since(since) < grace {
+                ledger.insert(doc_id.clone(), since.timestamp_millis());
+                report.deferred_documents.push(doc_id);
+                continue;
+            }
+
+            for chunk in chunks {
+                report.chunks += 1;
+                report.reclaimable_bytes += chunk.len;
+                if !dry_run {
+                    self.delete(chunk.key).await;
+                }
+            }
+            report.unreachable_documents.push(doc_id);
+        }
+
+        if let (false, Some(key)) = (dry_run, ledger_key) {
+            if ledger.is_empty() {
+                self.delete(key).await;
+            } else if let Ok(data) = serde_json::to_vec(&ledger) {
+                self.put(key, data).await;
+            }
+        }
+        report
+    }
+}
+
+/// List the files below the part of a filesystem storage directory holding `prefix`
+///
+/// Storage lays a key out as a path, splitting its first part after two
+/// characters: `<root>/ab/cdef/snapshot/<hash>` holds `abcdef/snapshot/<hash>`.
+#[cfg(not(target_arch = "wasm32"))]
+async fn list_files(root: &Path, prefix: &StorageKey) -> Vec<StoredChunk> {
+    let parts: Vec<&String> = prefix.into_iter().collect();
+    let mut start = root.to_path_buf();
+    if let Some((first, rest)) = parts.split_first() {
+        if first.len() > 2 && first.is_char_boundary(2) {
+            start.push(&first[..2]);
+            start.push(&first[2..]);
+            start.extend(rest.iter().map(|part| part.as_str()));
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let mut pending = vec![start];
+    while let Some(dir) = pending.pop() {
+        let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            if metadata.is_dir() {
+                pending.push(entry.path());
+                continue;
+            }
+            let path = entry.path();
+            let Ok(relative) = path.strip_prefix(root) else {
+                continue;
+            };
+            let components: Vec<String> = relative
+                .iter()
+                .map(|part| part.to_string_lossy().into_owned())
+                .collect();
+            let [head, tail, rest @ ..] = components.as_slice() else {
+                continue;
+            };
+            let key_parts = std::iter::once(format!("{head}{tail}")).chain(rest.iter().cloned());
+            let Ok(key) = StorageKey::from_parts(key_parts) else {
+                continue;
+            };
+            if !prefix.is_prefix_of(&key) {
+                continue;
+            }
+            chunks.push(StoredChunk {
+                key,
+                len: metadata.len(),
+                modified: metadata.modified().ok().map(chrono::DateTime::from),
+            });
+        }
+    }
+    chunks
+}
@@ -0,0 +1,159 @@
+use automerge::ActorId;
+use ed25519_dalek::{Signature, Signer, Verifier};
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Multicodec prefix of an Ed25519 public key in a `did:key`
+const ED25519_PUB: [u8; 2] = [0xed, 0x01];
+
+/// `did:key` of an Ed25519 public key
+pub fn did_key(key: &VerifyingKey) -> String {
+    let mut bytes = ED25519_PUB.to_vec();
+    bytes.extend_from_slice(key.as_bytes());
+    format!("did:key:z{}", bs58::encode(bytes).into_string())
+}
+
+/// Ed25519 public key of a `did:key`, or `None` for other DIDs
+pub fn parse_did_key(did: &str) -> Option<VerifyingKey> {
+    let encoded = did.strip_prefix("did:key:z")?;
+    let bytes = bs58::decode(encoded).into_vec().ok()?;
+    let key: [u8; 32] = bytes.strip_prefix(&ED25519_PUB)?.try_into().ok()?;
+    VerifyingKey::from_bytes(&key).ok()
+}
+
+/// Who authors the changes made through a `TonkCore`
+///
+/// Automerge gives every process a random actor ID, so the changes in a
+/// document's history can't be traced back to anyone. With an identity set,
+/// changes are instead written under an actor ID derived from the operator's
+/// DID, the device and the install, and the root document records which
+/// identity each such actor ID belongs to, signed by the operator's key.
+///
+/// The signature shows that the operator vouched for the actor ID. It doesn't
+/// stop a peer that can write to the space from making changes under that
+/// actor ID, since Automerge changes aren't signed themselves.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Identity {
+    /// DID of the operator of the space, such as `did:key:z6Mk...`
+    pub operator_did: String,
+    /// Name of this device, unique among the operator's devices
+    pub device: String,
+}
+
+impl Identity {
+    pub fn new(operator_did: impl Into<String>, device: impl Into<String>) -> Self {
+        Self {
+            operator_did: operator_did.into(),
+            device: device.into(),
+        }
+    }
+
+    /// A device of the operator holding `key`, whose DID is the key's `did:key`
+    pub fn for_key(key: &VerifyingKey, device: impl Into<String>) -> Self {
+        Self::new(did_key(key), device)
+    }
+
+    /// Actor ID of this identity's changes from the install with ID `install`
+    ///
+    /// Mixing in the install keeps two installs given the same device name
+    /// from writing under one actor ID, which would produce conflicting changes.
+    pub fn actor_id(&self, install: &[u8]) -> ActorId {
+        let mut hasher = Sha256::new();
+        hasher.update(self.operator_did.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.device.as_bytes());
+        hasher.update([0]);
+        hasher.update(install);
+        ActorId::from(&hasher.finalize()[..16])
+    }
+
+    fn signed_payload(&self, actor: &str) -> Vec<u8> {
+        serde_json::to_vec(&(actor, &self.operator_did, &self.device)).unwrap_or_default()
+    }
+
+    /// Whether `signature` is the operator's, vouching that hex-encoded `actor` is this identity
+    pub fn verify(&self, actor: &str, signature: &[u8]) -> bool {
+        let (Some(key), Ok(signature)) = (
+            parse_did_key(&self.operator_did),
+            Signature::from_slice(signature),
+        ) else {
+            return false;
+        };
+        key.verify(&self.signed_payload(actor), &signature).is_ok()
+    }
+}
+
+/// An identity with the actor ID this install writes under, and the
+/// operator's signature vouching for it
+#[derive(Debug, Clone)]
+pub struct Author {
+    pub identity: Identity,
+    pub actor_id: ActorId,
+    /// Ed25519 signature by the operator's key, checked by [`Identity::verify`]
+    pub signature: Vec<u8>,
+}
+
+impl Author {
+    pub fn new(key: &SigningKey, device: impl Into<String>, install: &[u8]) -> Self {
+        let identity = Identity::for_key(&key.verifying_key(), device);
+        let actor_id = identity.actor_id(install);
+        let signature = key
+            .sign(&identity.signed_payload(&actor_id.to_hex_string()))
+            .to_bytes()
+            .to_vec();
+        Self {
+            identity,
+            actor_id,
+            signature,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_did_key_round_trip() {
+        let key = SigningKey::from_bytes(&[7; 32]).verifying_key();
+        let did = did_key(&key);
+        assert!(did.starts_with("did:key:z6Mk"));
+        assert_eq!(parse_did_key(&did), Some(key));
+        assert_eq!(parse_did_key("did:web:example.com"), None);
+        assert_eq!(parse_did_key("did:key:z6Mk"), None);
+    }
+
+    #[test]
+    fn test_actor_id_is_stable_per_install() {
+        let laptop = Identity::new("did:key:z6Mk", "laptop");
+        assert_eq!(laptop.actor_id(b"a"), laptop.clone().actor_id(b"a"));
+        assert_ne!(laptop.actor_id(b"a"), laptop.actor_id(b"b"));
+        assert_ne!(
+            laptop.actor_id(b"a"),
+            Identity::new("did:key:z6Mk", "phone").actor_id(b"a")
+        );
+        assert_ne!(
+            laptop.actor_id(b"a"),
+            Identity::new("did:key:z6Mn", "laptop").actor_id(b"a")
+        );
+    }
+
+    #[test]
+    fn test_signature_vouches_for_actor() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let author = Author::new(&key, "laptop", b"install");
+        let actor = author.actor_id.to_hex_string();
+        assert!(author.identity.verify(&actor, &author.signature));
+
+        // Claiming another actor, device or operator breaks the signature
+        assert!(!author.identity.verify("00", &author.signature));
+        let phone = Identity::new(author.identity.operator_did.clone(), "phone");
+        assert!(!phone.verify(&actor, &author.signature));
+        let stranger =
+            Identity::for_key(&SigningKey::from_bytes(&[8; 32]).verifying_key(), "laptop");
+        assert!(!stranger.verify(&actor, &author.signature));
+        assert!(!author.identity.verify(&actor, &[0; 64]));
+    }
+}
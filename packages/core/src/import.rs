@@ -0,0 +1,410 @@
+#[cfg(feature = "bundle")]
+use crate::bundle::EntryMetadata;
+#[cfg(feature = "bundle")]
+use crate::error::{Result, VfsError};
+use serde::{Deserialize, Serialize};
+
+/// Limits applied while importing a bundle into storage.
+///
+/// Limits are checked against the sizes recorded in the bundle index before any
+/// entry is decompressed, so an oversized bundle is rejected deterministically
+/// instead of exhausting the heap part way through an import.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ImportLimits {
+    /// Maximum number of storage entries in the bundle
+    pub max_entries: Option<usize>,
+    /// Maximum uncompressed size of a single storage entry in bytes
+    pub max_entry_bytes: Option<u64>,
+    /// Maximum total uncompressed size of all storage entries in bytes
+    pub max_total_bytes: Option<u64>,
+    /// Number of entries to import before yielding to the event loop (0 disables yielding)
+    pub yield_every: usize,
+}
+
+impl Default for ImportLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: None,
+            max_entry_bytes: None,
+            max_total_bytes: None,
+            yield_every: 64,
+        }
+    }
+}
+
+impl ImportLimits {
+    /// Create limits with no size restrictions
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum number of storage entries
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Set the maximum uncompressed size of a single entry
+    pub fn with_max_entry_bytes(mut self, max_entry_bytes: u64) -> Self {
+        self.max_entry_bytes = Some(max_entry_bytes);
+        self
+    }
+
+    /// Set the maximum total uncompressed size of all entries
+    pub fn with_max_total_bytes(mut self, max_total_bytes: u64) -> Self {
+        self.max_total_bytes = Some(max_total_bytes);
+        self
+    }
+
+    /// Set how many entries are imported between yields
+    pub fn with_yield_every(mut self, yield_every: usize) -> Self {
+        self.yield_every = yield_every;
+        self
+    }
+
+    /// Check a set of bundle entries against these limits
+    #[cfg(feature = "bundle")]
+    pub fn check(&self, entries: &[EntryMetadata]) -> Result<()> {
+        self.check_count(entries.len())?;
+        for entry in entries {
+            self.check_entry(entry)?;
+        }
+        self.check_total(entries.iter().map(|e| e.uncompressed_size).sum())
+    }
+
+    #[cfg(feature = "bundle")]
+    fn check_count(&self, count: usize) -> Result<()> {
+        if let Some(max) = self.max_entries {
+            if count > max {
+                return Err(VfsError::ImportLimitExceeded(format!(
+                    "bundle has {} storage entries, limit is {}",
+                    count, max
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "bundle")]
+    fn check_entry(&self, entry: &EntryMetadata) -> Result<()> {
+        if let Some(max) = self.max_entry_bytes {
+            if entry.uncompressed_size > max {
+                return Err(VfsError::ImportLimitExceeded(format!(
+                    "entry {} is {} bytes, limit is {}",
+                    entry.path, entry.uncompressed_size, max
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "bundle")]
+    fn check_total(&self, total: u64) -> Result<()> {
+        if let Some(max) = self.max_total_bytes {
+            if total > max {
+                return Err(VfsError::ImportLimitExceeded(format!(
+                    "bundle storage is {} bytes uncompressed, limit is {}",
+                    total, max
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Progress and memory diagnostics reported during a bundle import
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportProgress {
+    /// Number of entries imported so far
+    pub entries_imported: usize,
+    /// Total number of entries to import
+    pub entries_total: usize,
+    /// Uncompressed bytes imported so far
+    pub bytes_imported: u64,
+    /// Total uncompressed bytes to import
+    pub bytes_total: u64,
+    /// Current size of the wasm linear memory, if available
+    pub heap_bytes: Option<u64>,
+    /// Largest observed size of the wasm linear memory during the import
+    pub heap_high_water_bytes: Option<u64>,
+}
+
+/// Callback invoked with import progress and memory diagnostics
+#[cfg(not(target_arch = "wasm32"))]
+pub type ImportProgressCallback = std::sync::Arc<dyn Fn(&ImportProgress) + Send + Sync>;
+
+/// Callback invoked with import progress and memory diagnostics
+#[cfg(target_arch = "wasm32")]
+pub type ImportProgressCallback = std::rc::Rc<dyn Fn(&ImportProgress)>;
+
+/// Tracks an in-flight import, enforcing yields and reporting progress
+#[cfg(feature = "bundle")]
+pub(crate) struct ImportTracker {
+    limits: ImportLimits,
+    on_progress: Option<ImportProgressCallback>,
+    progress: ImportProgress,
+    /// Whether the totals are known up front (false while streaming)
+    totals_known: bool,
+}
+
+#[cfg(feature = "bundle")]
+impl ImportTracker {
+    /// Validate the entries against the limits and start tracking
+    pub(crate) fn start(
+        limits: ImportLimits,
+        on_progress: Option<ImportProgressCallback>,
+        entries: &[EntryMetadata],
+    ) -> Result<Self> {
+        limits.check(entries)?;
+
+        let heap = current_heap_bytes();
+        let tracker = Self {
+            limits,
+            on_progress,
+            progress: ImportProgress {
+                entries_total: entries.len(),
+                bytes_total: entries.iter().map(|e| e.uncompressed_size).sum(),
+                heap_bytes: heap,
+                heap_high_water_bytes: heap,
+                ..Default::default()
+            },
+            totals_known: true,
+        };
+        tracker.report();
+        Ok(tracker)
+    }
+
+    /// Start tracking an import whose entries are discovered as it streams in
+    ///
+    /// Totals grow as entries are admitted, so they only reach their final values
+    /// once the whole bundle has arrived.
+    pub(crate) fn start_streaming(
+        limits: ImportLimits,
+        on_progress: Option<ImportProgressCallback>,
+    ) -> Self {
+        let heap = current_heap_bytes();
+        let tracker = Self {
+            limits,
+            on_progress,
+            progress: ImportProgress {
+                heap_bytes: heap,
+                heap_high_water_bytes: heap,
+                ..Default::default()
+            },
+            totals_known: false,
+        };
+        tracker.report();
+        tracker
+    }
+
+    /// Check a newly discovered entry against the limits before its data is read
+    pub(crate) fn admit(&mut self, entry: &EntryMetadata) -> Result<()> {
+        self.progress.entries_total += 1;
+        self.progress.bytes_total += entry.uncompressed_size;
+
+        self.limits.check_count(self.progress.entries_total)?;
+        self.limits.check_entry(entry)?;
+        self.limits.check_total(self.progress.bytes_total)
+    }
+
+    /// Report the final progress of a streamed import
+    pub(crate) fn finish(&mut self) {
+        if !self.totals_known {
+            self.totals_known = true;
+            self.report();
+        }
+    }
+
+    /// Record an imported entry, yielding to the event loop when due
+    pub(crate) async fn record(&mut self, bytes: usize) {
+        self.progress.entries_imported += 1;
+        self.progress.bytes_imported += bytes as u64;
+        self.sample_heap();
+
+        let done =
+            self.totals_known && self.progress.entries_imported == self.progress.entries_total;
+        let yield_every = self.limits.yield_every;
+        let due = yield_every > 0 && self.progress.entries_imported.is_multiple_of(yield_every);
+        if due || done {
+            self.report();
+        }
+        if due {
+            yield_now().await;
+        }
+    }
+
+    /// Get the progress recorded so far
+    pub(crate) fn progress(&self) -> &ImportProgress {
+        &self.progress
+    }
+
+    fn sample_heap(&mut self) {
+        if let Some(heap) = current_heap_bytes() {
+            self.progress.heap_bytes = Some(heap);
+            let high = self.progress.heap_high_water_bytes.unwrap_or(0).max(heap);
+            self.progress.heap_high_water_bytes = Some(high);
+        }
+    }
+
+    fn report(&self) {
+        if let Some(callback) = &self.on_progress {
+            callback(&self.progress);
+        }
+    }
+}
+
+/// Current size of the wasm linear memory in bytes
+#[cfg(target_arch = "wasm32")]
+pub fn current_heap_bytes() -> Option<u64> {
+    use wasm_bindgen::JsCast;
+
+    wasm_bindgen::memory()
+        .dyn_into::<js_sys::WebAssembly::Memory>()
+        .ok()
+        .and_then(|memory| memory.buffer().dyn_into::<js_sys::ArrayBuffer>().ok())
+        .map(|buffer| buffer.byte_length() as u64)
+}
+
+/// Current size of the wasm linear memory in bytes (always `None` on native targets)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn current_heap_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &js_sys::Function, timeout: i32) -> wasm_bindgen::JsValue;
+}
+
+/// Yield to the JS event loop so the page stays responsive during long imports
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn yield_now() {
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        set_timeout(&resolve, 0);
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn yield_now() {
+    tokio::task::yield_now().await;
+}
+
+#[cfg(all(test, feature = "bundle"))]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn entry(path: &str, size: u64) -> EntryMetadata {
+        EntryMetadata {
+            path: path.to_string(),
+            local_header_offset: 0,
+            compressed_size: size,
+            uncompressed_size: size,
+            crc32: 0,
+            compression_method: 0,
+        }
+    }
+
+    #[test]
+    fn test_limits_unlimited_accepts_everything() {
+        let entries = vec![entry("storage/a", 1 << 30), entry("storage/b", 1 << 30)];
+        assert!(ImportLimits::unlimited().check(&entries).is_ok());
+    }
+
+    #[test]
+    fn test_limits_reject_oversized_bundles() {
+        let entries = vec![entry("storage/a", 100), entry("storage/b", 300)];
+
+        let err = ImportLimits::default()
+            .with_max_entries(1)
+            .check(&entries)
+            .unwrap_err();
+        assert!(matches!(err, VfsError::ImportLimitExceeded(_)));
+
+        let err = ImportLimits::default()
+            .with_max_entry_bytes(200)
+            .check(&entries)
+            .unwrap_err();
+        assert!(err.to_string().contains("storage/b"));
+
+        let err = ImportLimits::default()
+            .with_max_total_bytes(399)
+            .check(&entries)
+            .unwrap_err();
+        assert!(matches!(err, VfsError::ImportLimitExceeded(_)));
+
+        assert!(ImportLimits::default()
+            .with_max_entries(2)
+            .with_max_entry_bytes(300)
+            .with_max_total_bytes(400)
+            .check(&entries)
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_tracker_reports_progress() {
+        let entries = vec![
+            entry("storage/a", 10),
+            entry("storage/b", 20),
+            entry("storage/c", 30),
+        ];
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let reports_clone = reports.clone();
+        let callback: ImportProgressCallback = Arc::new(move |progress: &ImportProgress| {
+            reports_clone.lock().unwrap().push(progress.clone());
+        });
+
+        let limits = ImportLimits::default().with_yield_every(2);
+        let mut tracker = ImportTracker::start(limits, Some(callback), &entries).unwrap();
+        for e in &entries {
+            tracker.record(e.uncompressed_size as usize).await;
+        }
+
+        assert_eq!(tracker.progress().entries_imported, 3);
+        assert_eq!(tracker.progress().bytes_imported, 60);
+
+        // Initial report, one after two entries, and a final report
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].entries_imported, 0);
+        assert_eq!(reports[0].bytes_total, 60);
+        assert_eq!(reports[1].entries_imported, 2);
+        assert_eq!(reports[2].entries_imported, 3);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_tracker_checks_running_totals() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let reports_clone = reports.clone();
+        let callback: ImportProgressCallback = Arc::new(move |progress: &ImportProgress| {
+            reports_clone.lock().unwrap().push(progress.clone());
+        });
+
+        let limits = ImportLimits::default()
+            .with_max_total_bytes(50)
+            .with_yield_every(0);
+        let mut tracker = ImportTracker::start_streaming(limits, Some(callback));
+
+        tracker.admit(&entry("storage/a", 20)).unwrap();
+        tracker.record(20).await;
+        tracker.admit(&entry("storage/b", 20)).unwrap();
+        tracker.record(20).await;
+        tracker.finish();
+
+        // Only the initial and final reports, since totals weren't known up front
+        {
+            let reports = reports.lock().unwrap();
+            assert_eq!(reports.len(), 2);
+            assert_eq!(reports[1].entries_total, 2);
+            assert_eq!(reports[1].bytes_imported, 40);
+        }
+
+        let err = tracker.admit(&entry("storage/c", 20)).unwrap_err();
+        assert!(matches!(err, VfsError::ImportLimitExceeded(_)));
+    }
+}
@@ -0,0 +1,267 @@
+use crate::gc::RepoStorage;
+use crate::vfs::VfsEvent;
+use samod::storage::StorageKey;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// First component of the storage key of every journal entry
+const JOURNAL_PREFIX: &str = "journal";
+
+/// Second component of the key recording which cursors are stored
+const RANGE_KEY: &str = "range";
+
+/// How many events the journal keeps
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct JournalPolicy {
+    /// Oldest entries are deleted once there are more than this; `0` keeps them all
+    pub max_entries: u64,
+}
+
+impl Default for JournalPolicy {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+        }
+    }
+}
+
+/// What happened to the path of a journal entry, named like the event it records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JournalOp {
+    DocumentCreated,
+    DocumentUpdated,
+    DocumentDeleted,
+    DirectoryCreated,
+    ConflictDetected,
+    /// Events were dropped before they could be recorded; rescan the tree
+    Gap,
+}
+
+/// A VFS event as recorded in the journal
+///
+/// Changes made through the VFS are recorded just before they are made, so
+/// an entry may describe a change that then failed or changed nothing, but no
+/// change is made without an entry. Conflicts are recorded once detected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    /// Position in the journal; pass the last one seen to `journal_since` to resume
+    pub cursor: u64,
+    pub op: JournalOp,
+    /// Path the entry is about; `/` for a gap
+    pub path: String,
+    /// Document the event is about, absent for deletions and gaps
+    pub doc_id: Option<String>,
+    /// Heads of the document when the entry was recorded, hex-encoded; for a
+    /// change, the heads it was made on
+    pub heads: Vec<String>,
+    /// Unix time in milliseconds the event was recorded
+    pub timestamp: i64,
+    /// For a gap, how many events were dropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub missed: Option<u64>,
+}
+
+impl JournalEntry {
+    /// Start an entry for `event`, or `None` for events that aren't journaled
+    pub(crate) fn from_event(event: &VfsEvent) -> Option<Self> {
+        let (op, path, doc_id) = match event {
+            VfsEvent::DocumentCreated { path, doc_id } => {
+                (JournalOp::DocumentCreated, path, Some(doc_id))
+            }
+            VfsEvent::DocumentUpdated { path, doc_id, .. } => {
+                (JournalOp::DocumentUpdated, path, Some(doc_id))
+            }
+            VfsEvent::DocumentDeleted { path } => (JournalOp::DocumentDeleted, path, None),
+            VfsEvent::DirectoryCreated { path, doc_id } => {
+                (JournalOp::DirectoryCreated, path, Some(doc_id))
+            }
+            VfsEvent::ConflictDetected { path, doc_id, .. } => {
+                (JournalOp::ConflictDetected, path, Some(doc_id))
+            }
+            VfsEvent::Lagged { .. } => return None,
+        };
+        Some(Self {
+            cursor: 0,
+            op,
+            path: path.clone(),
+            doc_id: doc_id.map(|id| id.to_string()),
+            heads: Vec::new(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            missed: None,
+        })
+    }
+
+    /// An entry recording that `missed` events were dropped
+    pub(crate) fn gap(missed: u64) -> Self {
+        Self {
+            cursor: 0,
+            op: JournalOp::Gap,
+            path: "/".to_string(),
+            doc_id: None,
+            heads: Vec::new(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            missed: Some(missed),
+        }
+    }
+}
+
+/// Storage key of the entry at `cursor`, zero-padded so keys sort in journal order
+fn entry_key(cursor: u64) -> Option<StorageKey> {
+    StorageKey::from_parts([JOURNAL_PREFIX.to_string(), format!("{cursor:020}")]).ok()
+}
+
+fn range_key() -> Option<StorageKey> {
+    StorageKey::from_parts([JOURNAL_PREFIX, RANGE_KEY]).ok()
+}
+
+/// Cursors of the entries kept, shared by clones of a `TonkCore`
+#[derive(Debug)]
+pub(crate) struct Journal {
+    /// Oldest and newest cursors in storage, `None` until read from storage
+    range: Mutex<Option<(u64, u64)>>,
+    /// Bumped whenever recording starts or stops, so a superseded recorder stops
+    schedule: watch::Sender<u64>,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self {
+            range: Mutex::default(),
+            schedule: watch::channel(0).0,
+        }
+    }
+}
+
+impl Journal {
+    /// Start a new recorder, returning its generation
+    pub(crate) fn reschedule(&self) -> u64 {
+        self.schedule.send_modify(|generation| *generation += 1);
+        *self.schedule.borrow()
+    }
+
+    /// Resolves once recording has been restarted or stopped after `generation`
+    pub(crate) async fn superseded(&self, generation: u64) {
+        let mut schedule = self.schedule.subscribe();
+        let _ = schedule.wait_for(|current| *current != generation).await;
+    }
+
+    /// Oldest and newest cursors stored, read from storage on first use
+    ///
+    /// The stored range is written after each entry, so entries found past its
+    /// end were appended by a run that stopped in between and are counted too.
+    async fn range(&self, storage: &RepoStorage) -> (u64, u64) {
+        if let Some(range) = *self.range.lock().unwrap() {
+            return range;
+        }
+        let stored = match range_key() {
+            Some(key) => storage.load(key).await,
+            None => None,
+        };
+        let (oldest, mut newest) = stored
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or((1, 0));
+        while let Some(key) = entry_key(newest + 1) {
+            if storage.load(key).await.is_none() {
+                break;
+            }
+            newest += 1;
+        }
+        *self.range.lock().unwrap().get_or_insert((oldest, newest))
+    }
+
+    /// Entries recorded after `cursor`, oldest first
+    ///
+    /// Only the entries after `cursor` are loaded. Stops before an entry that
+    /// is still being written, so no entry is skipped over.
+    pub(crate) async fn since(&self, storage: &RepoStorage, cursor: u64) -> Vec<JournalEntry> {
+        let (oldest, newest) = self.range(storage).await;
+        let mut entries = Vec::new();
+        for cursor in (cursor + 1).max(oldest)..=newest {
+            let Some(key) = entry_key(cursor) else {
+                continue;
+            };
+            match storage
+                .load(key)
+                .await
+                .and_then(|data| serde_json::from_slice(&data).ok())
+            {
+                Some(entry) => entries.push(entry),
+                // Trimmed since the range was read
+                None if self
+                    .range
+                    .lock()
+                    .unwrap()
+                    .is_some_and(|(oldest, _)| cursor < oldest) => {}
+                None => break,
+            }
+        }
+        entries
+    }
+
+    /// Store `entry` under the next cursor, then trim the journal to `policy`
+    pub(crate) async fn append(
+        &self,
+        storage: &RepoStorage,
+        policy: &JournalPolicy,
+        mut entry: JournalEntry,
+    ) {
+        self.range(storage).await;
+        let (expired, cursor, range) = {
+            let mut range = self.range.lock().unwrap();
+            let (oldest, newest) = range.get_or_insert((1, 0));
+            *newest += 1;
+            let first_kept = if policy.max_entries > 0 {
+                (*newest + 1)
+                    .saturating_sub(policy.max_entries)
+                    .max(*oldest)
+            } else {
+                *oldest
+            };
+            let expired = *oldest..first_kept;
+            *oldest = first_kept;
+            (expired, *newest, (*oldest, *newest))
+        };
+
+        entry.cursor = cursor;
+        let (Some(key), Ok(data)) = (entry_key(cursor), serde_json::to_vec(&entry)) else {
+            return;
+        };
+        storage.put(key, data).await;
+        if let (Some(key), Ok(data)) = (range_key(), serde_json::to_vec(&range)) {
+            storage.put(key, data).await;
+        }
+        for cursor in expired {
+            if let Some(key) = entry_key(cursor) {
+                storage.delete(key).await;
+            }
+        }
+    }
+}
+
+/// Appends entries to a journal under a policy; what a VFS records its changes with
+#[derive(Clone)]
+pub(crate) struct JournalWriter {
+    journal: Arc<Journal>,
+    storage: RepoStorage,
+    policy: JournalPolicy,
+}
+
+impl JournalWriter {
+    pub(crate) fn new(journal: Arc<Journal>, storage: RepoStorage, policy: JournalPolicy) -> Self {
+        Self {
+            journal,
+            storage,
+            policy,
+        }
+    }
+
+    pub(crate) async fn record(&self, entry: JournalEntry) {
+        self.journal
+            .append(&self.storage, &self.policy, entry)
+            .await;
+    }
+}
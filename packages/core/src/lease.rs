@@ -0,0 +1,379 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Longest lease the relay grants; longer requests are shortened to this
+pub const MAX_LEASE_TTL: Duration = Duration::from_secs(600);
+
+/// Message a client sends on the relay's `/lease/{space_id}` channel
+///
+/// Each request carries an `id` that the relay echoes in its reply.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum LeaseRequest {
+    /// Take the lease on a path unless another client holds it
+    Acquire { id: u64, path: String, ttl_ms: u64 },
+    /// Extend a lease this client holds
+    Renew { id: u64, path: String, ttl_ms: u64 },
+    /// Give up a lease this client holds
+    Release { id: u64, path: String },
+}
+
+/// Message the relay sends on the `/lease/{space_id}` channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum LeaseReply {
+    /// The lease is held by the requester for `ttl_ms` from now
+    Granted {
+        id: u64,
+        path: String,
+        ttl_ms: u64,
+    },
+    /// Another client holds the lease for at least `expires_in_ms` more
+    Denied {
+        id: u64,
+        path: String,
+        expires_in_ms: u64,
+    },
+    Released {
+        id: u64,
+        path: String,
+    },
+    /// A lease the client held expired before it was renewed
+    Lost {
+        path: String,
+    },
+    Error {
+        #[serde(default)]
+        id: Option<u64>,
+        message: String,
+    },
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+pub use client::Lease;
+#[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+pub(crate) use client::LeaseClient;
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+mod client {
+    use super::{LeaseReply, LeaseRequest, MAX_LEASE_TTL};
+    use crate::error::{Result, VfsError};
+    use futures::{SinkExt, StreamExt};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex, Weak};
+    use std::time::Duration;
+    use tokio::sync::{mpsc, oneshot, watch};
+    use tokio::task::JoinHandle;
+    use tokio_tungstenite::connect_async;
+    use tokio_tungstenite::tungstenite::Message;
+
+    /// URL of a space's lease channel on the relay at `relay_url`
+    ///
+    /// The query of `relay_url` is kept, so a sync token it carries is
+    /// presented to the lease channel as well.
+    pub(super) fn lease_url(relay_url: &str, space_id: &str) -> String {
+        let (base, query) = match relay_url.split_once('?') {
+            Some((base, query)) => (base, Some(query)),
+            None => (relay_url, None),
+        };
+        let url = format!("{}/lease/{}", base.trim_end_matches('/'), space_id);
+        match query {
+            Some(query) => format!("{}?{}", url, query),
+            None => url,
+        }
+    }
+
+    /// A connection to a relay's lease channel, shared by every lease of a `TonkCore`
+    pub(crate) struct LeaseClient {
+        outgoing: mpsc::UnboundedSender<LeaseRequest>,
+        /// Requests waiting for a reply, by id
+        pending: Mutex<HashMap<u64, oneshot::Sender<LeaseReply>>>,
+        /// Whether each held lease has been lost, by path
+        held: Mutex<HashMap<String, watch::Sender<bool>>>,
+        next_id: AtomicU64,
+    }
+
+    impl LeaseClient {
+        /// Open the lease channel of the space `space_id` on the relay at `relay_url`
+        pub(crate) async fn connect(relay_url: &str, space_id: &str) -> Result<Arc<Self>> {
+            let url = lease_url(relay_url, space_id);
+            let (socket, _) = connect_async(&url).await.map_err(|e| {
+                VfsError::WebSocketError(format!("Failed to connect to {url}: {e}"))
+            })?;
+            let (mut sink, mut stream) = socket.split();
+            let (outgoing, mut requests) = mpsc::unbounded_channel();
+
+            let client = Arc::new(Self {
+                outgoing,
+                pending: Mutex::new(HashMap::new()),
+                held: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(1),
+            });
+
+            // The task only holds a weak reference, so it ends once the client is dropped
+            let weak = Arc::downgrade(&client);
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        request = requests.recv() => {
+                            let Some(request) = request else { break };
+                            let Ok(text) = serde_json::to_string(&request) else { continue };
+                            if sink.send(Message::Text(text.into())).await.is_err() {
+                                break;
+                            }
+                        }
+                        message = stream.next() => {
+                            let text = match message {
+                                Some(Ok(Message::Text(text))) => text,
+                                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                                Some(Ok(_)) => continue,
+                            };
+                            match serde_json::from_str::<LeaseReply>(&text) {
+                                Ok(reply) => match weak.upgrade() {
+                                    Some(client) => client.dispatch(reply),
+                                    None => break,
+                                },
+                                Err(e) => tracing::warn!("Invalid lease message: {}", e),
+                            }
+                        }
+                    }
+                }
+                Self::disconnected(&weak);
+            });
+
+            Ok(client)
+        }
+
+        /// Whether the channel to the relay has closed
+        pub(crate) fn is_closed(&self) -> bool {
+            self.outgoing.is_closed()
+        }
+
+        fn dispatch(&self, reply: LeaseReply) {
+            let id = match &reply {
+                LeaseReply::Granted { id, .. }
+                | LeaseReply::Denied { id, .. }
+                | LeaseReply::Released { id, .. } => Some(*id),
+                LeaseReply::Error { id, .. } => *id,
+                LeaseReply::Lost { path } => {
+                    self.mark_lost(path);
+                    None
+                }
+            };
+            let waiter = id.and_then(|id| self.pending.lock().unwrap().remove(&id));
+            if let Some(waiter) = waiter {
+                let _ = waiter.send(reply);
+            }
+        }
+
+        /// Fail outstanding requests and mark every held lease lost
+        fn disconnected(weak: &Weak<Self>) {
+            if let Some(client) = weak.upgrade() {
+                client.pending.lock().unwrap().clear();
+                for (_, lost) in client.held.lock().unwrap().drain() {
+                    let _ = lost.send(true);
+                }
+            }
+        }
+
+        fn mark_lost(&self, path: &str) {
+            if let Some(lost) = self.held.lock().unwrap().remove(path) {
+                let _ = lost.send(true);
+            }
+        }
+
+        /// Send a request and wait for the relay's reply
+        async fn request(&self, request: impl FnOnce(u64) -> LeaseRequest) -> Result<LeaseReply> {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let (tx, rx) = oneshot::channel();
+            self.pending.lock().unwrap().insert(id, tx);
+
+            let closed = || VfsError::WebSocketError("Lease channel closed".to_string());
+            if self.outgoing.send(request(id)).is_err() {
+                self.pending.lock().unwrap().remove(&id);
+                return Err(closed());
+            }
+            match rx.await.map_err(|_| closed())? {
+                LeaseReply::Error { message, .. } => Err(VfsError::WebSocketError(message)),
+                reply => Ok(reply),
+            }
+        }
+
+        /// Take or extend the lease on a path, returning the TTL granted
+        async fn grant(&self, path: &str, ttl: Duration, renew: bool) -> Result<Duration> {
+            let ttl_ms = ttl.min(MAX_LEASE_TTL).as_millis() as u64;
+            let reply = self
+                .request(|id| {
+                    let path = path.to_string();
+                    if renew {
+                        LeaseRequest::Renew { id, path, ttl_ms }
+                    } else {
+                        LeaseRequest::Acquire { id, path, ttl_ms }
+                    }
+                })
+                .await?;
+
+            match reply {
+                LeaseReply::Granted { ttl_ms, .. } => Ok(Duration::from_millis(ttl_ms)),
+                LeaseReply::Denied { expires_in_ms, .. } => Err(VfsError::LeaseUnavailable(
+                    format!("{} (free in {} ms at most)", path, expires_in_ms),
+                )),
+                other => Err(VfsError::WebSocketError(format!(
+                    "Unexpected lease reply: {:?}",
+                    other
+                ))),
+            }
+        }
+
+        /// Acquire the lease on a path, renewing it until the returned handle is dropped
+        ///
+        /// A path this client already holds is unavailable until its `Lease` is dropped.
+        pub(crate) async fn acquire(self: &Arc<Self>, path: &str, ttl: Duration) -> Result<Lease> {
+            if self.held.lock().unwrap().contains_key(path) {
+                return Err(VfsError::LeaseUnavailable(format!(
+                    "{} (held by this instance)",
+                    path
+                )));
+            }
+            let granted = self.grant(path, ttl, false).await?;
+            let (lost_tx, lost) = watch::channel(false);
+            self.held
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), lost_tx.clone());
+
+            // Renew halfway through each term so a slow round trip doesn't lose the lease
+            let client = Arc::clone(self);
+            let renew_path = path.to_string();
+            let renewal = tokio::spawn(async move {
+                let mut term = granted;
+                loop {
+                    tokio::time::sleep(term / 2).await;
+                    if *lost_tx.borrow() {
+                        break;
+                    }
+                    match client.grant(&renew_path, ttl, true).await {
+                        Ok(granted) => term = granted,
+                        Err(e) => {
+                            tracing::warn!("Lost lease on {}: {}", renew_path, e);
+                            client.mark_lost(&renew_path);
+                            let _ = lost_tx.send(true);
+                            break;
+                        }
+                    }
+                }
+            });
+
+            Ok(Lease {
+                path: path.to_string(),
+                client: Arc::clone(self),
+                lost,
+                renewal,
+            })
+        }
+
+        fn release(&self, path: &str) {
+            self.held.lock().unwrap().remove(path);
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let _ = self.outgoing.send(LeaseRequest::Release {
+                id,
+                path: path.to_string(),
+            });
+        }
+    }
+
+    /// An advisory lease on a path, held until it is dropped or lost
+    ///
+    /// The relay arbitrates leases and forgets them when they expire or the
+    /// holder disconnects; nothing is written to the documents. The lease is
+    /// renewed in the background, and [`Lease::lost`] resolves if it expires
+    /// anyway, such as when the relay can't be reached for a whole term.
+    pub struct Lease {
+        path: String,
+        client: Arc<LeaseClient>,
+        lost: watch::Receiver<bool>,
+        renewal: JoinHandle<()>,
+    }
+
+    impl Lease {
+        /// Path the lease is held on
+        pub fn path(&self) -> &str {
+            &self.path
+        }
+
+        /// Whether the lease is still held
+        pub fn is_held(&self) -> bool {
+            !*self.lost.borrow()
+        }
+
+        /// Wait until the lease is lost
+        pub async fn lost(&mut self) {
+            let _ = self.lost.wait_for(|lost| *lost).await;
+        }
+    }
+
+    impl Drop for Lease {
+        fn drop(&mut self) {
+            self.renewal.abort();
+            if self.is_held() {
+                self.client.release(&self.path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_message_format() {
+        let request = LeaseRequest::Acquire {
+            id: 1,
+            path: "/scheduler".to_string(),
+            ttl_ms: 30_000,
+        };
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({"type": "acquire", "id": 1, "path": "/scheduler", "ttlMs": 30000})
+        );
+
+        let reply: LeaseReply = serde_json::from_str(
+            r#"{"type": "denied", "id": 1, "path": "/scheduler", "expiresInMs": 500}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            reply,
+            LeaseReply::Denied {
+                id: 1,
+                path: "/scheduler".to_string(),
+                expires_in_ms: 500
+            }
+        );
+        let error: LeaseReply =
+            serde_json::from_str(r#"{"type": "error", "message": "Invalid request"}"#).unwrap();
+        assert!(matches!(error, LeaseReply::Error { id: None, .. }));
+    }
+
+    #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+    #[test]
+    fn test_lease_url_keeps_the_token() {
+        assert_eq!(
+            client::lease_url("ws://relay:8081/", "abc"),
+            "ws://relay:8081/lease/abc"
+        );
+        assert_eq!(
+            client::lease_url("ws://relay:8081/?token=secret", "abc"),
+            "ws://relay:8081/lease/abc?token=secret"
+        );
+    }
+}
@@ -1,16 +1,57 @@
+#[cfg(target_arch = "wasm32")]
+pub mod browser_storage;
+#[cfg(feature = "bundle")]
 pub mod bundle;
+pub mod capabilities;
+pub mod compaction;
 pub mod error;
+pub mod eviction;
+pub mod gc;
+pub mod identity;
+pub mod import;
+pub mod journal;
+pub mod lease;
+pub mod outbox;
+pub mod profile;
+pub mod sync_status;
+pub mod telemetry;
 pub mod tonk_core;
 pub mod vfs;
+#[cfg(any(target_arch = "wasm32", feature = "websocket"))]
 pub mod websocket;
 
-pub use bundle::{Bundle, BundlePath};
+#[cfg(target_arch = "wasm32")]
+pub use browser_storage::StorageEstimate;
+#[cfg(feature = "bundle")]
+pub use bundle::{Bundle, BundlePath, NamedRoot};
+pub use capabilities::{Capabilities, PeerInfo, ProtocolVersions};
+pub use compaction::{CompactionPolicy, CompactionReport, CompactionStats};
+pub use eviction::StorageEviction;
+pub use gc::{GcReport, DEFAULT_GC_GRACE};
+pub use identity::Identity;
+pub use import::{ImportLimits, ImportProgress, ImportProgressCallback};
+pub use journal::{JournalEntry, JournalOp, JournalPolicy};
+#[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+pub use lease::Lease;
+pub use outbox::{OutboxFlush, OutboxStatus, ReconnectPolicy};
+pub use profile::{SpaceProfile, PROFILE_PATH};
+pub use sync_status::{ConnectionHandle, SyncStatus};
 #[cfg(target_arch = "wasm32")]
 pub use tonk_core::ConnectionState;
 pub use tonk_core::{StorageConfig, TonkCore, TonkCoreBuilder};
 pub use vfs::{
-    DirNode, DocNode, DocumentWatcher, NodeType, RefNode, Timestamps, VfsEvent, VirtualFileSystem,
+    BatchWatcher, ConflictPolicy, ConflictValue, ContentPatch, CopyAction, CopyOperation,
+    CopyOptions, DirNode, DirectoryStats, DocNode, DocumentWatcher, EventChannelConfig,
+    EventChannelStats, EventReceiver, HistoryEntry, JsonSchema, ListOptions, ListPage, Mount,
+    MountSource, NodeType, OverflowPolicy, PatchKind, RefNode, SortBy, SortOrder, Throttle,
+    ThrottledEvents, Timestamps, TrashEntry, TraversalLimits, TraversalProgress,
+    TraversalProgressCallback, Validator, VfsEvent, VfsStats, VirtualFileSystem,
+    NODE_SCHEMA_VERSION,
 };
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
 pub mod wasm;
+
+// Browser instances restore their root from the manifest of the last bundle loaded
+#[cfg(all(target_arch = "wasm32", not(feature = "bundle")))]
+compile_error!("tonk-core needs the `bundle` feature when built for wasm32");
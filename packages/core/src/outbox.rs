@@ -0,0 +1,372 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+
+/// Number of flush events buffered for slow subscribers
+const FLUSH_CHANNEL_CAPACITY: usize = 16;
+
+/// How a dropped connection is retried
+///
+/// The first retry waits `initial_delay_ms`, and each later one twice as long as
+/// the one before, up to `max_delay_ms`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReconnectPolicy {
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Give up after this many consecutive failed attempts (`None` retries forever)
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 1_000,
+            max_delay_ms: 30_000,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before retry number `attempt` (counting from 0), or `None` to give up
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+        let delay = self
+            .initial_delay_ms
+            .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX))
+            .min(self.max_delay_ms);
+        Some(Duration::from_millis(delay))
+    }
+}
+
+/// Local writes waiting to reach a peer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxStatus {
+    /// Documents written that no peer has acknowledged yet, plus writes whose paths were missed
+    pub pending_changes: usize,
+    /// Paths of the documents written that no peer has acknowledged yet
+    pub pending_paths: Vec<String>,
+    /// Unix time in milliseconds at which the outbox was last flushed
+    pub last_sync_at: Option<i64>,
+    /// Reconnect attempts made since the connection was last ready
+    pub reconnect_attempts: u32,
+}
+
+/// Sent once a peer has acknowledged writes in the outbox
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxFlush {
+    /// Pending changes that were synced
+    pub flushed_changes: usize,
+    /// Unix time in milliseconds of the flush
+    pub synced_at: i64,
+}
+
+/// What is kept in storage of the outbox, so pending writes survive a reload
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct OutboxRecord {
+    pub pending_paths: Vec<String>,
+    pub missed: usize,
+    pub last_sync_at: Option<i64>,
+}
+
+/// Writes no peer has acknowledged yet, each with the generation of its latest write
+#[derive(Debug, Default)]
+struct Pending {
+    paths: BTreeMap<String, u64>,
+    /// Writes whose events were dropped because the event channel lagged
+    missed: usize,
+    /// Generation of the latest missed write
+    missed_at: u64,
+    /// Incremented on every write, so a flush only covers writes made before
+    /// the sync it waited for
+    generation: u64,
+}
+
+/// Tracks writes until a peer acknowledges them, and how the connection is retried
+#[derive(Debug)]
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+pub(crate) struct Outbox {
+    pending: Mutex<Pending>,
+    /// Unix time in milliseconds of the last flush, 0 if there hasn't been one
+    last_sync_at: AtomicI64,
+    reconnect_attempts: AtomicU32,
+    reconnect: Mutex<Option<ReconnectPolicy>>,
+    online_listener: AtomicBool,
+    /// Wakes the task confirming writes with the peer
+    flush_requested: Notify,
+    flushes: broadcast::Sender<OutboxFlush>,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self {
+            pending: Mutex::default(),
+            last_sync_at: AtomicI64::new(0),
+            reconnect_attempts: AtomicU32::new(0),
+            reconnect: Mutex::new(Some(ReconnectPolicy::default())),
+            online_listener: AtomicBool::new(false),
+            flush_requested: Notify::new(),
+            flushes: broadcast::channel(FLUSH_CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+impl Outbox {
+    /// An outbox holding the writes of a stored record, all still pending
+    pub(crate) fn restore(record: OutboxRecord) -> Self {
+        let outbox = Self::default();
+        {
+            let mut pending = outbox.pending.lock().unwrap();
+            pending.generation = 1;
+            pending.paths = record
+                .pending_paths
+                .into_iter()
+                .map(|path| (path, 1))
+                .collect();
+            pending.missed = record.missed;
+            pending.missed_at = 1;
+        }
+        outbox
+            .last_sync_at
+            .store(record.last_sync_at.unwrap_or(0), Ordering::Relaxed);
+        outbox
+    }
+
+    /// What to store of the outbox
+    pub(crate) fn record(&self) -> OutboxRecord {
+        let pending = self.pending.lock().unwrap();
+        let last_sync_at = self.last_sync_at.load(Ordering::Relaxed);
+        OutboxRecord {
+            pending_paths: pending.paths.keys().cloned().collect(),
+            missed: pending.missed,
+            last_sync_at: (last_sync_at > 0).then_some(last_sync_at),
+        }
+    }
+
+    /// Record a write to `path`, pending until a peer acknowledges it
+    pub(crate) fn record_write(&self, path: &str) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.generation += 1;
+        let generation = pending.generation;
+        pending.paths.insert(path.to_string(), generation);
+        drop(pending);
+        self.flush_requested.notify_one();
+    }
+
+    /// Record writes whose paths aren't known
+    pub(crate) fn record_missed(&self, count: usize) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.generation += 1;
+        pending.missed += count;
+        pending.missed_at = pending.generation;
+        drop(pending);
+        self.flush_requested.notify_one();
+    }
+
+    /// Generation of the latest write, to pass to [`Self::flushed_through`]
+    /// once a sync that started after it has finished
+    pub(crate) fn generation(&self) -> u64 {
+        self.pending.lock().unwrap().generation
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        let pending = self.pending.lock().unwrap();
+        pending.paths.is_empty() && pending.missed == 0
+    }
+
+    /// Mark the writes up to `generation` as synced and notify subscribers
+    ///
+    /// Writes made since stay pending. Returns `None`, and sends nothing, if
+    /// there was nothing to flush.
+    pub(crate) fn flushed_through(&self, generation: u64) -> Option<OutboxFlush> {
+        let mut pending = self.pending.lock().unwrap();
+        let before = pending.paths.len();
+        pending.paths.retain(|_, written| *written > generation);
+        let mut flushed_changes = before - pending.paths.len();
+        if pending.missed_at <= generation {
+            flushed_changes += std::mem::take(&mut pending.missed);
+        }
+        drop(pending);
+        if flushed_changes == 0 {
+            return None;
+        }
+
+        let flush = OutboxFlush {
+            flushed_changes,
+            synced_at: chrono::Utc::now().timestamp_millis(),
+        };
+        self.last_sync_at.store(flush.synced_at, Ordering::Relaxed);
+        let _ = self.flushes.send(flush.clone());
+        Some(flush)
+    }
+
+    /// Wake the task that confirms pending writes, such as when a peer connects
+    pub(crate) fn request_flush(&self) {
+        self.flush_requested.notify_one();
+    }
+
+    /// Wait until a write is recorded or a flush is requested
+    pub(crate) async fn flush_requested(&self) {
+        self.flush_requested.notified().await;
+    }
+
+    pub(crate) fn status(&self) -> OutboxStatus {
+        let record = self.record();
+        OutboxStatus {
+            pending_changes: record.pending_paths.len() + record.missed,
+            pending_paths: record.pending_paths,
+            last_sync_at: record.last_sync_at,
+            reconnect_attempts: self.reconnect_attempts.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<OutboxFlush> {
+        self.flushes.subscribe()
+    }
+
+    pub(crate) fn reconnect_policy(&self) -> Option<ReconnectPolicy> {
+        self.reconnect.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_reconnect_policy(&self, policy: Option<ReconnectPolicy>) {
+        *self.reconnect.lock().unwrap() = policy;
+    }
+
+    /// Delay before the next reconnect attempt, counting it, or `None` to stay disconnected
+    pub(crate) fn next_reconnect_delay(&self) -> Option<Duration> {
+        let policy = self.reconnect_policy()?;
+        let attempt = self.reconnect_attempts.load(Ordering::Relaxed);
+        let delay = policy.delay(attempt)?;
+        self.reconnect_attempts.fetch_add(1, Ordering::Relaxed);
+        Some(delay)
+    }
+
+    pub(crate) fn reset_reconnect_attempts(&self) {
+        self.reconnect_attempts.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns true the first time it's called, so a listener is only installed once
+    pub(crate) fn claim_online_listener(&self) -> bool {
+        !self.online_listener.swap(true, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gc::RepoStorage;
+    use samod::storage::InMemoryStorage;
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let policy = ReconnectPolicy {
+            initial_delay_ms: 100,
+            max_delay_ms: 1_000,
+            max_attempts: Some(6),
+        };
+        let delays: Vec<_> = (0..7).map(|attempt| policy.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1_000, 1_000]
+                .into_iter()
+                .map(|ms| Some(Duration::from_millis(ms)))
+                .chain([None])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ReconnectPolicy::default().delay(200),
+            Some(Duration::from_secs(30))
+        );
+
+        let outbox = Outbox::default();
+        outbox.set_reconnect_policy(Some(policy));
+        assert_eq!(
+            outbox.next_reconnect_delay(),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            outbox.next_reconnect_delay(),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(outbox.status().reconnect_attempts, 2);
+        outbox.reset_reconnect_attempts();
+        assert_eq!(
+            outbox.next_reconnect_delay(),
+            Some(Duration::from_millis(100))
+        );
+
+        outbox.set_reconnect_policy(None);
+        assert_eq!(outbox.next_reconnect_delay(), None);
+    }
+
+    #[test]
+    fn test_outbox_flush() {
+        let outbox = Outbox::default();
+        let mut flushes = outbox.subscribe();
+        assert_eq!(outbox.status(), OutboxStatus::default());
+        assert_eq!(outbox.flushed_through(outbox.generation()), None);
+
+        outbox.record_write("/b");
+        outbox.record_write("/a");
+        outbox.record_write("/a");
+        outbox.record_missed(2);
+        let status = outbox.status();
+        assert_eq!(status.pending_changes, 4);
+        assert_eq!(status.pending_paths, vec!["/a", "/b"]);
+
+        let flush = outbox.flushed_through(outbox.generation()).unwrap();
+        assert_eq!(flush.flushed_changes, 4);
+        assert_eq!(flushes.try_recv().unwrap(), flush);
+
+        let status = outbox.status();
+        assert_eq!(status.pending_changes, 0);
+        assert_eq!(status.last_sync_at, Some(flush.synced_at));
+    }
+
+    #[test]
+    fn test_writes_during_a_sync_stay_pending() {
+        let outbox = Outbox::default();
+        outbox.record_write("/a");
+        outbox.record_write("/b");
+        let synced = outbox.generation();
+
+        // Written again while the peer was catching up with the first writes
+        outbox.record_write("/b");
+        outbox.record_missed(1);
+
+        let flush = outbox.flushed_through(synced).unwrap();
+        assert_eq!(flush.flushed_changes, 1);
+        let status = outbox.status();
+        assert_eq!(status.pending_paths, vec!["/b"]);
+        assert_eq!(status.pending_changes, 2);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_survives_a_reload() {
+        let root = crate::TonkCore::new().await.unwrap().vfs().root_id();
+        let storage = RepoStorage::InMemory(InMemoryStorage::new());
+        assert_eq!(storage.outbox_record(&root).await, OutboxRecord::default());
+
+        let outbox = Outbox::default();
+        outbox.record_write("/a");
+        outbox.record_missed(3);
+        storage.put_outbox_record(&root, &outbox.record()).await;
+
+        let restored = Outbox::restore(storage.outbox_record(&root).await);
+        assert_eq!(restored.status(), outbox.status());
+        let flush = restored.flushed_through(restored.generation()).unwrap();
+        assert_eq!(flush.flushed_changes, 4);
+        assert!(restored.is_empty());
+        assert_eq!(restored.record().last_sync_at, Some(flush.synced_at));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::error::Result;
+use crate::vfs::{read_doc_node, VirtualFileSystem};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+/// Path of the document describing the space
+pub const PROFILE_PATH: &str = "/.tonk/profile";
+
+/// Human-readable description of a space
+///
+/// Stored at [`PROFILE_PATH`] so the space can be identified by more than its
+/// root document ID. The icon is kept as the document's bytes, the other fields
+/// as its content.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpaceProfile {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Icon image, in whatever format `icon_type` names
+    #[serde(default)]
+    pub icon: Option<Vec<u8>>,
+    /// MIME type of the icon, such as `image/png`
+    #[serde(default)]
+    pub icon_type: Option<String>,
+    /// DID of whoever created the space
+    #[serde(default)]
+    pub created_by: Option<String>,
+}
+
+/// The profile fields stored as document content
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileContent {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    icon_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_by: Option<String>,
+}
+
+/// Read the profile of the space, if one has been set
+pub(crate) async fn read_profile(vfs: &VirtualFileSystem) -> Result<Option<SpaceProfile>> {
+    let Some(handle) = vfs.find_document(PROFILE_PATH).await? else {
+        return Ok(None);
+    };
+
+    let node = read_doc_node(&handle)?;
+    let content: ProfileContent = serde_json::from_value(node.content)?;
+    Ok(Some(SpaceProfile {
+        name: content.name,
+        description: content.description,
+        icon: node.bytes.filter(|bytes| !bytes.is_empty()),
+        icon_type: content.icon_type,
+        created_by: content.created_by,
+    }))
+}
+
+/// Create or replace the profile of the space
+pub(crate) async fn write_profile(vfs: &VirtualFileSystem, profile: &SpaceProfile) -> Result<()> {
+    let content = ProfileContent {
+        name: profile.name.clone(),
+        description: profile.description.clone(),
+        icon_type: profile.icon_type.clone(),
+        created_by: profile.created_by.clone(),
+    };
+    // Always store bytes, empty when there is no icon, so replacing a profile
+    // clears an icon it no longer has
+    let icon = Bytes::from(profile.icon.clone().unwrap_or_default());
+
+    if !vfs
+        .set_document_with_bytes(PROFILE_PATH, content.clone(), icon.clone())
+        .await?
+    {
+        vfs.create_document_with_bytes(PROFILE_PATH, content, icon)
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,293 @@
+use crate::capabilities::PeerInfo;
+use automerge::ChangeHash;
+use samod::DocHandle;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Time without sync traffic after which a connection counts as settled
+pub const SYNC_SETTLE_TIME: Duration = Duration::from_millis(250);
+
+/// How often waits for sync re-check the status
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Snapshot of sync activity across all connections
+///
+/// Message and byte counts cover native WebSocket connections. Browser
+/// connections are opened by samod, so their traffic isn't visible and only
+/// `connections` and `docs_pending` reflect them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    /// Open connections to peers
+    pub connections: usize,
+    /// Documents being looked up that aren't available yet
+    pub docs_pending: usize,
+    /// Bytes handed to a connection but not yet flushed to the network
+    pub bytes_in_flight: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Milliseconds since the last sync activity
+    pub idle_ms: u64,
+}
+
+impl SyncStatus {
+    /// Whether a peer is connected and nothing has been exchanged for [`SYNC_SETTLE_TIME`]
+    pub fn is_settled(&self) -> bool {
+        self.connections > 0
+            && self.docs_pending == 0
+            && self.bytes_in_flight == 0
+            && self.idle_ms >= SYNC_SETTLE_TIME.as_millis() as u64
+    }
+}
+
+/// An open native connection to a relay, as listed by `TonkCore::connections`
+///
+/// Browser connections are opened by samod and can't read the relay's
+/// response headers, so they aren't listed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionHandle {
+    id: u64,
+    url: String,
+    peer: Option<PeerInfo>,
+}
+
+impl ConnectionHandle {
+    /// URL the connection was opened with
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// What the relay said about itself when accepting the connection, if it did
+    pub fn peer(&self) -> Option<&PeerInfo> {
+        self.peer.as_ref()
+    }
+}
+
+/// Whether some peer shares `handle` and every peer sharing it has
+/// acknowledged the document's current heads
+///
+/// Equal heads mean each side has the other's changes, so nothing is left to
+/// send either way.
+pub(crate) fn peers_have_caught_up(handle: &DocHandle) -> bool {
+    let heads = handle.with_document(|doc| doc.get_heads());
+    let (peers, _) = handle.peers();
+    heads_acknowledged(
+        &heads,
+        peers.values().map(|peer| peer.last_acked_heads.as_deref()),
+    )
+}
+
+/// Whether there is at least one peer and each acknowledged exactly `heads`
+fn heads_acknowledged<'a>(
+    heads: &[ChangeHash],
+    acked: impl IntoIterator<Item = Option<&'a [ChangeHash]>>,
+) -> bool {
+    let heads: HashSet<&ChangeHash> = heads.iter().collect();
+    let mut peers = 0;
+    for acked in acked {
+        peers += 1;
+        match acked {
+            Some(acked) if acked.iter().collect::<HashSet<_>>() == heads => {}
+            _ => return false,
+        }
+    }
+    peers > 0
+}
+
+/// Counts sync traffic and outstanding document lookups
+#[derive(Debug, Default)]
+pub(crate) struct SyncTracker {
+    connections: AtomicUsize,
+    docs_pending: AtomicUsize,
+    bytes_in_flight: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    /// Unix time in milliseconds of the last activity
+    last_activity: AtomicI64,
+    next_connection_id: AtomicU64,
+    open: Mutex<Vec<ConnectionHandle>>,
+}
+
+impl SyncTracker {
+    fn touch(&self) {
+        self.last_activity
+            .store(chrono::Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "websocket"), allow(dead_code))]
+    pub(crate) fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+    }
+
+    #[cfg_attr(not(feature = "websocket"), allow(dead_code))]
+    pub(crate) fn connection_closed(&self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// List a connection to `url` as open until [`Self::unregister`] is called with the returned ID
+    #[cfg_attr(
+        any(target_arch = "wasm32", not(feature = "websocket")),
+        allow(dead_code)
+    )]
+    pub(crate) fn register(&self, url: &str, peer: Option<PeerInfo>) -> u64 {
+        let id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        self.open.lock().unwrap().push(ConnectionHandle {
+            id,
+            url: url.to_string(),
+            peer,
+        });
+        id
+    }
+
+    #[cfg_attr(
+        any(target_arch = "wasm32", not(feature = "websocket")),
+        allow(dead_code)
+    )]
+    pub(crate) fn unregister(&self, id: u64) {
+        self.open.lock().unwrap().retain(|handle| handle.id != id);
+    }
+
+    /// Connections listed by [`Self::register`] that are still open
+    pub(crate) fn connections(&self) -> Vec<ConnectionHandle> {
+        self.open.lock().unwrap().clone()
+    }
+
+    /// Record a message handed to a connection, unflushed until [`Self::flushed`]
+    #[cfg_attr(
+        any(target_arch = "wasm32", not(feature = "websocket")),
+        allow(dead_code)
+    )]
+    pub(crate) fn sent(&self, bytes: u64) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+        self.bytes_in_flight.fetch_add(bytes, Ordering::Relaxed);
+        self.touch();
+    }
+
+    #[cfg_attr(
+        any(target_arch = "wasm32", not(feature = "websocket")),
+        allow(dead_code)
+    )]
+    pub(crate) fn flushed(&self, bytes: u64) {
+        self.bytes_in_flight.fetch_sub(bytes, Ordering::Relaxed);
+        self.touch();
+    }
+
+    #[cfg_attr(
+        any(target_arch = "wasm32", not(feature = "websocket")),
+        allow(dead_code)
+    )]
+    pub(crate) fn received(&self, bytes: u64) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Run a document lookup, counting the document as pending until it finishes
+    pub(crate) async fn lookup<F: Future>(&self, lookup: F) -> F::Output {
+        struct Pending<'a>(&'a SyncTracker);
+
+        impl Drop for Pending<'_> {
+            fn drop(&mut self) {
+                self.0.docs_pending.fetch_sub(1, Ordering::Relaxed);
+                self.0.touch();
+            }
+        }
+
+        self.docs_pending.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+        let _pending = Pending(self);
+        lookup.await
+    }
+
+    pub(crate) fn status(&self) -> SyncStatus {
+        let idle_ms =
+            chrono::Utc::now().timestamp_millis() - self.last_activity.load(Ordering::Relaxed);
+        SyncStatus {
+            connections: self.connections.load(Ordering::Relaxed),
+            docs_pending: self.docs_pending.load(Ordering::Relaxed),
+            bytes_in_flight: self.bytes_in_flight.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            idle_ms: idle_ms.max(0) as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::watcher::sleep;
+
+    #[test]
+    fn test_heads_must_match_every_peer() {
+        let a = ChangeHash([1; 32]);
+        let b = ChangeHash([2; 32]);
+        let heads = [a, b];
+
+        assert!(!heads_acknowledged(&heads, []));
+        assert!(heads_acknowledged(&heads, [Some(&[b, a][..])]));
+        assert!(!heads_acknowledged(&heads, [Some(&[a][..])]));
+        assert!(!heads_acknowledged(&heads, [Some(&heads[..]), None]));
+        assert!(!heads_acknowledged(
+            &heads,
+            [Some(&heads[..]), Some(&[a, b, ChangeHash([3; 32])][..])]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sync_status_settles() {
+        let tracker = SyncTracker::default();
+        assert!(!tracker.status().is_settled());
+
+        tracker.connection_opened();
+        tracker.sent(100);
+        tracker.received(40);
+        let status = tracker.status();
+        assert_eq!(status.connections, 1);
+        assert_eq!(status.bytes_in_flight, 100);
+        assert_eq!(status.bytes_sent, 100);
+        assert_eq!(status.bytes_received, 40);
+        assert!(!status.is_settled());
+
+        tracker.flushed(100);
+        tracker.lookup(async {}).await;
+        assert_eq!(tracker.status().docs_pending, 0);
+        sleep(SYNC_SETTLE_TIME + Duration::from_millis(50)).await;
+        assert!(tracker.status().is_settled());
+    }
+
+    #[test]
+    fn test_connections_are_listed_until_unregistered() {
+        let tracker = SyncTracker::default();
+        let relay = PeerInfo {
+            name: "tonk-relay".to_string(),
+            ..PeerInfo::default()
+        };
+        let first = tracker.register("ws://a.example", Some(relay.clone()));
+        let second = tracker.register("ws://b.example", None);
+
+        let connections = tracker.connections();
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].url(), "ws://a.example");
+        assert_eq!(connections[0].peer(), Some(&relay));
+        assert_eq!(connections[1].peer(), None);
+
+        tracker.unregister(first);
+        assert_eq!(tracker.connections()[0].url(), "ws://b.example");
+        tracker.unregister(second);
+        assert!(tracker.connections().is_empty());
+    }
+}
@@ -0,0 +1,23 @@
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::Registry;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// A tracing layer that receives tonk-core's spans and events
+///
+/// Embedders pass one to `TonkCoreBuilder::with_tracing_layer` to forward VFS,
+/// sync connection and document spans to their own logging pipeline.
+pub type TracingLayer = Box<dyn Layer<Registry> + Send + Sync + 'static>;
+
+/// Install `layer` as the global tracing subscriber
+///
+/// Events are filtered by `RUST_LOG`, defaulting to `info`. Returns false if a
+/// global subscriber was already installed, in which case tonk-core's spans go
+/// to that subscriber instead.
+pub fn install_tracing_layer(layer: TracingLayer) -> bool {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::registry()
+        .with(layer.with_filter(filter))
+        .try_init()
+        .is_ok()
+}
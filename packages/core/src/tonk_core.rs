@@ -1,20 +1,63 @@
-use crate::bundle::BundleConfig;
+#[cfg(target_arch = "wasm32")]
+use crate::browser_storage::{self, StorageEstimate};
+#[cfg(feature = "bundle")]
+use crate::bundle::{
+    try_lock_file, BundleConfig, BundleStreamReader, Manifest, NamedRoot, VerifyingKey,
+};
+use crate::capabilities::Capabilities;
+use crate::compaction::{
+    compact_document, Compaction, CompactionPolicy, CompactionReport, CompactionStats,
+};
 use crate::error::{Result, VfsError};
-use crate::vfs::VirtualFileSystem;
+use crate::eviction::{EvictionMonitor, StorageEviction};
+use crate::gc::{GcReport, RepoStorage};
+use crate::identity::{Author, SigningKey};
+#[cfg(feature = "bundle")]
+use crate::import::{ImportLimits, ImportProgressCallback, ImportTracker};
+use crate::journal::{Journal, JournalEntry, JournalPolicy, JournalWriter};
+#[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+use crate::lease::{Lease, LeaseClient};
+#[cfg(target_arch = "wasm32")]
+use crate::outbox::{Outbox, OutboxFlush, OutboxStatus, ReconnectPolicy};
+use crate::profile::{self, SpaceProfile};
+#[cfg(target_arch = "wasm32")]
+use crate::sync_status::SyncTracker;
+use crate::sync_status::{ConnectionHandle, SyncStatus};
+use crate::telemetry::{install_tracing_layer, TracingLayer};
+use crate::vfs::mount::{normalize_mount_point, MountRecord};
+use crate::vfs::types::DocNode;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::vfs::{CopyOperation, CopyOptions};
+use crate::vfs::{
+    EventChannelConfig, Mount, MountSource, TraversalLimits, VirtualFileSystem,
+    DEFAULT_MODIFIED_PROPAGATION_WINDOW,
+};
+#[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+use crate::websocket::FramingConfig;
+#[cfg(feature = "bundle")]
 use crate::Bundle;
+#[cfg(feature = "bundle")]
+use futures::{Stream, StreamExt};
 use rand::rng;
+use samod::storage::InMemoryStorage;
+#[cfg(feature = "bundle")]
+use samod::storage::StorageKey;
 #[cfg(not(target_arch = "wasm32"))]
 use samod::storage::TokioFilesystemStorage as FilesystemStorage;
-use samod::storage::{InMemoryStorage, StorageKey};
 #[cfg(target_arch = "wasm32")]
 use samod::storage::{IndexedDbStorage, LocalStorage};
 #[cfg(not(target_arch = "wasm32"))]
 use samod::RepoBuilder;
 use samod::{DocHandle, DocumentId, PeerId, Repo};
+#[cfg(feature = "bundle")]
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 #[cfg(not(target_arch = "wasm32"))]
 use std::path::PathBuf;
 use std::sync::Arc;
-#[cfg(target_arch = "wasm32")]
+use std::time::Duration;
+#[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+use tokio::sync::Mutex;
 use tokio::sync::RwLock;
 use tracing::info;
 
@@ -36,6 +79,24 @@ pub enum StorageConfig {
 pub struct TonkCoreBuilder {
     peer_id: Option<PeerId>,
     storage_config: StorageConfig,
+    #[cfg(feature = "bundle")]
+    import_limits: ImportLimits,
+    #[cfg(feature = "bundle")]
+    on_import_progress: Option<ImportProgressCallback>,
+    #[cfg(feature = "bundle")]
+    trusted_keys: Vec<VerifyingKey>,
+    quota: Option<u64>,
+    delta_events: bool,
+    traversal_limits: TraversalLimits,
+    modified_propagation: Option<Duration>,
+    /// Operator's key and this device's name, when changes are authored as an identity
+    identity: Option<(SigningKey, String)>,
+    event_channel: EventChannelConfig,
+    tracing_layer: Option<TracingLayer>,
+    #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+    framing: Option<FramingConfig>,
+    /// Whether the storage was declared to hold only this space's documents
+    exclusive_storage: bool,
 }
 
 impl TonkCoreBuilder {
@@ -44,6 +105,22 @@ impl TonkCoreBuilder {
         Self {
             peer_id: None,
             storage_config: StorageConfig::InMemory,
+            #[cfg(feature = "bundle")]
+            import_limits: ImportLimits::default(),
+            #[cfg(feature = "bundle")]
+            on_import_progress: None,
+            #[cfg(feature = "bundle")]
+            trusted_keys: Vec::new(),
+            quota: None,
+            delta_events: false,
+            traversal_limits: TraversalLimits::default(),
+            modified_propagation: Some(DEFAULT_MODIFIED_PROPAGATION_WINDOW),
+            identity: None,
+            event_channel: EventChannelConfig::default(),
+            tracing_layer: None,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+            framing: None,
+            exclusive_storage: false,
         }
     }
 
@@ -59,8 +136,140 @@ impl TonkCoreBuilder {
         self
     }
 
+    /// Declare that no other space writes to the configured storage
+    ///
+    /// `TonkCore::gc` deletes every stored document the space doesn't reach, so
+    /// it refuses to run on storage other spaces may share: a filesystem
+    /// directory, or IndexedDB without a namespace, unless declared here.
+    /// In-memory storage and namespaced IndexedDB are never shared.
+    pub fn with_exclusive_storage(mut self) -> Self {
+        self.exclusive_storage = true;
+        self
+    }
+
+    /// Whether only this space writes to the configured storage
+    fn storage_is_exclusive(&self) -> bool {
+        self.exclusive_storage
+            || match &self.storage_config {
+                StorageConfig::InMemory => true,
+                #[cfg(not(target_arch = "wasm32"))]
+                StorageConfig::Filesystem(_) => false,
+                #[cfg(target_arch = "wasm32")]
+                StorageConfig::IndexedDB { namespace } => namespace.is_some(),
+            }
+    }
+
+    /// Set limits applied when loading from a bundle (defaults to unlimited)
+    #[cfg(feature = "bundle")]
+    pub fn with_import_limits(mut self, limits: ImportLimits) -> Self {
+        self.import_limits = limits;
+        self
+    }
+
+    /// Set a callback that receives progress and memory diagnostics while loading a bundle
+    #[cfg(feature = "bundle")]
+    pub fn with_import_progress(mut self, callback: ImportProgressCallback) -> Self {
+        self.on_import_progress = Some(callback);
+        self
+    }
+
+    /// Only load bundles whose content index is signed by one of these keys
+    #[cfg(feature = "bundle")]
+    pub fn with_trusted_keys(mut self, keys: Vec<VerifyingKey>) -> Self {
+        self.trusted_keys = keys;
+        self
+    }
+
+    /// Cap the stored size of the space; document writes that would go past it fail with `QuotaExceeded`
+    pub fn with_quota(mut self, bytes: u64) -> Self {
+        self.quota = Some(bytes);
+        self
+    }
+
+    /// Include the changed content paths in `VfsEvent::DocumentUpdated` events
+    pub fn with_delta_events(mut self) -> Self {
+        self.delta_events = true;
+        self
+    }
+
+    /// Set the depth and breadth limits applied when walking the directory tree
+    pub fn with_traversal_limits(mut self, limits: TraversalLimits) -> Self {
+        self.traversal_limits = limits;
+        self
+    }
+
+    /// Set the window within which writes update ancestor directories' modified times once
+    ///
+    /// `None` stops writes from updating ancestors' modified times at all.
+    pub fn with_modified_propagation(mut self, window: Option<Duration>) -> Self {
+        self.modified_propagation = window;
+        self
+    }
+
+    /// Author changes as `device` of the operator holding `key`, rather than under a random
+    /// actor ID
+    ///
+    /// The operator's DID is the `did:key` of `key`. Document history then records who made
+    /// each change; see [`crate::identity::Identity`].
+    pub fn with_identity(mut self, key: SigningKey, device: impl Into<String>) -> Self {
+        self.identity = Some((key, device.into()));
+        self
+    }
+
+    /// The author changes are written as, bound to the install `storage` belongs to
+    async fn author(
+        identity: &Option<(SigningKey, String)>,
+        storage: &RepoStorage,
+    ) -> Option<Author> {
+        match identity {
+            Some((key, device)) => Some(Author::new(
+                key,
+                device.clone(),
+                &storage.install_id().await,
+            )),
+            None => None,
+        }
+    }
+
+    /// Set the VFS event buffer size and what happens when a subscriber overflows it
+    pub fn with_event_channel(mut self, config: EventChannelConfig) -> Self {
+        self.event_channel = config;
+        self
+    }
+
+    /// Install a tracing layer to receive tonk-core's spans and events
+    ///
+    /// The layer becomes the global subscriber when the instance is built. If the
+    /// embedder has already installed a global subscriber, the layer is ignored
+    /// and spans go to the existing subscriber.
+    pub fn with_tracing_layer(mut self, layer: TracingLayer) -> Self {
+        self.tracing_layer = Some(layer);
+        self
+    }
+
+    /// Split large sync messages into frames on WebSocket connections
+    ///
+    /// Only used with peers that support chunked framing; others are sent whole
+    /// messages. Browser connections are opened by samod and always send whole
+    /// messages.
+    #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+    pub fn with_framing(mut self, framing: FramingConfig) -> Self {
+        self.framing = Some(framing);
+        self
+    }
+
+    fn install_tracing(&mut self) {
+        if let Some(layer) = self.tracing_layer.take() {
+            if !install_tracing_layer(layer) {
+                tracing::warn!("A global tracing subscriber is already installed");
+            }
+        }
+    }
+
     /// Create a new TonkCore instance with the configured settings
-    pub async fn build(self) -> Result<TonkCore> {
+    pub async fn build(mut self) -> Result<TonkCore> {
+        self.install_tracing();
+        let exclusive_storage = self.storage_is_exclusive();
         let peer_id = self.peer_id.unwrap_or_else(|| {
             let mut rng = rng();
             PeerId::new_with_rng(&mut rng)
@@ -69,50 +278,75 @@ impl TonkCoreBuilder {
         #[cfg(not(target_arch = "wasm32"))]
         {
             let runtime = tokio::runtime::Handle::current();
-            let samod = match self.storage_config {
+            let (samod, storage) = match self.storage_config {
                 StorageConfig::InMemory => {
                     let storage = InMemoryStorage::new();
-                    RepoBuilder::new(runtime)
-                        .with_storage(storage)
+                    let samod = RepoBuilder::new(runtime)
+                        .with_storage(storage.clone())
                         .with_peer_id(peer_id)
-                        .with_concurrency(samod::ConcurrencyConfig::Threadpool(
-                            rayon::ThreadPoolBuilder::new().build().unwrap(),
-                        ))
+                        .with_concurrency(concurrency())
                         .load()
-                        .await
+                        .await;
+                    (samod, RepoStorage::InMemory(storage))
                 }
                 StorageConfig::Filesystem(path) => {
                     std::fs::create_dir_all(&path).map_err(VfsError::IoError)?;
                     let storage = FilesystemStorage::new(&path);
-                    RepoBuilder::new(runtime)
-                        .with_storage(storage)
+                    let samod = RepoBuilder::new(runtime)
+                        .with_storage(storage.clone())
                         .with_peer_id(peer_id)
-                        .with_concurrency(samod::ConcurrencyConfig::Threadpool(
-                            rayon::ThreadPoolBuilder::new().build().unwrap(),
-                        ))
+                        .with_concurrency(concurrency())
                         .load()
-                        .await
+                        .await;
+                    (samod, RepoStorage::Filesystem(storage, path))
                 }
             };
 
             let samod = Arc::new(samod);
-            let vfs = Arc::new(VirtualFileSystem::new(samod.clone()).await?);
+            let vfs = Arc::new(
+                VirtualFileSystem::new(samod.clone())
+                    .await?
+                    .with_quota(self.quota)
+                    .with_delta_events(self.delta_events)
+                    .with_traversal_limits(self.traversal_limits.clone())
+                    .with_modified_propagation(self.modified_propagation)
+                    .with_identity(Self::author(&self.identity, &storage).await)
+                    .with_event_channel(self.event_channel.clone()),
+            );
 
             info!("TonkCore initialized with peer ID: {}", samod.peer_id());
 
-            Ok(TonkCore { samod, vfs })
+            let eviction = Arc::new(EvictionMonitor::fresh(vfs.root_id()));
+            Ok(TonkCore {
+                samod,
+                vfs,
+                mounts: Mounts::default(),
+                storage,
+                exclusive_storage,
+                compaction: Arc::default(),
+                journal: Arc::default(),
+                eviction,
+                #[cfg(feature = "websocket")]
+                framing: self.framing,
+                ws_url: Arc::new(RwLock::new(None)),
+                #[cfg(feature = "websocket")]
+                leases: Arc::default(),
+                #[cfg(feature = "websocket")]
+                disconnects: Arc::default(),
+            })
         }
 
         #[cfg(target_arch = "wasm32")]
         {
-            let (samod, stored_root_id): (Repo, Option<DocumentId>) = match self.storage_config {
+            let (samod, storage, stored_manifest) = match self.storage_config {
                 StorageConfig::InMemory => {
+                    let storage = InMemoryStorage::new();
                     let samod = Repo::build_wasm()
                         .with_peer_id(peer_id)
-                        .with_storage(InMemoryStorage::new())
+                        .with_storage(storage.clone())
                         .load()
                         .await;
-                    (samod, None)
+                    (samod, RepoStorage::InMemory(storage), None)
                 }
                 StorageConfig::IndexedDB { ref namespace } => {
                     let storage = match namespace {
@@ -123,16 +357,16 @@ impl TonkCoreBuilder {
                     };
 
                     // Check for manifest
-                    let stored_root_id = if let Ok(manifest_key) =
+                    let stored_manifest = if let Ok(manifest_key) =
                         StorageKey::from_parts(vec!["__tonk_manifest__".to_string()])
                     {
                         match storage.load(manifest_key.clone()).await {
                             Some(manifest_data) => {
                                 eprintln!("Found stored manifest in IndexedDB");
-                                // Try to parse and extract root_id
-                                serde_json::from_slice::<crate::bundle::Manifest>(&manifest_data)
+                                // Try to parse, keeping it only if the root ID is valid
+                                serde_json::from_slice::<Manifest>(&manifest_data)
                                     .ok()
-                                    .and_then(|m| m.root_id.parse::<DocumentId>().ok())
+                                    .filter(|m| m.root_id.parse::<DocumentId>().is_ok())
                             }
                             None => {
                                 eprintln!("No stored manifest found");
@@ -143,241 +377,602 @@ impl TonkCoreBuilder {
                         None
                     };
 
-                    // Now build repo with storage (storage is moved here)
                     let samod = Repo::build_wasm()
                         .with_peer_id(peer_id)
-                        .with_storage(storage)
+                        .with_storage(storage.clone())
                         .load_local()
                         .await;
 
-                    (samod, stored_root_id)
+                    (samod, RepoStorage::IndexedDb(storage), stored_manifest)
                 }
             };
 
             let samod = Arc::new(samod);
 
             // Initialize VFS based on whether we found a manifest
-            let vfs = if let Some(root_id) = stored_root_id {
+            let root_id = stored_manifest
+                .as_ref()
+                .and_then(|m| m.root_id.parse::<DocumentId>().ok());
+
+            // Browsers evict IndexedDB under storage pressure, which can leave the
+            // manifest behind without the documents it names
+            let restored = EvictionMonitor::restored();
+            if let Some(root_id) = &root_id {
+                restored.check_stored_root(&storage, root_id).await;
+            }
+
+            let vfs = if let Some(root_id) = root_id {
                 eprintln!(
                     "Restoring VFS from stored manifest with root ID: {}",
                     root_id
                 );
-                Arc::new(VirtualFileSystem::from_root_id(samod.clone(), root_id).await?)
+                Arc::new(
+                    VirtualFileSystem::from_root_id(samod.clone(), root_id)
+                        .await?
+                        .with_quota(self.quota)
+                        .with_delta_events(self.delta_events)
+                        .with_traversal_limits(self.traversal_limits.clone())
+                        .with_modified_propagation(self.modified_propagation)
+                        .with_identity(Self::author(&self.identity, &storage).await)
+                        .with_event_channel(self.event_channel.clone()),
+                )
             } else {
-                Arc::new(VirtualFileSystem::new(samod.clone()).await?)
+                Arc::new(
+                    VirtualFileSystem::new(samod.clone())
+                        .await?
+                        .with_quota(self.quota)
+                        .with_delta_events(self.delta_events)
+                        .with_traversal_limits(self.traversal_limits.clone())
+                        .with_modified_propagation(self.modified_propagation)
+                        .with_identity(Self::author(&self.identity, &storage).await)
+                        .with_event_channel(self.event_channel.clone()),
+                )
             };
 
+            let mounts = match &stored_manifest {
+                Some(manifest) => load_mounts(&samod, &vfs, &manifest.roots).await?,
+                None => Mounts::default(),
+            };
+            restore_mounts(&samod, &storage, &vfs, &mounts).await;
+
             info!("TonkCore initialized with peer ID: {}", samod.peer_id());
 
+            let eviction = match stored_manifest {
+                Some(_) => restored,
+                None => EvictionMonitor::fresh(vfs.root_id()),
+            };
+            let outbox = new_outbox(&vfs, &storage).await;
             Ok(TonkCore {
                 samod,
                 vfs,
+                mounts,
+                storage,
+                exclusive_storage,
+                compaction: Arc::default(),
+                journal: Arc::default(),
+                eviction: Arc::new(eviction),
                 connection_state: Arc::new(RwLock::new(ConnectionState::Disconnected)),
+                outbox,
                 ws_url: Arc::new(RwLock::new(None)),
             })
         }
     }
+}
 
+#[cfg(feature = "bundle")]
+impl TonkCoreBuilder {
     /// Load from bundle data with the configured settings
     pub async fn from_bundle(
-        self,
+        mut self,
         mut bundle: Bundle<std::io::Cursor<Vec<u8>>>,
     ) -> Result<TonkCore> {
+        self.install_tracing();
+        let exclusive_storage = self.storage_is_exclusive();
         let peer_id = self.peer_id.unwrap_or_else(|| {
             let mut rng = rng();
             PeerId::new_with_rng(&mut rng)
         });
         use crate::BundlePath;
+        use std::io::Read;
+
+        // Check limits against the index before reading any entry data, then import
+        // entries one at a time so only a single decompressed entry is held at once
+        let storage_entries = bundle.prefix_entries(&BundlePath::from("storage"));
+        let mut tracker = ImportTracker::start(
+            self.import_limits,
+            self.on_import_progress,
+            &storage_entries,
+        )?;
+
+        let target = ImportTarget::new(&self.storage_config)?;
+        for entry in &storage_entries {
+            // Reading through the index sizes keeps an entry that inflates past the
+            // size the limits were checked against from being buffered
+            let Some(mut reader) = bundle
+                .get_reader(&BundlePath::from(entry.path.as_str()))
+                .map_err(VfsError::Other)?
+            else {
+                continue;
+            };
+            // Storage takes each entry whole, so it's read into memory here
+            let mut data = crate::bundle::entry_buffer(entry.uncompressed_size);
+            reader.read_to_end(&mut data)?;
+            let len = data.len();
 
-        #[cfg(not(target_arch = "wasm32"))]
-        let runtime = tokio::runtime::Handle::current();
+            target.put(&entry.path, data).await?;
+            tracker.record(len).await;
+        }
+        target.put_manifest(bundle.manifest()).await;
 
-        // TODO: helper that reduces duplicated code populating storage
-        let samod = match &self.storage_config {
-            StorageConfig::InMemory => {
-                let storage = InMemoryStorage::new();
-
-                // Extract storage entries from bundle and populate in-memory storage
-                let storage_prefix = BundlePath::from("storage");
-                let storage_entries = bundle.prefix(&storage_prefix).map_err(VfsError::Other)?;
-
-                for (bundle_path, data) in storage_entries {
-                    let path_str = bundle_path.to_string();
-                    if let Some(relative_path) = path_str.strip_prefix("storage/") {
-                        let path_parts: Vec<String> =
-                            relative_path.split('/').map(|s| s.to_string()).collect();
-
-                        let reconstructed_parts =
-                            if path_parts.len() >= 2 && path_parts[0].len() == 2 {
-                                // Looks like a splayed document
-                                let mut parts = vec![format!("{}{}", path_parts[0], path_parts[1])];
-                                parts.extend_from_slice(&path_parts[2..]);
-                                parts
-                            } else {
-                                path_parts
-                            };
-
-                        if let Ok(storage_key) = StorageKey::from_parts(reconstructed_parts.clone())
-                        {
-                            eprintln!(
-                                "Loading storage key: {:?} (from path: {})",
-                                reconstructed_parts, relative_path
-                            );
-                            samod::storage::Storage::put(&storage, storage_key.clone(), data).await;
-                        }
-                    }
-                }
+        let (samod, storage) = target.load(peer_id).await;
+        let samod = Arc::new(samod);
+        let vfs = VirtualFileSystem::from_bundle(samod.clone(), &mut bundle)
+            .await?
+            .with_quota(self.quota)
+            .with_delta_events(self.delta_events)
+            .with_traversal_limits(self.traversal_limits.clone())
+            .with_modified_propagation(self.modified_propagation)
+            .with_identity(Self::author(&self.identity, &storage).await)
+            .with_event_channel(self.event_channel.clone());
+        let vfs = Arc::new(vfs);
+        let mounts = load_mounts(&samod, &vfs, &bundle.manifest().roots).await?;
+        restore_mounts(&samod, &storage, &vfs, &mounts).await;
 
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    RepoBuilder::new(runtime)
-                        .with_storage(storage)
-                        .with_peer_id(peer_id)
-                        .with_concurrency(samod::ConcurrencyConfig::Threadpool(
-                            rayon::ThreadPoolBuilder::new().build().unwrap(),
-                        ))
-                        .load()
-                        .await
-                }
+        let progress = tracker.progress();
+        info!(
+            "TonkCore loaded from bundle with peer ID: {} ({} entries, {} bytes)",
+            samod.peer_id(),
+            progress.entries_imported,
+            progress.bytes_imported
+        );
 
-                #[cfg(target_arch = "wasm32")]
-                {
-                    Repo::build_wasm()
-                        .with_peer_id(peer_id)
-                        .with_storage(storage)
-                        .load()
-                        .await
-                }
-            }
-            #[cfg(not(target_arch = "wasm32"))]
-            StorageConfig::Filesystem(storage_path) => {
-                std::fs::create_dir_all(storage_path).map_err(VfsError::IoError)?;
+        #[cfg(target_arch = "wasm32")]
+        {
+            let outbox = new_outbox(&vfs, &storage).await;
+            Ok(TonkCore {
+                samod,
+                vfs,
+                mounts,
+                storage,
+                exclusive_storage,
+                compaction: Arc::default(),
+                journal: Arc::default(),
+                eviction: Arc::new(EvictionMonitor::restored()),
+                connection_state: Arc::new(RwLock::new(ConnectionState::Disconnected)),
+                outbox,
+                ws_url: Arc::new(RwLock::new(None)),
+            })
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        Ok(TonkCore {
+            samod,
+            vfs,
+            mounts,
+            storage,
+            exclusive_storage,
+            compaction: Arc::default(),
+            journal: Arc::default(),
+            eviction: Arc::new(EvictionMonitor::restored()),
+            #[cfg(feature = "websocket")]
+            framing: self.framing,
+            ws_url: Arc::new(RwLock::new(None)),
+            #[cfg(feature = "websocket")]
+            leases: Arc::default(),
+            #[cfg(feature = "websocket")]
+            disconnects: Arc::default(),
+        })
+    }
 
-                // Extract all storage files from bundle to the filesystem storage directory
-                let storage_prefix = BundlePath::from("storage");
-                let storage_entries = bundle.prefix(&storage_prefix).map_err(VfsError::Other)?;
+    /// Load from byte data with the configured settings
+    pub async fn from_bytes(self, data: Vec<u8>) -> Result<TonkCore> {
+        let bundle = Bundle::from_bytes_trusting(data, &self.trusted_keys)?;
+        self.from_bundle(bundle).await
+    }
 
-                for (bundle_path, data) in storage_entries {
-                    let path_str = bundle_path.to_string();
+    /// Load from file with the configured settings
+    ///
+    /// Holds a shared lock on the file while reading it, failing with
+    /// `VfsError::BundleLocked` if another process is writing it.
+    pub async fn from_file<P: AsRef<std::path::Path>>(self, path: P) -> Result<TonkCore> {
+        use std::io::Read;
 
-                    if let Some(relative_path) = path_str.strip_prefix("storage/") {
-                        let full_path = storage_path.join(relative_path);
+        let path = path.as_ref();
+        let mut data = Vec::new();
+        {
+            let mut file = std::fs::File::open(path)?;
+            if !try_lock_file(&file, false)? {
+                return Err(VfsError::BundleLocked(path.display().to_string()));
+            }
+            file.read_to_end(&mut data)?;
+        }
+        self.from_bytes(data).await
+    }
 
-                        if let Some(parent) = full_path.parent() {
-                            std::fs::create_dir_all(parent).map_err(VfsError::IoError)?;
-                        }
+    /// Load from bundle data that arrives in chunks, such as a network download
+    ///
+    /// Entries are written to storage as soon as each one has fully arrived, so the
+    /// import overlaps with the transfer and the whole bundle is never held in memory.
+    /// Limits are checked per entry as they are discovered rather than up front. The
+    /// bundle must record entry sizes in its local headers, which every bundle written
+    /// by `to_bytes` does.
+    pub async fn from_byte_stream<S>(mut self, mut chunks: S) -> Result<TonkCore>
+    where
+        S: Stream<Item = Result<Vec<u8>>> + Unpin,
+    {
+        self.install_tracing();
+        let exclusive_storage = self.storage_is_exclusive();
+        let peer_id = self.peer_id.unwrap_or_else(|| {
+            let mut rng = rng();
+            PeerId::new_with_rng(&mut rng)
+        });
 
-                        std::fs::write(&full_path, data).map_err(VfsError::IoError)?;
+        let mut tracker =
+            ImportTracker::start_streaming(self.import_limits, self.on_import_progress);
+        let target = ImportTarget::new(&self.storage_config)?;
+        let mut reader = BundleStreamReader::new();
+        let mut manifest = None;
+        let mut admitted = None;
+
+        loop {
+            // Admit each entry when its header arrives, before buffering its data
+            while let Some(entry) = reader.peek_entry().map_err(VfsError::Other)? {
+                if admitted.as_ref() != Some(&entry.local_header_offset) {
+                    if entry.path.starts_with("storage/") {
+                        tracker.admit(&entry)?;
                     }
+                    admitted = Some(entry.local_header_offset);
                 }
 
-                let storage = FilesystemStorage::new(storage_path);
-                RepoBuilder::new(runtime)
-                    .with_storage(storage)
-                    .with_peer_id(peer_id)
-                    .with_concurrency(samod::ConcurrencyConfig::Threadpool(
-                        rayon::ThreadPoolBuilder::new().build().unwrap(),
-                    ))
-                    .load()
-                    .await
-            }
-            #[cfg(target_arch = "wasm32")]
-            StorageConfig::IndexedDB { ref namespace } => {
-                let storage = match namespace {
-                    Some(ns) => {
-                        IndexedDbStorage::with_names(&format!("samod_storage_{}", ns), "data")
-                    }
-                    None => IndexedDbStorage::new(),
+                let Some((entry, data)) = reader.next_entry().map_err(VfsError::Other)? else {
+                    break;
                 };
-
-                // Extract storage entries from bundle and populate IndexedDB
-                let storage_prefix = BundlePath::from("storage");
-                let storage_entries = bundle.prefix(&storage_prefix).map_err(VfsError::Other)?;
-
-                for (bundle_path, data) in storage_entries {
-                    let path_str = bundle_path.to_string();
-                    if let Some(relative_path) = path_str.strip_prefix("storage/") {
-                        let path_parts: Vec<String> =
-                            relative_path.split('/').map(|s| s.to_string()).collect();
-
-                        let reconstructed_parts =
-                            if path_parts.len() >= 2 && path_parts[0].len() == 2 {
-                                // Looks like a splayed document
-                                let mut parts = vec![format!("{}{}", path_parts[0], path_parts[1])];
-                                parts.extend_from_slice(&path_parts[2..]);
-                                parts
-                            } else {
-                                path_parts
-                            };
-
-                        if let Ok(storage_key) = StorageKey::from_parts(reconstructed_parts.clone())
-                        {
-                            eprintln!(
-                                "Loading storage key: {:?} (from path: {})",
-                                reconstructed_parts, relative_path
-                            );
-                            storage.put(storage_key.clone(), data).await;
+                if entry.path == "manifest.json" {
+                    let parsed = Manifest::parse(&data).map_err(VfsError::Other)?;
+                    match &parsed.integrity {
+                        Some(integrity) => {
+                            integrity
+                                .verify_signature(&parsed, &self.trusted_keys)
+                                .map_err(VfsError::Other)?;
                         }
+                        None if !self.trusted_keys.is_empty() => {
+                            return Err(VfsError::Other(anyhow::anyhow!(
+                                "Bundle has no content index to check its signature against"
+                            )));
+                        }
+                        None => {}
                     }
-                }
-
-                // Store manifest in IndexedDB for offline initialization
-                if let Ok(manifest_key) =
-                    StorageKey::from_parts(vec!["__tonk_manifest__".to_string()])
-                {
-                    if let Ok(manifest_json) = serde_json::to_vec(bundle.manifest()) {
-                        eprintln!(
-                            "Storing manifest with root ID: {}",
-                            bundle.manifest().root_id
-                        );
-                        storage.put(manifest_key, manifest_json).await;
+                    manifest = Some(parsed);
+                } else if entry.path.starts_with("storage/") {
+                    // Entries are checked against the content index as they arrive,
+                    // which needs the manifest to come first when keys are trusted
+                    let integrity = manifest.as_ref().and_then(|m| m.integrity.as_ref());
+                    if let Some(message) =
+                        integrity.and_then(|integrity| integrity.check_entry(&entry.path, &data))
+                    {
+                        return Err(VfsError::Other(anyhow::anyhow!(
+                            "Entry {}: {message}",
+                            entry.path
+                        )));
+                    }
+                    if integrity.is_none() && !self.trusted_keys.is_empty() {
+                        return Err(VfsError::Other(anyhow::anyhow!(
+                            "Entry {} arrived before a signed manifest",
+                            entry.path
+                        )));
                     }
+                    let len = data.len();
+                    target.put(&entry.path, data).await?;
+                    tracker.record(len).await;
                 }
-
-                Repo::build_wasm()
-                    .with_peer_id(peer_id)
-                    .with_storage(storage)
-                    .load_local()
-                    .await
             }
-        };
 
+            if reader.is_finished() {
+                break;
+            }
+            match chunks.next().await {
+                Some(chunk) => reader.push(&chunk?),
+                None => break,
+            }
+        }
+        reader.finish().map_err(VfsError::Other)?;
+        tracker.finish();
+
+        let manifest = manifest
+            .ok_or_else(|| VfsError::Other(anyhow::anyhow!("manifest.json not found in bundle")))?;
+        let root_id = manifest
+            .root_id
+            .parse::<DocumentId>()
+            .map_err(|e| VfsError::Other(anyhow::anyhow!("Failed to parse root ID: {}", e)))?;
+        target.put_manifest(&manifest).await;
+
+        let (samod, storage) = target.load(peer_id).await;
         let samod = Arc::new(samod);
-        let vfs = VirtualFileSystem::from_bundle(samod.clone(), &mut bundle).await?;
-        let vfs = Arc::new(vfs);
+        let vfs = Arc::new(
+            VirtualFileSystem::from_root_id(samod.clone(), root_id)
+                .await?
+                .with_quota(self.quota)
+                .with_delta_events(self.delta_events)
+                .with_traversal_limits(self.traversal_limits.clone())
+                .with_modified_propagation(self.modified_propagation)
+                .with_identity(Self::author(&self.identity, &storage).await)
+                .with_event_channel(self.event_channel.clone()),
+        );
+        let mounts = load_mounts(&samod, &vfs, &manifest.roots).await?;
+        restore_mounts(&samod, &storage, &vfs, &mounts).await;
 
+        let progress = tracker.progress();
         info!(
-            "TonkCore loaded from bundle with peer ID: {}",
-            samod.peer_id()
+            "TonkCore streamed from bundle with peer ID: {} ({} entries, {} bytes)",
+            samod.peer_id(),
+            progress.entries_imported,
+            progress.bytes_imported
         );
 
         #[cfg(target_arch = "wasm32")]
         {
+            let outbox = new_outbox(&vfs, &storage).await;
             Ok(TonkCore {
                 samod,
                 vfs,
+                mounts,
+                storage,
+                exclusive_storage,
+                compaction: Arc::default(),
+                journal: Arc::default(),
+                eviction: Arc::new(EvictionMonitor::restored()),
                 connection_state: Arc::new(RwLock::new(ConnectionState::Disconnected)),
+                outbox,
                 ws_url: Arc::new(RwLock::new(None)),
             })
         }
 
         #[cfg(not(target_arch = "wasm32"))]
-        Ok(TonkCore { samod, vfs })
+        Ok(TonkCore {
+            samod,
+            vfs,
+            mounts,
+            storage,
+            exclusive_storage,
+            compaction: Arc::default(),
+            journal: Arc::default(),
+            eviction: Arc::new(EvictionMonitor::restored()),
+            #[cfg(feature = "websocket")]
+            framing: self.framing,
+            ws_url: Arc::new(RwLock::new(None)),
+            #[cfg(feature = "websocket")]
+            leases: Arc::default(),
+            #[cfg(feature = "websocket")]
+            disconnects: Arc::default(),
+        })
     }
+}
 
-    /// Load from byte data with the configured settings
-    pub async fn from_bytes(self, data: Vec<u8>) -> Result<TonkCore> {
-        let bundle = Bundle::from_bytes(data)?;
-        self.from_bundle(bundle).await
+/// How native repos run document work: on a rayon pool with the `threadpool`
+/// feature, otherwise on the tokio runtime
+#[cfg(not(target_arch = "wasm32"))]
+fn concurrency() -> samod::ConcurrencyConfig {
+    #[cfg(feature = "threadpool")]
+    return samod::ConcurrencyConfig::Threadpool(rayon::ThreadPoolBuilder::new().build().unwrap());
+    #[cfg(not(feature = "threadpool"))]
+    samod::ConcurrencyConfig::AsyncRuntime
+}
+
+/// Named document trees mounted alongside the main one
+type Mounts = Arc<std::sync::RwLock<Vec<Mount>>>;
+
+/// Open the named roots recorded in a manifest as mounts sharing the main tree's settings
+#[cfg(feature = "bundle")]
+async fn load_mounts(
+    samod: &Arc<Repo>,
+    vfs: &VirtualFileSystem,
+    roots: &BTreeMap<String, NamedRoot>,
+) -> Result<Mounts> {
+    let mut mounts = Vec::with_capacity(roots.len());
+    for (name, root) in roots {
+        let root_id = root.root_id.parse::<DocumentId>().map_err(|e| {
+            VfsError::Other(anyhow::anyhow!(
+                "Failed to parse root ID of '{}': {}",
+                name,
+                e
+            ))
+        })?;
+        let mount_vfs = VirtualFileSystem::from_root_id(samod.clone(), root_id)
+            .await?
+            .with_settings_of(vfs);
+        mounts.push(Mount {
+            name: name.clone(),
+            vfs: Arc::new(mount_vfs),
+            sync_priority: root.sync_priority,
+            exported: true,
+            mount_point: None,
+            relay_url: None,
+        });
     }
+    Ok(Arc::new(std::sync::RwLock::new(mounts)))
+}
 
-    /// Load from file with the configured settings
-    pub async fn from_file<P: AsRef<std::path::Path>>(self, path: P) -> Result<TonkCore> {
-        let data = std::fs::read(path).map_err(VfsError::IoError)?;
-        self.from_bytes(data).await
+/// Reopen the mounts recorded in storage for the space of `vfs`, other than
+/// those already in `mounts`
+///
+/// A recorded mount whose root can't be found, such as a space that was
+/// mounted by ID but never synced, is skipped so the space itself still opens.
+#[cfg(any(target_arch = "wasm32", feature = "bundle"))]
+async fn restore_mounts(
+    samod: &Arc<Repo>,
+    storage: &RepoStorage,
+    vfs: &VirtualFileSystem,
+    mounts: &Mounts,
+) {
+    for record in storage.mount_records(&vfs.root_id()).await {
+        if mounts.read().unwrap().iter().any(|m| m.name == record.name) {
+            continue;
+        }
+        let mount_vfs = match record.root_id.parse::<DocumentId>() {
+            Ok(root_id) => VirtualFileSystem::from_root_id(samod.clone(), root_id).await,
+            Err(e) => Err(VfsError::Other(anyhow::anyhow!("{}", e))),
+        };
+        match mount_vfs {
+            Ok(mount_vfs) => mounts.write().unwrap().push(Mount {
+                name: record.name,
+                vfs: Arc::new(mount_vfs.with_settings_of(vfs)),
+                sync_priority: record.sync_priority,
+                exported: record.exported,
+                mount_point: record.mount_point,
+                relay_url: None,
+            }),
+            Err(e) => tracing::warn!("Skipping recorded mount '{}': {}", record.name, e),
+        }
+    }
+}
+
+/// Storage being populated by a bundle import, before a repo is loaded on top of it
+#[cfg(feature = "bundle")]
+enum ImportTarget {
+    InMemory(InMemoryStorage),
+    #[cfg(not(target_arch = "wasm32"))]
+    Filesystem(PathBuf),
+    #[cfg(target_arch = "wasm32")]
+    IndexedDb(IndexedDbStorage),
+}
+
+#[cfg(feature = "bundle")]
+impl ImportTarget {
+    fn new(storage_config: &StorageConfig) -> Result<Self> {
+        match storage_config {
+            StorageConfig::InMemory => Ok(Self::InMemory(InMemoryStorage::new())),
+            #[cfg(not(target_arch = "wasm32"))]
+            StorageConfig::Filesystem(storage_path) => {
+                std::fs::create_dir_all(storage_path).map_err(VfsError::IoError)?;
+                Ok(Self::Filesystem(storage_path.clone()))
+            }
+            #[cfg(target_arch = "wasm32")]
+            StorageConfig::IndexedDB { namespace } => {
+                let storage = match namespace {
+                    Some(ns) => {
+                        IndexedDbStorage::with_names(&format!("samod_storage_{}", ns), "data")
+                    }
+                    None => IndexedDbStorage::new(),
+                };
+                Ok(Self::IndexedDb(storage))
+            }
+        }
+    }
+
+    /// Write a `storage/...` bundle entry into the target
+    async fn put(&self, path: &str, data: Vec<u8>) -> Result<()> {
+        match self {
+            Self::InMemory(storage) => {
+                if let Some(storage_key) = storage_key_for_bundle_path(path) {
+                    samod::storage::Storage::put(storage, storage_key, data).await;
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Filesystem(storage_path) => {
+                if let Some(relative_path) = path.strip_prefix("storage/") {
+                    let full_path = storage_path.join(relative_path);
+
+                    if let Some(parent) = full_path.parent() {
+                        std::fs::create_dir_all(parent).map_err(VfsError::IoError)?;
+                    }
+
+                    std::fs::write(&full_path, data).map_err(VfsError::IoError)?;
+                }
+            }
+            #[cfg(target_arch = "wasm32")]
+            Self::IndexedDb(storage) => {
+                if let Some(storage_key) = storage_key_for_bundle_path(path) {
+                    storage.put(storage_key, data).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Store the manifest for offline initialization, where the storage supports it
+    async fn put_manifest(&self, manifest: &Manifest) {
+        #[cfg(target_arch = "wasm32")]
+        if let Self::IndexedDb(storage) = self {
+            if let Ok(manifest_key) = StorageKey::from_parts(vec!["__tonk_manifest__".to_string()])
+            {
+                if let Ok(manifest_json) = serde_json::to_vec(manifest) {
+                    eprintln!("Storing manifest with root ID: {}", manifest.root_id);
+                    storage.put(manifest_key, manifest_json).await;
+                }
+            }
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = manifest;
+    }
+
+    /// Load a repo over the populated storage
+    async fn load(self, peer_id: PeerId) -> (Repo, RepoStorage) {
+        #[cfg(not(target_arch = "wasm32"))]
+        let runtime = tokio::runtime::Handle::current();
+
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::InMemory(storage) => {
+                let samod = RepoBuilder::new(runtime)
+                    .with_storage(storage.clone())
+                    .with_peer_id(peer_id)
+                    .with_concurrency(concurrency())
+                    .load()
+                    .await;
+                (samod, RepoStorage::InMemory(storage))
+            }
+            #[cfg(target_arch = "wasm32")]
+            Self::InMemory(storage) => {
+                let samod = Repo::build_wasm()
+                    .with_peer_id(peer_id)
+                    .with_storage(storage.clone())
+                    .load()
+                    .await;
+                (samod, RepoStorage::InMemory(storage))
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Filesystem(storage_path) => {
+                let storage = FilesystemStorage::new(&storage_path);
+                let samod = RepoBuilder::new(runtime)
+                    .with_storage(storage.clone())
+                    .with_peer_id(peer_id)
+                    .with_concurrency(concurrency())
+                    .load()
+                    .await;
+                (samod, RepoStorage::Filesystem(storage, storage_path))
+            }
+            #[cfg(target_arch = "wasm32")]
+            Self::IndexedDb(storage) => {
+                let samod = Repo::build_wasm()
+                    .with_peer_id(peer_id)
+                    .with_storage(storage.clone())
+                    .load_local()
+                    .await;
+                (samod, RepoStorage::IndexedDb(storage))
+            }
+        }
     }
 }
 
+/// Map a `storage/...` bundle path to its storage key, joining splayed document ids
+#[cfg(feature = "bundle")]
+fn storage_key_for_bundle_path(path: &str) -> Option<StorageKey> {
+    let relative_path = path.strip_prefix("storage/")?;
+    let path_parts: Vec<String> = relative_path.split('/').map(|s| s.to_string()).collect();
+
+    let reconstructed_parts = if path_parts.len() >= 2 && path_parts[0].len() == 2 {
+        // Looks like a splayed document
+        let mut parts = vec![format!("{}{}", path_parts[0], path_parts[1])];
+        parts.extend_from_slice(&path_parts[2..]);
+        parts
+    } else {
+        path_parts
+    };
+
+    StorageKey::from_parts(reconstructed_parts).ok()
+}
+
 impl Default for TonkCoreBuilder {
     fn default() -> Self {
         Self::new()
@@ -434,10 +1029,32 @@ pub enum ConnectionState {
 pub struct TonkCore {
     samod: Arc<Repo>,
     vfs: Arc<VirtualFileSystem>,
+    mounts: Mounts,
+    /// Storage the repo was built on, scanned by `gc` and compaction
+    storage: RepoStorage,
+    /// Whether no other space writes to `storage`, which `gc` requires
+    exclusive_storage: bool,
+    compaction: Arc<Compaction>,
+    /// Recorder of VFS events, when started
+    journal: Arc<Journal>,
+    /// Whether the local replica was found to have lost its documents
+    eviction: Arc<EvictionMonitor>,
+    #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+    framing: Option<FramingConfig>,
     #[cfg(target_arch = "wasm32")]
     connection_state: Arc<RwLock<ConnectionState>>,
+    /// Writes no peer has acknowledged yet, and how dropped connections are retried
     #[cfg(target_arch = "wasm32")]
+    outbox: Arc<Outbox>,
+    /// URL of the relay last connected to
     ws_url: Arc<RwLock<Option<String>>>,
+    /// Lease channels to relays by space ID, each opened by the first
+    /// `acquire_lease` for a path in that space
+    #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+    leases: Arc<Mutex<HashMap<String, Arc<LeaseClient>>>>,
+    /// Signalled by `disconnect` to close the open connections
+    #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+    disconnects: Arc<tokio::sync::watch::Sender<()>>,
 }
 
 impl TonkCore {
@@ -487,16 +1104,19 @@ impl TonkCore {
     }
 
     /// Load from file with default in-memory storage
+    #[cfg(feature = "bundle")]
     pub async fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
         TonkCoreBuilder::new().from_file(path).await
     }
 
     /// Load from bytes with default in-memory storage
+    #[cfg(feature = "bundle")]
     pub async fn from_bytes(data: Vec<u8>) -> Result<Self> {
         TonkCoreBuilder::new().from_bytes(data).await
     }
 
     /// Load from bundle with explicit storage configuration
+    #[cfg(feature = "bundle")]
     pub async fn from_bundle(
         bundle: Bundle<std::io::Cursor<Vec<u8>>>,
         storage_config: StorageConfig,
@@ -508,6 +1128,7 @@ impl TonkCore {
     }
 
     /// Export the current state to a bundle as bytes
+    #[cfg(feature = "bundle")]
     pub async fn fork_to_bytes(&self, config: Option<BundleConfig>) -> Result<Vec<u8>> {
         // Create a new samod instance with in-memory storage for the copied VFS to avoid conflicts
         #[cfg(not(target_arch = "wasm32"))]
@@ -520,9 +1141,7 @@ impl TonkCore {
                 RepoBuilder::new(runtime)
                     .with_storage(storage)
                     .with_peer_id(peer_id)
-                    .with_concurrency(samod::ConcurrencyConfig::Threadpool(
-                        rayon::ThreadPoolBuilder::new().build().unwrap(),
-                    ))
+                    .with_concurrency(concurrency())
                     .load()
                     .await,
             )
@@ -543,113 +1162,498 @@ impl TonkCore {
 
         let copied_vfs = Arc::new(VirtualFileSystem::new(new_samod.clone()).await?);
 
-        // Recursively copy all files and directories from /app
-        self.copy_directory_recursive(&self.vfs, &copied_vfs, "/app")
-            .await?;
+        // Copy all files and directories from /app
+        Self::copy_directory(&self.vfs, &copied_vfs, "/app").await?;
 
         // Also copy /src if it exists
         if self.vfs.exists("/src").await? {
-            self.copy_directory_recursive(&self.vfs, &copied_vfs, "/src")
-                .await?;
+            Self::copy_directory(&self.vfs, &copied_vfs, "/src").await?;
         }
 
         // Export the copied VFS to bytes
         copied_vfs.to_bytes(config).await
     }
 
-    /// Recursively copy a directory and its contents from source VFS to destination VFS
-    fn copy_directory_recursive<'a>(
-        #[allow(clippy::only_used_in_recursion)] &'a self,
-        source_vfs: &'a VirtualFileSystem,
-        dest_vfs: &'a VirtualFileSystem,
-        path: &'a str,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send + 'a>> {
-        Box::pin(async move {
-            use crate::vfs::backend::AutomergeHelpers;
-            use crate::vfs::types::NodeType;
-            use bytes::Bytes;
-
-            // List all entries in the current directory
-            let entries = source_vfs.list_directory(path).await?;
-
-            for entry in entries {
-                // Construct the full path for this entry
-                let entry_path = if path == "/" {
-                    format!("/{}", entry.name)
-                } else {
-                    format!("{}/{}", path, entry.name)
-                };
-
-                match entry.node_type {
-                    NodeType::Directory => {
-                        // Create the directory in the destination VFS
-                        dest_vfs.create_directory(&entry_path).await?;
+    /// Copy a directory and its contents from source VFS to destination VFS
+    #[cfg(feature = "bundle")]
+    async fn copy_directory(
+        source_vfs: &VirtualFileSystem,
+        dest_vfs: &VirtualFileSystem,
+        path: &str,
+    ) -> Result<()> {
+        use crate::vfs::backend::AutomergeHelpers;
+        use crate::vfs::types::NodeType;
+        use bytes::Bytes;
 
-                        // Recursively copy the directory's contents
-                        self.copy_directory_recursive(source_vfs, dest_vfs, &entry_path)
-                            .await?;
-                    }
-                    NodeType::Document => {
-                        // Find the document in the source VFS
-                        if let Some(doc_handle) = source_vfs.find_document(&entry_path).await? {
-                            // Try to read the document with bytes first
-                            let has_bytes = doc_handle.with_document(|doc| {
-                                use automerge::ReadDoc;
-                                matches!(doc.get(automerge::ROOT, "bytes"), Ok(Some(_)))
-                            });
-
-                            if has_bytes {
-                                // Read the document content with bytes
-                                let doc_node = AutomergeHelpers::read_bytes_document::<
-                                    serde_json::Value,
-                                >(&doc_handle)?;
-                                dest_vfs
-                                    .create_document_with_bytes(
-                                        &entry_path,
-                                        doc_node.content,
-                                        Bytes::from(doc_node.bytes.unwrap_or_default()),
-                                    )
-                                    .await?;
-                            } else {
-                                // Read the document content without bytes
-                                let doc_node = AutomergeHelpers::read_document::<serde_json::Value>(
-                                    &doc_handle,
-                                )?;
-                                dest_vfs
-                                    .create_document(&entry_path, doc_node.content)
-                                    .await?;
-                            }
+        // Parents are listed before their children, so directories exist before
+        // anything is copied into them
+        for (entry_path, entry) in source_vfs.walk(path, None).await? {
+            match entry.node_type {
+                NodeType::Directory => {
+                    // Create the directory in the destination VFS
+                    dest_vfs.create_directory(&entry_path).await?;
+                }
+                NodeType::Document => {
+                    // Find the document in the source VFS
+                    if let Some(doc_handle) = source_vfs.find_document(&entry_path).await? {
+                        // Try to read the document with bytes first
+                        let has_bytes = doc_handle.with_document(|doc| {
+                            use automerge::ReadDoc;
+                            matches!(doc.get(automerge::ROOT, "bytes"), Ok(Some(_)))
+                        });
+
+                        if has_bytes {
+                            // Read the document content with bytes
+                            let doc_node = AutomergeHelpers::read_bytes_document::<
+                                serde_json::Value,
+                            >(&doc_handle)?;
+                            dest_vfs
+                                .create_document_with_bytes(
+                                    &entry_path,
+                                    doc_node.content,
+                                    Bytes::from(doc_node.bytes.unwrap_or_default()),
+                                )
+                                .await?;
+                        } else {
+                            // Read the document content without bytes
+                            let doc_node =
+                                AutomergeHelpers::read_document::<serde_json::Value>(&doc_handle)?;
+                            dest_vfs
+                                .create_document(&entry_path, doc_node.content)
+                                .await?;
                         }
                     }
                 }
+                NodeType::Symlink => {
+                    let target = source_vfs.read_link(&entry_path).await?;
+                    dest_vfs.create_link(&entry_path, &target).await?;
+                }
             }
 
-            Ok(())
-        })
-    }
+            // Carry extended attributes over to the copy
+            for (key, value) in entry.metadata {
+                dest_vfs.set_metadata(&entry_path, &key, value).await?;
+            }
+        }
+
+        Ok(())
+    }
 
     /// Export the current state to a bundle as bytes
+    ///
+    /// Exported mounts are included as named roots in the manifest.
+    #[cfg(feature = "bundle")]
     pub async fn to_bytes(&self, config: Option<BundleConfig>) -> Result<Vec<u8>> {
-        self.vfs.to_bytes(config).await
+        self.vfs.to_bytes_with_mounts(config, &self.mounts()).await
     }
 
     /// Export the current state to a bundle file
+    ///
+    /// Holds an exclusive lock on the file while writing it, failing with
+    /// `VfsError::BundleLocked` if another process has it open.
+    #[cfg(feature = "bundle")]
     pub async fn to_file<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
+        use std::io::Write;
+
         let bytes = self.to_bytes(None).await?;
-        std::fs::write(path, bytes).map_err(VfsError::IoError)?;
+
+        let path = path.as_ref();
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if !try_lock_file(&file, true)? {
+            return Err(VfsError::BundleLocked(path.display().to_string()));
+        }
+
+        // Only truncate once the lock is held, so a reader never sees a partial file
+        file.set_len(0)?;
+        file.write_all(&bytes)?;
         Ok(())
     }
 
+    /// Export everything below a VFS directory to a directory on disk
+    ///
+    /// See `VirtualFileSystem::export_to_dir`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn export_to_dir<P: AsRef<std::path::Path>>(
+        &self,
+        path: &str,
+        dir: P,
+    ) -> Result<usize> {
+        self.vfs.export_to_dir(path, dir).await
+    }
+
+    /// Import a directory on disk below a VFS directory
+    ///
+    /// See `VirtualFileSystem::import_from_dir`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn import_from_dir<P: AsRef<std::path::Path>>(
+        &self,
+        path: &str,
+        dir: P,
+    ) -> Result<usize> {
+        self.vfs.import_from_dir(path, dir).await
+    }
+
+    /// Import a directory on disk below a VFS directory, choosing how existing
+    /// paths are treated
+    ///
+    /// See `VirtualFileSystem::import_from_dir_with_options`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn import_from_dir_with_options<P: AsRef<std::path::Path>>(
+        &self,
+        path: &str,
+        dir: P,
+        options: CopyOptions,
+    ) -> Result<Vec<CopyOperation>> {
+        self.vfs
+            .import_from_dir_with_options(path, dir, options)
+            .await
+    }
+
     /// Create a new TonkCore with a specific peer ID
     pub async fn with_peer_id(peer_id: PeerId) -> Result<Self> {
         TonkCoreBuilder::new().with_peer_id(peer_id).build().await
     }
 
+    /// Describe the features compiled into this build
+    pub fn capabilities() -> Capabilities {
+        Capabilities::current()
+    }
+
     /// Get access to the VFS layer
     pub fn vfs(&self) -> Arc<VirtualFileSystem> {
         Arc::clone(&self.vfs)
     }
 
+    /// Get the document tree mounted under `name`
+    pub fn mount(&self, name: &str) -> Option<Arc<VirtualFileSystem>> {
+        self.mounts
+            .read()
+            .unwrap()
+            .iter()
+            .find(|m| m.name == name)
+            .map(|m| Arc::clone(&m.vfs))
+    }
+
+    /// List the mounted document trees, highest sync priority first
+    pub fn mounts(&self) -> Vec<Mount> {
+        let mut mounts = self.mounts.read().unwrap().clone();
+        mounts.sort_by(|a, b| {
+            b.sync_priority
+                .cmp(&a.sync_priority)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        mounts
+    }
+
+    /// Create an empty document tree mounted under `name`
+    ///
+    /// The tree lives in the same repo as the main VFS and shares its settings.
+    /// Exported mounts are written to bundles as named roots and restored on load.
+    pub async fn create_mount(
+        &self,
+        name: &str,
+        sync_priority: i32,
+        exported: bool,
+    ) -> Result<Arc<VirtualFileSystem>> {
+        if name.is_empty() {
+            return Err(VfsError::InvalidPath(
+                "Mount name cannot be empty".to_string(),
+            ));
+        }
+        if self.mount(name).is_some() {
+            return Err(VfsError::MountExists(name.to_string()));
+        }
+
+        let vfs = Arc::new(
+            VirtualFileSystem::new(self.samod.clone())
+                .await?
+                .with_settings_of(&self.vfs),
+        );
+
+        {
+            let mut mounts = self.mounts.write().unwrap();
+            if mounts.iter().any(|m| m.name == name) {
+                return Err(VfsError::MountExists(name.to_string()));
+            }
+            mounts.push(Mount {
+                name: name.to_string(),
+                vfs: Arc::clone(&vfs),
+                sync_priority,
+                exported,
+                mount_point: None,
+                relay_url: None,
+            });
+        }
+
+        self.record_mounts().await;
+        Ok(vfs)
+    }
+
+    /// Attach another space's tree at `mount_point` in this space's namespace
+    ///
+    /// The tree is opened in the same repo as the main VFS and shares its
+    /// settings. A space is opened by its root document, which has to be in
+    /// local storage or held by a connected peer. A bundle is loaded into a
+    /// throwaway repo and its tree copied into a new root, so it becomes a
+    /// local space of its own.
+    ///
+    /// Mounted spaces are never exported with this one, and the main tree's
+    /// walks and exports don't descend into them; use [`TonkCore::vfs_for_path`]
+    /// to reach files below a mount point.
+    pub async fn mount_space(
+        &self,
+        source: MountSource,
+        mount_point: &str,
+    ) -> Result<Arc<VirtualFileSystem>> {
+        let mount_point = normalize_mount_point(mount_point)?;
+        let overlaps = |mounts: &[Mount]| {
+            mounts.iter().any(|m| {
+                m.name == mount_point
+                    || m.relative_path(&mount_point).is_some()
+                    || m.mount_point
+                        .as_ref()
+                        .is_some_and(|existing| existing.starts_with(&format!("{}/", mount_point)))
+            })
+        };
+        if overlaps(&self.mounts.read().unwrap()) {
+            return Err(VfsError::MountExists(mount_point));
+        }
+
+        let vfs = match source {
+            MountSource::Space(root_id) => {
+                VirtualFileSystem::from_root_id(self.samod.clone(), root_id).await?
+            }
+            #[cfg(feature = "bundle")]
+            MountSource::Bundle(data) => {
+                let root_id = self.import_bundle_documents(data).await?;
+                VirtualFileSystem::from_root_id(self.samod.clone(), root_id).await?
+            }
+        };
+        let vfs = Arc::new(vfs.with_settings_of(&self.vfs));
+
+        {
+            let mut mounts = self.mounts.write().unwrap();
+            if overlaps(&mounts) {
+                return Err(VfsError::MountExists(mount_point));
+            }
+            mounts.push(Mount {
+                name: mount_point.clone(),
+                vfs: Arc::clone(&vfs),
+                sync_priority: 0,
+                exported: false,
+                mount_point: Some(mount_point),
+                relay_url: None,
+            });
+        }
+
+        self.record_mounts().await;
+        Ok(vfs)
+    }
+
+    /// Write the documents of a bundle into this repo's storage under their own
+    /// IDs, returning the ID of the bundle's root
+    ///
+    /// Keeping the IDs lets a space mounted from a bundle sync with peers that
+    /// hold the same space.
+    #[cfg(feature = "bundle")]
+    async fn import_bundle_documents(&self, data: Vec<u8>) -> Result<DocumentId> {
+        use crate::BundlePath;
+        use std::io::Read;
+
+        let mut bundle = Bundle::from_bytes(data)?;
+        let storage_entries = bundle.prefix_entries(&BundlePath::from("storage"));
+        ImportLimits::default().check(&storage_entries)?;
+
+        for entry in &storage_entries {
+            let Some(storage_key) = storage_key_for_bundle_path(&entry.path) else {
+                continue;
+            };
+            let Some(mut reader) = bundle
+                .get_reader(&BundlePath::from(entry.path.as_str()))
+                .map_err(VfsError::Other)?
+            else {
+                continue;
+            };
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            self.storage.put(storage_key, data).await;
+        }
+
+        bundle
+            .manifest()
+            .root_id
+            .parse::<DocumentId>()
+            .map_err(|e| VfsError::Other(anyhow::anyhow!("Failed to parse root ID: {}", e)))
+    }
+
+    /// Detach the space mounted at `mount_point`, returning whether one was
+    pub async fn unmount_space(&self, mount_point: &str) -> Result<bool> {
+        let mount_point = normalize_mount_point(mount_point)?;
+        let removed = {
+            let mut mounts = self.mounts.write().unwrap();
+            let before = mounts.len();
+            mounts.retain(|m| m.mount_point.as_ref() != Some(&mount_point));
+            mounts.len() != before
+        };
+        if removed {
+            self.record_mounts().await;
+        }
+        Ok(removed)
+    }
+
+    /// Record the current mounts in storage, so `gc` keeps their trees after a restart
+    async fn record_mounts(&self) {
+        let records: Vec<MountRecord> = self.mounts().iter().map(MountRecord::from).collect();
+        self.storage
+            .put_mount_records(&self.vfs.root_id(), &records)
+            .await;
+    }
+
+    /// Find the tree holding `path`, and the path within that tree
+    ///
+    /// Paths below a mount point go to the mounted space, everything else to
+    /// the main VFS.
+    pub fn vfs_for_path(&self, path: &str) -> (Arc<VirtualFileSystem>, String) {
+        self.mounts
+            .read()
+            .unwrap()
+            .iter()
+            .find_map(|m| Some((Arc::clone(&m.vfs), m.relative_path(path)?)))
+            .unwrap_or_else(|| (self.vfs(), path.to_string()))
+    }
+
+    /// Find the tree holding both `from_path` and `to_path`, and their paths within it
+    ///
+    /// Fails with [`VfsError::InvalidPath`] if the paths are in different
+    /// spaces, as documents can't be moved or linked between them.
+    pub fn vfs_for_paths(
+        &self,
+        from_path: &str,
+        to_path: &str,
+    ) -> Result<(Arc<VirtualFileSystem>, String, String)> {
+        let (vfs, from) = self.vfs_for_path(from_path);
+        let (to_vfs, to) = self.vfs_for_path(to_path);
+        if vfs.root_id() != to_vfs.root_id() {
+            return Err(VfsError::InvalidPath(format!(
+                "{} and {} are in different spaces",
+                from_path, to_path
+            )));
+        }
+        Ok((vfs, from, to))
+    }
+
+    /// Map a path inside a tree found by `vfs_for_path` back to the namespace
+    pub fn namespace_path(&self, vfs: &Arc<VirtualFileSystem>, path: &str) -> String {
+        let mount_point = self
+            .mounts
+            .read()
+            .unwrap()
+            .iter()
+            .find(|m| Arc::ptr_eq(&m.vfs, vfs))
+            .and_then(|m| m.mount_point.clone());
+        match mount_point {
+            Some(mount_point) if path == "/" => mount_point,
+            Some(mount_point) => format!("{}{}", mount_point, path),
+            None => path.to_string(),
+        }
+    }
+
+    /// Read many documents at once, keyed by path, from whichever trees hold them
+    ///
+    /// Paths with no document are left out of the result.
+    pub async fn read_many(
+        &self,
+        paths: &[String],
+    ) -> Result<HashMap<String, DocNode<serde_json::Value>>> {
+        let mut trees: Vec<(Arc<VirtualFileSystem>, Vec<String>)> = Vec::new();
+        for path in paths {
+            let (vfs, path) = self.vfs_for_path(path);
+            match trees.iter_mut().find(|(tree, _)| Arc::ptr_eq(tree, &vfs)) {
+                Some((_, tree_paths)) => tree_paths.push(path),
+                None => trees.push((vfs, vec![path])),
+            }
+        }
+
+        let mut nodes = HashMap::new();
+        for (vfs, tree_paths) in &trees {
+            for (path, node) in vfs.read_many(tree_paths).await? {
+                nodes.insert(self.namespace_path(vfs, &path), node);
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Sync the space mounted at `mount_point` with its own relay
+    ///
+    /// Runs until the connection closes, like `connect_websocket`. Connections
+    /// belong to the repo rather than to a tree, so a relay asking for a
+    /// document of another mount is still answered.
+    #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+    pub async fn connect_mount(&self, mount_point: &str, url: &str) -> Result<()> {
+        let vfs = self.record_mount_relay(mount_point, url)?;
+        info!("Connecting {} to WebSocket peer at: {}", mount_point, url);
+
+        self.run_connection(
+            url,
+            crate::websocket::connect_tracked(
+                Arc::clone(&self.samod),
+                url,
+                self.framing.as_ref(),
+                vfs.sync_tracker(),
+                None,
+            ),
+        )
+        .await
+    }
+
+    /// Sync the space mounted at `mount_point` with its own relay (WASM)
+    ///
+    /// The connection runs in the background and isn't retried if it drops.
+    /// Connections belong to the repo rather than to a tree, so a relay asking
+    /// for a document of another mount is still answered.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn connect_mount(&self, mount_point: &str, url: &str) -> Result<()> {
+        self.record_mount_relay(mount_point, url)?;
+        info!("Connecting {} to WebSocket peer at: {}", mount_point, url);
+
+        let samod = Arc::clone(&self.samod);
+        let url = url.to_string();
+        wasm_bindgen_futures::spawn_local(async move {
+            match crate::websocket::connect_wasm(samod, &url).await {
+                Ok(reason) => info!("Connection to {} finished: {:?}", url, reason),
+                Err(e) => tracing::warn!("Connection to {} failed: {}", url, e),
+            }
+        });
+        Ok(())
+    }
+
+    /// Remember the relay a mounted space is synced with
+    #[cfg(any(target_arch = "wasm32", feature = "websocket"))]
+    fn record_mount_relay(&self, mount_point: &str, url: &str) -> Result<Arc<VirtualFileSystem>> {
+        let mount_point = normalize_mount_point(mount_point)?;
+        let mut mounts = self.mounts.write().unwrap();
+        let mount = mounts
+            .iter_mut()
+            .find(|m| m.mount_point.as_ref() == Some(&mount_point))
+            .ok_or_else(|| VfsError::PathNotFound(mount_point.clone()))?;
+        mount.relay_url = Some(url.to_string());
+        Ok(Arc::clone(&mount.vfs))
+    }
+
+    /// Read the space profile stored at `/.tonk/profile`, if one has been set
+    pub async fn profile(&self) -> Result<Option<SpaceProfile>> {
+        profile::read_profile(&self.vfs).await
+    }
+
+    /// Create or replace the space profile
+    pub async fn set_profile(&self, profile: &SpaceProfile) -> Result<()> {
+        profile::write_profile(&self.vfs, profile).await
+    }
+
     /// Get access to the underlying Repo instance
     pub fn samod(&self) -> Arc<Repo> {
         Arc::clone(&self.samod)
@@ -661,17 +1665,66 @@ impl TonkCore {
     }
 
     /// Connect to a WebSocket peer
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+    #[tracing::instrument(name = "sync_connection", skip_all, fields(url = %url, peer_id = %self.peer_id()))]
     pub async fn connect_websocket(&self, url: &str) -> Result<()> {
         info!("Connecting to WebSocket peer at: {}", url);
+        *self.ws_url.write().await = Some(url.to_string());
+
+        self.run_connection(
+            url,
+            crate::websocket::connect_tracked(
+                Arc::clone(&self.samod),
+                url,
+                self.framing.as_ref(),
+                self.vfs.sync_tracker(),
+                Some(&self.eviction),
+            ),
+        )
+        .await
+    }
 
-        let conn_finished = crate::websocket::connect(Arc::clone(&self.samod), url).await?;
-
-        info!("Successfully connected to WebSocket peer at: {}", url);
-        info!("Connection finished with reason: {:?}", conn_finished);
+    /// Run a connection until it finishes or `disconnect` is called
+    #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+    async fn run_connection(
+        &self,
+        url: &str,
+        connection: impl std::future::Future<Output = Result<samod::ConnFinishedReason>>,
+    ) -> Result<()> {
+        let mut disconnected = self.disconnects.subscribe();
+        tokio::select! {
+            conn_finished = connection => {
+                let conn_finished = conn_finished?;
+                info!("Connection to {} finished with reason: {:?}", url, conn_finished);
+            }
+            _ = disconnected.changed() => {
+                info!("Disconnected from WebSocket peer at: {}", url);
+            }
+        }
         Ok(())
     }
 
+    /// Close every connection opened by `connect_websocket` and `connect_mount`
+    ///
+    /// The calls running those connections return `Ok` once they're closed.
+    #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+    pub async fn disconnect(&self) {
+        *self.ws_url.write().await = None;
+        self.disconnects.send_replace(());
+    }
+
+    /// Stop reconnecting to the relay (WASM)
+    ///
+    /// samod has no way to close a browser connection it opened, so a
+    /// connection that is up stays up until it drops. It then isn't retried,
+    /// nor when the browser comes back online, until `connect_websocket` is
+    /// called again.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn disconnect(&self) {
+        *self.ws_url.write().await = None;
+        self.outbox.reset_reconnect_attempts();
+    }
+
     /// Connect using network URIs from manifest
     // TODO: connect to from_bundle for network connection
     // pub async fn connect_from_manifest(&self) -> Result<(), VfsError> {
@@ -686,60 +1739,64 @@ impl TonkCore {
     // }
 
     /// Connect to a WebSocket peer (WASM)
+    ///
+    /// If the connection drops it is retried according to the reconnect policy,
+    /// and immediately when the browser comes back online.
     #[cfg(target_arch = "wasm32")]
     pub async fn connect_websocket(&self, url: &str) -> Result<()> {
-        info!("Connecting to WebSocket peer at: {}", url);
+        *self.ws_url.write().await = Some(url.to_string());
+        self.outbox.reset_reconnect_attempts();
 
-        {
-            let mut ws_url = self.ws_url.write().await;
-            *ws_url = Some(url.to_string());
-        }
-
-        {
-            let mut state = self.connection_state.write().await;
-            *state = ConnectionState::Connecting;
-        }
-
-        let samod = Arc::clone(&self.samod);
-        let url_str = url.to_string();
-        let state_clone = Arc::clone(&self.connection_state);
+        let link = self.wasm_link();
+        link.watch_online();
+        link.connect(url.to_string()).await;
+        Ok(())
+    }
 
-        let events =
-            samod.connect_wasm_websocket_observable(&url_str, samod::ConnDirection::Outgoing);
+    /// Connect to a relay with a share link token
+    ///
+    /// The relay serves the connection read-only: it ends the connection if
+    /// this instance sends it changes, so writes made here stay local.
+    #[cfg(any(target_arch = "wasm32", feature = "websocket"))]
+    pub async fn connect_websocket_with_share(&self, url: &str, token: &str) -> Result<()> {
+        self.connect_websocket(&crate::websocket::share_url(url, token))
+            .await
+    }
 
-        let state_for_open = Arc::clone(&state_clone);
-        wasm_bindgen_futures::spawn_local(async move {
-            if events.on_open.await.is_ok() {
-                let mut state = state_for_open.write().await;
-                *state = ConnectionState::Open;
-            }
-        });
+    /// Connect to a relay that requires a sync token to connect with full access
+    #[cfg(any(target_arch = "wasm32", feature = "websocket"))]
+    pub async fn connect_websocket_with_token(&self, url: &str, token: &str) -> Result<()> {
+        self.connect_websocket(&crate::websocket::token_url(url, token))
+            .await
+    }
 
-        let state_for_ready = Arc::clone(&state_clone);
-        wasm_bindgen_futures::spawn_local(async move {
-            if events.on_ready.await.is_ok() {
-                let mut state = state_for_ready.write().await;
-                *state = ConnectionState::Connected;
-            }
-        });
+    #[cfg(target_arch = "wasm32")]
+    fn wasm_link(&self) -> WasmLink {
+        WasmLink {
+            samod: Arc::clone(&self.samod),
+            connection_state: Arc::clone(&self.connection_state),
+            ws_url: Arc::clone(&self.ws_url),
+            tracker: self.vfs.sync_tracker(),
+            outbox: Arc::clone(&self.outbox),
+        }
+    }
 
-        let state_for_finished = Arc::clone(&state_clone);
-        wasm_bindgen_futures::spawn_local(async move {
-            let reason = events.finished.await;
+    /// Writes waiting to reach a peer, and when they last did
+    #[cfg(target_arch = "wasm32")]
+    pub fn outbox_status(&self) -> OutboxStatus {
+        self.outbox.status()
+    }
 
-            let mut state = state_for_finished.write().await;
-            match reason {
-                samod::ConnFinishedReason::Error(e) => {
-                    *state = ConnectionState::Failed(e);
-                }
-                _ => {
-                    *state = ConnectionState::Disconnected;
-                }
-            }
-        });
+    /// Subscribe to notifications sent when peers have acknowledged pending writes
+    #[cfg(target_arch = "wasm32")]
+    pub fn subscribe_outbox(&self) -> tokio::sync::broadcast::Receiver<OutboxFlush> {
+        self.outbox.subscribe()
+    }
 
-        info!("WebSocket connection initiated at: {}", url);
-        Ok(())
+    /// Set how dropped connections are retried, or disable retries with `None`
+    #[cfg(target_arch = "wasm32")]
+    pub fn set_reconnect_policy(&self, policy: Option<ReconnectPolicy>) {
+        self.outbox.set_reconnect_policy(policy);
     }
 
     #[cfg(target_arch = "wasm32")]
@@ -755,42 +1812,639 @@ impl TonkCore {
         state.clone()
     }
 
+    /// How the local replica was found to have lost its documents, if it was
+    ///
+    /// Reads of evicted documents fail until they are synced again from the relay.
+    pub fn storage_eviction(&self) -> Option<StorageEviction> {
+        self.eviction.current()
+    }
+
+    /// Subscribe to evictions of the local replica
+    ///
+    /// The receiver sees a change as soon as one is found: at start, when the
+    /// stored root is missing, or on connecting to a relay that hosts another
+    /// root. One found before subscribing is reported as a change too.
+    pub fn subscribe_eviction(&self) -> tokio::sync::watch::Receiver<Option<StorageEviction>> {
+        self.eviction.subscribe()
+    }
+
+    /// Compare the root a relay hosts with this instance's
+    ///
+    /// Native connections do this with the root the relay names when accepting
+    /// them. Browsers can't read those headers, so apps pass the root of the
+    /// relay's manifest instead. Returns whether the roots match; if not, an
+    /// eviction is reported to subscribers.
+    pub fn check_relay_root(&self, relay_root: &str) -> bool {
+        self.eviction.check_relay_root(relay_root)
+    }
+
+    /// The origin's storage usage and quota, and whether it is persistent or was evicted
     #[cfg(target_arch = "wasm32")]
+    pub async fn storage_estimate(&self) -> Result<StorageEstimate> {
+        let mut estimate = browser_storage::estimate().await?;
+        estimate.evicted = self.eviction.current().is_some();
+        Ok(estimate)
+    }
+
     pub async fn ws_url(&self) -> Option<String> {
         let url = self.ws_url.read().await;
         url.clone()
     }
 
-    /// Find a document by its ID
-    pub async fn find_document(&self, doc_id: DocumentId) -> Result<DocHandle> {
-        self.samod
-            .find(doc_id.clone())
-            .await
-            .map_err(|e| VfsError::SamodError(format!("Failed to find document {doc_id}: {e}")))?
-            .ok_or_else(|| VfsError::SamodError(format!("Document {doc_id} not found")))
+    /// Acquire an advisory lease on a path from the relay last connected to
+    ///
+    /// Only one client at a time holds the lease on a path; others get
+    /// [`VfsError::LeaseUnavailable`] until it is released or expires. The relay
+    /// keeps leases in memory only, and `ttl` is capped at
+    /// [`MAX_LEASE_TTL`](crate::lease::MAX_LEASE_TTL).
+    ///
+    /// A path below a mount point is leased from the relay its space was
+    /// connected to with `connect_mount`.
+    #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+    pub async fn acquire_lease(&self, path: &str, ttl: Duration) -> Result<Lease> {
+        let (vfs, path) = self.vfs_for_path(path);
+        let url = if Arc::ptr_eq(&vfs, &self.vfs) {
+            self.ws_url().await
+        } else {
+            self.mounts()
+                .into_iter()
+                .find(|m| Arc::ptr_eq(&m.vfs, &vfs))
+                .and_then(|m| m.relay_url)
+        }
+        .ok_or_else(|| VfsError::WebSocketError("Not connected to a relay".to_string()))?;
+
+        let space_id = vfs.root_id().to_string();
+        let client = {
+            let mut clients = self.leases.lock().await;
+            match clients.get(&space_id) {
+                Some(open) if !open.is_closed() => Arc::clone(open),
+                _ => {
+                    let opened = LeaseClient::connect(&url, &space_id).await?;
+                    clients.insert(space_id, Arc::clone(&opened));
+                    opened
+                }
+            }
+        };
+        client.acquire(&path, ttl).await
+    }
+
+    /// Snapshot of sync activity across this instance's connections
+    pub fn sync_status(&self) -> SyncStatus {
+        self.vfs.sync_tracker().status()
+    }
+
+    /// Native connections of the main tree and its mounts that are open
+    ///
+    /// Each says which relay it's connected to and what that relay reported
+    /// about itself. Browser connections aren't listed.
+    pub fn connections(&self) -> Vec<ConnectionHandle> {
+        // Mounts share the main tree's tracker, so it lists their connections too
+        self.vfs.sync_tracker().connections()
+    }
+
+    /// Wait until the main tree, and each mount connected to its own relay, is
+    /// in sync with its peers
+    ///
+    /// A document is in sync once each peer sharing it has acknowledged its
+    /// current heads, so this resolves as soon as both sides have each other's
+    /// changes rather than after a quiet period. Fails with
+    /// [`VfsError::SyncTimeout`] if that hasn't happened within `timeout`.
+    pub async fn wait_for_sync(&self, timeout: Duration) -> Result<()> {
+        let mut trees = vec![self.vfs()];
+        trees.extend(
+            self.mounts()
+                .into_iter()
+                .filter(|mount| mount.relay_url.is_some())
+                .map(|mount| mount.vfs),
+        );
+        futures::future::try_join_all(trees.iter().map(|vfs| vfs.wait_for_sync(timeout))).await?;
+        Ok(())
+    }
+
+    /// Delete documents in storage that no tree reaches any more
+    ///
+    /// Removing a path only drops it from the index, so its document stays in
+    /// storage. This walks the main tree, every mount, and every mount recorded
+    /// in storage by an earlier run, then deletes the storage chunks of every
+    /// other document older than `grace`. A document's age counts from when its
+    /// newest chunk was written where the storage records that (filesystem
+    /// storage does), and otherwise from the first non-dry-run pass that found
+    /// it unreachable. Younger documents are reported as deferred, so one just
+    /// created and not yet linked into a tree isn't collected. With `dry_run`
+    /// nothing is deleted and the report only says what would be reclaimed.
+    ///
+    /// Documents created with [`TonkCore::create_document`] and never linked
+    /// into a tree are unreachable too. So is anything another space writes to
+    /// the same storage, which is why this fails unless the storage is in
+    /// memory, namespaced IndexedDB, or declared with
+    /// [`TonkCoreBuilder::with_exclusive_storage`]. A recorded mount whose root
+    /// can't be found fails the pass rather than have its tree collected.
+    /// [`DEFAULT_GC_GRACE`](crate::DEFAULT_GC_GRACE) is a reasonable grace period.
+    pub async fn gc(&self, dry_run: bool, grace: Duration) -> Result<GcReport> {
+        if !self.exclusive_storage {
+            return Err(VfsError::Other(anyhow::anyhow!(
+                "gc would delete other spaces' documents in shared storage; use storage only \
+                 this space writes to and declare it with TonkCoreBuilder::with_exclusive_storage"
+            )));
+        }
+        let root_id = self.vfs.root_id();
+        let mut reachable = self.vfs.collect_all_document_ids().await?;
+        let mounts = self.mounts();
+        for mount in &mounts {
+            reachable.extend(mount.vfs.collect_all_document_ids().await?);
+        }
+        for record in self.storage.mount_records(&root_id).await {
+            let Ok(mount_root) = record.root_id.parse::<DocumentId>() else {
+                continue;
+            };
+            if mounts.iter().any(|m| m.vfs.root_id() == mount_root) {
+                continue;
+            }
+            let vfs = VirtualFileSystem::from_root_id(self.samod.clone(), mount_root)
+                .await?
+                .with_settings_of(&self.vfs);
+            reachable.extend(vfs.collect_all_document_ids().await?);
+        }
+
+        let report = self
+            .storage
+            .collect_garbage(&root_id, &reachable, grace, dry_run)
+            .await;
+        info!(
+            "GC found {} unreachable documents in {} chunks ({} bytes){}, deferring {}",
+            report.unreachable_documents.len(),
+            report.chunks,
+            report.reclaimable_bytes,
+            if dry_run { ", dry run" } else { "" },
+            report.deferred_documents.len()
+        );
+        Ok(report)
+    }
+
+    /// Rewrite the document at `path` as a single snapshot in storage
+    ///
+    /// Every chunk the snapshot supersedes is deleted. Paths below a mount point
+    /// are compacted in the mounted space.
+    pub async fn compact(&self, path: &str) -> Result<CompactionReport> {
+        let (vfs, inner_path) = self.vfs_for_path(path);
+        let handle = vfs
+            .find_document(&inner_path)
+            .await?
+            .ok_or_else(|| VfsError::PathNotFound(path.to_string()))?;
+
+        let report = compact_document(&self.storage, &handle, 0).await;
+        self.compaction.record(&report);
+        Ok(report)
+    }
+
+    /// Compact every stored document kept in at least `min_chunks` chunks
+    pub async fn compact_all(&self, min_chunks: usize) -> Result<CompactionReport> {
+        let mut report = CompactionReport::default();
+        for (doc_id, chunks) in self.storage.document_chunks(None).await {
+            if chunks.len() < min_chunks.max(2) {
+                continue;
+            }
+            let found = self.samod.find(doc_id.clone()).await.map_err(|e| {
+                VfsError::SamodError(format!("Failed to find document {doc_id}: {e}"))
+            })?;
+            if let Some(handle) = found {
+                report.add(&compact_document(&self.storage, &handle, min_chunks).await);
+            }
+        }
+
+        info!(
+            "Compacted {} documents, reclaiming {} bytes",
+            report.documents, report.bytes_reclaimed
+        );
+        self.compaction.record(&report);
+        Ok(report)
+    }
+
+    /// Compact documents in the background every `policy.interval_ms`
+    ///
+    /// Replaces any schedule already running. The task holds on to this
+    /// instance until [`TonkCore::stop_compaction`] is called.
+    pub fn start_compaction(&self, policy: CompactionPolicy) {
+        let generation = self.compaction.reschedule();
+        let tonk = self.clone();
+        let task = async move {
+            loop {
+                crate::vfs::watcher::sleep(policy.interval()).await;
+                if !tonk.compaction.is_current(generation) {
+                    break;
+                }
+                if let Err(e) = tonk.compact_all(policy.min_chunks).await {
+                    tracing::warn!("Scheduled compaction failed: {}", e);
+                }
+            }
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        tokio::spawn(task);
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(task);
+    }
+
+    /// Stop the schedule started by [`TonkCore::start_compaction`]
+    pub fn stop_compaction(&self) {
+        self.compaction.reschedule();
+    }
+
+    /// Totals across every compaction this instance has run
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.compaction.stats()
+    }
+
+    /// Record the VFS's events in storage until [`TonkCore::stop_journal`] is called
+    ///
+    /// With persistent storage the journal survives restarts, so an indexer can
+    /// catch up on what changed with [`TonkCore::journal_since`] instead of
+    /// walking the whole tree. Changes are recorded just before they are made,
+    /// and conflicts once detected; if conflict events are dropped, a
+    /// [`JournalOp::Gap`](crate::journal::JournalOp::Gap) entry says so. Events
+    /// of mounted spaces aren't recorded. Replaces any recorder already running.
+    pub fn start_journal(&self, policy: JournalPolicy) {
+        use crate::vfs::VfsEvent;
+
+        let generation = self.journal.reschedule();
+        let writer = JournalWriter::new(Arc::clone(&self.journal), self.storage.clone(), policy);
+        self.vfs.set_journal(Some(writer.clone()));
+
+        // Conflicts are only known once a change has arrived, so they're
+        // recorded from events
+        let journal = Arc::clone(&self.journal);
+        let samod = Arc::clone(&self.samod);
+        let mut events = self.vfs.subscribe_events();
+        let task = async move {
+            let superseded = journal.superseded(generation);
+            tokio::pin!(superseded);
+            loop {
+                let event = tokio::select! {
+                    _ = &mut superseded => break,
+                    event = events.recv() => event,
+                };
+                let entry = match event {
+                    Ok(event @ VfsEvent::ConflictDetected { .. }) => {
+                        let Some(mut entry) = JournalEntry::from_event(&event) else {
+                            continue;
+                        };
+                        let doc_id = entry.doc_id.as_ref().and_then(|id| id.parse().ok());
+                        if let Some(doc_id) = doc_id {
+                            if let Ok(Some(handle)) = samod.find(doc_id).await {
+                                entry.heads = handle.with_document(|doc| {
+                                    doc.get_heads()
+                                        .iter()
+                                        .map(|head| head.to_string())
+                                        .collect()
+                                });
+                            }
+                        }
+                        entry
+                    }
+                    Ok(VfsEvent::Lagged { missed }) => {
+                        tracing::warn!("Journal missed {} events", missed);
+                        JournalEntry::gap(missed)
+                    }
+                    Ok(_) => continue,
+                    Err(_) => break,
+                };
+                writer.record(entry).await;
+            }
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        tokio::spawn(task);
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(task);
+    }
+
+    /// Stop the recorder started by [`TonkCore::start_journal`], keeping its entries
+    ///
+    /// Changes made once this returns aren't recorded.
+    pub fn stop_journal(&self) {
+        self.vfs.set_journal(None);
+        self.journal.reschedule();
+    }
+
+    /// Journal entries recorded after `cursor`, oldest first
+    ///
+    /// Pass `0` for every entry kept, then the `cursor` of the last entry handled
+    /// to resume from there.
+    pub async fn journal_since(&self, cursor: u64) -> Vec<JournalEntry> {
+        self.journal.since(&self.storage, cursor).await
+    }
+
+    /// Paths referencing `doc_id`, in the main VFS and in spaces mounted into it
+    ///
+    /// Paths in mounted spaces are given below their mount point. See
+    /// `VirtualFileSystem::paths_for_document`.
+    pub async fn paths_for_document(&self, doc_id: &DocumentId) -> Result<Vec<String>> {
+        let mut paths = self.vfs.paths_for_document(doc_id).await?;
+        for mount in self.mounts() {
+            let Some(mount_point) = &mount.mount_point else {
+                continue;
+            };
+            for path in mount.vfs.paths_for_document(doc_id).await? {
+                paths.push(if path == "/" {
+                    mount_point.clone()
+                } else {
+                    format!("{}{}", mount_point, path)
+                });
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Find a document by its ID
+    pub async fn find_document(&self, doc_id: DocumentId) -> Result<DocHandle> {
+        self.vfs
+            .sync_tracker()
+            .lookup(self.samod.find(doc_id.clone()))
+            .await
+            .map_err(|e| VfsError::SamodError(format!("Failed to find document {doc_id}: {e}")))?
+            .ok_or_else(|| VfsError::SamodError(format!("Document {doc_id} not found")))
+    }
+
+    /// Create a new document
+    pub async fn create_document(&self, initial_doc: automerge::Automerge) -> Result<DocHandle> {
+        let handle = self
+            .samod
+            .create(initial_doc)
+            .await
+            .map_err(|e| VfsError::SamodError(format!("Failed to create document: {e}")))?;
+
+        Ok(handle)
+    }
+}
+
+impl Clone for TonkCore {
+    fn clone(&self) -> Self {
+        Self {
+            samod: Arc::clone(&self.samod),
+            vfs: Arc::clone(&self.vfs),
+            mounts: Arc::clone(&self.mounts),
+            storage: self.storage.clone(),
+            compaction: Arc::clone(&self.compaction),
+            journal: Arc::clone(&self.journal),
+            eviction: Arc::clone(&self.eviction),
+            #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+            framing: self.framing.clone(),
+            #[cfg(target_arch = "wasm32")]
+            connection_state: Arc::clone(&self.connection_state),
+            #[cfg(target_arch = "wasm32")]
+            outbox: Arc::clone(&self.outbox),
+            ws_url: Arc::clone(&self.ws_url),
+            #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+            leases: Arc::clone(&self.leases),
+            #[cfg(all(not(target_arch = "wasm32"), feature = "websocket"))]
+            disconnects: Arc::clone(&self.disconnects),
+        }
+    }
+}
+
+/// Time a peer has to acknowledge the outbox's writes before the flush is retried
+#[cfg(target_arch = "wasm32")]
+const OUTBOX_FLUSH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Create an outbox that records the VFS's writes until a peer acknowledges them
+///
+/// Writes pending when the page was last closed are restored from `storage`.
+/// Every write is recorded, whether or not a peer is connected when it's made,
+/// and only counts as flushed once the connected peers have acknowledged the
+/// heads of every document in the tree.
+#[cfg(target_arch = "wasm32")]
+async fn new_outbox(vfs: &Arc<VirtualFileSystem>, storage: &RepoStorage) -> Arc<Outbox> {
+    use crate::vfs::VfsEvent;
+
+    let root = vfs.root_id();
+    let outbox = Arc::new(Outbox::restore(storage.outbox_record(&root).await));
+
+    let recorder = Arc::clone(&outbox);
+    let recorder_storage = storage.clone();
+    let recorder_root = root.clone();
+    let mut events = vfs.subscribe_events();
+    wasm_bindgen_futures::spawn_local(async move {
+        loop {
+            match events.recv().await {
+                Ok(VfsEvent::DocumentCreated { path, .. })
+                | Ok(VfsEvent::DocumentUpdated { path, .. })
+                | Ok(VfsEvent::DocumentDeleted { path })
+                | Ok(VfsEvent::DirectoryCreated { path, .. }) => recorder.record_write(&path),
+                Ok(VfsEvent::Lagged { missed }) => recorder.record_missed(missed as usize),
+                // Conflicts come from remote changes, not local writes
+                Ok(VfsEvent::ConflictDetected { .. }) => continue,
+                Err(_) => break,
+            }
+            recorder_storage
+                .put_outbox_record(&recorder_root, &recorder.record())
+                .await;
+        }
+        // Let the flusher see the VFS is gone
+        recorder.request_flush();
+    });
+
+    let flusher = Arc::clone(&outbox);
+    let flusher_storage = storage.clone();
+    let weak_vfs = Arc::downgrade(vfs);
+    wasm_bindgen_futures::spawn_local(async move {
+        loop {
+            flusher.flush_requested().await;
+            let Some(vfs) = weak_vfs.upgrade() else {
+                break;
+            };
+            if flusher.is_empty() || vfs.sync_tracker().status().connections == 0 {
+                continue;
+            }
+
+            // Writes made while waiting stay pending for the next round
+            let generation = flusher.generation();
+            match vfs.wait_for_sync(OUTBOX_FLUSH_TIMEOUT).await {
+                Ok(()) => {
+                    if let Some(flush) = flusher.flushed_through(generation) {
+                        info!("Outbox flushed {} pending changes", flush.flushed_changes);
+                        flusher_storage
+                            .put_outbox_record(&root, &flusher.record())
+                            .await;
+                    }
+                }
+                Err(e) => {
+                    tracing::debug!("Outbox not flushed: {}", e);
+                    flusher.request_flush();
+                }
+            }
+        }
+    });
+    outbox
+}
+
+/// What a browser connection needs to reconnect itself
+#[cfg(target_arch = "wasm32")]
+#[derive(Clone)]
+struct WasmLink {
+    samod: Arc<Repo>,
+    connection_state: Arc<RwLock<ConnectionState>>,
+    ws_url: Arc<RwLock<Option<String>>>,
+    tracker: Arc<SyncTracker>,
+    outbox: Arc<Outbox>,
+}
+
+/// A [`WasmLink`] that doesn't keep the TonkCore alive
+#[cfg(target_arch = "wasm32")]
+struct WeakLink {
+    samod: std::sync::Weak<Repo>,
+    connection_state: std::sync::Weak<RwLock<ConnectionState>>,
+    ws_url: std::sync::Weak<RwLock<Option<String>>>,
+    tracker: std::sync::Weak<SyncTracker>,
+    outbox: std::sync::Weak<Outbox>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl WeakLink {
+    fn upgrade(&self) -> Option<WasmLink> {
+        Some(WasmLink {
+            samod: self.samod.upgrade()?,
+            connection_state: self.connection_state.upgrade()?,
+            ws_url: self.ws_url.upgrade()?,
+            tracker: self.tracker.upgrade()?,
+            outbox: self.outbox.upgrade()?,
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl WasmLink {
+    fn downgrade(&self) -> WeakLink {
+        WeakLink {
+            samod: Arc::downgrade(&self.samod),
+            connection_state: Arc::downgrade(&self.connection_state),
+            ws_url: Arc::downgrade(&self.ws_url),
+            tracker: Arc::downgrade(&self.tracker),
+            outbox: Arc::downgrade(&self.outbox),
+        }
+    }
+
+    async fn connect(self, url: String) {
+        use tracing::Instrument;
+
+        // The connection outlives this call, so its tasks carry the span themselves
+        let span =
+            tracing::info_span!("sync_connection", url = %url, peer_id = %self.samod.peer_id());
+        info!(parent: &span, "Connecting to WebSocket peer at: {}", url);
+
+        {
+            let mut state = self.connection_state.write().await;
+            *state = ConnectionState::Connecting;
+        }
+
+        let events = self.samod.connect_wasm_websocket_observable(
+            &crate::websocket::client_url(&url),
+            samod::ConnDirection::Outgoing,
+        );
+
+        let state_for_open = Arc::clone(&self.connection_state);
+        wasm_bindgen_futures::spawn_local(
+            async move {
+                if events.on_open.await.is_ok() {
+                    let mut state = state_for_open.write().await;
+                    *state = ConnectionState::Open;
+                }
+            }
+            .instrument(span.clone()),
+        );
+
+        let link = self.clone();
+        wasm_bindgen_futures::spawn_local(
+            async move {
+                if events.on_ready.await.is_err() {
+                    return;
+                }
+                {
+                    let mut state = link.connection_state.write().await;
+                    *state = ConnectionState::Connected;
+                    link.tracker.connection_opened();
+                }
+                link.outbox.reset_reconnect_attempts();
+
+                // Changes made offline go out with the initial sync
+                link.outbox.request_flush();
+            }
+            .instrument(span.clone()),
+        );
+
+        let link = self;
+        wasm_bindgen_futures::spawn_local(
+            async move {
+                let reason = events.finished.await;
+
+                {
+                    let mut state = link.connection_state.write().await;
+                    if *state == ConnectionState::Connected {
+                        link.tracker.connection_closed();
+                    }
+                    match reason {
+                        samod::ConnFinishedReason::Error(e) => {
+                            *state = ConnectionState::Failed(e);
+                        }
+                        _ => {
+                            *state = ConnectionState::Disconnected;
+                        }
+                    }
+                }
+                link.reconnect_later();
+            }
+            .instrument(span.clone()),
+        );
+
+        info!(parent: &span, "WebSocket connection initiated at: {}", url);
+    }
+
+    /// Retry the connection once the reconnect policy's delay has passed
+    fn reconnect_later(self) {
+        let Some(delay) = self.outbox.next_reconnect_delay() else {
+            return;
+        };
+        info!("Reconnecting in {:?}", delay);
+        wasm_bindgen_futures::spawn_local(async move {
+            crate::vfs::watcher::sleep(delay).await;
+            self.reconnect().await;
+        });
+    }
+
+    /// Reconnect to the last relay unless a connection is already up or underway
+    async fn reconnect(self) {
+        let idle = matches!(
+            *self.connection_state.read().await,
+            ConnectionState::Disconnected | ConnectionState::Failed(_)
+        );
+        let url = self.ws_url.read().await.clone();
+        if let (true, Some(url)) = (idle, url) {
+            self.connect(url).await;
+        }
     }
 
-    /// Create a new document
-    pub async fn create_document(&self, initial_doc: automerge::Automerge) -> Result<DocHandle> {
-        let handle = self
-            .samod
-            .create(initial_doc)
-            .await
-            .map_err(|e| VfsError::SamodError(format!("Failed to create document: {e}")))?;
+    /// Reconnect straight away whenever the browser comes back online
+    fn watch_online(&self) {
+        use wasm_bindgen::closure::Closure;
+        use wasm_bindgen::JsCast;
 
-        Ok(handle)
-    }
-}
+        if !self.outbox.claim_online_listener() {
+            return;
+        }
 
-impl Clone for TonkCore {
-    fn clone(&self) -> Self {
-        Self {
-            samod: Arc::clone(&self.samod),
-            vfs: Arc::clone(&self.vfs),
-            #[cfg(target_arch = "wasm32")]
-            connection_state: Arc::clone(&self.connection_state),
-            #[cfg(target_arch = "wasm32")]
-            ws_url: Arc::clone(&self.ws_url),
+        let weak = self.downgrade();
+        let on_online = Closure::<dyn FnMut()>::new(move || {
+            if let Some(link) = weak.upgrade() {
+                link.outbox.reset_reconnect_attempts();
+                wasm_bindgen_futures::spawn_local(link.reconnect());
+            }
+        });
+        let target: web_sys::EventTarget = js_sys::global().unchecked_into();
+        if target
+            .add_event_listener_with_callback("online", on_online.as_ref().unchecked_ref())
+            .is_ok()
+        {
+            // The listener only holds weak references, so it is left installed
+            on_online.forget();
         }
     }
 }
@@ -841,6 +2495,7 @@ mod tests {
         assert_eq!(tonk.peer_id(), samod.peer_id());
     }
 
+    #[cfg(feature = "websocket")]
     #[tokio::test]
     async fn test_websocket_connection_failure() {
         let tonk = TonkCore::new().await.unwrap();
@@ -860,6 +2515,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "bundle")]
     #[tokio::test]
     async fn test_bundle_export() {
         // Create a new sync engine and add some data
@@ -884,7 +2540,7 @@ mod tests {
     }
 
     #[tokio::test]
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(all(not(target_arch = "wasm32"), feature = "bundle"))]
     async fn test_bundle_round_trip() {
         // Create first engine with some data
         let tonk1 = TonkCore::new().await.unwrap();
@@ -955,6 +2611,104 @@ mod tests {
         assert_eq!(doc_node.content, "test content");
     }
 
+    #[cfg(feature = "bundle")]
+    #[tokio::test]
+    async fn test_bundle_import_limits() {
+        let tonk = TonkCore::new().await.unwrap();
+        tonk.vfs()
+            .create_document("/test.txt", "limited".to_string())
+            .await
+            .unwrap();
+        let bundle_bytes = tonk.to_bytes(None).await.unwrap();
+
+        // A bundle larger than the limit is rejected before import
+        let result = TonkCore::builder()
+            .with_import_limits(ImportLimits::default().with_max_total_bytes(1))
+            .from_bytes(bundle_bytes.clone())
+            .await;
+        assert!(matches!(result, Err(VfsError::ImportLimitExceeded(_))));
+
+        // A bundle within limits imports and reports progress
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reports_clone = reports.clone();
+        let tonk2 = TonkCore::builder()
+            .with_import_limits(ImportLimits::default().with_yield_every(1))
+            .with_import_progress(Arc::new(move |progress| {
+                reports_clone.lock().unwrap().push(progress.clone());
+            }))
+            .from_bytes(bundle_bytes)
+            .await
+            .unwrap();
+        assert!(tonk2.vfs().exists("/test.txt").await.unwrap());
+
+        let reports = reports.lock().unwrap();
+        let last = reports.last().unwrap();
+        assert!(last.entries_total > 0);
+        assert_eq!(last.entries_imported, last.entries_total);
+        assert_eq!(last.bytes_imported, last.bytes_total);
+    }
+
+    #[cfg(feature = "bundle")]
+    #[tokio::test]
+    async fn test_bundle_import_from_byte_stream() {
+        use crate::vfs::backend::AutomergeHelpers;
+
+        let tonk = TonkCore::new().await.unwrap();
+        tonk.vfs()
+            .create_document("/docs/streamed.txt", "streamed content".to_string())
+            .await
+            .unwrap();
+        let bundle_bytes = tonk.to_bytes(None).await.unwrap();
+        let chunks = || {
+            futures::stream::iter(
+                bundle_bytes
+                    .chunks(100)
+                    .map(|chunk| Ok(chunk.to_vec()))
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reports_clone = reports.clone();
+        let tonk2 = TonkCore::builder()
+            .with_import_progress(Arc::new(move |progress| {
+                reports_clone.lock().unwrap().push(progress.clone());
+            }))
+            .from_byte_stream(chunks())
+            .await
+            .unwrap();
+
+        let handle = tonk2
+            .vfs()
+            .find_document("/docs/streamed.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        let doc_node: crate::vfs::types::DocNode<String> =
+            AutomergeHelpers::read_document(&handle).unwrap();
+        assert_eq!(doc_node.content, "streamed content");
+
+        let reports = reports.lock().unwrap();
+        let last = reports.last().unwrap();
+        assert!(last.entries_total > 0);
+        assert_eq!(last.entries_imported, last.entries_total);
+
+        // Limits are enforced as entries arrive
+        let result = TonkCore::builder()
+            .with_import_limits(ImportLimits::default().with_max_total_bytes(1))
+            .from_byte_stream(chunks())
+            .await;
+        assert!(matches!(result, Err(VfsError::ImportLimitExceeded(_))));
+
+        // A truncated download is rejected
+        let truncated =
+            futures::stream::iter(vec![Ok(bundle_bytes[..bundle_bytes.len() / 2].to_vec())]);
+        assert!(TonkCore::builder()
+            .from_byte_stream(truncated)
+            .await
+            .is_err());
+    }
+
     #[tokio::test]
     #[cfg(not(target_arch = "wasm32"))]
     async fn test_filesystem_storage() {
@@ -1002,7 +2756,7 @@ mod tests {
     }
 
     #[tokio::test]
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(all(not(target_arch = "wasm32"), feature = "bundle"))]
     async fn test_bundle_with_in_memory_storage() {
         use crate::vfs::backend::AutomergeHelpers;
 
@@ -1039,8 +2793,494 @@ mod tests {
         assert_eq!(doc_node.content, "bundle test");
     }
 
+    #[cfg(feature = "bundle")]
+    #[tokio::test]
+    async fn test_space_profile() {
+        let tonk = TonkCore::new().await.unwrap();
+        assert!(tonk.profile().await.unwrap().is_none());
+
+        let profile = SpaceProfile {
+            name: "Notes".to_string(),
+            description: Some("Shared notes".to_string()),
+            icon: Some(vec![0x89, b'P', b'N', b'G']),
+            icon_type: Some("image/png".to_string()),
+            created_by: Some("did:key:z6Mk".to_string()),
+        };
+        tonk.set_profile(&profile).await.unwrap();
+        assert_eq!(tonk.profile().await.unwrap(), Some(profile.clone()));
+
+        // Replacing the profile clears fields it no longer has
+        let renamed = SpaceProfile {
+            name: "Journal".to_string(),
+            ..Default::default()
+        };
+        tonk.set_profile(&renamed).await.unwrap();
+        assert_eq!(tonk.profile().await.unwrap(), Some(renamed.clone()));
+
+        // The profile travels with the bundle
+        let bundle = Bundle::from_bytes(tonk.to_bytes(None).await.unwrap()).unwrap();
+        let loaded = TonkCore::from_bundle(bundle, StorageConfig::InMemory)
+            .await
+            .unwrap();
+        assert_eq!(loaded.profile().await.unwrap(), Some(renamed));
+    }
+
+    #[tokio::test]
+    #[cfg(all(not(target_arch = "wasm32"), feature = "bundle"))]
+    async fn test_mounts_round_trip() {
+        use crate::vfs::backend::AutomergeHelpers;
+
+        let tonk = TonkCore::new().await.unwrap();
+        let data = tonk.create_mount("data", 1, true).await.unwrap();
+        tonk.create_mount("scratch", 5, false).await.unwrap();
+        tonk.create_mount("settings", 10, true).await.unwrap();
+        assert!(matches!(
+            tonk.create_mount("data", 0, true).await,
+            Err(VfsError::MountExists(_))
+        ));
+
+        data.create_document("/notes.txt", "mounted".to_string())
+            .await
+            .unwrap();
+        assert!(!tonk.vfs().exists("/notes.txt").await.unwrap());
+
+        let bundle = Bundle::from_bytes(tonk.to_bytes(None).await.unwrap()).unwrap();
+        let roots = &bundle.manifest().roots;
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots["data"].root_id, data.root_id().to_string());
+
+        let loaded = TonkCore::from_bundle(bundle, StorageConfig::InMemory)
+            .await
+            .unwrap();
+        let names: Vec<String> = loaded.mounts().into_iter().map(|m| m.name).collect();
+        assert_eq!(names, vec!["settings", "data"]);
+        assert!(loaded.mount("scratch").is_none());
+
+        let handle = loaded
+            .mount("data")
+            .unwrap()
+            .find_document("/notes.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        let doc_node: crate::vfs::types::DocNode<String> =
+            AutomergeHelpers::read_document(&handle).unwrap();
+        assert_eq!(doc_node.content, "mounted");
+    }
+
+    #[tokio::test]
+    #[cfg(all(not(target_arch = "wasm32"), feature = "bundle"))]
+    async fn test_mount_space() {
+        use crate::vfs::backend::AutomergeHelpers;
+
+        let team = TonkCore::new().await.unwrap();
+        team.vfs()
+            .create_document("/notes.txt", "team".to_string())
+            .await
+            .unwrap();
+        let team_bytes = team.to_bytes(None).await.unwrap();
+
+        let tonk = TonkCore::new().await.unwrap();
+        let mounted = tonk
+            .mount_space(MountSource::Bundle(team_bytes), "/mnt/team/")
+            .await
+            .unwrap();
+        for overlapping in ["/mnt/team", "/mnt", "/mnt/team/sub"] {
+            assert!(matches!(
+                tonk.mount_space(MountSource::Space(mounted.root_id()), overlapping)
+                    .await,
+                Err(VfsError::MountExists(_))
+            ));
+        }
+
+        let (vfs, path) = tonk.vfs_for_path("/mnt/team/notes.txt");
+        assert_eq!(vfs.root_id(), mounted.root_id());
+        assert_eq!(path, "/notes.txt");
+        let handle = vfs.find_document(&path).await.unwrap().unwrap();
+        let doc_node: crate::vfs::types::DocNode<String> =
+            AutomergeHelpers::read_document(&handle).unwrap();
+        assert_eq!(doc_node.content, "team");
+
+        // The bundle's documents keep their IDs, so the mount syncs with the team
+        assert_eq!(mounted.root_id(), team.vfs().root_id());
+
+        let (vfs, path) = tonk.vfs_for_path("/mnt/teamwork");
+        assert_eq!(vfs.root_id(), tonk.vfs().root_id());
+        assert_eq!(path, "/mnt/teamwork");
+
+        let nodes = tonk
+            .read_many(&["/mnt/team/notes.txt".to_string(), "/notes.txt".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes["/mnt/team/notes.txt"].content, "team");
+        assert!(matches!(
+            tonk.vfs_for_paths("/mnt/team/notes.txt", "/notes.txt"),
+            Err(VfsError::InvalidPath(_))
+        ));
+        let (vfs, _, to) = tonk
+            .vfs_for_paths("/mnt/team/notes.txt", "/mnt/team/copy.txt")
+            .unwrap();
+        assert_eq!(to, "/copy.txt");
+        assert_eq!(tonk.namespace_path(&vfs, &to), "/mnt/team/copy.txt");
+
+        // A space already in the repo can be mounted by its root ID
+        tonk.mount_space(MountSource::Space(mounted.root_id()), "/mirror")
+            .await
+            .unwrap();
+        let (vfs, path) = tonk.vfs_for_path("/mirror/notes.txt");
+        assert!(vfs.exists(&path).await.unwrap());
+
+        // Mounted spaces stay out of the space's own export
+        let bundle = Bundle::from_bytes(tonk.to_bytes(None).await.unwrap()).unwrap();
+        assert!(bundle.manifest().roots.is_empty());
+        assert!(!tonk.vfs().exists("/notes.txt").await.unwrap());
+
+        assert!(tonk.unmount_space("/mirror").await.unwrap());
+        assert!(!tonk.unmount_space("/mirror").await.unwrap());
+        let (vfs, _) = tonk.vfs_for_path("/mirror/notes.txt");
+        assert_eq!(vfs.root_id(), tonk.vfs().root_id());
+    }
+
+    #[tokio::test]
+    #[cfg(all(not(target_arch = "wasm32"), feature = "bundle"))]
+    async fn test_mounts_are_restored_on_load() {
+        let team = TonkCore::new().await.unwrap();
+        team.vfs()
+            .create_document("/notes.txt", "team".to_string())
+            .await
+            .unwrap();
+        let team_bytes = team.to_bytes(None).await.unwrap();
+        let space_bytes = TonkCore::new().await.unwrap().to_bytes(None).await.unwrap();
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = || StorageConfig::Filesystem(temp_dir.path().to_path_buf());
+        {
+            let tonk =
+                TonkCore::from_bundle(Bundle::from_bytes(space_bytes.clone()).unwrap(), storage())
+                    .await
+                    .unwrap();
+            tonk.mount_space(MountSource::Bundle(team_bytes), "/mnt/team")
+                .await
+                .unwrap();
+            tonk.create_mount("scratch", 3, false).await.unwrap();
+        }
+
+        let tonk = TonkCore::from_bundle(Bundle::from_bytes(space_bytes).unwrap(), storage())
+            .await
+            .unwrap();
+        let names: Vec<String> = tonk.mounts().into_iter().map(|m| m.name).collect();
+        assert_eq!(names, vec!["scratch", "/mnt/team"]);
+        let (vfs, path) = tonk.vfs_for_path("/mnt/team/notes.txt");
+        assert_eq!(vfs.root_id(), team.vfs().root_id());
+        assert!(vfs.exists(&path).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_gc_unreachable_documents() {
+        let tonk = TonkCore::new().await.unwrap();
+        let vfs = tonk.vfs();
+        vfs.create_document("/keep.txt", "keep".to_string())
+            .await
+            .unwrap();
+        vfs.create_document("/drop.txt", "drop".to_string())
+            .await
+            .unwrap();
+        let dropped = vfs.metadata("/drop.txt").await.unwrap().pointer.to_string();
+        assert!(tonk
+            .gc(true, Duration::ZERO)
+            .await
+            .unwrap()
+            .unreachable_documents
+            .is_empty());
+
+        vfs.remove_document("/drop.txt").await.unwrap();
+        let report = tonk.gc(true, Duration::ZERO).await.unwrap();
+        assert!(report.dry_run);
+        assert_eq!(report.unreachable_documents, vec![dropped.clone()]);
+        assert!(report.chunks > 0);
+        assert!(report.reclaimable_bytes > 0);
+        // A dry run leaves storage alone
+        assert_eq!(tonk.gc(true, Duration::ZERO).await.unwrap(), report);
+
+        let collected = tonk.gc(false, Duration::ZERO).await.unwrap();
+        assert_eq!(collected.unreachable_documents, vec![dropped]);
+        assert_eq!(collected.reclaimable_bytes, report.reclaimable_bytes);
+        assert!(tonk
+            .gc(true, Duration::ZERO)
+            .await
+            .unwrap()
+            .unreachable_documents
+            .is_empty());
+        assert!(vfs.find_document("/keep.txt").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_gc_defers_young_documents() {
+        let tonk = TonkCore::new().await.unwrap();
+        let vfs = tonk.vfs();
+        vfs.create_document("/drop.txt", "drop".to_string())
+            .await
+            .unwrap();
+        let dropped = vfs.metadata("/drop.txt").await.unwrap().pointer.to_string();
+        vfs.remove_document("/drop.txt").await.unwrap();
+
+        // In-memory storage has no write times, so age counts from the first pass
+        let grace = Duration::from_millis(200);
+        let report = tonk.gc(false, grace).await.unwrap();
+        assert!(report.unreachable_documents.is_empty());
+        assert_eq!(report.deferred_documents, vec![dropped.clone()]);
+        assert_eq!(report.chunks, 0);
+
+        tokio::time::sleep(grace).await;
+        let report = tonk.gc(false, grace).await.unwrap();
+        assert_eq!(report.unreachable_documents, vec![dropped]);
+        assert!(report.deferred_documents.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_gc_ages_filesystem_documents_by_write_time() {
+        let dir = TempDir::new().unwrap();
+        let tonk = TonkCore::builder()
+            .with_storage(StorageConfig::Filesystem(dir.path().to_path_buf()))
+            .with_exclusive_storage()
+            .build()
+            .await
+            .unwrap();
+        let vfs = tonk.vfs();
+        vfs.create_document("/drop.txt", "drop".to_string())
+            .await
+            .unwrap();
+        let dropped = vfs.metadata("/drop.txt").await.unwrap().pointer.to_string();
+        vfs.remove_document("/drop.txt").await.unwrap();
+
+        // Sizes come from file metadata
+        let chunks = tonk.storage.document_chunks(None).await;
+        let (_, stored) = chunks
+            .iter()
+            .find(|(doc_id, _)| doc_id.to_string() == dropped)
+            .unwrap();
+        assert!(stored.iter().all(|chunk| chunk.modified.is_some()));
+
+        let report = tonk.gc(true, Duration::from_secs(3600)).await.unwrap();
+        assert_eq!(report.deferred_documents, vec![dropped.clone()]);
+        let report = tonk.gc(false, Duration::ZERO).await.unwrap();
+        assert_eq!(report.unreachable_documents, vec![dropped]);
+        let bytes: u64 = stored.iter().map(|chunk| chunk.len).sum();
+        assert_eq!(report.reclaimable_bytes, bytes);
+    }
+
+    #[tokio::test]
+    async fn test_gc_refuses_shared_storage() {
+        let dir = TempDir::new().unwrap();
+        let other = TonkCore::builder()
+            .with_storage(StorageConfig::Filesystem(dir.path().to_path_buf()))
+            .build()
+            .await
+            .unwrap();
+        other
+            .vfs()
+            .create_document("/other.txt", "other".to_string())
+            .await
+            .unwrap();
+
+        // Another space's documents in the same directory are out of this one's reach
+        let tonk = TonkCore::builder()
+            .with_storage(StorageConfig::Filesystem(dir.path().to_path_buf()))
+            .build()
+            .await
+            .unwrap();
+        assert!(tonk.gc(false, Duration::ZERO).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gc_keeps_recorded_mounts() {
+        let tonk = TonkCore::new().await.unwrap();
+        let mounted = VirtualFileSystem::new(tonk.samod()).await.unwrap();
+        mounted
+            .create_document("/notes.txt", "notes".to_string())
+            .await
+            .unwrap();
+        tonk.mount_space(MountSource::Space(mounted.root_id()), "/mnt/notes")
+            .await
+            .unwrap();
+        let records = tonk.storage.mount_records(&tonk.vfs().root_id()).await;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].mount_point.as_deref(), Some("/mnt/notes"));
+
+        // A later run that hasn't mounted the space yet still keeps its tree
+        tonk.mounts.write().unwrap().clear();
+        let report = tonk.gc(false, Duration::ZERO).await.unwrap();
+        assert!(report.unreachable_documents.is_empty());
+        assert!(mounted.exists("/notes.txt").await.unwrap());
+
+        // Once unmounted, the tree is garbage
+        tonk.record_mounts().await;
+        let report = tonk.gc(false, Duration::ZERO).await.unwrap();
+        assert!(report
+            .unreachable_documents
+            .contains(&mounted.root_id().to_string()));
+    }
+
+    #[cfg(feature = "bundle")]
+    #[tokio::test]
+    async fn test_compact_document() {
+        let tonk = TonkCore::new().await.unwrap();
+        let vfs = tonk.vfs();
+        vfs.create_document("/notes.txt", "hello".to_string())
+            .await
+            .unwrap();
+        let handle = vfs.find_document("/notes.txt").await.unwrap().unwrap();
+        let doc_id = handle.document_id().clone();
+
+        // Simulate history piling up as incremental chunks
+        let saved = handle.with_document(|doc| doc.save());
+        for name in ["a", "b", "c"] {
+            let key = StorageKey::from_parts([
+                doc_id.to_string(),
+                "incremental".to_string(),
+                name.into(),
+            ])
+            .unwrap();
+            tonk.storage.put(key, saved.clone()).await;
+        }
+        let chunks = |tonk: &TonkCore| {
+            let tonk = tonk.clone();
+            let doc_id = doc_id.clone();
+            async move { tonk.storage.document_chunks(Some(&doc_id)).await[&doc_id].len() }
+        };
+        let before = chunks(&tonk).await;
+        assert!(before >= 4);
+
+        // Documents below the threshold are left alone
+        assert_eq!(tonk.compact_all(before + 1).await.unwrap().documents, 0);
+
+        let report = tonk.compact("/notes.txt").await.unwrap();
+        assert_eq!(report.documents, 1);
+        assert_eq!(report.chunks_removed, before);
+        assert!(report.bytes_reclaimed > 0);
+        assert_eq!(chunks(&tonk).await, 1);
+        assert_eq!(tonk.compact("/notes.txt").await.unwrap().documents, 0);
+
+        let stats = tonk.compaction_stats();
+        assert_eq!(stats.runs, 3);
+        assert_eq!(stats.documents_compacted, 1);
+        assert_eq!(stats.bytes_reclaimed, report.bytes_reclaimed);
+        assert!(matches!(
+            tonk.compact("/missing.txt").await,
+            Err(VfsError::PathNotFound(_))
+        ));
+    }
+
     #[tokio::test]
     #[cfg(not(target_arch = "wasm32"))]
+    async fn test_journal_survives_restart() {
+        use crate::journal::JournalOp;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage_path = temp_dir.path().join("tonk_storage");
+        let open = || {
+            TonkCore::builder()
+                .with_storage(StorageConfig::Filesystem(storage_path.clone()))
+                .build()
+        };
+
+        let tonk = open().await.unwrap();
+        tonk.start_journal(JournalPolicy { max_entries: 3 });
+        for name in ["a", "b", "c", "d"] {
+            tonk.vfs()
+                .create_document(&format!("/{name}.txt"), name.to_string())
+                .await
+                .unwrap();
+        }
+        let entries = timeout(Duration::from_secs(5), async {
+            loop {
+                let entries = tonk.journal_since(0).await;
+                if entries.last().is_some_and(|entry| entry.path == "/d.txt") {
+                    break entries;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        // Only the newest entries are kept
+        assert_eq!(entries.len(), 3);
+        let last = entries.last().unwrap();
+        assert_eq!(last.op, JournalOp::DocumentCreated);
+        assert!(!last.heads.is_empty());
+        assert!(tonk.journal_since(last.cursor).await.is_empty());
+        tonk.stop_journal();
+        drop(tonk);
+
+        // A new instance on the same storage replays and continues the journal
+        let tonk = open().await.unwrap();
+        let replayed = tonk.journal_since(entries[0].cursor).await;
+        assert_eq!(replayed, entries[1..]);
+
+        tonk.start_journal(JournalPolicy::default());
+        tonk.vfs()
+            .create_document("/e.txt", "e".to_string())
+            .await
+            .unwrap();
+        let next = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(entry) = tonk.journal_since(last.cursor).await.pop() {
+                    break entry;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(next.path, "/e.txt");
+        assert_eq!(next.cursor, last.cursor + 1);
+    }
+
+    #[tokio::test]
+    async fn test_journal_records_ahead_and_stops_at_once() {
+        use crate::journal::JournalOp;
+
+        let tonk = TonkCore::new().await.unwrap();
+        tonk.start_journal(JournalPolicy::default());
+        tonk.vfs()
+            .create_document("/a.txt", "a".to_string())
+            .await
+            .unwrap();
+        tonk.vfs()
+            .update_document("/a.txt", "b".to_string())
+            .await
+            .unwrap();
+
+        // Entries are stored by the time the change returns
+        let entries = tonk.journal_since(0).await;
+        let ops: Vec<_> = entries.iter().map(|entry| entry.op).collect();
+        assert_eq!(
+            ops,
+            [JournalOp::DocumentCreated, JournalOp::DocumentUpdated]
+        );
+        assert!(entries.iter().all(|entry| entry.path == "/a.txt"));
+        let handle = tonk.vfs().find_document("/a.txt").await.unwrap().unwrap();
+        let heads: Vec<String> = handle.with_document(|doc| {
+            doc.get_heads()
+                .iter()
+                .map(|head| head.to_string())
+                .collect()
+        });
+        assert_ne!(entries[1].heads, heads);
+
+        // Nothing is recorded once stopped
+        tonk.stop_journal();
+        tonk.vfs()
+            .create_document("/b.txt", "b".to_string())
+            .await
+            .unwrap();
+        assert!(tonk.journal_since(entries[1].cursor).await.is_empty());
+    }
+
+    #[tokio::test]
+    #[cfg(all(not(target_arch = "wasm32"), feature = "bundle"))]
     async fn test_fork_to_bytes() {
         use crate::vfs::backend::AutomergeHelpers;
 
@@ -1187,4 +3427,55 @@ mod tests {
             "/outside.txt should NOT exist in fork"
         );
     }
+
+    #[tokio::test]
+    async fn test_wait_for_sync_needs_a_peer_to_catch_up() {
+        let tonk = TonkCore::new().await.unwrap();
+        tonk.vfs()
+            .create_document("/notes.txt", "unsynced".to_string())
+            .await
+            .unwrap();
+
+        // Nothing is ever acknowledged without a peer, however quiet it gets
+        match tonk.wait_for_sync(Duration::from_millis(400)).await {
+            Err(VfsError::SyncTimeout(reason)) => {
+                assert!(reason.contains("2 documents not in sync"), "{}", reason)
+            }
+            other => panic!("Expected a sync timeout, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "websocket")]
+    #[tokio::test]
+    async fn test_disconnect_ends_connections() {
+        use futures::StreamExt;
+
+        // A peer that keeps every connection open until the client closes it
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    if let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await {
+                        while let Some(Ok(_)) = ws.next().await {}
+                    }
+                });
+            }
+        });
+
+        let tonk = TonkCore::new().await.unwrap();
+        let connection = tokio::spawn({
+            let tonk = tonk.clone();
+            async move { tonk.connect_websocket(&url).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        tonk.disconnect().await;
+        timeout(Duration::from_secs(5), connection)
+            .await
+            .expect("connect_websocket should return once disconnected")
+            .unwrap()
+            .unwrap();
+        assert_eq!(tonk.sync_status().connections, 0);
+    }
 }
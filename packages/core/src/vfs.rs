@@ -1,10 +1,22 @@
 pub mod backend;
+pub mod events;
 pub mod filesystem;
+pub mod mime;
+pub mod mount;
 pub mod path_index;
+mod tar;
+pub mod traversal;
 pub mod types;
+pub mod validation;
 pub mod watcher;
 
+pub use events::{
+    EventChannelConfig, EventChannelStats, EventReceiver, OverflowPolicy, Throttle, ThrottledEvents,
+};
 pub use filesystem::*;
+pub use mount::{Mount, MountSource};
 pub use path_index::{PathEntry, PathIndex};
+pub use traversal::{TraversalLimits, TraversalProgress, TraversalProgressCallback};
 pub use types::*;
-pub use watcher::DocumentWatcher;
+pub use validation::{JsonSchema, Validator};
+pub use watcher::{BatchWatcher, DocumentWatcher};
@@ -1,8 +1,11 @@
 use crate::error::{Result, VfsError};
+use crate::identity::{Author, Identity};
+use crate::vfs::path_index::PATH_INDEX_VERSION;
 use crate::vfs::types::*;
 use automerge::{transaction::Transactable, ObjType, ReadDoc, ScalarValue, Value};
 use bytes::Bytes;
 use samod::{DocHandle, DocumentId};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 /// Helper functions for working with Automerge documents in the VFS
 pub struct AutomergeHelpers;
@@ -13,6 +16,7 @@ impl AutomergeHelpers {
         handle.with_document(|doc| {
             let mut tx = doc.transaction();
             tx.put(automerge::ROOT, "type", "directory")?;
+            tx.put(automerge::ROOT, "version", NODE_SCHEMA_VERSION as i64)?;
             tx.put(automerge::ROOT, "name", name)?;
 
             let now = chrono::Utc::now().timestamp_millis();
@@ -28,6 +32,46 @@ impl AutomergeHelpers {
         })
     }
 
+    /// Initialize a document as a symbolic link to `target`
+    pub fn init_as_symlink(handle: &DocHandle, name: &str, target: &str) -> Result<()> {
+        handle.with_document(|doc| {
+            let mut tx = doc.transaction();
+            tx.put(automerge::ROOT, "type", "symlink")?;
+            tx.put(automerge::ROOT, "version", NODE_SCHEMA_VERSION as i64)?;
+            tx.put(automerge::ROOT, "name", name)?;
+            tx.put(automerge::ROOT, "target", target)?;
+
+            let now = chrono::Utc::now().timestamp_millis();
+            let timestamps_obj =
+                tx.put_object(automerge::ROOT, "timestamps", automerge::ObjType::Map)?;
+            tx.put(timestamps_obj.clone(), "created", now)?;
+            tx.put(timestamps_obj, "modified", now)?;
+
+            tx.commit();
+            Ok(())
+        })
+    }
+
+    /// Read the target path of a symbolic link
+    pub fn read_symlink_target(handle: &DocHandle) -> Result<String> {
+        handle.with_document(|doc| {
+            let node_type = doc
+                .get(automerge::ROOT, "type")?
+                .and_then(|(value, _)| Self::extract_string_value(&value))
+                .unwrap_or_default();
+            if node_type != "symlink" {
+                return Err(VfsError::NodeTypeMismatch {
+                    expected: "symlink".to_string(),
+                    actual: node_type,
+                });
+            }
+
+            doc.get(automerge::ROOT, "target")?
+                .and_then(|(value, _)| Self::extract_string_value(&value))
+                .ok_or(VfsError::InvalidDocumentStructure)
+        })
+    }
+
     /// Read a directory node from an Automerge document
     pub fn read_directory(handle: &DocHandle) -> Result<DirNode> {
         handle.with_document(|doc| {
@@ -74,6 +118,7 @@ impl AutomergeHelpers {
 
             Ok(DirNode {
                 node_type: NodeType::Directory,
+                version: Self::read_schema_version(doc),
                 name,
                 timestamps,
                 children,
@@ -171,6 +216,7 @@ impl AutomergeHelpers {
         handle.with_document(|doc| {
             let mut tx = doc.transaction();
             tx.put(automerge::ROOT, "type", "document")?;
+            tx.put(automerge::ROOT, "version", NODE_SCHEMA_VERSION as i64)?;
             tx.put(automerge::ROOT, "name", name)?;
 
             let now = chrono::Utc::now().timestamp_millis();
@@ -222,6 +268,7 @@ impl AutomergeHelpers {
         handle.with_document(|doc| {
             let mut tx = doc.transaction();
             tx.put(automerge::ROOT, "type", "document")?;
+            tx.put(automerge::ROOT, "version", NODE_SCHEMA_VERSION as i64)?;
             tx.put(automerge::ROOT, "name", name)?;
 
             let now = chrono::Utc::now().timestamp_millis();
@@ -264,6 +311,72 @@ impl AutomergeHelpers {
         })
     }
 
+    /// Read the layout version of a node, treating unversioned nodes as version 0
+    pub fn read_schema_version<R: ReadDoc>(doc: &R) -> u32 {
+        match doc.get(automerge::ROOT, "version") {
+            Ok(Some((Value::Scalar(scalar), _))) => match scalar.as_ref() {
+                ScalarValue::Int(v) => u32::try_from(*v).unwrap_or(0),
+                ScalarValue::Uint(v) => u32::try_from(*v).unwrap_or(0),
+                _ => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    /// Upgrade a directory or document node to the current layout version
+    ///
+    /// Converts legacy JSON-string document content to native Automerge objects and
+    /// stamps the current version. Nodes already at (or beyond) the current version
+    /// are left alone. Returns `true` if the node was changed.
+    pub fn upgrade_node(handle: &DocHandle) -> Result<bool> {
+        handle.with_document(|doc| {
+            if Self::read_schema_version(doc) >= NODE_SCHEMA_VERSION {
+                return Ok(false);
+            }
+
+            // Legacy documents store content as a JSON string
+            let legacy_content = match doc.get(automerge::ROOT, "content")? {
+                Some((value @ Value::Scalar(_), _)) => {
+                    let content_str = Self::extract_string_value(&value)
+                        .ok_or(VfsError::InvalidDocumentStructure)?;
+                    Some(
+                        serde_json::from_str::<serde_json::Value>(&content_str)
+                            .map_err(VfsError::SerializationError)?,
+                    )
+                }
+                _ => None,
+            };
+
+            let mut tx = doc.transaction();
+            if let Some(content) = legacy_content {
+                match &content {
+                    serde_json::Value::Object(map) => {
+                        let content_obj =
+                            tx.put_object(automerge::ROOT, "content", ObjType::Map)?;
+                        for (k, v) in map {
+                            Self::put_json_value(&mut tx, content_obj.clone(), k, v)?;
+                        }
+                    }
+                    serde_json::Value::Array(arr) => {
+                        let content_obj =
+                            tx.put_object(automerge::ROOT, "content", ObjType::List)?;
+                        for (i, item) in arr.iter().enumerate() {
+                            Self::insert_json_value(&mut tx, content_obj.clone(), i, item)?;
+                        }
+                    }
+                    _ => {
+                        let content_obj =
+                            tx.put_object(automerge::ROOT, "content", ObjType::Map)?;
+                        Self::put_json_value(&mut tx, content_obj, "value", &content)?;
+                    }
+                }
+            }
+            tx.put(automerge::ROOT, "version", NODE_SCHEMA_VERSION as i64)?;
+            tx.commit();
+            Ok(true)
+        })
+    }
+
     // Helper functions
     pub fn extract_string_value(value: &Value) -> Option<String> {
         match value {
@@ -376,12 +489,10 @@ impl AutomergeHelpers {
     }
 
     /// Read an Automerge value and convert it to a JSON value
-    fn read_automerge_value(
-        doc: &automerge::Automerge,
+    fn read_automerge_value<R: ReadDoc>(
+        doc: &R,
         obj_id: automerge::ObjId,
     ) -> Result<serde_json::Value> {
-        use automerge::ReadDoc;
-
         let obj_type = doc.object_type(&obj_id);
 
         match obj_type {
@@ -415,8 +526,8 @@ impl AutomergeHelpers {
     }
 
     /// Convert an Automerge Value to a JSON value
-    fn value_to_json(
-        doc: &automerge::Automerge,
+    fn value_to_json<R: ReadDoc>(
+        doc: &R,
         value: &Value,
         obj_id: automerge::ObjId,
     ) -> Result<serde_json::Value> {
@@ -606,10 +717,7 @@ impl AutomergeHelpers {
             .and_then(|(value, _)| Self::extract_string_value(&value))
             .unwrap_or_else(|| "document".to_string());
 
-        let node_type = match node_type_str.as_str() {
-            "directory" => NodeType::Directory,
-            _ => NodeType::Document,
-        };
+        let node_type = NodeType::from_str(&node_type_str).unwrap_or(NodeType::Document);
 
         let pointer_str = doc
             .get(obj_id.clone(), "pointer")
@@ -629,6 +737,7 @@ impl AutomergeHelpers {
             node_type,
             timestamps,
             name,
+            metadata: Metadata::new(),
         })
     }
 
@@ -650,10 +759,7 @@ impl AutomergeHelpers {
             .and_then(|(value, _)| Self::extract_string_value(&value))
             .unwrap_or_else(|| "document".to_string());
 
-        let node_type = match node_type_str.as_str() {
-            "directory" => NodeType::Directory,
-            _ => NodeType::Document,
-        };
+        let node_type = NodeType::from_str(&node_type_str).unwrap_or(NodeType::Document);
 
         let pointer_str = tx
             .get(obj_id.clone(), "pointer")
@@ -673,6 +779,7 @@ impl AutomergeHelpers {
             node_type,
             timestamps,
             name,
+            metadata: Metadata::new(),
         })
     }
 
@@ -682,11 +789,7 @@ impl AutomergeHelpers {
         ref_node: &RefNode,
     ) -> Result<()> {
         tx.put(obj_id.clone(), "name", ref_node.name.clone())?;
-        let type_str = match ref_node.node_type {
-            NodeType::Directory => "directory",
-            NodeType::Document => "document",
-        };
-        tx.put(obj_id.clone(), "type", type_str)?;
+        tx.put(obj_id.clone(), "type", ref_node.node_type.as_str())?;
         tx.put(obj_id.clone(), "pointer", ref_node.pointer.to_string())?;
 
         let timestamps_obj = tx.put_object(obj_id, "timestamps", automerge::ObjType::Map)?;
@@ -792,6 +895,7 @@ impl AutomergeHelpers {
 
             Ok(DocNode {
                 node_type: NodeType::Document,
+                version: Self::read_schema_version(doc),
                 name,
                 timestamps,
                 content,
@@ -860,6 +964,7 @@ impl AutomergeHelpers {
 
             Ok(DocNode {
                 node_type: NodeType::Document,
+                version: Self::read_schema_version(doc),
                 name,
                 timestamps,
                 content,
@@ -1240,6 +1345,125 @@ impl AutomergeHelpers {
         })
     }
 
+    /// Apply a JSON merge patch (RFC 7386) at a specific path
+    /// path: ["content", "x"] -> merges into doc.content.x
+    ///
+    /// Objects in the patch are merged key by key into existing objects, `null`
+    /// deletes a key, and any other value (including arrays) replaces the target.
+    /// Only keys whose values actually differ are written, so unrelated concurrent
+    /// edits are left untouched. Returns `false` if the patch changed nothing.
+    pub fn merge_patch_document(
+        handle: &DocHandle,
+        path: &[String],
+        patch: &serde_json::Value,
+    ) -> Result<bool> {
+        if path.is_empty() {
+            return Err(VfsError::Other(anyhow::anyhow!("Path cannot be empty")));
+        }
+
+        handle.with_document(|doc| {
+            // Navigate and read the current target BEFORE creating transaction
+            let (parent_obj, final_key) = Self::navigate_to_parent(doc, path)?;
+            let target = match doc.get(parent_obj.clone(), final_key.as_str())? {
+                Some((value, id)) => Some(Self::value_to_json(doc, &value, id)?),
+                None => None,
+            };
+
+            let mut tx = doc.transaction();
+
+            let changed = match (target, patch) {
+                (None, serde_json::Value::Null) => false,
+                (Some(_), serde_json::Value::Null) => {
+                    tx.delete(parent_obj, final_key.as_str())?;
+                    true
+                }
+                (
+                    Some(serde_json::Value::Object(target_map)),
+                    serde_json::Value::Object(patch_map),
+                ) => match tx.get(parent_obj.clone(), final_key.as_str())? {
+                    Some((Value::Object(ObjType::Map), obj_id)) => {
+                        Self::apply_merge_patch(&mut tx, obj_id, &target_map, patch_map)?
+                    }
+                    _ => {
+                        let merged = Self::strip_nulls(patch);
+                        Self::put_json_value(&mut tx, parent_obj, &final_key, &merged)?;
+                        true
+                    }
+                },
+                (Some(target), _) if Self::json_values_equal(&target, patch) => false,
+                _ => {
+                    let value = Self::strip_nulls(patch);
+                    Self::put_json_value(&mut tx, parent_obj, &final_key, &value)?;
+                    true
+                }
+            };
+
+            if changed {
+                Self::update_modified_timestamp(&mut tx, automerge::ROOT)?;
+                tx.commit();
+            }
+            Ok(changed)
+        })
+    }
+
+    /// Merge a patch object into an existing Automerge map, writing only differences
+    fn apply_merge_patch(
+        tx: &mut automerge::transaction::Transaction<'_>,
+        obj_id: automerge::ObjId,
+        target: &serde_json::Map<String, serde_json::Value>,
+        patch: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<bool> {
+        let mut changed = false;
+
+        for (key, patch_value) in patch {
+            match (target.get(key), patch_value) {
+                (None, serde_json::Value::Null) => {}
+                (Some(_), serde_json::Value::Null) => {
+                    tx.delete(obj_id.clone(), key.as_str())?;
+                    changed = true;
+                }
+                (
+                    Some(serde_json::Value::Object(target_map)),
+                    serde_json::Value::Object(patch_map),
+                ) => {
+                    if let Ok(Some((Value::Object(ObjType::Map), nested_id))) =
+                        tx.get(obj_id.clone(), key.as_str())
+                    {
+                        changed |= Self::apply_merge_patch(tx, nested_id, target_map, patch_map)?;
+                    } else {
+                        Self::put_json_value(
+                            tx,
+                            obj_id.clone(),
+                            key,
+                            &Self::strip_nulls(patch_value),
+                        )?;
+                        changed = true;
+                    }
+                }
+                (Some(target_value), _) if Self::json_values_equal(target_value, patch_value) => {}
+                _ => {
+                    Self::put_json_value(tx, obj_id.clone(), key, &Self::strip_nulls(patch_value))?;
+                    changed = true;
+                }
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// Remove `null` members from objects, as a merge patch applied to nothing would
+    fn strip_nulls(value: &serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Object(map) => serde_json::Value::Object(
+                map.iter()
+                    .filter(|(_, v)| !v.is_null())
+                    .map(|(k, v)| (k.clone(), Self::strip_nulls(v)))
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+
     /// Patch a document at a specific path
     /// path: ["content", "x"] -> updates doc.content.x
     pub fn patch_document(
@@ -1386,168 +1610,930 @@ impl AutomergeHelpers {
     }
 
     // ============================================================================
-    // Path Index Helpers (Native Automerge Structure)
+    // Child Ordering Helpers
     // ============================================================================
 
-    /// Initialize a document as a path index with native Automerge structure
-    ///
-    /// Structure:
-    /// ```text
-    /// ROOT
-    /// ├── type: "path_index"
-    /// ├── version: 2
-    /// ├── last_updated: i64
-    /// └── entries: Map (path -> entry)
-    /// ```
-    pub fn init_as_path_index(handle: &DocHandle) -> Result<()> {
-        handle.with_document(|doc| {
-            let mut tx = doc.transaction();
-            tx.put(automerge::ROOT, "type", "path_index")?;
-            tx.put(automerge::ROOT, "version", 2i64)?;
-            tx.put(
-                automerge::ROOT,
-                "last_updated",
-                chrono::Utc::now().timestamp_millis(),
-            )?;
-            tx.put_object(automerge::ROOT, "entries", ObjType::Map)?;
-            tx.commit();
-            Ok(())
-        })
-    }
-
-    /// Read the entire path index from native Automerge structure
-    pub fn read_path_index_native(handle: &DocHandle) -> Result<crate::vfs::path_index::PathIndex> {
-        use crate::vfs::path_index::PathIndex;
-
+    /// Read the explicit child order of a directory, dropping duplicate names
+    pub fn read_child_order(handle: &DocHandle) -> Result<Vec<String>> {
         handle.with_document(|doc| {
-            let mut index = PathIndex::new();
-
-            // Read last_updated
-            if let Ok(Some((Value::Scalar(s), _))) = doc.get(automerge::ROOT, "last_updated") {
-                if let Some(ts) = s.to_i64() {
-                    if let Some(dt) = chrono::DateTime::from_timestamp_millis(ts) {
-                        index.last_updated = dt;
-                    }
-                }
-            }
-
-            // Read entries map
-            if let Ok(Some((Value::Object(ObjType::Map), entries_id))) =
-                doc.get(automerge::ROOT, "entries")
+            let mut order = Vec::new();
+            if let Ok(Some((Value::Object(ObjType::List), order_id))) =
+                doc.get(automerge::ROOT, "order")
             {
-                // Iterate over all keys in the entries map
-                for key in doc.keys(entries_id.clone()) {
-                    if let Ok(Some((Value::Object(ObjType::Map), entry_id))) =
-                        doc.get(entries_id.clone(), key.as_str())
-                    {
-                        if let Some(entry) = Self::read_path_entry_from_obj(doc, entry_id.clone()) {
-                            index.paths.insert(key.to_string(), entry);
+                let mut seen = std::collections::HashSet::new();
+                for i in 0..doc.length(order_id.clone()) {
+                    if let Ok(Some((value, _))) = doc.get(order_id.clone(), i) {
+                        if let Some(name) = Self::extract_string_value(&value) {
+                            if seen.insert(name.clone()) {
+                                order.push(name);
+                            }
                         }
                     }
                 }
             }
-
-            Ok(index)
+            Ok(order)
         })
     }
 
-    /// Read a single PathEntry from an Automerge object
-    fn read_path_entry_from_obj(
-        doc: &automerge::Automerge,
-        entry_id: automerge::ObjId,
-    ) -> Option<crate::vfs::path_index::PathEntry> {
-        use crate::vfs::path_index::PathEntry;
+    /// Set the explicit child order of a directory
+    ///
+    /// The existing list is edited in place, keeping names common to the old and
+    /// new order, so concurrent reorders from other peers merge instead of one
+    /// replacing the other. Returns `true` if the order changed.
+    pub fn set_child_order(handle: &DocHandle, names: &[String]) -> Result<bool> {
+        handle.with_document(|doc| {
+            let mut tx = doc.transaction();
 
-        let doc_id = doc
-            .get(entry_id.clone(), "doc_id")
-            .ok()
-            .flatten()
-            .and_then(|(v, _)| Self::extract_string_value(&v))?;
+            let order_id = match tx.get(automerge::ROOT, "order") {
+                Ok(Some((Value::Object(ObjType::List), id))) => id,
+                _ => tx.put_object(automerge::ROOT, "order", ObjType::List)?,
+            };
 
-        let node_type_str = doc
-            .get(entry_id.clone(), "node_type")
-            .ok()
-            .flatten()
-            .and_then(|(v, _)| Self::extract_string_value(&v))?;
+            let current: Vec<String> = (0..tx.length(order_id.clone()))
+                .map(|i| {
+                    tx.get(order_id.clone(), i)
+                        .ok()
+                        .flatten()
+                        .and_then(|(value, _)| Self::extract_string_value(&value))
+                        .unwrap_or_default()
+                })
+                .collect();
 
-        let node_type = NodeType::from_str(&node_type_str)?;
+            if current == names {
+                return Ok(false);
+            }
 
-        let created = doc
-            .get(entry_id.clone(), "created")
-            .ok()
-            .flatten()
-            .and_then(|(v, _)| {
-                if let Value::Scalar(s) = v {
-                    s.to_i64().and_then(chrono::DateTime::from_timestamp_millis)
-                } else {
-                    None
+            // Longest common subsequence of the old and new order
+            let (n, m) = (current.len(), names.len());
+            let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+            for i in (0..n).rev() {
+                for j in (0..m).rev() {
+                    lcs[i][j] = if current[i] == names[j] {
+                        lcs[i + 1][j + 1] + 1
+                    } else {
+                        lcs[i + 1][j].max(lcs[i][j + 1])
+                    };
                 }
-            })
-            .unwrap_or_else(chrono::Utc::now);
+            }
 
-        let modified = doc
-            .get(entry_id, "modified")
-            .ok()
-            .flatten()
-            .and_then(|(v, _)| {
-                if let Value::Scalar(s) = v {
-                    s.to_i64().and_then(chrono::DateTime::from_timestamp_millis)
+            // Walk both lists, deleting and inserting around the common names
+            let (mut i, mut j, mut pos) = (0, 0, 0);
+            while i < n || j < m {
+                if i < n && j < m && current[i] == names[j] {
+                    i += 1;
+                    j += 1;
+                    pos += 1;
+                } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+                    tx.insert(order_id.clone(), pos, names[j].as_str())?;
+                    j += 1;
+                    pos += 1;
                 } else {
-                    None
+                    tx.delete(order_id.clone(), pos)?;
+                    i += 1;
                 }
-            })
-            .unwrap_or_else(chrono::Utc::now);
+            }
 
-        Some(PathEntry {
-            doc_id,
-            node_type,
-            created,
-            modified,
+            Self::update_modified_timestamp(&mut tx, automerge::ROOT)?;
+            tx.commit();
+            Ok(true)
         })
     }
 
-    /// Set or update a single path entry
-    pub fn set_path_entry(
-        handle: &DocHandle,
-        path: &str,
-        doc_id: &str,
-        node_type: NodeType,
-        created: Option<chrono::DateTime<chrono::Utc>>,
-    ) -> Result<()> {
-        handle.with_document(|doc| {
-            let mut tx = doc.transaction();
-            let now = chrono::Utc::now();
+    // ============================================================================
+    // Metadata Helpers (Extended Attributes)
+    // ============================================================================
 
-            // Get or create entries map
-            let entries_id = match tx.get(automerge::ROOT, "entries") {
-                Ok(Some((Value::Object(ObjType::Map), id))) => id,
-                _ => tx.put_object(automerge::ROOT, "entries", ObjType::Map)?,
-            };
+    /// Read the `metadata` map of an object, returning an empty map if absent
+    fn read_metadata_map<R: ReadDoc>(doc: &R, obj_id: automerge::ObjId) -> Result<Metadata> {
+        match doc.get(obj_id, "metadata") {
+            Ok(Some((Value::Object(ObjType::Map), meta_id))) => {
+                match Self::read_automerge_value(doc, meta_id)? {
+                    serde_json::Value::Object(map) => Ok(map.into_iter().collect()),
+                    _ => Ok(Metadata::new()),
+                }
+            }
+            _ => Ok(Metadata::new()),
+        }
+    }
 
-            // Check if entry already exists to preserve created timestamp
-            let existing_created =
-                tx.get(entries_id.clone(), path)
-                    .ok()
-                    .flatten()
-                    .and_then(|(v, entry_id)| {
-                        if let Value::Object(ObjType::Map) = v {
-                            tx.get(entry_id, "created")
-                                .ok()
-                                .flatten()
-                                .and_then(|(v, _)| {
-                                    if let Value::Scalar(s) = v {
-                                        s.to_i64().and_then(chrono::DateTime::from_timestamp_millis)
-                                    } else {
-                                        None
-                                    }
-                                })
-                        } else {
-                            None
+    /// Write a complete `metadata` map onto an object
+    fn write_metadata_map(
+        tx: &mut automerge::transaction::Transaction<'_>,
+        obj_id: automerge::ObjId,
+        metadata: &Metadata,
+    ) -> Result<()> {
+        let meta_id = tx.put_object(obj_id, "metadata", ObjType::Map)?;
+        for (key, value) in metadata {
+            Self::put_json_value(tx, meta_id.clone(), key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Set a single key in the `metadata` map of an object (`null` removes the key)
+    ///
+    /// Returns `true` if the stored metadata changed
+    fn put_metadata_key(
+        tx: &mut automerge::transaction::Transaction<'_>,
+        obj_id: automerge::ObjId,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<bool> {
+        let existing = Self::read_metadata_map(&*tx, obj_id.clone())?;
+        match (existing.get(key), value) {
+            (None, serde_json::Value::Null) => return Ok(false),
+            (Some(current), _) if Self::json_values_equal(current, value) => return Ok(false),
+            _ => {}
+        }
+
+        let meta_id = match tx.get(obj_id.clone(), "metadata") {
+            Ok(Some((Value::Object(ObjType::Map), id))) => id,
+            _ => tx.put_object(obj_id, "metadata", ObjType::Map)?,
+        };
+        Self::put_json_value(tx, meta_id, key, value)?;
+        Ok(true)
+    }
+
+    /// Read all metadata stored on a document or directory node
+    pub fn read_node_metadata(handle: &DocHandle) -> Result<Metadata> {
+        handle.with_document(|doc| Self::read_metadata_map(doc, automerge::ROOT))
+    }
+
+    /// Set a metadata key on a document or directory node (`null` removes the key)
+    pub fn set_node_metadata(
+        handle: &DocHandle,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<bool> {
+        handle.with_document(|doc| {
+            let mut tx = doc.transaction();
+            let changed = Self::put_metadata_key(&mut tx, automerge::ROOT, key, value)?;
+            if changed {
+                Self::update_modified_timestamp(&mut tx, automerge::ROOT)?;
+                tx.commit();
+            }
+            Ok(changed)
+        })
+    }
+
+    // ============================================================================
+    // Conflict Helpers
+    // ============================================================================
+
+    /// Record in the path index which identity `author.actor_id` belongs to,
+    /// with the operator's signature vouching for it
+    ///
+    /// Does nothing if it's already recorded.
+    pub fn register_author(handle: &DocHandle, author: &Author) -> Result<()> {
+        let actor = author.actor_id.to_hex_string();
+        if Self::read_authors(handle).get(&actor) == Some(&author.identity) {
+            return Ok(());
+        }
+        handle.with_document(|doc| {
+            let mut tx = doc.transaction();
+            let authors_id = match tx.get(automerge::ROOT, "authors")? {
+                Some((Value::Object(ObjType::Map), id)) => id,
+                _ => tx.put_object(automerge::ROOT, "authors", ObjType::Map)?,
+            };
+            let author_id = tx.put_object(authors_id, actor.as_str(), ObjType::Map)?;
+            tx.put(
+                &author_id,
+                "operatorDid",
+                author.identity.operator_did.as_str(),
+            )?;
+            tx.put(&author_id, "device", author.identity.device.as_str())?;
+            tx.put(
+                &author_id,
+                "signature",
+                ScalarValue::Bytes(author.signature.clone()),
+            )?;
+            tx.commit();
+            Ok(())
+        })
+    }
+
+    /// Identities recorded in the path index with a valid signature, keyed by
+    /// hex-encoded actor ID
+    ///
+    /// Peers that registered at the same time may each have created the
+    /// `authors` map, so the entries of every conflicting map are read.
+    pub fn read_authors(handle: &DocHandle) -> HashMap<String, Identity> {
+        handle.with_document(|doc| {
+            let maps = doc.get_all(automerge::ROOT, "authors").unwrap_or_default();
+            let mut authors = HashMap::new();
+            for (value, authors_id) in maps {
+                if !matches!(value, Value::Object(ObjType::Map)) {
+                    continue;
+                }
+                for actor in doc.keys(&authors_id) {
+                    let Ok(Some((Value::Object(ObjType::Map), author_id))) =
+                        doc.get(&authors_id, actor.as_str())
+                    else {
+                        continue;
+                    };
+                    let field = |name: &str| doc.get(&author_id, name).ok().flatten();
+                    let string =
+                        |name: &str| field(name).and_then(|(v, _)| Self::extract_string_value(&v));
+                    let (Some(operator_did), Some(device)) =
+                        (string("operatorDid"), string("device"))
+                    else {
+                        continue;
+                    };
+                    let identity = Identity::new(operator_did, device);
+                    let signed = match field("signature") {
+                        Some((Value::Scalar(scalar), _)) => match scalar.as_ref() {
+                            ScalarValue::Bytes(signature) => identity.verify(&actor, signature),
+                            _ => false,
+                        },
+                        _ => false,
+                    };
+                    if signed {
+                        authors.insert(actor, identity);
+                    }
+                }
+            }
+            authors
+        })
+    }
+
+    /// Every change in a document's history, oldest first, with the author of each
+    /// looked up in `authors`
+    pub fn history(handle: &DocHandle, authors: &HashMap<String, Identity>) -> Vec<HistoryEntry> {
+        handle.with_document(|doc| {
+            doc.get_changes(&[])
+                .into_iter()
+                .map(|change| {
+                    let actor = change.actor_id().to_hex_string();
+                    HistoryEntry {
+                        hash: change.hash().to_string(),
+                        seq: change.seq(),
+                        timestamp: Some(change.timestamp()).filter(|&time| time != 0),
+                        message: change.message().cloned(),
+                        author: authors.get(&actor).cloned(),
+                        actor,
+                    }
+                })
+                .collect()
+        })
+    }
+
+    /// Get the concurrent values at a path within a document's content
+    ///
+    /// Returns an empty list when the value has a single writer.
+    pub fn get_conflicts(handle: &DocHandle, json_path: &[String]) -> Result<Vec<ConflictValue>> {
+        let mut full_path = vec!["content".to_string()];
+        full_path.extend(json_path.iter().cloned());
+
+        handle.with_document(|doc| {
+            let (parent_obj, final_key) = Self::navigate_to_parent(doc, &full_path)?;
+            let values = match doc.object_type(&parent_obj) {
+                Ok(ObjType::List) => match final_key.parse::<usize>() {
+                    Ok(index) => doc.get_all(&parent_obj, index)?,
+                    Err(_) => Vec::new(),
+                },
+                _ => doc.get_all(&parent_obj, final_key.as_str())?,
+            };
+
+            if values.len() < 2 {
+                return Ok(Vec::new());
+            }
+
+            values
+                .into_iter()
+                .map(|(value, id)| {
+                    let actor = match &id {
+                        automerge::ObjId::Id(_, actor, _) => actor.to_hex_string(),
+                        automerge::ObjId::Root => String::new(),
+                    };
+                    Ok(ConflictValue {
+                        actor,
+                        value: Self::value_to_json(doc, &value, id)?,
+                    })
+                })
+                .collect()
+        })
+    }
+
+    /// Find every location within a document's content that has concurrent values
+    pub fn find_conflicts<R: ReadDoc>(doc: &R) -> Vec<Vec<String>> {
+        let mut conflicts = Vec::new();
+        if let Ok(Some((Value::Object(_), content_id))) = doc.get(automerge::ROOT, "content") {
+            Self::collect_conflicts(doc, content_id, &mut Vec::new(), &mut conflicts);
+        }
+        conflicts
+    }
+
+    fn collect_conflicts<R: ReadDoc>(
+        doc: &R,
+        obj_id: automerge::ObjId,
+        path: &mut Vec<String>,
+        conflicts: &mut Vec<Vec<String>>,
+    ) {
+        let props: Vec<automerge::Prop> = match doc.object_type(&obj_id) {
+            Ok(ObjType::Map) | Ok(ObjType::Table) => doc.keys(&obj_id).map(Into::into).collect(),
+            Ok(ObjType::List) => (0..doc.length(&obj_id)).map(Into::into).collect(),
+            _ => return,
+        };
+
+        for prop in props {
+            let values = doc.get_all(&obj_id, prop.clone()).unwrap_or_default();
+            path.push(prop.to_string());
+            if values.len() > 1 {
+                conflicts.push(path.clone());
+            }
+            if let Ok(Some((Value::Object(_), child_id))) = doc.get(&obj_id, prop) {
+                Self::collect_conflicts(doc, child_id, path, conflicts);
+            }
+            path.pop();
+        }
+    }
+
+    // ============================================================================
+    // Delta Helpers
+    // ============================================================================
+
+    /// Read a document's content as JSON, or `null` if it has none
+    ///
+    /// Legacy documents that store content as a JSON string are parsed.
+    pub fn content_json<R: ReadDoc>(doc: &R) -> serde_json::Value {
+        match doc.get(automerge::ROOT, "content") {
+            Ok(Some((Value::Object(_), content_id))) => {
+                Self::read_automerge_value(doc, content_id).unwrap_or(serde_json::Value::Null)
+            }
+            Ok(Some((value, _))) => Self::extract_string_value(&value)
+                .and_then(|s| serde_json::from_str(&s).ok())
+                .unwrap_or(serde_json::Value::Null),
+            _ => serde_json::Value::Null,
+        }
+    }
+
+    /// Find the JSON paths at which two versions of a document's content differ
+    ///
+    /// Objects are compared key by key. Arrays of the same length are compared
+    /// element by element; an array whose length changed is reported as a whole,
+    /// since its indices no longer line up. An empty path means the content itself
+    /// was replaced. Paths are sorted and never nested inside one another.
+    pub fn changed_json_paths(
+        before: &serde_json::Value,
+        after: &serde_json::Value,
+    ) -> Vec<Vec<String>> {
+        let mut changed = Vec::new();
+        Self::collect_changed_paths(before, after, &mut Vec::new(), &mut changed);
+        changed.sort();
+        changed
+    }
+
+    /// Describe how a document's content changed as patches at JSON paths
+    ///
+    /// Follows the same rules as [`Self::changed_json_paths`]: keys present on
+    /// only one side are added or removed, and an array whose length changed is
+    /// replaced as a whole.
+    pub fn content_patches(
+        before: &serde_json::Value,
+        after: &serde_json::Value,
+    ) -> Vec<ContentPatch> {
+        Self::changed_json_paths(before, after)
+            .into_iter()
+            .map(|path| {
+                let value = Self::json_at(after, &path);
+                let kind = match (Self::json_at(before, &path), value) {
+                    (None, _) => PatchKind::Added,
+                    (Some(_), None) => PatchKind::Removed,
+                    (Some(_), Some(_)) => PatchKind::Changed,
+                };
+                ContentPatch {
+                    path,
+                    kind,
+                    value: value.cloned(),
+                }
+            })
+            .collect()
+    }
+
+    fn json_at<'a>(value: &'a serde_json::Value, path: &[String]) -> Option<&'a serde_json::Value> {
+        path.iter().try_fold(value, |value, key| match value {
+            serde_json::Value::Object(map) => map.get(key),
+            serde_json::Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+            _ => None,
+        })
+    }
+
+    fn collect_changed_paths(
+        before: &serde_json::Value,
+        after: &serde_json::Value,
+        path: &mut Vec<String>,
+        changed: &mut Vec<Vec<String>>,
+    ) {
+        match (before, after) {
+            (serde_json::Value::Object(a), serde_json::Value::Object(b)) => {
+                let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+                for key in keys {
+                    path.push(key.clone());
+                    match (a.get(key), b.get(key)) {
+                        (Some(x), Some(y)) => Self::collect_changed_paths(x, y, path, changed),
+                        _ => changed.push(path.clone()),
+                    }
+                    path.pop();
+                }
+            }
+            (serde_json::Value::Array(a), serde_json::Value::Array(b)) if a.len() == b.len() => {
+                for (i, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+                    path.push(i.to_string());
+                    Self::collect_changed_paths(x, y, path, changed);
+                    path.pop();
+                }
+            }
+            _ => {
+                if !Self::json_values_equal(before, after) {
+                    changed.push(path.clone());
+                }
+            }
+        }
+    }
+
+    // ============================================================================
+    // Path Index Helpers (Native Automerge Structure)
+    // ============================================================================
+
+    /// Initialize a document as a path index with native Automerge structure
+    ///
+    /// Entries are sharded by parent directory, so a write touches only the
+    /// shard of the directory it happens in and listing a directory reads only
+    /// its own shard.
+    ///
+    /// Structure:
+    /// ```text
+    /// ROOT
+    /// ├── type: "path_index"
+    /// ├── version: 3
+    /// ├── last_updated: i64
+    /// └── shards: Map (directory path -> Map (child name -> entry))
+    /// ```
+    pub fn init_as_path_index(handle: &DocHandle) -> Result<()> {
+        handle.with_document(|doc| {
+            let mut tx = doc.transaction();
+            tx.put(automerge::ROOT, "type", "path_index")?;
+            tx.put(automerge::ROOT, "version", PATH_INDEX_VERSION as i64)?;
+            tx.put(
+                automerge::ROOT,
+                "last_updated",
+                chrono::Utc::now().timestamp_millis(),
+            )?;
+            let shards_id = tx.put_object(automerge::ROOT, "shards", ObjType::Map)?;
+            tx.put_object(shards_id, "/", ObjType::Map)?;
+            tx.commit();
+            Ok(())
+        })
+    }
+
+    /// Bring the per-directory shards up to date with a version 2 path index
+    ///
+    /// Version 2 kept every entry in one `entries` map keyed by absolute path.
+    /// The map is kept so peers that haven't upgraded can still read the index:
+    /// writes are mirrored into it, and entries such peers add or change there
+    /// are copied into the shards here, the more recently modified side winning.
+    /// Removals made by those peers aren't carried over. Once every peer has
+    /// upgraded, [`Self::drop_legacy_path_entries`] deletes the map. Returns
+    /// `false` if there was nothing to migrate.
+    pub fn migrate_path_index(handle: &DocHandle) -> Result<bool> {
+        handle.with_document(|doc| {
+            let Some(entries_id) = Self::legacy_entries(doc) else {
+                return Ok(false);
+            };
+
+            let entries: Vec<_> = doc
+                .keys(entries_id.clone())
+                .filter_map(|path| match doc.get(entries_id.clone(), path.as_str()) {
+                    Ok(Some((Value::Object(ObjType::Map), entry_id))) => {
+                        Self::read_path_entry_from_obj(doc, entry_id).map(|entry| (path, entry))
+                    }
+                    _ => None,
+                })
+                .collect();
+            let legacy: BTreeSet<String> = entries.iter().map(|(path, _)| path.clone()).collect();
+            let sharded = Self::read_shards(doc);
+
+            let mut tx = doc.transaction();
+            for (path, entry) in &entries {
+                let current = sharded.get(Self::shard_key(path));
+                if current.is_some_and(|current| current.modified >= entry.modified) {
+                    continue;
+                }
+                let (dir, name) = Self::split_index_path(path);
+                let shard_id = Self::shard_or_create(&mut tx, dir)?;
+                Self::write_path_entry(&mut tx, shard_id, name, entry)?;
+                if entry.node_type == NodeType::Directory {
+                    Self::shard_or_create(&mut tx, path)?;
+                }
+                if current.is_some() {
+                    Self::mirror_legacy_entry(&mut tx, path)?;
+                }
+            }
+            // Entries only the shards have, such as those written before the
+            // legacy map was kept, are filled in for peers still reading it
+            for path in sharded.keys().filter(|path| !legacy.contains(*path)) {
+                Self::mirror_legacy_entry(&mut tx, path)?;
+            }
+            Self::shard_or_create(&mut tx, "/")?;
+            if Self::read_schema_version(&tx) != PATH_INDEX_VERSION {
+                tx.put(automerge::ROOT, "version", PATH_INDEX_VERSION as i64)?;
+            }
+            if tx.pending_ops() > 0 {
+                tx.put(
+                    automerge::ROOT,
+                    "last_updated",
+                    chrono::Utc::now().timestamp_millis(),
+                )?;
+                tx.commit();
+            } else {
+                tx.rollback();
+            }
+            Ok(true)
+        })
+    }
+
+    /// Delete the version 2 `entries` map kept by [`Self::migrate_path_index`]
+    ///
+    /// Only call this once no peer on the old layout is left to read it.
+    /// Returns `false` if there was no such map.
+    pub fn drop_legacy_path_entries(handle: &DocHandle) -> Result<bool> {
+        handle.with_document(|doc| {
+            if Self::legacy_entries(doc).is_none() {
+                return Ok(false);
+            }
+            let mut tx = doc.transaction();
+            tx.delete(automerge::ROOT, "entries")?;
+            tx.commit();
+            Ok(true)
+        })
+    }
+
+    /// The version 2 `entries` map, if the index still keeps one
+    fn legacy_entries<R: ReadDoc>(doc: &R) -> Option<automerge::ObjId> {
+        match doc.get(automerge::ROOT, "entries") {
+            Ok(Some((Value::Object(ObjType::Map), id))) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// The version 2 `entries` map of an index that has no shards yet
+    ///
+    /// Only readers that don't migrate the index, see
+    /// [`VirtualFileSystem::with_index_upkeep`](crate::vfs::VirtualFileSystem::with_index_upkeep),
+    /// come across one.
+    fn unmigrated_entries<R: ReadDoc>(doc: &R) -> Option<automerge::ObjId> {
+        Self::conflicting_maps(doc, &automerge::ROOT, "shards")
+            .is_empty()
+            .then(|| Self::legacy_entries(doc))
+            .flatten()
+    }
+
+    /// Copy a path's sharded entry into the version 2 `entries` map, if kept
+    ///
+    /// A path without an entry is removed from the map.
+    fn mirror_legacy_entry(
+        tx: &mut automerge::transaction::Transaction<'_>,
+        path: &str,
+    ) -> Result<()> {
+        let Some(entries_id) = Self::legacy_entries(tx) else {
+            return Ok(());
+        };
+        let path = Self::shard_key(path);
+        match Self::path_entry_obj(tx, path).and_then(|id| Self::read_path_entry_from_obj(tx, id)) {
+            Some(entry) => Self::write_path_entry(tx, entries_id, path, &entry)?,
+            None => {
+                if tx.get(entries_id.clone(), path)?.is_some() {
+                    tx.delete(entries_id, path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Split an absolute path into the directory whose shard holds it and its name
+    fn split_index_path(path: &str) -> (&str, &str) {
+        let path = path.trim_end_matches('/');
+        match path.rfind('/') {
+            Some(0) | None => ("/", path.trim_start_matches('/')),
+            Some(i) => (&path[..i], &path[i + 1..]),
+        }
+    }
+
+    /// Normalize a directory path to the key of its shard
+    fn shard_key(dir_path: &str) -> &str {
+        match dir_path.trim_end_matches('/') {
+            "" => "/",
+            dir => dir,
+        }
+    }
+
+    /// Every map stored under `key`, the winning value last
+    ///
+    /// Peers that create the same map concurrently leave conflicting values
+    /// under its key, each holding part of the entries.
+    fn conflicting_maps<R: ReadDoc>(
+        doc: &R,
+        obj: &automerge::ObjId,
+        key: &str,
+    ) -> Vec<automerge::ObjId> {
+        doc.get_all(obj, key)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(value, id)| matches!(value, Value::Object(ObjType::Map)).then_some(id))
+            .collect()
+    }
+
+    /// Find every shard holding children of `dir_path`, the winning one last
+    ///
+    /// A directory's shard is created under its path only when missing, but
+    /// peers creating the same directory concurrently each create one. Readers
+    /// take the union, so neither peer's entries are lost.
+    fn shards<R: ReadDoc>(doc: &R, dir_path: &str) -> Vec<automerge::ObjId> {
+        let key = Self::shard_key(dir_path);
+        Self::conflicting_maps(doc, &automerge::ROOT, "shards")
+            .iter()
+            .flat_map(|shards_id| Self::conflicting_maps(doc, shards_id, key))
+            .collect()
+    }
+
+    /// Find the shard new children of `dir_path` are written to
+    fn shard<R: ReadDoc>(doc: &R, dir_path: &str) -> Option<automerge::ObjId> {
+        Self::shards(doc, dir_path).pop()
+    }
+
+    /// Find the shard holding the children of `dir_path`, creating it if needed
+    fn shard_or_create(
+        tx: &mut automerge::transaction::Transaction<'_>,
+        dir_path: &str,
+    ) -> Result<automerge::ObjId> {
+        if let Some(id) = Self::shard(tx, dir_path) {
+            return Ok(id);
+        }
+        let shards_id = match Self::conflicting_maps(tx, &automerge::ROOT, "shards").pop() {
+            Some(id) => id,
+            None => tx.put_object(automerge::ROOT, "shards", ObjType::Map)?,
+        };
+        Ok(tx.put_object(shards_id, Self::shard_key(dir_path), ObjType::Map)?)
+    }
+
+    /// Delete the shard of a directory that no longer has children
+    fn remove_shard_if_empty(
+        tx: &mut automerge::transaction::Transaction<'_>,
+        dir_path: &str,
+    ) -> Result<()> {
+        let shards = Self::shards(tx, dir_path);
+        if shards.is_empty() || shards.iter().any(|shard_id| tx.length(shard_id) > 0) {
+            return Ok(());
+        }
+        let key = Self::shard_key(dir_path);
+        for shards_id in Self::conflicting_maps(tx, &automerge::ROOT, "shards") {
+            if tx.get(&shards_id, key)?.is_some() {
+                tx.delete(shards_id, key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the entry object for a path
+    fn path_entry_obj<R: ReadDoc>(doc: &R, path: &str) -> Option<automerge::ObjId> {
+        let (dir, name) = Self::split_index_path(path);
+        if let Some(entries_id) = Self::unmigrated_entries(doc) {
+            return match doc.get(entries_id, Self::shard_key(path)) {
+                Ok(Some((Value::Object(ObjType::Map), entry_id))) => Some(entry_id),
+                _ => None,
+            };
+        }
+        Self::shards(doc, dir)
+            .into_iter()
+            .rev()
+            .find_map(|shard_id| match doc.get(shard_id, name) {
+                Ok(Some((Value::Object(ObjType::Map), entry_id))) => Some(entry_id),
+                _ => None,
+            })
+    }
+
+    /// Delete a path's entry from every shard of its directory
+    ///
+    /// Returns `false` if no shard had one.
+    fn delete_path_entry(
+        tx: &mut automerge::transaction::Transaction<'_>,
+        path: &str,
+    ) -> Result<bool> {
+        let (dir, name) = Self::split_index_path(path);
+        let mut deleted = false;
+        for shard_id in Self::shards(tx, dir) {
+            if tx.get(&shard_id, name)?.is_some() {
+                tx.delete(shard_id, name)?;
+                deleted = true;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Write a complete entry into a map, replacing any entry under the same key
+    fn write_path_entry(
+        tx: &mut automerge::transaction::Transaction<'_>,
+        map_id: automerge::ObjId,
+        key: &str,
+        entry: &crate::vfs::path_index::PathEntry,
+    ) -> Result<()> {
+        let entry_id = tx.put_object(map_id, key, ObjType::Map)?;
+        tx.put(entry_id.clone(), "doc_id", entry.doc_id.as_str())?;
+        tx.put(entry_id.clone(), "node_type", entry.node_type.as_str())?;
+        tx.put(
+            entry_id.clone(),
+            "created",
+            entry.created.timestamp_millis(),
+        )?;
+        tx.put(
+            entry_id.clone(),
+            "modified",
+            entry.modified.timestamp_millis(),
+        )?;
+        if !entry.metadata.is_empty() {
+            Self::write_metadata_map(tx, entry_id, &entry.metadata)?;
+        }
+        Ok(())
+    }
+
+    /// Read the entire path index from native Automerge structure
+    pub fn read_path_index_native(handle: &DocHandle) -> Result<crate::vfs::path_index::PathIndex> {
+        use crate::vfs::path_index::PathIndex;
+
+        handle.with_document(|doc| {
+            let mut index = PathIndex::new();
+
+            // Read last_updated
+            if let Ok(Some((Value::Scalar(s), _))) = doc.get(automerge::ROOT, "last_updated") {
+                if let Some(ts) = s.to_i64() {
+                    if let Some(dt) = chrono::DateTime::from_timestamp_millis(ts) {
+                        index.last_updated = dt;
+                    }
+                }
+            }
+
+            // Read every shard, or the version 2 entries map of an index that
+            // hasn't been migrated yet, so readers that mustn't write can see it
+            if let Some(entries_id) = Self::unmigrated_entries(doc) {
+                index.paths.extend(doc.keys(&entries_id).filter_map(|path| {
+                    match doc.get(&entries_id, path.as_str()) {
+                        Ok(Some((Value::Object(ObjType::Map), entry_id))) => {
+                            Self::read_path_entry_from_obj(doc, entry_id).map(|entry| (path, entry))
+                        }
+                        _ => None,
+                    }
+                }));
+            }
+            index.paths.extend(Self::read_shards(doc));
+
+            Ok(index)
+        })
+    }
+
+    /// Read the entries of every shard, keyed by absolute path
+    fn read_shards<R: ReadDoc>(doc: &R) -> BTreeMap<String, crate::vfs::path_index::PathEntry> {
+        let dirs: BTreeSet<String> = Self::conflicting_maps(doc, &automerge::ROOT, "shards")
+            .into_iter()
+            .flat_map(|shards_id| doc.keys(shards_id).collect::<Vec<_>>())
+            .collect();
+        dirs.iter()
+            .flat_map(|dir| Self::read_shard(doc, dir))
+            .collect()
+    }
+
+    /// Read the entries of one directory's shards, keyed by absolute path
+    ///
+    /// Where conflicting shards hold the same name, the winning shard's entry is used.
+    fn read_shard<R: ReadDoc>(
+        doc: &R,
+        dir_path: &str,
+    ) -> Vec<(String, crate::vfs::path_index::PathEntry)> {
+        let dir = Self::shard_key(dir_path);
+        if let Some(entries_id) = Self::unmigrated_entries(doc) {
+            return doc
+                .keys(&entries_id)
+                .filter(|path| path != "/" && Self::split_index_path(path).0 == dir)
+                .filter_map(|path| match doc.get(&entries_id, path.as_str()) {
+                    Ok(Some((Value::Object(ObjType::Map), entry_id))) => {
+                        Self::read_path_entry_from_obj(doc, entry_id).map(|entry| (path, entry))
+                    }
+                    _ => None,
+                })
+                .collect();
+        }
+        let mut entries = BTreeMap::new();
+        for shard_id in Self::shards(doc, dir) {
+            for name in doc.keys(&shard_id) {
+                if let Ok(Some((Value::Object(ObjType::Map), entry_id))) =
+                    doc.get(&shard_id, name.as_str())
+                {
+                    if let Some(entry) = Self::read_path_entry_from_obj(doc, entry_id) {
+                        entries.insert(name, entry);
+                    }
+                }
+            }
+        }
+        entries
+            .into_iter()
+            .map(|(name, entry)| {
+                let path = if dir == "/" {
+                    format!("/{}", name)
+                } else {
+                    format!("{}/{}", dir, name)
+                };
+                (path, entry)
+            })
+            .collect()
+    }
+
+    /// Read a single PathEntry from an Automerge object
+    fn read_path_entry_from_obj<R: ReadDoc>(
+        doc: &R,
+        entry_id: automerge::ObjId,
+    ) -> Option<crate::vfs::path_index::PathEntry> {
+        use crate::vfs::path_index::PathEntry;
+
+        let doc_id = doc
+            .get(entry_id.clone(), "doc_id")
+            .ok()
+            .flatten()
+            .and_then(|(v, _)| Self::extract_string_value(&v))?;
+
+        let node_type_str = doc
+            .get(entry_id.clone(), "node_type")
+            .ok()
+            .flatten()
+            .and_then(|(v, _)| Self::extract_string_value(&v))?;
+
+        let node_type = NodeType::from_str(&node_type_str)?;
+
+        let created = doc
+            .get(entry_id.clone(), "created")
+            .ok()
+            .flatten()
+            .and_then(|(v, _)| {
+                if let Value::Scalar(s) = v {
+                    s.to_i64().and_then(chrono::DateTime::from_timestamp_millis)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_else(chrono::Utc::now);
+
+        let modified = doc
+            .get(entry_id.clone(), "modified")
+            .ok()
+            .flatten()
+            .and_then(|(v, _)| {
+                if let Value::Scalar(s) = v {
+                    s.to_i64().and_then(chrono::DateTime::from_timestamp_millis)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_else(chrono::Utc::now);
+
+        let metadata = Self::read_metadata_map(doc, entry_id).unwrap_or_default();
+
+        Some(PathEntry {
+            doc_id,
+            node_type,
+            created,
+            modified,
+            metadata,
+        })
+    }
+
+    /// Set or update a single path entry
+    pub fn set_path_entry(
+        handle: &DocHandle,
+        path: &str,
+        doc_id: &str,
+        node_type: NodeType,
+        created: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<()> {
+        handle.with_document(|doc| {
+            let mut tx = doc.transaction();
+            let now = chrono::Utc::now();
+
+            let (dir, name) = Self::split_index_path(path);
+            let shard_id = Self::shard_or_create(&mut tx, dir)?;
+
+            // Check if entry already exists to preserve created timestamp
+            let existing_created = Self::path_entry_obj(&tx, path).and_then(|entry_id| {
+                tx.get(entry_id, "created")
+                    .ok()
+                    .flatten()
+                    .and_then(|(v, _)| {
+                        if let Value::Scalar(s) = v {
+                            s.to_i64().and_then(chrono::DateTime::from_timestamp_millis)
+                        } else {
+                            None
                         }
-                    });
+                    })
+            });
 
             // Create or replace the entry
-            let entry_id = tx.put_object(entries_id, path, ObjType::Map)?;
+            let entry_id = tx.put_object(shard_id, name, ObjType::Map)?;
             tx.put(entry_id.clone(), "doc_id", doc_id)?;
             tx.put(entry_id.clone(), "node_type", node_type.as_str())?;
             tx.put(
@@ -1560,6 +2546,13 @@ impl AutomergeHelpers {
             )?;
             tx.put(entry_id, "modified", now.timestamp_millis())?;
 
+            // Give a new directory its shard up front, so peers adding children
+            // concurrently write into the same map
+            if node_type == NodeType::Directory {
+                Self::shard_or_create(&mut tx, path)?;
+            }
+            Self::mirror_legacy_entry(&mut tx, path)?;
+
             // Update last_updated
             tx.put(automerge::ROOT, "last_updated", now.timestamp_millis())?;
 
@@ -1569,25 +2562,29 @@ impl AutomergeHelpers {
     }
 
     /// Update only the modified timestamp for a path
-    pub fn update_path_modified(handle: &DocHandle, path: &str) -> Result<bool> {
+    ///
+    /// With `ancestors` set, the entries of the path's ancestor directories are
+    /// updated in the same change, as by [`Self::touch_ancestor_entries`].
+    pub fn update_path_modified(
+        handle: &DocHandle,
+        path: &str,
+        ancestors: Option<std::time::Duration>,
+    ) -> Result<bool> {
         handle.with_document(|doc| {
             let mut tx = doc.transaction();
             let now = chrono::Utc::now();
 
-            // Get entries map
-            let entries_id = match tx.get(automerge::ROOT, "entries") {
-                Ok(Some((Value::Object(ObjType::Map), id))) => id,
-                _ => return Ok(false),
-            };
-
             // Get the entry for this path
-            let entry_id = match tx.get(entries_id, path) {
-                Ok(Some((Value::Object(ObjType::Map), id))) => id,
-                _ => return Ok(false),
+            let Some(entry_id) = Self::path_entry_obj(&tx, path) else {
+                return Ok(false);
             };
 
             // Update only the modified timestamp
             tx.put(entry_id, "modified", now.timestamp_millis())?;
+            Self::mirror_legacy_entry(&mut tx, path)?;
+            if let Some(window) = ancestors {
+                Self::touch_ancestors(&mut tx, path, now, window)?;
+            }
             tx.put(automerge::ROOT, "last_updated", now.timestamp_millis())?;
 
             tx.commit();
@@ -1595,25 +2592,101 @@ impl AutomergeHelpers {
         })
     }
 
-    /// Remove a path entry
-    pub fn remove_path_entry(handle: &DocHandle, path: &str) -> Result<bool> {
+    /// Update the modified timestamp of a path's ancestor directories
+    ///
+    /// Entries already modified within `window` are left alone, so a burst of
+    /// writes below a directory updates its entry once rather than on every
+    /// write. Returns how many entries were updated.
+    pub fn touch_ancestor_entries(
+        handle: &DocHandle,
+        path: &str,
+        window: std::time::Duration,
+    ) -> Result<usize> {
         handle.with_document(|doc| {
             let mut tx = doc.transaction();
+            let touched = Self::touch_ancestors(&mut tx, path, chrono::Utc::now(), window)?;
+            tx.commit();
+            Ok(touched)
+        })
+    }
 
-            // Get entries map
-            let entries_id = match tx.get(automerge::ROOT, "entries") {
-                Ok(Some((Value::Object(ObjType::Map), id))) => id,
-                _ => return Ok(false),
+    fn touch_ancestors(
+        tx: &mut automerge::transaction::Transaction<'_>,
+        path: &str,
+        now: chrono::DateTime<chrono::Utc>,
+        window: std::time::Duration,
+    ) -> Result<usize> {
+        let now = now.timestamp_millis();
+        let window = window.as_millis() as i64;
+        let mut touched = 0;
+
+        let mut current = path;
+        while let Some(last_slash) = current.rfind('/') {
+            if last_slash == 0 {
+                break; // The root has no entry
+            }
+            current = &current[..last_slash];
+
+            let Some(entry_id) = Self::path_entry_obj(tx, current) else {
+                continue;
+            };
+            let modified = match tx.get(entry_id.clone(), "modified") {
+                Ok(Some((Value::Scalar(s), _))) => s.to_i64(),
+                _ => None,
             };
+            if modified.is_some_and(|modified| now - modified < window) {
+                continue;
+            }
+
+            tx.put(entry_id, "modified", now)?;
+            Self::mirror_legacy_entry(tx, current)?;
+            touched += 1;
+        }
 
-            // Check if entry exists
-            let exists = tx.get(entries_id.clone(), path).ok().flatten().is_some();
-            if !exists {
+        Ok(touched)
+    }
+
+    /// Mirror a metadata key onto a path entry (`null` removes the key)
+    pub fn set_path_metadata(
+        handle: &DocHandle,
+        path: &str,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<bool> {
+        handle.with_document(|doc| {
+            let mut tx = doc.transaction();
+
+            // Get the entry for this path
+            let Some(entry_id) = Self::path_entry_obj(&tx, path) else {
                 return Ok(false);
+            };
+
+            let changed = Self::put_metadata_key(&mut tx, entry_id, key, value)?;
+            if changed {
+                Self::mirror_legacy_entry(&mut tx, path)?;
+                tx.put(
+                    automerge::ROOT,
+                    "last_updated",
+                    chrono::Utc::now().timestamp_millis(),
+                )?;
+                tx.commit();
             }
+            Ok(changed)
+        })
+    }
 
-            // Delete the entry
-            tx.delete(entries_id, path)?;
+    /// Remove a path entry
+    ///
+    /// A removed directory's shard is deleted too if it has no children left.
+    pub fn remove_path_entry(handle: &DocHandle, path: &str) -> Result<bool> {
+        handle.with_document(|doc| {
+            let mut tx = doc.transaction();
+
+            if !Self::delete_path_entry(&mut tx, path)? {
+                return Ok(false);
+            }
+            Self::remove_shard_if_empty(&mut tx, path)?;
+            Self::mirror_legacy_entry(&mut tx, path)?;
 
             // Update last_updated
             tx.put(
@@ -1627,87 +2700,87 @@ impl AutomergeHelpers {
         })
     }
 
-    /// Move a path entry (preserves metadata except modified timestamp)
-    pub fn move_path_entry(handle: &DocHandle, from: &str, to: &str) -> Result<bool> {
+    /// Move a path entry and, for a directory, every entry below it in one change
+    ///
+    /// A directory's descendants live in the shards of its subdirectories, so each
+    /// shard is copied under its new key and the old one deleted whole. Returns the
+    /// number of entries moved, or 0 if `from` has no entry.
+    pub fn move_path_subtree(handle: &DocHandle, from: &str, to: &str) -> Result<usize> {
+        let from = Self::shard_key(from);
+        let to = Self::shard_key(to);
         handle.with_document(|doc| {
-            let mut tx = doc.transaction();
-            let now = chrono::Utc::now();
-
-            // Get entries map
-            let entries_id = match tx.get(automerge::ROOT, "entries") {
-                Ok(Some((Value::Object(ObjType::Map), id))) => id,
-                _ => return Ok(false),
+            let Some(entry_id) = Self::path_entry_obj(doc, from) else {
+                return Ok(0);
+            };
+            let Some(mut entry) = Self::read_path_entry_from_obj(doc, entry_id) else {
+                return Ok(0);
             };
 
-            // Read the existing entry
-            let (doc_id, node_type, created) = match tx.get(entries_id.clone(), from) {
-                Ok(Some((Value::Object(ObjType::Map), entry_id))) => {
-                    let doc_id = tx
-                        .get(entry_id.clone(), "doc_id")
-                        .ok()
-                        .flatten()
-                        .and_then(|(v, _)| Self::extract_string_value(&v));
-
-                    let node_type_str = tx
-                        .get(entry_id.clone(), "node_type")
-                        .ok()
-                        .flatten()
-                        .and_then(|(v, _)| Self::extract_string_value(&v));
-
-                    let created = tx
-                        .get(entry_id, "created")
-                        .ok()
-                        .flatten()
-                        .and_then(|(v, _)| {
-                            if let Value::Scalar(s) = v {
-                                s.to_i64()
-                            } else {
-                                None
-                            }
-                        });
+            // Shards of the directory and its subdirectories, with their entries
+            let prefix = format!("{}/", from);
+            let shards: Vec<(String, Vec<(String, crate::vfs::path_index::PathEntry)>)> =
+                if entry.node_type == NodeType::Directory {
+                    Self::conflicting_maps(doc, &automerge::ROOT, "shards")
+                        .into_iter()
+                        .flat_map(|shards_id| doc.keys(shards_id).collect::<Vec<_>>())
+                        .filter(|dir| dir == from || dir.starts_with(&prefix))
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .map(|dir| {
+                            let entries = Self::read_shard(doc, &dir);
+                            (dir, entries)
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
 
-                    match (doc_id, node_type_str) {
-                        (Some(d), Some(n)) => (d, n, created),
-                        _ => return Ok(false),
+            let mut tx = doc.transaction();
+            let now = chrono::Utc::now();
+            let mut moved = 1;
+            let mut moved_paths = Vec::new();
+
+            Self::delete_path_entry(&mut tx, from)?;
+            Self::mirror_legacy_entry(&mut tx, from)?;
+            entry.modified = now;
+            let (to_dir, to_name) = Self::split_index_path(to);
+            let to_shard = Self::shard_or_create(&mut tx, to_dir)?;
+            Self::write_path_entry(&mut tx, to_shard, to_name, &entry)?;
+            Self::mirror_legacy_entry(&mut tx, to)?;
+
+            if entry.node_type == NodeType::Directory {
+                Self::shard_or_create(&mut tx, to)?;
+                for (dir, entries) in shards {
+                    let new_dir = format!("{}{}", to, &dir[from.len()..]);
+                    let new_shard = Self::shard_or_create(&mut tx, &new_dir)?;
+                    for (path, mut entry) in entries {
+                        let (_, name) = Self::split_index_path(&path);
+                        entry.modified = now;
+                        Self::write_path_entry(&mut tx, new_shard.clone(), name, &entry)?;
+                        moved_paths.push(format!("{}/{}", new_dir, name));
+                        moved_paths.push(path);
+                        moved += 1;
+                    }
+                    for shards_id in Self::conflicting_maps(&tx, &automerge::ROOT, "shards") {
+                        if tx.get(&shards_id, dir.as_str())?.is_some() {
+                            tx.delete(shards_id, dir.as_str())?;
+                        }
                     }
                 }
-                _ => return Ok(false),
-            };
-
-            // Delete the old entry
-            tx.delete(entries_id.clone(), from)?;
-
-            // Create the new entry
-            let new_entry_id = tx.put_object(entries_id, to, ObjType::Map)?;
-            tx.put(new_entry_id.clone(), "doc_id", doc_id.as_str())?;
-            tx.put(new_entry_id.clone(), "node_type", node_type.as_str())?;
-            tx.put(
-                new_entry_id.clone(),
-                "created",
-                created.unwrap_or_else(|| now.timestamp_millis()),
-            )?;
-            tx.put(new_entry_id, "modified", now.timestamp_millis())?;
+            }
+            for path in &moved_paths {
+                Self::mirror_legacy_entry(&mut tx, path)?;
+            }
 
-            // Update last_updated
             tx.put(automerge::ROOT, "last_updated", now.timestamp_millis())?;
-
             tx.commit();
-            Ok(true)
+            Ok(moved)
         })
     }
 
     /// Check if a path exists
     pub fn path_exists(handle: &DocHandle, path: &str) -> Result<bool> {
-        handle.with_document(|doc| {
-            // Get entries map
-            let entries_id = match doc.get(automerge::ROOT, "entries") {
-                Ok(Some((Value::Object(ObjType::Map), id))) => id,
-                _ => return Ok(false),
-            };
-
-            // Check if entry exists
-            Ok(doc.get(entries_id, path).ok().flatten().is_some())
-        })
+        handle.with_document(|doc| Ok(Self::path_entry_obj(doc, path).is_some()))
     }
 
     /// Get a single path entry
@@ -1716,19 +2789,8 @@ impl AutomergeHelpers {
         path: &str,
     ) -> Result<Option<crate::vfs::path_index::PathEntry>> {
         handle.with_document(|doc| {
-            // Get entries map
-            let entries_id = match doc.get(automerge::ROOT, "entries") {
-                Ok(Some((Value::Object(ObjType::Map), id))) => id,
-                _ => return Ok(None),
-            };
-
-            // Get the entry
-            match doc.get(entries_id, path) {
-                Ok(Some((Value::Object(ObjType::Map), entry_id))) => {
-                    Ok(Self::read_path_entry_from_obj(doc, entry_id))
-                }
-                _ => Ok(None),
-            }
+            Ok(Self::path_entry_obj(doc, path)
+                .and_then(|entry_id| Self::read_path_entry_from_obj(doc, entry_id)))
         })
     }
 
@@ -1737,50 +2799,6 @@ impl AutomergeHelpers {
         handle: &DocHandle,
         dir_path: &str,
     ) -> Result<Vec<(String, crate::vfs::path_index::PathEntry)>> {
-        handle.with_document(|doc| {
-            let mut children = Vec::new();
-
-            // Get entries map
-            let entries_id = match doc.get(automerge::ROOT, "entries") {
-                Ok(Some((Value::Object(ObjType::Map), id))) => id,
-                _ => return Ok(children),
-            };
-
-            let normalized_dir = dir_path.trim_end_matches('/');
-            let prefix = if normalized_dir.is_empty() || normalized_dir == "/" {
-                "/"
-            } else {
-                normalized_dir
-            };
-
-            // Iterate over all keys
-            for key in doc.keys(entries_id.clone()) {
-                let path = key.to_string();
-
-                // Check if this is a direct child
-                if path.starts_with(prefix) && path != prefix {
-                    let remainder = if prefix == "/" {
-                        &path[1..]
-                    } else if path.len() > prefix.len() {
-                        &path[prefix.len() + 1..]
-                    } else {
-                        continue;
-                    };
-
-                    // Only direct children (no more slashes)
-                    if !remainder.contains('/') {
-                        if let Ok(Some((Value::Object(ObjType::Map), entry_id))) =
-                            doc.get(entries_id.clone(), key.as_str())
-                        {
-                            if let Some(entry) = Self::read_path_entry_from_obj(doc, entry_id) {
-                                children.push((path, entry));
-                            }
-                        }
-                    }
-                }
-            }
-
-            Ok(children)
-        })
+        handle.with_document(|doc| Ok(Self::read_shard(doc, dir_path)))
     }
 }
@@ -0,0 +1,520 @@
+use crate::vfs::filesystem::VfsEvent;
+use crate::vfs::watcher::{monotonic_now, sleep};
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+
+/// Default number of events buffered for subscribers
+pub const DEFAULT_EVENT_CAPACITY: usize = 100;
+
+/// What happens when a subscriber falls a full buffer of events behind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OverflowPolicy {
+    /// Drop the oldest events; the lagging subscriber receives a
+    /// `VfsEvent::Lagged` with the number it missed in their place
+    #[default]
+    DropOldest,
+    /// Hold writes until the slowest subscriber has caught up, so no event is lost
+    ///
+    /// A subscriber that is kept alive but never read from stalls every write.
+    Block,
+}
+
+/// Buffer size and overflow behaviour of the VFS event channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EventChannelConfig {
+    /// Number of events buffered per channel, rounded up to a power of two
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for EventChannelConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_EVENT_CAPACITY,
+            overflow: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+impl EventChannelConfig {
+    /// Set the number of buffered events
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Set what happens when the buffer is full
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+}
+
+/// How the event channel has coped with load
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventChannelStats {
+    /// Effective buffer size
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+    /// Events sent while the buffer was full, each evicting one a subscriber hadn't seen
+    pub dropped: u64,
+    /// Sends that waited for a subscriber to catch up
+    pub blocked: u64,
+}
+
+/// Broadcast channel for VFS events that applies an overflow policy and counts overflows
+pub(crate) struct EventChannel {
+    tx: broadcast::Sender<VfsEvent>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    dropped: AtomicU64,
+    blocked: AtomicU64,
+    /// Notified whenever a subscriber receives an event or goes away
+    consumed: Arc<Notify>,
+}
+
+impl EventChannel {
+    pub(crate) fn new(config: &EventChannelConfig) -> Self {
+        // The broadcast buffer is always a power of two; track its real size
+        let capacity = config.capacity.max(1).next_power_of_two();
+        let (tx, _) = broadcast::channel(capacity);
+        Self {
+            tx,
+            capacity,
+            overflow: config.overflow,
+            dropped: AtomicU64::new(0),
+            blocked: AtomicU64::new(0),
+            consumed: Arc::new(Notify::new()),
+        }
+    }
+
+    pub(crate) fn config(&self) -> EventChannelConfig {
+        EventChannelConfig {
+            capacity: self.capacity,
+            overflow: self.overflow,
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> EventReceiver {
+        EventReceiver {
+            rx: self.tx.subscribe(),
+            consumed: Consumed(Arc::clone(&self.consumed)),
+        }
+    }
+
+    /// Raw sender for callers outside an async context; these sends always drop oldest
+    pub(crate) fn sender(&self) -> broadcast::Sender<VfsEvent> {
+        self.tx.clone()
+    }
+
+    /// Send an event to every subscriber, applying the overflow policy if the buffer is full
+    pub(crate) async fn send(&self, event: VfsEvent) {
+        if self.tx.len() >= self.capacity {
+            match self.overflow {
+                OverflowPolicy::DropOldest => {
+                    let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                    // Log at powers of two so a sustained overflow doesn't flood the log
+                    if dropped.is_power_of_two() {
+                        tracing::warn!(
+                            "VFS event buffer full, {} events dropped for slow subscribers",
+                            dropped
+                        );
+                    }
+                }
+                OverflowPolicy::Block => {
+                    self.blocked.fetch_add(1, Ordering::Relaxed);
+                    loop {
+                        // Register before checking, so a receive in between still wakes us
+                        let consumed = self.consumed.notified();
+                        futures::pin_mut!(consumed);
+                        consumed.as_mut().enable();
+                        if self.tx.len() < self.capacity || self.tx.receiver_count() == 0 {
+                            break;
+                        }
+                        consumed.await;
+                    }
+                }
+            }
+        }
+        let _ = self.tx.send(event);
+    }
+
+    pub(crate) fn stats(&self) -> EventChannelStats {
+        EventChannelStats {
+            capacity: self.capacity,
+            overflow: self.overflow,
+            dropped: self.dropped.load(Ordering::Relaxed),
+            blocked: self.blocked.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Subscription to VFS events
+///
+/// Events a subscriber missed because it fell a full buffer behind are
+/// reported in their place as one [`VfsEvent::Lagged`].
+pub struct EventReceiver {
+    rx: broadcast::Receiver<VfsEvent>,
+    // Declared after `rx` so senders woken by the drop no longer count it
+    consumed: Consumed,
+}
+
+/// Wakes senders waiting for room in the buffer, including when dropped
+struct Consumed(Arc<Notify>);
+
+impl Drop for Consumed {
+    fn drop(&mut self) {
+        self.0.notify_waiters();
+    }
+}
+
+impl EventReceiver {
+    /// Wait for the next event
+    ///
+    /// Fails with `RecvError::Closed` once the VFS is gone; missed events are
+    /// reported as `VfsEvent::Lagged` rather than `RecvError::Lagged`.
+    pub async fn recv(&mut self) -> Result<VfsEvent, broadcast::error::RecvError> {
+        let event = match self.rx.recv().await {
+            Err(broadcast::error::RecvError::Lagged(missed)) => Ok(VfsEvent::Lagged { missed }),
+            other => other,
+        };
+        self.consumed.0.notify_waiters();
+        event
+    }
+
+    /// Take the next event if one is buffered
+    pub fn try_recv(&mut self) -> Result<VfsEvent, broadcast::error::TryRecvError> {
+        let event = match self.rx.try_recv() {
+            Err(broadcast::error::TryRecvError::Lagged(missed)) => Ok(VfsEvent::Lagged { missed }),
+            other => other,
+        };
+        if event.is_ok() {
+            self.consumed.0.notify_waiters();
+        }
+        event
+    }
+}
+
+/// How bursts of changes are thinned out before reaching a subscriber
+///
+/// The default passes everything through as it arrives.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Throttle {
+    /// Changes arriving within this many milliseconds of the first in a burst are coalesced
+    pub coalesce_ms: u64,
+    /// Most deliveries per second (`None` for no limit)
+    pub max_per_second: Option<u32>,
+}
+
+impl Throttle {
+    /// Coalesce changes arriving within `window` of the first one
+    pub fn with_coalesce(mut self, window: Duration) -> Self {
+        self.coalesce_ms = window.as_millis() as u64;
+        self
+    }
+
+    /// Deliver at most `max` times per second
+    pub fn with_max_per_second(mut self, max: u32) -> Self {
+        self.max_per_second = Some(max);
+        self
+    }
+
+    pub(crate) fn window(&self) -> Option<Duration> {
+        (self.coalesce_ms > 0).then(|| Duration::from_millis(self.coalesce_ms))
+    }
+
+    /// Wait until another delivery is allowed, given when the last one happened
+    pub(crate) async fn pace(&self, last_delivery: &mut Option<Duration>) {
+        if let (Some(max), Some(last)) =
+            (self.max_per_second.filter(|max| *max > 0), *last_delivery)
+        {
+            let interval = Duration::from_secs(1) / max;
+            let elapsed = monotonic_now().saturating_sub(last);
+            if elapsed < interval {
+                sleep(interval - elapsed).await;
+            }
+        }
+        *last_delivery = Some(monotonic_now());
+    }
+}
+
+/// Subscription to VFS events that applies a [`Throttle`]
+///
+/// Events of the same kind for the same path within a burst are coalesced into
+/// the latest one, which takes its place at the end of the burst, so the order
+/// of delivery still matches the order of the last changes.
+pub struct ThrottledEvents {
+    rx: EventReceiver,
+    throttle: Throttle,
+    pending: VecDeque<VfsEvent>,
+    /// Events the receiver missed while a burst was being collected
+    lagged: u64,
+    last_delivery: Option<Duration>,
+}
+
+impl ThrottledEvents {
+    pub(crate) fn new(rx: EventReceiver, throttle: Throttle) -> Self {
+        Self {
+            rx,
+            throttle,
+            pending: VecDeque::new(),
+            lagged: 0,
+            last_delivery: None,
+        }
+    }
+
+    /// Wait for the next event
+    ///
+    /// Fails with `RecvError::Lagged` after the buffered events if the
+    /// subscriber fell behind, and with `RecvError::Closed` once the VFS is gone.
+    pub async fn recv(&mut self) -> Result<VfsEvent, broadcast::error::RecvError> {
+        if self.pending.is_empty() && self.lagged == 0 {
+            self.collect().await?;
+        }
+
+        self.throttle.pace(&mut self.last_delivery).await;
+        match self.pending.pop_front() {
+            Some(event) => Ok(event),
+            None => Err(broadcast::error::RecvError::Lagged(std::mem::take(
+                &mut self.lagged,
+            ))),
+        }
+    }
+
+    /// Wait for the next event, reporting missed events as `VfsEvent::Lagged`
+    ///
+    /// Returns `None` once the VFS is gone.
+    pub async fn next(&mut self) -> Option<VfsEvent> {
+        match self.recv().await {
+            Ok(event) => Some(event),
+            Err(broadcast::error::RecvError::Lagged(missed)) => Some(VfsEvent::Lagged { missed }),
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    }
+
+    /// Receive one event, then every other event of its burst
+    async fn collect(&mut self) -> Result<(), broadcast::error::RecvError> {
+        let first = self.rx.recv().await?;
+        self.push(first);
+        if self.pending.is_empty() {
+            // Only missed events so far, which are reported straight away
+            return Ok(());
+        }
+
+        let Some(window) = self.throttle.window() else {
+            return Ok(());
+        };
+        let deadline = sleep(window).fuse();
+        futures::pin_mut!(deadline);
+        loop {
+            futures::select! {
+                event = self.rx.recv().fuse() => match event {
+                    Ok(event) => self.push(event),
+                    Err(_) => break,
+                },
+                _ = deadline => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, event: VfsEvent) {
+        if let VfsEvent::Lagged { missed } = event {
+            self.lagged += missed;
+            return;
+        }
+        let same = |pending: &VfsEvent| {
+            std::mem::discriminant(pending) == std::mem::discriminant(&event)
+                && pending.path() == event.path()
+        };
+        if let Some(index) = self.pending.iter().position(same) {
+            self.pending.remove(index);
+        }
+        self.pending.push_back(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(n: usize) -> VfsEvent {
+        VfsEvent::DocumentDeleted {
+            path: format!("/{}", n),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_counts_overflow() {
+        let channel = EventChannel::new(&EventChannelConfig::default().with_capacity(3));
+        let mut rx = channel.subscribe();
+
+        for n in 0..6 {
+            channel.send(event(n)).await;
+        }
+
+        let stats = channel.stats();
+        assert_eq!(stats.capacity, 4);
+        assert_eq!(stats.dropped, 2);
+        assert!(matches!(
+            rx.recv().await,
+            Ok(VfsEvent::Lagged { missed: 2 })
+        ));
+        assert_eq!(rx.recv().await.unwrap().path(), Some("/2"));
+    }
+
+    #[tokio::test]
+    async fn test_block_waits_for_subscriber() {
+        let channel = Arc::new(EventChannel::new(
+            &EventChannelConfig::default()
+                .with_capacity(2)
+                .with_overflow(OverflowPolicy::Block),
+        ));
+        let mut rx = channel.subscribe();
+
+        let sender = Arc::clone(&channel);
+        let writes = tokio::spawn(async move {
+            for n in 0..5 {
+                sender.send(event(n)).await;
+            }
+        });
+
+        for n in 0..5 {
+            match rx.recv().await.unwrap() {
+                VfsEvent::DocumentDeleted { path } => assert_eq!(path, format!("/{}", n)),
+                other => panic!("Unexpected event {:?}", other),
+            }
+        }
+        writes.await.unwrap();
+
+        let stats = channel.stats();
+        assert_eq!(stats.dropped, 0);
+        assert!(stats.blocked > 0);
+    }
+
+    #[tokio::test]
+    async fn test_block_resumes_when_subscriber_goes_away() {
+        let channel = Arc::new(EventChannel::new(
+            &EventChannelConfig::default()
+                .with_capacity(2)
+                .with_overflow(OverflowPolicy::Block),
+        ));
+        let rx = channel.subscribe();
+        channel.send(event(0)).await;
+        channel.send(event(1)).await;
+
+        let sender = Arc::clone(&channel);
+        let mut write = tokio::spawn(async move { sender.send(event(2)).await });
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut write)
+            .await
+            .is_err());
+
+        drop(rx);
+        tokio::time::timeout(Duration::from_secs(1), write)
+            .await
+            .expect("the write should go through once nobody is subscribed")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_throttled_events_coalesce_per_path() {
+        let channel = EventChannel::new(&EventChannelConfig::default());
+        let mut events = ThrottledEvents::new(
+            channel.subscribe(),
+            Throttle::default().with_coalesce(Duration::from_millis(50)),
+        );
+
+        for n in [1, 2, 1, 1, 3] {
+            channel.send(event(n)).await;
+        }
+        channel
+            .send(VfsEvent::DocumentCreated {
+                path: "/1".to_string(),
+                doc_id: crate::TonkCore::new().await.unwrap().vfs().root_id(),
+            })
+            .await;
+
+        let mut received = Vec::new();
+        for _ in 0..4 {
+            received.push(events.recv().await.unwrap());
+        }
+        let paths: Vec<&str> = received.iter().filter_map(VfsEvent::path).collect();
+        assert_eq!(paths, vec!["/2", "/1", "/3", "/1"]);
+        assert!(matches!(received[3], VfsEvent::DocumentCreated { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_throttled_events_end_on_latest_change() {
+        let channel = EventChannel::new(&EventChannelConfig::default());
+        let mut events = ThrottledEvents::new(
+            channel.subscribe(),
+            Throttle::default().with_coalesce(Duration::from_millis(50)),
+        );
+        let doc_id = crate::TonkCore::new().await.unwrap().vfs().root_id();
+
+        // Deleted, recreated, then deleted again: the path ends up deleted
+        channel.send(event(1)).await;
+        channel
+            .send(VfsEvent::DocumentCreated {
+                path: "/1".to_string(),
+                doc_id,
+            })
+            .await;
+        channel.send(event(1)).await;
+
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            VfsEvent::DocumentCreated { .. }
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            VfsEvent::DocumentDeleted { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_throttled_events_rate_limit() {
+        let channel = EventChannel::new(&EventChannelConfig::default());
+        let mut events = ThrottledEvents::new(
+            channel.subscribe(),
+            Throttle::default().with_max_per_second(20),
+        );
+        for n in 0..3 {
+            channel.send(event(n)).await;
+        }
+
+        let started = std::time::Instant::now();
+        for _ in 0..3 {
+            events.recv().await.unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn test_lagged_subscriber_gets_lagged_event() {
+        let channel = EventChannel::new(&EventChannelConfig::default().with_capacity(2));
+        let mut events = ThrottledEvents::new(channel.subscribe(), Throttle::default());
+
+        for n in 0..5 {
+            channel.send(event(n)).await;
+        }
+        drop(channel);
+
+        assert!(matches!(
+            events.next().await,
+            Some(VfsEvent::Lagged { missed: 3 })
+        ));
+        assert_eq!(events.next().await.unwrap().path(), Some("/3"));
+        assert_eq!(events.next().await.unwrap().path(), Some("/4"));
+        assert!(events.next().await.is_none());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::error::{Result, VfsError};
+use crate::vfs::VirtualFileSystem;
+use samod::DocumentId;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A named document tree mounted alongside a space's primary VFS
+///
+/// Mounts let one bundle carry logically separate trees (e.g. `data` or
+/// `settings`) that are stored under their own root document.
+#[derive(Clone)]
+pub struct Mount {
+    /// Name the tree is recorded under in the bundle manifest's `roots`
+    pub name: String,
+    pub vfs: Arc<VirtualFileSystem>,
+    /// Mounts with a higher priority are listed, and should be synced, first
+    pub sync_priority: i32,
+    /// Whether the tree is included when the space is exported
+    pub exported: bool,
+    /// Path the tree is attached at in the main VFS namespace, for mounted spaces
+    pub mount_point: Option<String>,
+    /// Relay the tree was last connected to through `connect_mount`
+    pub relay_url: Option<String>,
+}
+
+impl Mount {
+    /// Map `path` to the matching path inside this mount, if it's below the mount point
+    pub fn relative_path(&self, path: &str) -> Option<String> {
+        let rest = path.strip_prefix(self.mount_point.as_deref()?)?;
+        if rest.is_empty() {
+            Some("/".to_string())
+        } else if rest.starts_with('/') {
+            Some(rest.to_string())
+        } else {
+            None
+        }
+    }
+}
+
+/// A mount as recorded in storage, so its tree is known after a restart
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MountRecord {
+    pub name: String,
+    /// ID of the mounted tree's root document
+    pub root_id: String,
+    pub sync_priority: i32,
+    pub exported: bool,
+    pub mount_point: Option<String>,
+}
+
+impl From<&Mount> for MountRecord {
+    fn from(mount: &Mount) -> Self {
+        Self {
+            name: mount.name.clone(),
+            root_id: mount.vfs.root_id().to_string(),
+            sync_priority: mount.sync_priority,
+            exported: mount.exported,
+            mount_point: mount.mount_point.clone(),
+        }
+    }
+}
+
+/// Where a mounted space comes from
+#[derive(Debug, Clone)]
+pub enum MountSource {
+    /// An existing space, by the ID of its root document
+    ///
+    /// The space has to be in local storage or held by a connected peer.
+    Space(DocumentId),
+    /// The bytes of a bundle, whose documents are imported under their own IDs
+    #[cfg(feature = "bundle")]
+    Bundle(Vec<u8>),
+}
+
+/// Check a mount point and strip any trailing slash
+pub(crate) fn normalize_mount_point(path: &str) -> Result<String> {
+    let invalid = |reason: &str| VfsError::InvalidPath(format!("Mount point {path}: {reason}"));
+
+    if !path.starts_with('/') {
+        return Err(invalid("must be absolute"));
+    }
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    if components.is_empty() {
+        return Err(invalid("cannot be the root"));
+    }
+    if components.iter().any(|c| *c == "." || *c == "..") {
+        return Err(invalid("cannot contain '.' or '..'"));
+    }
+    Ok(format!("/{}", components.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_mount_point() {
+        assert_eq!(normalize_mount_point("/mnt/team/").unwrap(), "/mnt/team");
+        assert_eq!(normalize_mount_point("//mnt//team").unwrap(), "/mnt/team");
+        assert!(normalize_mount_point("/").is_err());
+        assert!(normalize_mount_point("mnt/team").is_err());
+        assert!(normalize_mount_point("/mnt/../team").is_err());
+    }
+}
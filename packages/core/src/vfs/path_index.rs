@@ -1,8 +1,11 @@
-use super::types::NodeType;
+use super::types::{Metadata, NodeType};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// Layout version of the path index document; 3 shards entries by directory
+pub const PATH_INDEX_VERSION: u32 = 3;
+
 /// Path index is source of truth for VFS structure
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct PathIndex {
@@ -32,6 +35,10 @@ pub struct PathEntry {
     /// Modified timestamp
     #[serde(with = "chrono::serde::ts_milliseconds")]
     pub modified: DateTime<Utc>,
+
+    /// Extended attributes mirrored from the node document
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
 }
 
 impl PathIndex {
@@ -60,6 +67,7 @@ impl PathIndex {
                     node_type,
                     created: now,
                     modified: now,
+                    metadata: Metadata::new(),
                 },
             );
         }
@@ -126,6 +134,21 @@ impl PathIndex {
         self.paths.keys().collect()
     }
 
+    /// Map each document ID to the paths whose entries reference it, sorted
+    pub fn reverse_index(&self) -> HashMap<String, Vec<String>> {
+        let mut reverse: HashMap<String, Vec<String>> = HashMap::new();
+        for (path, entry) in &self.paths {
+            reverse
+                .entry(entry.doc_id.clone())
+                .or_default()
+                .push(path.clone());
+        }
+        for paths in reverse.values_mut() {
+            paths.sort();
+        }
+        reverse
+    }
+
     /// Move a path (for rename/move operations)
     pub fn move_path(&mut self, from_path: &str, to_path: &str) -> Result<(), String> {
         if let Some(mut entry) = self.paths.remove(from_path) {
@@ -386,6 +409,31 @@ mod tests {
         assert_eq!(dir_entry.node_type, NodeType::Directory);
     }
 
+    #[test]
+    fn test_reverse_index() {
+        let mut index = PathIndex::new();
+
+        index.set_path(
+            "/b.json".to_string(),
+            "doc1".to_string(),
+            NodeType::Document,
+        );
+        index.set_path(
+            "/a.json".to_string(),
+            "doc1".to_string(),
+            NodeType::Document,
+        );
+        index.set_path("/dir".to_string(), "doc2".to_string(), NodeType::Directory);
+
+        let reverse = index.reverse_index();
+        assert_eq!(
+            reverse["doc1"],
+            vec!["/a.json".to_string(), "/b.json".to_string()]
+        );
+        assert_eq!(reverse["doc2"], vec!["/dir".to_string()]);
+        assert!(!reverse.contains_key("doc3"));
+    }
+
     #[test]
     fn test_serialization() {
         let mut index = PathIndex::new();
@@ -0,0 +1,201 @@
+use crate::error::{Result, VfsError};
+use crate::vfs::types::NodeType;
+use std::io::{Read, Write};
+
+/// What a tar entry holds; other entry kinds (links, devices) are skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TarEntryKind {
+    File,
+    Directory,
+}
+
+impl TarEntryKind {
+    /// Type of the node an entry of this kind is imported as
+    pub(crate) fn node_type(self) -> NodeType {
+        match self {
+            TarEntryKind::File => NodeType::Document,
+            TarEntryKind::Directory => NodeType::Directory,
+        }
+    }
+}
+
+/// A file or directory read from a tar archive
+#[derive(Debug)]
+pub(crate) struct TarEntry {
+    /// Path within the archive, without leading `./` or trailing `/`
+    pub path: String,
+    pub kind: TarEntryKind,
+    pub data: Vec<u8>,
+}
+
+fn invalid(message: impl std::fmt::Display) -> VfsError {
+    VfsError::Other(anyhow::anyhow!("Invalid tar archive: {}", message))
+}
+
+/// Writes GNU tar archives, using long name entries for paths that don't fit a header
+pub(crate) struct TarWriter<W: Write> {
+    builder: ::tar::Builder<W>,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self {
+            builder: ::tar::Builder::new(writer),
+        }
+    }
+
+    pub(crate) fn append_directory(&mut self, path: &str, mtime: u64) -> Result<()> {
+        self.append(
+            &format!("{}/", path),
+            ::tar::EntryType::Directory,
+            0o755,
+            mtime,
+            &[],
+        )
+    }
+
+    pub(crate) fn append_file(&mut self, path: &str, data: &[u8], mtime: u64) -> Result<()> {
+        self.append(path, ::tar::EntryType::Regular, 0o644, mtime, data)
+    }
+
+    /// Write the end-of-archive marker and return the underlying writer
+    pub(crate) fn finish(self) -> Result<W> {
+        Ok(self.builder.into_inner()?)
+    }
+
+    fn append(
+        &mut self,
+        path: &str,
+        kind: ::tar::EntryType,
+        mode: u32,
+        mtime: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        // GNU headers store numbers too large for their octal fields in binary
+        let mut header = ::tar::Header::new_gnu();
+        header.set_entry_type(kind);
+        header.set_mode(mode);
+        header.set_mtime(mtime);
+        header.set_size(data.len() as u64);
+        self.builder
+            .append_data(&mut header, path, data)
+            .map_err(|e| invalid(format!("can't write '{}': {}", path, e)))
+    }
+}
+
+/// Reads ustar, GNU and PAX archives entry by entry
+pub(crate) struct TarReader<R: Read> {
+    archive: ::tar::Archive<R>,
+}
+
+impl<R: Read> TarReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            archive: ::tar::Archive::new(reader),
+        }
+    }
+
+    /// Call `f` with each file and directory in the archive, in order
+    ///
+    /// An entry's data is read as it arrives rather than allocated up front from
+    /// the size its header claims, so a bogus size can't exhaust memory.
+    pub(crate) fn for_each_entry(
+        &mut self,
+        mut f: impl FnMut(TarEntry) -> Result<()>,
+    ) -> Result<()> {
+        for entry in self.archive.entries().map_err(invalid)? {
+            let mut entry = entry.map_err(invalid)?;
+            let kind = match entry.header().entry_type() {
+                ::tar::EntryType::Regular | ::tar::EntryType::Continuous => TarEntryKind::File,
+                ::tar::EntryType::Directory => TarEntryKind::Directory,
+                _ => continue,
+            };
+            let path = String::from_utf8_lossy(&entry.path_bytes())
+                .trim_start_matches("./")
+                .trim_end_matches('/')
+                .to_string();
+
+            let size = entry.size();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).map_err(invalid)?;
+            if (data.len() as u64) < size {
+                return Err(invalid("truncated entry"));
+            }
+
+            f(TarEntry { path, kind, data })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(archive: &[u8]) -> Result<Vec<(String, TarEntryKind, usize)>> {
+        let mut entries = Vec::new();
+        TarReader::new(archive).for_each_entry(|entry| {
+            entries.push((entry.path, entry.kind, entry.data.len()));
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let long_path = format!("{}/{}", "d".repeat(120), "f".repeat(120));
+        let very_long_path = "x".repeat(300);
+
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_directory("docs", 0).unwrap();
+        writer.append_file("docs/a.txt", b"hello", 1).unwrap();
+        writer.append_file(&long_path, b"long", 2).unwrap();
+        writer.append_file(&very_long_path, &[0; 600], 3).unwrap();
+        let archive = writer.finish().unwrap();
+        assert_eq!(archive.len() % 512, 0);
+
+        assert_eq!(
+            read_all(&archive).unwrap(),
+            vec![
+                ("docs".to_string(), TarEntryKind::Directory, 0),
+                ("docs/a.txt".to_string(), TarEntryKind::File, 5),
+                (long_path, TarEntryKind::File, 4),
+                (very_long_path, TarEntryKind::File, 600),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_large_numbers_are_written() {
+        // Too large for an octal header field
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_file("a.txt", b"hello", u64::MAX / 2).unwrap();
+        let archive = writer.finish().unwrap();
+        assert_eq!(
+            read_all(&archive).unwrap(),
+            vec![("a.txt".to_string(), TarEntryKind::File, 5)]
+        );
+    }
+
+    #[test]
+    fn test_rejects_corrupt_header() {
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_file("a.txt", b"hello", 0).unwrap();
+        let mut archive = writer.finish().unwrap();
+        archive[0] = b'b';
+
+        assert!(read_all(&archive).is_err());
+    }
+
+    #[test]
+    fn test_rejects_truncated_entry() {
+        let mut header = ::tar::Header::new_gnu();
+        header.set_path("huge.bin").unwrap();
+        header.set_size(1 << 40);
+        header.set_cksum();
+        let mut archive = header.as_bytes().to_vec();
+        archive.extend_from_slice(&[1; 512]);
+
+        assert!(read_all(&archive).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::error::{Result, VfsError};
+use crate::import::yield_now;
+use serde::{Deserialize, Serialize};
+
+/// Default maximum depth of a path below the root
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Limits applied to operations that walk the directory tree.
+///
+/// Walks over pathological trees (very deep nesting, directories with huge
+/// numbers of children) fail with `TraversalLimitExceeded` rather than running
+/// unbounded, and yield periodically so a wasm host stays responsive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TraversalLimits {
+    /// Maximum number of path components below the root
+    pub max_depth: Option<usize>,
+    /// Maximum number of children in a single directory
+    pub max_breadth: Option<usize>,
+    /// Number of nodes to visit before yielding to the event loop (0 disables yielding)
+    pub yield_every: usize,
+}
+
+impl Default for TraversalLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_breadth: None,
+            yield_every: 64,
+        }
+    }
+}
+
+impl TraversalLimits {
+    /// Create limits with no depth or breadth restrictions
+    pub fn unlimited() -> Self {
+        Self {
+            max_depth: None,
+            ..Self::default()
+        }
+    }
+
+    /// Set the maximum path depth
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Set the maximum number of children in a directory
+    pub fn with_max_breadth(mut self, max_breadth: usize) -> Self {
+        self.max_breadth = Some(max_breadth);
+        self
+    }
+
+    /// Set how many nodes are visited between yields
+    pub fn with_yield_every(mut self, yield_every: usize) -> Self {
+        self.yield_every = yield_every;
+        self
+    }
+
+    /// Check that `path` is no deeper than the maximum depth
+    pub fn check_depth(&self, path: &str) -> Result<()> {
+        if let Some(max) = self.max_depth {
+            let depth = path_depth(path);
+            if depth > max {
+                return Err(VfsError::TraversalLimitExceeded(format!(
+                    "{} is {} levels deep, limit is {}",
+                    path, depth, max
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the directory at `path` has no more than the maximum number of children
+    pub fn check_breadth(&self, path: &str, children: usize) -> Result<()> {
+        if let Some(max) = self.max_breadth {
+            if children > max {
+                return Err(VfsError::TraversalLimitExceeded(format!(
+                    "{} has {} children, limit is {}",
+                    path, children, max
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Number of components in a path; the root has depth 0
+pub fn path_depth(path: &str) -> usize {
+    path.split('/').filter(|s| !s.is_empty()).count()
+}
+
+/// Progress reported while walking the directory tree
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraversalProgress {
+    /// Number of nodes visited so far
+    pub nodes_visited: usize,
+    /// Path of the most recently visited node
+    pub current_path: String,
+    /// Whether the walk has finished
+    pub done: bool,
+}
+
+/// Callback invoked with progress while walking the directory tree
+#[cfg(not(target_arch = "wasm32"))]
+pub type TraversalProgressCallback = std::sync::Arc<dyn Fn(&TraversalProgress) + Send + Sync>;
+
+/// Callback invoked with progress while walking the directory tree
+#[cfg(target_arch = "wasm32")]
+pub type TraversalProgressCallback = std::rc::Rc<dyn Fn(&TraversalProgress)>;
+
+/// Tracks an in-flight walk, enforcing limits and yields and reporting progress
+pub(crate) struct TraversalTracker<'a> {
+    limits: &'a TraversalLimits,
+    on_progress: Option<TraversalProgressCallback>,
+    progress: TraversalProgress,
+}
+
+impl<'a> TraversalTracker<'a> {
+    pub(crate) fn new(
+        limits: &'a TraversalLimits,
+        on_progress: Option<TraversalProgressCallback>,
+    ) -> Self {
+        Self {
+            limits,
+            on_progress,
+            progress: TraversalProgress::default(),
+        }
+    }
+
+    /// Check a directory's child count before its children are visited
+    pub(crate) fn enter(&self, path: &str, children: usize) -> Result<()> {
+        self.limits.check_breadth(path, children)
+    }
+
+    /// Record a visited node, yielding to the event loop when due
+    pub(crate) async fn visit(&mut self, path: &str) -> Result<()> {
+        self.limits.check_depth(path)?;
+
+        self.progress.nodes_visited += 1;
+        self.progress.current_path = path.to_string();
+
+        let yield_every = self.limits.yield_every;
+        if yield_every > 0 && self.progress.nodes_visited.is_multiple_of(yield_every) {
+            self.report();
+            yield_now().await;
+        }
+        Ok(())
+    }
+
+    /// Report the final progress of the walk
+    pub(crate) fn finish(&mut self) {
+        self.progress.done = true;
+        self.report();
+    }
+
+    fn report(&self) {
+        if let Some(callback) = &self.on_progress {
+            callback(&self.progress);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_depth() {
+        assert_eq!(path_depth("/"), 0);
+        assert_eq!(path_depth("/a"), 1);
+        assert_eq!(path_depth("/a/b/c"), 3);
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = TraversalLimits::unlimited()
+            .with_max_depth(2)
+            .with_max_breadth(3);
+
+        assert!(limits.check_depth("/a/b").is_ok());
+        assert!(matches!(
+            limits.check_depth("/a/b/c"),
+            Err(VfsError::TraversalLimitExceeded(_))
+        ));
+        assert!(limits.check_breadth("/a", 3).is_ok());
+        assert!(matches!(
+            limits.check_breadth("/a", 4),
+            Err(VfsError::TraversalLimitExceeded(_))
+        ));
+    }
+}
@@ -1,6 +1,23 @@
+use crate::identity::Identity;
 use chrono::{DateTime, Utc};
 use samod::DocumentId;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// App-level extended attributes attached to a VFS node
+pub type Metadata = BTreeMap<String, serde_json::Value>;
+
+/// Layout version written to the root of every directory and document node
+///
+/// - `0`: unversioned nodes written before the `version` field existed. Document
+///   `content` is either native Automerge objects or, in the oldest layout, a JSON
+///   string.
+/// - `1`: adds the root `version` field; `content` is always native Automerge objects.
+///
+/// Readers accept every version, including newer ones, by reading only the fields
+/// they know about. Older nodes are upgraded in place with
+/// `VirtualFileSystem::upgrade_schema`.
+pub const NODE_SCHEMA_VERSION: u32 = 1;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum NodeType {
@@ -8,6 +25,9 @@ pub enum NodeType {
     Document,
     #[serde(rename = "directory")]
     Directory,
+    /// A link to another path, followed when reading or writing through it
+    #[serde(rename = "symlink")]
+    Symlink,
 }
 
 impl NodeType {
@@ -16,6 +36,7 @@ impl NodeType {
         match self {
             NodeType::Document => "document",
             NodeType::Directory => "directory",
+            NodeType::Symlink => "symlink",
         }
     }
 
@@ -24,6 +45,7 @@ impl NodeType {
         match s {
             "document" => Some(NodeType::Document),
             "directory" => Some(NodeType::Directory),
+            "symlink" => Some(NodeType::Symlink),
             _ => None,
         }
     }
@@ -69,6 +91,8 @@ pub struct RefNode {
     pub node_type: NodeType,
     pub timestamps: Timestamps,
     pub name: String,
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
 }
 
 impl RefNode {
@@ -78,6 +102,7 @@ impl RefNode {
             node_type: NodeType::Document,
             timestamps: Timestamps::now(),
             name,
+            metadata: Metadata::new(),
         }
     }
 
@@ -87,14 +112,183 @@ impl RefNode {
             node_type: NodeType::Directory,
             timestamps: Timestamps::now(),
             name,
+            metadata: Metadata::new(),
         }
     }
 }
 
+/// One of several values written concurrently to the same location
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConflictValue {
+    /// Hex-encoded ID of the actor that wrote this value
+    pub actor: String,
+    pub value: serde_json::Value,
+}
+
+/// A change in a document's history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// Hex-encoded hash of the change
+    pub hash: String,
+    /// Hex-encoded ID of the actor that made the change
+    pub actor: String,
+    /// Position of the change among the actor's changes, starting at 1
+    pub seq: u64,
+    /// Unix time in milliseconds the change was made, if it was recorded
+    pub timestamp: Option<i64>,
+    pub message: Option<String>,
+    /// Identity the actor belongs to, if it was registered in the space
+    pub author: Option<Identity>,
+}
+
+/// How a location in a document's content changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PatchKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A change to a document's content at one JSON path
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContentPatch {
+    /// Keys and array indices from the content root; empty for the whole content
+    pub path: Vec<String>,
+    pub kind: PatchKind,
+    /// The new value, absent for removals
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+}
+
+/// A document or directory that has been moved to the trash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashEntry {
+    /// Current location inside the trash directory
+    pub trash_path: String,
+    /// Path the node was deleted from
+    pub original_path: String,
+    #[serde(rename = "type")]
+    pub node_type: NodeType,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// Document count and stored size for a directory subtree
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryStats {
+    pub documents: usize,
+    pub bytes: u64,
+}
+
+/// Size accounting for a VFS, measured from saved document lengths
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VfsStats {
+    /// Totals across the whole VFS
+    pub total: DirectoryStats,
+    /// Recursive totals for each directory, keyed by path ("/" for the root)
+    pub directories: BTreeMap<String, DirectoryStats>,
+}
+
+/// Field a paged directory listing is sorted by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SortBy {
+    /// The directory's own order, as set with `set_order`
+    #[default]
+    Order,
+    Name,
+    Created,
+    Modified,
+}
+
+/// Direction of a sorted directory listing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Which slice of a directory listing to return, and in what order
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ListOptions {
+    /// Number of entries to skip
+    pub offset: usize,
+    /// Maximum number of entries to return; all remaining entries if unset
+    pub limit: Option<usize>,
+    pub sort_by: SortBy,
+    pub order: SortOrder,
+}
+
+/// One page of a directory listing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListPage {
+    pub entries: Vec<RefNode>,
+    /// Number of children in the directory
+    pub total: usize,
+    /// Offset of the next page, if there are more entries
+    pub next_offset: Option<usize>,
+}
+
+/// What a copy or import does with an entry whose path is already taken
+///
+/// Directories landing on existing directories are merged whatever the policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictPolicy {
+    /// Leave the existing node, and anything below a skipped directory, alone
+    Skip,
+    /// Replace the existing document's content
+    #[default]
+    Overwrite,
+    /// Write to the first free path with a numeric suffix, e.g. `notes-1.txt`
+    Rename,
+    /// Fail with `DocumentExists` before anything is written
+    Fail,
+}
+
+/// How a copy or import treats existing paths
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CopyOptions {
+    pub conflict: ConflictPolicy,
+    /// Work out the operations without writing anything
+    pub dry_run: bool,
+}
+
+/// What a copy or import does at one path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CopyAction {
+    CreateDirectory,
+    Create,
+    Overwrite,
+    Skip,
+}
+
+/// One step of a copy or import, performed or, in a dry run, planned
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CopyOperation {
+    /// Path of the entry in the source tree, archive or directory on disk
+    pub source: String,
+    /// Path written to, which differs from the requested one when renamed
+    pub path: String,
+    pub action: CopyAction,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DirNode {
     #[serde(rename = "type")]
     pub node_type: NodeType,
+    /// Layout version of the node (0 for unversioned nodes)
+    #[serde(default)]
+    pub version: u32,
     pub name: String,
     pub timestamps: Timestamps,
     pub children: Vec<RefNode>,
@@ -104,6 +298,7 @@ impl DirNode {
     pub fn new(name: String) -> Self {
         Self {
             node_type: NodeType::Directory,
+            version: NODE_SCHEMA_VERSION,
             name,
             timestamps: Timestamps::now(),
             children: Vec::new(),
@@ -139,6 +334,9 @@ impl DirNode {
 pub struct DocNode<T> {
     #[serde(rename = "type")]
     pub node_type: NodeType,
+    /// Layout version of the node (0 for unversioned nodes)
+    #[serde(default)]
+    pub version: u32,
     pub name: String,
     pub timestamps: Timestamps,
     pub content: T,
@@ -149,6 +347,7 @@ impl<T> DocNode<T> {
     pub fn new(name: String, content: T, bytes: Option<Vec<u8>>) -> Self {
         Self {
             node_type: NodeType::Document,
+            version: NODE_SCHEMA_VERSION,
             name,
             timestamps: Timestamps::now(),
             content,
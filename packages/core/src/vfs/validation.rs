@@ -0,0 +1,562 @@
+use crate::error::{Result, VfsError};
+use serde_json::Value;
+use std::sync::{Arc, RwLock};
+
+/// Checks the content of documents before it is written
+///
+/// Register one for a path prefix with `VirtualFileSystem::register_validator`.
+/// Closures taking the content and returning `Err(problem)` implement it.
+pub trait Validator: Send + Sync {
+    /// Return a description of the problem if `content` is invalid
+    fn validate(&self, content: &Value) -> std::result::Result<(), String>;
+}
+
+impl<F> Validator for F
+where
+    F: Fn(&Value) -> std::result::Result<(), String> + Send + Sync,
+{
+    fn validate(&self, content: &Value) -> std::result::Result<(), String> {
+        self(content)
+    }
+}
+
+fn normalize_prefix(prefix: &str) -> String {
+    let trimmed = prefix.trim_end_matches('/');
+    if trimmed.is_empty() {
+        "/".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Whether `path` is `prefix` or lies below it
+fn covers(prefix: &str, path: &str) -> bool {
+    prefix == "/"
+        || path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// A validator and the path prefix it applies to
+type PrefixValidator = (String, Arc<dyn Validator>);
+
+/// Validators by the path prefix they apply to
+#[derive(Clone, Default)]
+pub(crate) struct ValidatorRegistry {
+    validators: Arc<RwLock<Vec<PrefixValidator>>>,
+}
+
+impl ValidatorRegistry {
+    pub(crate) fn register(&self, prefix: &str, validator: Arc<dyn Validator>) {
+        self.validators
+            .write()
+            .unwrap()
+            .push((normalize_prefix(prefix), validator));
+    }
+
+    /// Remove the validators registered for exactly `prefix`, returning how many there were
+    pub(crate) fn remove(&self, prefix: &str) -> usize {
+        let prefix = normalize_prefix(prefix);
+        let mut validators = self.validators.write().unwrap();
+        let before = validators.len();
+        validators.retain(|(p, _)| *p != prefix);
+        before - validators.len()
+    }
+
+    /// Validators that apply to `path`
+    fn matching(&self, path: &str) -> Vec<Arc<dyn Validator>> {
+        self.validators
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(prefix, _)| covers(prefix, path))
+            .map(|(_, validator)| Arc::clone(validator))
+            .collect()
+    }
+
+    /// Check the content a write would leave at `path`
+    ///
+    /// `content` is only computed when some validator applies to the path.
+    pub(crate) fn check(&self, path: &str, content: impl FnOnce() -> Result<Value>) -> Result<()> {
+        let validators = self.matching(path);
+        if validators.is_empty() {
+            return Ok(());
+        }
+
+        let content = content()?;
+        for validator in validators {
+            validator
+                .validate(&content)
+                .map_err(|problem| VfsError::ValidationFailed(format!("{}: {}", path, problem)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Keywords that only annotate a schema and are ignored when validating
+const ANNOTATIONS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "$defs",
+    "definitions",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "format",
+    "readOnly",
+    "writeOnly",
+    "deprecated",
+];
+
+const TYPES: &[&str] = &[
+    "null", "boolean", "object", "array", "number", "integer", "string",
+];
+
+/// A [`Validator`] checking content against a JSON Schema
+///
+/// Supports the structural keywords `type`, `enum`, `const`, `properties`,
+/// `required`, `additionalProperties`, `minProperties`, `maxProperties`,
+/// `items`, `minItems`, `maxItems`, `uniqueItems`, `minLength`, `maxLength`,
+/// `minimum`, `maximum`, `exclusiveMinimum`, `exclusiveMaximum`, `multipleOf`,
+/// `allOf`, `anyOf`, `oneOf` and `not`, as well as boolean schemas.
+/// Annotations such as `title` and `format` are ignored. Any other keyword,
+/// including `$ref` and `pattern`, is rejected when the schema is built rather
+/// than silently skipped.
+#[derive(Debug, Clone)]
+pub struct JsonSchema {
+    schema: Value,
+}
+
+impl JsonSchema {
+    pub fn new(schema: Value) -> Result<Self> {
+        check_schema(&schema, "")?;
+        Ok(Self { schema })
+    }
+}
+
+impl Validator for JsonSchema {
+    fn validate(&self, content: &Value) -> std::result::Result<(), String> {
+        validate_value(&self.schema, content, "")
+    }
+}
+
+fn schema_error(at: &str, problem: impl std::fmt::Display) -> VfsError {
+    VfsError::InvalidSchema(format!(
+        "`{}`: {}",
+        if at.is_empty() { "#" } else { at },
+        problem
+    ))
+}
+
+/// Check that a schema only uses supported keywords, with values of the right shape
+fn check_schema(schema: &Value, at: &str) -> Result<()> {
+    let object = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(object) => object,
+        _ => return Err(schema_error(at, "a schema must be an object or a boolean")),
+    };
+
+    for (keyword, value) in object {
+        let here = format!("{}/{}", at, keyword);
+        let is_count = || value.as_u64().is_some();
+        let valid = match keyword.as_str() {
+            k if ANNOTATIONS.contains(&k) => true,
+            "type" => match value {
+                Value::String(name) => TYPES.contains(&name.as_str()),
+                Value::Array(names) => names
+                    .iter()
+                    .all(|name| name.as_str().is_some_and(|name| TYPES.contains(&name))),
+                _ => false,
+            },
+            "enum" => value.is_array(),
+            "const" => true,
+            "required" => value
+                .as_array()
+                .is_some_and(|names| names.iter().all(Value::is_string)),
+            "minProperties" | "maxProperties" | "minItems" | "maxItems" | "minLength"
+            | "maxLength" => is_count(),
+            "minimum" | "maximum" | "exclusiveMinimum" | "exclusiveMaximum" => value.is_number(),
+            "multipleOf" => value.as_f64().is_some_and(|m| m > 0.0),
+            "uniqueItems" => value.is_boolean(),
+            "properties" => {
+                let properties = value
+                    .as_object()
+                    .ok_or_else(|| schema_error(&here, "must be an object"))?;
+                for (name, property) in properties {
+                    check_schema(property, &format!("{}/{}", here, name))?;
+                }
+                true
+            }
+            "additionalProperties" | "items" | "not" => {
+                check_schema(value, &here)?;
+                true
+            }
+            "allOf" | "anyOf" | "oneOf" => {
+                let schemas = value
+                    .as_array()
+                    .filter(|schemas| !schemas.is_empty())
+                    .ok_or_else(|| schema_error(&here, "must be a non-empty array"))?;
+                for (i, schema) in schemas.iter().enumerate() {
+                    check_schema(schema, &format!("{}/{}", here, i))?;
+                }
+                true
+            }
+            _ => return Err(schema_error(&here, "unsupported keyword")),
+        };
+
+        if !valid {
+            return Err(schema_error(&here, "invalid value"));
+        }
+    }
+
+    Ok(())
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        name => type_name(value) == name,
+    }
+}
+
+/// Escape a property name for use in a JSON pointer
+fn pointer_segment(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+/// Validate `value` against a schema already accepted by [`check_schema`]
+fn validate_value(schema: &Value, value: &Value, at: &str) -> std::result::Result<(), String> {
+    let location = if at.is_empty() { "content" } else { at };
+    let object = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(format!("`{}` is not allowed", location)),
+        Value::Object(object) => object,
+        _ => return Ok(()),
+    };
+    let number = |keyword: &str| object.get(keyword).and_then(Value::as_f64);
+    let count = |keyword: &str| object.get(keyword).and_then(Value::as_u64);
+
+    if let Some(types) = object.get("type") {
+        let allowed: Vec<&str> = match types {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !allowed.iter().any(|name| has_type(value, name)) {
+            return Err(format!(
+                "`{}` must be of type {}, found {}",
+                location,
+                allowed.join(" or "),
+                type_name(value)
+            ));
+        }
+    }
+
+    if let Some(Value::Array(options)) = object.get("enum") {
+        if !options.contains(value) {
+            return Err(format!(
+                "`{}` must be one of {}",
+                location,
+                Value::from(options.clone())
+            ));
+        }
+    }
+    if let Some(expected) = object.get("const") {
+        if expected != value {
+            return Err(format!("`{}` must be {}", location, expected));
+        }
+    }
+
+    match value {
+        Value::String(s) => {
+            let length = s.chars().count() as u64;
+            if let Some(min) = count("minLength").filter(|min| length < *min) {
+                return Err(format!(
+                    "`{}` must be at least {} characters",
+                    location, min
+                ));
+            }
+            if let Some(max) = count("maxLength").filter(|max| length > *max) {
+                return Err(format!("`{}` must be at most {} characters", location, max));
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or(f64::NAN);
+            if let Some(min) = number("minimum").filter(|min| n < *min) {
+                return Err(format!("`{}` must be at least {}", location, min));
+            }
+            if let Some(max) = number("maximum").filter(|max| n > *max) {
+                return Err(format!("`{}` must be at most {}", location, max));
+            }
+            if let Some(min) = number("exclusiveMinimum").filter(|min| n <= *min) {
+                return Err(format!("`{}` must be greater than {}", location, min));
+            }
+            if let Some(max) = number("exclusiveMaximum").filter(|max| n >= *max) {
+                return Err(format!("`{}` must be less than {}", location, max));
+            }
+            if let Some(m) = number("multipleOf") {
+                let quotient = n / m;
+                if (quotient - quotient.round()).abs() > 1e-9 {
+                    return Err(format!("`{}` must be a multiple of {}", location, m));
+                }
+            }
+        }
+        Value::Array(items) => {
+            let length = items.len() as u64;
+            if let Some(min) = count("minItems").filter(|min| length < *min) {
+                return Err(format!("`{}` must have at least {} items", location, min));
+            }
+            if let Some(max) = count("maxItems").filter(|max| length > *max) {
+                return Err(format!("`{}` must have at most {} items", location, max));
+            }
+            if object.get("uniqueItems") == Some(&Value::Bool(true)) {
+                for (i, item) in items.iter().enumerate() {
+                    if items[..i].contains(item) {
+                        return Err(format!("`{}` must not contain duplicate items", location));
+                    }
+                }
+            }
+            if let Some(item_schema) = object.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_value(item_schema, item, &format!("{}/{}", at, i))?;
+                }
+            }
+        }
+        Value::Object(properties) => {
+            let length = properties.len() as u64;
+            if let Some(min) = count("minProperties").filter(|min| length < *min) {
+                return Err(format!(
+                    "`{}` must have at least {} properties",
+                    location, min
+                ));
+            }
+            if let Some(max) = count("maxProperties").filter(|max| length > *max) {
+                return Err(format!(
+                    "`{}` must have at most {} properties",
+                    location, max
+                ));
+            }
+            if let Some(Value::Array(required)) = object.get("required") {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !properties.contains_key(name) {
+                        return Err(format!(
+                            "`{}` is missing required property `{}`",
+                            location, name
+                        ));
+                    }
+                }
+            }
+
+            let declared = object.get("properties").and_then(Value::as_object);
+            for (name, property) in properties {
+                let here = format!("{}/{}", at, pointer_segment(name));
+                match declared.and_then(|declared| declared.get(name)) {
+                    Some(property_schema) => validate_value(property_schema, property, &here)?,
+                    None => match object.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            return Err(format!(
+                                "`{}` has unexpected property `{}`",
+                                location, name
+                            ))
+                        }
+                        Some(additional) => validate_value(additional, property, &here)?,
+                        None => {}
+                    },
+                }
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(Value::Array(schemas)) = object.get("allOf") {
+        for schema in schemas {
+            validate_value(schema, value, at)?;
+        }
+    }
+    if let Some(Value::Array(schemas)) = object.get("anyOf") {
+        if !schemas
+            .iter()
+            .any(|schema| validate_value(schema, value, at).is_ok())
+        {
+            return Err(format!(
+                "`{}` matches none of the `anyOf` schemas",
+                location
+            ));
+        }
+    }
+    if let Some(Value::Array(schemas)) = object.get("oneOf") {
+        let matches = schemas
+            .iter()
+            .filter(|schema| validate_value(schema, value, at).is_ok())
+            .count();
+        if matches != 1 {
+            return Err(format!(
+                "`{}` matches {} of the `oneOf` schemas instead of exactly one",
+                location, matches
+            ));
+        }
+    }
+    if let Some(schema) = object.get("not") {
+        if validate_value(schema, value, at).is_ok() {
+            return Err(format!("`{}` must not match the `not` schema", location));
+        }
+    }
+
+    Ok(())
+}
+
+/// Content after replacing the value at `json_path`, creating objects along the way
+pub(crate) fn with_value_at(mut content: Value, json_path: &[String], value: Value) -> Value {
+    if let Some(target) = value_at_mut(&mut content, json_path) {
+        *target = value;
+    }
+    content
+}
+
+/// Content after a JSON merge patch at `json_path`
+pub(crate) fn with_merge_patch(mut content: Value, json_path: &[String], patch: &Value) -> Value {
+    if let Some(target) = value_at_mut(&mut content, json_path) {
+        merge_patch(target, patch);
+    }
+    content
+}
+
+/// Content after splicing the text at `json_path`
+pub(crate) fn with_text_splice(
+    mut content: Value,
+    json_path: &[String],
+    index: usize,
+    delete_count: isize,
+    insert: &str,
+) -> Value {
+    if let Some(target) = value_at_mut(&mut content, json_path) {
+        let mut chars: Vec<char> = target.as_str().unwrap_or_default().chars().collect();
+        let start = index.min(chars.len());
+        let end = (start + delete_count.max(0) as usize).min(chars.len());
+        chars.splice(start..end, insert.chars());
+        *target = Value::String(chars.into_iter().collect());
+    }
+    content
+}
+
+/// The value at `json_path`, creating objects for missing keys
+fn value_at_mut<'a>(content: &'a mut Value, json_path: &[String]) -> Option<&'a mut Value> {
+    let mut current = content;
+    for key in json_path {
+        current = match current {
+            Value::Array(items) => items.get_mut(key.parse::<usize>().ok()?)?,
+            other => {
+                if !other.is_object() {
+                    *other = Value::Object(Default::default());
+                }
+                other
+                    .as_object_mut()?
+                    .entry(key.clone())
+                    .or_insert(Value::Null)
+            }
+        };
+    }
+    Some(current)
+}
+
+/// Apply an RFC 7386 merge patch to `target`
+pub(crate) fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_schema() {
+        let schema = JsonSchema::new(json!({
+            "type": "object",
+            "required": ["title", "done"],
+            "properties": {
+                "title": {"type": "string", "minLength": 1},
+                "done": {"type": "boolean"},
+                "priority": {"type": "integer", "minimum": 1, "maximum": 5},
+                "tags": {"type": "array", "items": {"enum": ["home", "work"]}, "uniqueItems": true}
+            },
+            "additionalProperties": false
+        }))
+        .unwrap();
+
+        assert!(schema
+            .validate(&json!({"title": "a", "done": false, "priority": 2, "tags": ["home"]}))
+            .is_ok());
+        assert_eq!(
+            schema.validate(&json!({"title": "a"})).unwrap_err(),
+            "`content` is missing required property `done`"
+        );
+        assert_eq!(
+            schema
+                .validate(&json!({"title": 1, "done": true}))
+                .unwrap_err(),
+            "`/title` must be of type string, found number"
+        );
+        assert!(schema
+            .validate(&json!({"title": "a", "done": true, "priority": 2.5}))
+            .is_err());
+        assert!(schema
+            .validate(&json!({"title": "a", "done": true, "tags": ["home", "home"]}))
+            .is_err());
+        assert!(schema
+            .validate(&json!({"title": "a", "done": true, "extra": 1}))
+            .is_err());
+
+        assert!(matches!(
+            JsonSchema::new(json!({"type": "string", "pattern": "^a"})),
+            Err(VfsError::InvalidSchema(_))
+        ));
+        assert!(JsonSchema::new(json!({"type": "strong"})).is_err());
+    }
+
+    #[test]
+    fn test_prospective_content() {
+        let content = json!({"a": {"b": 1}, "text": "hello"});
+        assert_eq!(
+            with_value_at(content.clone(), &["a".into(), "c".into()], json!(2)),
+            json!({"a": {"b": 1, "c": 2}, "text": "hello"})
+        );
+        assert_eq!(
+            with_merge_patch(content.clone(), &[], &json!({"a": null, "n": 1})),
+            json!({"text": "hello", "n": 1})
+        );
+        assert_eq!(
+            with_text_splice(content, &["text".into()], 5, 0, " world"),
+            json!({"a": {"b": 1}, "text": "hello world"})
+        );
+    }
+}
@@ -1,15 +1,48 @@
+use crate::vfs::backend::AutomergeHelpers;
+use crate::vfs::events::Throttle;
+use crate::vfs::{ContentPatch, VfsEvent};
 use futures::stream::StreamExt;
+use futures::FutureExt;
 use samod::DocHandle;
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+use tokio::sync::broadcast;
 
 /// A watcher for document changes in the VFS
 pub struct DocumentWatcher {
     handle: DocHandle,
+    /// VFS path and event channel used to report newly introduced conflicts
+    conflict_events: Option<(String, broadcast::Sender<VfsEvent>)>,
+    throttle: Throttle,
 }
 
 impl DocumentWatcher {
     /// Create a new document watcher
     pub fn new(handle: DocHandle) -> Self {
-        Self { handle }
+        Self {
+            handle,
+            conflict_events: None,
+            throttle: Throttle::default(),
+        }
+    }
+
+    /// Coalesce bursts of changes and limit how often the callback runs
+    ///
+    /// Changes arriving within the coalescing window of the first one produce a
+    /// single callback with the document as it is once the window closes.
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Emit `VfsEvent::ConflictDetected` when a change introduces new conflicts
+    pub(crate) fn with_conflict_events(
+        mut self,
+        path: &str,
+        event_tx: broadcast::Sender<VfsEvent>,
+    ) -> Self {
+        self.conflict_events = Some((path.to_string(), event_tx));
+        self
     }
 
     /// Get the document handle
@@ -29,12 +62,78 @@ impl DocumentWatcher {
         F: FnMut(&mut automerge::Automerge) + Send,
     {
         let mut changes = self.handle.changes();
-        while (changes.next().await).is_some() {
+
+        // Conflicts that already exist when watching starts aren't reported
+        let mut known_conflicts: HashSet<Vec<String>> = match self.conflict_events {
+            Some(_) => self
+                .handle
+                .with_document(|doc| AutomergeHelpers::find_conflicts(doc).into_iter().collect()),
+            None => HashSet::new(),
+        };
+
+        let mut last_delivery = None;
+        let mut closed = false;
+        while !closed && (changes.next().await).is_some() {
+            if let Some(window) = self.throttle.window() {
+                let deadline = sleep(window).fuse();
+                futures::pin_mut!(deadline);
+                loop {
+                    futures::select! {
+                        change = changes.next().fuse() => if change.is_none() {
+                            closed = true;
+                            break;
+                        },
+                        _ = deadline => break,
+                    }
+                }
+            }
+            self.throttle.pace(&mut last_delivery).await;
+
             // When a change occurs, call the callback with the current document state
-            self.handle.with_document(|doc| callback(doc));
+            self.handle.with_document(|doc| {
+                if let Some((path, event_tx)) = &self.conflict_events {
+                    let conflicts: HashSet<Vec<String>> =
+                        AutomergeHelpers::find_conflicts(doc).into_iter().collect();
+                    let mut json_paths: Vec<Vec<String>> =
+                        conflicts.difference(&known_conflicts).cloned().collect();
+                    if !json_paths.is_empty() {
+                        json_paths.sort();
+                        let _ = event_tx.send(VfsEvent::ConflictDetected {
+                            path: path.clone(),
+                            doc_id: self.handle.document_id().clone(),
+                            json_paths,
+                        });
+                    }
+                    known_conflicts = conflicts;
+                }
+                callback(doc)
+            });
         }
     }
 
+    /// Watch for changes and call the callback with what changed in the content
+    ///
+    /// The content is compared before and after each change, and the
+    /// differences are delivered as patches at JSON paths. Changes that leave
+    /// the content as it was, such as metadata updates, produce no callback.
+    pub async fn on_patches<F>(self, mut callback: F)
+    where
+        F: FnMut(Vec<ContentPatch>) + Send,
+    {
+        let mut content = self
+            .handle
+            .with_document(|doc| AutomergeHelpers::content_json(doc));
+        self.on_change(move |doc| {
+            let after = AutomergeHelpers::content_json(doc);
+            let patches = AutomergeHelpers::content_patches(&content, &after);
+            content = after;
+            if !patches.is_empty() {
+                callback(patches);
+            }
+        })
+        .await
+    }
+
     /// Watch for changes with a timeout, useful for tests
     pub async fn on_change_timeout<F>(
         self,
@@ -48,6 +147,108 @@ impl DocumentWatcher {
     }
 }
 
+/// A watcher that coalesces changes across many documents
+///
+/// Rather than one callback per document change, changes that arrive within the
+/// coalescing window of the first one are delivered together as a single list of
+/// affected paths. This keeps bursts of sync traffic from triggering a callback
+/// (and re-render) per document.
+pub struct BatchWatcher {
+    handles: Vec<(String, DocHandle)>,
+    window: Duration,
+}
+
+impl BatchWatcher {
+    /// Create a batch watcher over `(path, handle)` pairs
+    pub fn new(handles: Vec<(String, DocHandle)>, window: Duration) -> Self {
+        Self { handles, window }
+    }
+
+    /// Get the paths being watched
+    pub fn paths(&self) -> Vec<&str> {
+        self.handles.iter().map(|(path, _)| path.as_str()).collect()
+    }
+
+    /// Watch for changes and call the callback once per batch with the sorted,
+    /// de-duplicated paths that changed. Runs until every change stream closes.
+    pub async fn on_changes<F>(self, mut callback: F)
+    where
+        F: FnMut(Vec<String>) + Send,
+    {
+        let mut changes = futures::stream::select_all(
+            self.handles
+                .into_iter()
+                .map(|(path, handle)| handle.changes().map(move |_| path.clone()).boxed()),
+        );
+
+        while let Some(first) = changes.next().await {
+            let mut batch = BTreeSet::from([first]);
+
+            let deadline = sleep(self.window).fuse();
+            futures::pin_mut!(deadline);
+            loop {
+                futures::select! {
+                    path = changes.next() => match path {
+                        Some(path) => {
+                            batch.insert(path);
+                        }
+                        None => break,
+                    },
+                    _ = deadline => break,
+                }
+            }
+
+            callback(batch.into_iter().collect());
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &js_sys::Function, timeout: i32) -> wasm_bindgen::JsValue;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn sleep(duration: Duration) {
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        set_timeout(&resolve, duration.as_millis() as i32);
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn performance_now() -> f64;
+}
+
+/// Time on a monotonic clock, measured from an arbitrary origin
+///
+/// Unlike the wall clock, this never goes backwards when the system clock is
+/// adjusted, so differences between two readings are safe to sleep on.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn monotonic_now() -> Duration {
+    Duration::from_secs_f64(performance_now().max(0.0) / 1_000.0)
+}
+
+/// Time on a monotonic clock, measured from an arbitrary origin
+///
+/// Unlike the wall clock, this never goes backwards when the system clock is
+/// adjusted, so differences between two readings are safe to sleep on.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn monotonic_now() -> Duration {
+    static ORIGIN: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    ORIGIN.get_or_init(std::time::Instant::now).elapsed()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -170,4 +371,178 @@ mod tests {
         listener_task.abort();
         let _ = listener_task.await;
     }
+
+    #[tokio::test]
+    async fn test_batch_watcher_coalesces_changes() {
+        let tonk = TonkCore::new().await.unwrap();
+        let mut handles = Vec::new();
+        for i in 0..5 {
+            let handle = tonk
+                .create_document(automerge::Automerge::new())
+                .await
+                .unwrap();
+            handles.push((format!("/doc{}", i), handle));
+        }
+
+        let watcher = BatchWatcher::new(handles.clone(), Duration::from_millis(50));
+        assert_eq!(watcher.paths().len(), 5);
+
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let listener_task = tokio::spawn({
+            let batches = batches.clone();
+            async move {
+                watcher
+                    .on_changes(move |paths| batches.lock().unwrap().push(paths))
+                    .await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // A burst across every document, with repeated changes to one of them
+        for (_, handle) in handles.iter().chain(handles.iter().take(1)) {
+            handle.with_document(|doc| {
+                doc.transact::<_, _, AutomergeError>(|tx| {
+                    tx.put(ROOT, "value", 1)?;
+                    Ok(())
+                })
+                .unwrap();
+            });
+        }
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        // A later, separate change arrives as its own batch
+        handles[3].1.with_document(|doc| {
+            doc.transact::<_, _, AutomergeError>(|tx| {
+                tx.put(ROOT, "value", 2)?;
+                Ok(())
+            })
+            .unwrap();
+        });
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        let batches = batches.lock().unwrap().clone();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(
+            batches[0],
+            vec!["/doc0", "/doc1", "/doc2", "/doc3", "/doc4"]
+        );
+        assert_eq!(batches[1], vec!["/doc3"]);
+
+        listener_task.abort();
+        let _ = listener_task.await;
+    }
+
+    #[tokio::test]
+    async fn test_on_patches() {
+        use crate::vfs::PatchKind;
+        use serde_json::json;
+
+        let tonk = TonkCore::new().await.unwrap();
+        let vfs = tonk.vfs();
+        vfs.create_document("/todo.json", json!({"title": "a", "tags": ["x"]}))
+            .await
+            .unwrap();
+        let watcher = vfs.watch_document("/todo.json").await.unwrap().unwrap();
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let listener_task = tokio::spawn({
+            let received = received.clone();
+            async move {
+                watcher
+                    .on_patches(move |patches| received.lock().unwrap().push(patches))
+                    .await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        vfs.update_document(
+            "/todo.json",
+            json!({"title": "b", "tags": ["x"], "done": true}),
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        vfs.merge_patch_document("/todo.json", &[], json!({"done": null, "tags": ["x", "y"]}))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 2);
+        assert_eq!(
+            received[0],
+            vec![
+                ContentPatch {
+                    path: vec!["done".to_string()],
+                    kind: PatchKind::Added,
+                    value: Some(json!(true)),
+                },
+                ContentPatch {
+                    path: vec!["title".to_string()],
+                    kind: PatchKind::Changed,
+                    value: Some(json!("b")),
+                },
+            ]
+        );
+        assert_eq!(
+            received[1],
+            vec![
+                ContentPatch {
+                    path: vec!["done".to_string()],
+                    kind: PatchKind::Removed,
+                    value: None,
+                },
+                ContentPatch {
+                    path: vec!["tags".to_string()],
+                    kind: PatchKind::Changed,
+                    value: Some(json!(["x", "y"])),
+                },
+            ]
+        );
+
+        listener_task.abort();
+        let _ = listener_task.await;
+    }
+
+    #[tokio::test]
+    async fn test_throttled_watcher_coalesces_changes() {
+        let tonk = TonkCore::new().await.unwrap();
+        let handle = tonk
+            .create_document(automerge::Automerge::new())
+            .await
+            .unwrap();
+        let watcher = DocumentWatcher::new(handle.clone())
+            .with_throttle(Throttle::default().with_coalesce(Duration::from_millis(50)));
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let listener_task = tokio::spawn({
+            let received = received.clone();
+            async move {
+                watcher
+                    .on_change(move |doc| {
+                        if let Ok(Some((value, _))) = doc.get(ROOT, "count") {
+                            received.lock().unwrap().push(value.to_string());
+                        }
+                    })
+                    .await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        for count in 0..5i64 {
+            handle.with_document(|doc| {
+                doc.transact::<_, _, AutomergeError>(|tx| {
+                    tx.put(ROOT, "count", count)?;
+                    Ok(())
+                })
+                .unwrap();
+            });
+        }
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        assert_eq!(*received.lock().unwrap(), vec!["4"]);
+
+        listener_task.abort();
+        let _ = listener_task.await;
+    }
 }
@@ -1,15 +1,29 @@
 use crate::bundle::{Bundle, BundleConfig, BundlePath};
+use crate::compaction::CompactionPolicy;
+use crate::error::{ErrorCode, VfsError};
+use crate::eviction::StorageEviction;
+use crate::identity::SigningKey;
+use crate::import::{current_heap_bytes, ImportLimits, ImportProgress};
+use crate::journal::JournalPolicy;
+use crate::outbox::{OutboxFlush, ReconnectPolicy};
+use crate::profile::SpaceProfile;
 use crate::tonk_core::TonkCore;
-use crate::StorageConfig;
+use crate::vfs::{
+    CopyOptions, JsonSchema, ListOptions, MountSource, Throttle, ThrottledEvents, VfsEvent,
+};
+use crate::{StorageConfig, TonkCoreBuilder};
 use automerge::AutoSerde;
 use bytes::Bytes;
-use js_sys::{Array, Function, Promise, Uint8Array};
+use js_sys::{Array, Function, Object, Promise, Reflect, Symbol, Uint8Array};
 use serde_wasm_bindgen::Serializer;
+use std::collections::HashMap;
 use std::io::Cursor;
+use std::rc::Rc;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, watch, Mutex};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::{future_to_promise, spawn_local, JsFuture};
+use web_sys::{ReadableStream, ReadableStreamDefaultController, ReadableStreamDefaultReader};
 
 #[cfg(feature = "wee_alloc")]
 #[global_allocator]
@@ -34,17 +48,150 @@ pub fn init() {
     console_error_panic_hook::set_once();
 }
 
-fn js_error(err: impl std::fmt::Display) -> JsValue {
-    JsValue::from_str(&err.to_string())
+#[wasm_bindgen(typescript_custom_section)]
+const TS_ERROR_CODES: &'static str = r#"
+/**
+ * Stable code of an error thrown by tonk-core, found on `error.code`.
+ *
+ * | Code                         | Raised when                                        | `context`            |
+ * | ---------------------------- | -------------------------------------------------- | -------------------- |
+ * | `PATH_NOT_FOUND`             | Nothing exists at the path                         | `path`               |
+ * | `DOCUMENT_EXISTS`            | Something already exists at the path               | `path`               |
+ * | `INVALID_PATH`               | The path or a name in it is malformed              |                      |
+ * | `ROOT_PATH`                  | The operation can't apply to `/`                   |                      |
+ * | `CIRCULAR_MOVE`              | A directory would move into itself                 |                      |
+ * | `LINK_LOOP`                  | Symbolic links form a loop                         | `path`               |
+ * | `DIRECTORY_NOT_EMPTY`        | A directory with children was deleted without `recursive` | `path`        |
+ * | `NODE_TYPE_MISMATCH`         | A document was found where a directory was needed, or the reverse | `expected`, `actual` |
+ * | `AUTOMERGE`                  | Automerge rejected a change                        |                      |
+ * | `SAMOD`                      | The repo failed                                    |                      |
+ * | `WEB_SOCKET`                 | A sync connection failed                           |                      |
+ * | `SERIALIZATION`              | A value couldn't be converted to or from JSON      |                      |
+ * | `IO`                         | Storage failed                                     |                      |
+ * | `DOCUMENT_NOT_FOUND`         | A document the tree refers to isn't available      | `document`           |
+ * | `INVALID_DOCUMENT_STRUCTURE` | A document isn't a valid VFS node                  |                      |
+ * | `CONCURRENT_MODIFICATION`    | Another writer changed the node first              |                      |
+ * | `TRANSACTION_FAILED`         | A transaction was rolled back                      |                      |
+ * | `IMPORT_LIMIT_EXCEEDED`      | An import went over its limits                     |                      |
+ * | `TRAVERSAL_LIMIT_EXCEEDED`   | A walk went over its limits                        |                      |
+ * | `MOUNT_EXISTS`               | A space is already mounted there                   | `path`               |
+ * | `BUNDLE_LOCKED`              | Another process has the bundle file open           | `path`               |
+ * | `QUOTA_EXCEEDED`             | A write would go over the storage quota            | `used`, `quota`      |
+ * | `SYNC_TIMEOUT`               | A peer didn't sync in time                         |                      |
+ * | `VALIDATION_FAILED`          | A validator or schema rejected a write             |                      |
+ * | `INVALID_SCHEMA`             | A JSON schema is malformed                         |                      |
+ * | `LEASE_UNAVAILABLE`          | Another client holds the lease                     |                      |
+ * | `NOT_IMPLEMENTED`            | The operation isn't supported here                 |                      |
+ * | `INVALID_ARGUMENT`           | An argument couldn't be converted from JavaScript  |                      |
+ * | `OTHER`                      | Anything else                                      |                      |
+ *
+ * Codes are never renamed or reused; new ones may be added.
+ */
+export type TonkErrorCode =
+  | "PATH_NOT_FOUND"
+  | "DOCUMENT_EXISTS"
+  | "INVALID_PATH"
+  | "ROOT_PATH"
+  | "CIRCULAR_MOVE"
+  | "LINK_LOOP"
+  | "DIRECTORY_NOT_EMPTY"
+  | "NODE_TYPE_MISMATCH"
+  | "AUTOMERGE"
+  | "SAMOD"
+  | "WEB_SOCKET"
+  | "SERIALIZATION"
+  | "IO"
+  | "DOCUMENT_NOT_FOUND"
+  | "INVALID_DOCUMENT_STRUCTURE"
+  | "CONCURRENT_MODIFICATION"
+  | "TRANSACTION_FAILED"
+  | "IMPORT_LIMIT_EXCEEDED"
+  | "TRAVERSAL_LIMIT_EXCEEDED"
+  | "MOUNT_EXISTS"
+  | "BUNDLE_LOCKED"
+  | "QUOTA_EXCEEDED"
+  | "SYNC_TIMEOUT"
+  | "VALIDATION_FAILED"
+  | "INVALID_SCHEMA"
+  | "LEASE_UNAVAILABLE"
+  | "NOT_IMPLEMENTED"
+  | "INVALID_ARGUMENT"
+  | "OTHER";
+
+/**
+ * Shape of every error tonk-core rejects with. `name` is `"TonkError"`.
+ */
+export interface TonkErrorInfo extends Error {
+  code: TonkErrorCode;
+  context: {
+    path?: string;
+    document?: string;
+    expected?: string;
+    actual?: string;
+    used?: number;
+    quota?: number;
+  };
+}
+"#;
+
+/// Build the `Error` rejected to JavaScript, with `code` and `context` set on it
+fn coded_error(code: &str, message: &str, context: &JsValue) -> JsValue {
+    let error = js_sys::Error::new(message);
+    error.set_name("TonkError");
+    let _ = Reflect::set(&error, &JsValue::from_str("code"), &JsValue::from_str(code));
+    let _ = Reflect::set(&error, &JsValue::from_str("context"), context);
+    error.into()
+}
+
+/// Convert an error for JavaScript, keeping its stable code and context
+fn js_error(err: impl Into<VfsError>) -> JsValue {
+    let err = err.into();
+    let context = to_js_value(&err.context()).unwrap_or_else(|_| Object::new().into());
+    coded_error(err.code().as_str(), &err.to_string(), &context)
+}
+
+/// Reject a value passed in from JavaScript that couldn't be used
+fn invalid_argument(message: impl std::fmt::Display) -> JsValue {
+    coded_error(
+        ErrorCode::InvalidArgument.as_str(),
+        &message.to_string(),
+        &Object::new(),
+    )
+}
+
+/// Read optional copy or import options, using the defaults when absent
+fn copy_options(options: JsValue) -> Result<CopyOptions, JsValue> {
+    if options.is_undefined() || options.is_null() {
+        return Ok(CopyOptions::default());
+    }
+    serde_wasm_bindgen::from_value(options)
+        .map_err(|e| invalid_argument(format!("Invalid copy options: {}", e)))
 }
 
 fn to_js_value<T: serde::Serialize>(value: &T) -> Result<JsValue, JsValue> {
     let serializer = Serializer::json_compatible();
-    value
-        .serialize(&serializer)
-        .map_err(|e| js_error(format!("Failed to serialize to JsValue: {}", e)))
+    value.serialize(&serializer).map_err(|e| {
+        coded_error(
+            ErrorCode::Serialization.as_str(),
+            &format!("Failed to serialize to JsValue: {}", e),
+            &Object::new(),
+        )
+    })
+}
+
+/// Read `{ coalesceMs, maxPerSecond }`, treating undefined or null as no throttling
+fn throttle_from_js(throttle: JsValue) -> Result<Throttle, JsValue> {
+    if throttle.is_undefined() || throttle.is_null() {
+        return Ok(Throttle::default());
+    }
+    serde_wasm_bindgen::from_value(throttle)
+        .map_err(|e| invalid_argument(format!("Invalid throttle: {}", e)))
 }
 
+// Declared after the helpers above so the module can use `console_error!`
+mod worker;
+pub use worker::{serve_message_port, WasmTonkClient};
+
 #[wasm_bindgen]
 pub struct WasmTonkCore {
     tonk: Arc<Mutex<TonkCore>>,
@@ -102,6 +249,43 @@ impl WasmTonkCore {
         })
     }
 
+    /// Connect read-only to a relay with a share link token
+    #[wasm_bindgen(js_name = connectWebsocketWithShare)]
+    pub fn connect_websocket_with_share(&self, url: String, token: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            match tonk.connect_websocket_with_share(&url, &token).await {
+                Ok(_) => Ok(JsValue::undefined()),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Connect to a relay that requires a sync token
+    #[wasm_bindgen(js_name = connectWebsocketWithToken)]
+    pub fn connect_websocket_with_token(&self, url: String, token: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            match tonk.connect_websocket_with_token(&url, &token).await {
+                Ok(_) => Ok(JsValue::undefined()),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Stop reconnecting to the relay; an open connection stays up until it drops
+    #[wasm_bindgen]
+    pub fn disconnect(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            tonk.disconnect().await;
+            Ok(JsValue::undefined())
+        })
+    }
+
     #[wasm_bindgen(js_name = fromBytes)]
     pub fn from_bytes(data: Uint8Array) -> Promise {
         future_to_promise(async move {
@@ -142,7 +326,7 @@ impl WasmTonkCore {
                 }
                 Err(e) => {
                     console_error!("Failed to get bundle bytes: {:?}", e);
-                    Err(js_error("Failed to get bundle bytes"))
+                    Err(js_error(anyhow::anyhow!("Failed to get bundle bytes")))
                 }
             }
         })
@@ -162,7 +346,7 @@ impl WasmTonkCore {
                     Ok(config) => Some(config),
                     Err(e) => {
                         console_error!("Failed to parse bundle config: {}", e);
-                        return Err(JsValue::from_str(&format!("Invalid bundle config: {}", e)));
+                        return Err(invalid_argument(format!("Invalid bundle config: {}", e)));
                     }
                 }
             };
@@ -195,7 +379,7 @@ impl WasmTonkCore {
                     Ok(config) => Some(config),
                     Err(e) => {
                         console_error!("Failed to parse bundle config: {}", e);
-                        return Err(JsValue::from_str(&format!("Invalid bundle config: {}", e)));
+                        return Err(invalid_argument(format!("Invalid bundle config: {}", e)));
                     }
                 }
             };
@@ -219,11 +403,11 @@ impl WasmTonkCore {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             // Deserialize JsValue to serde_json::Value
             let content_value: serde_json::Value = serde_wasm_bindgen::from_value(content)
-                .map_err(|e| js_error(format!("Invalid content value: {}", e)))?;
+                .map_err(|e| invalid_argument(format!("Invalid content value: {}", e)))?;
 
             match vfs.create_document(&path, content_value).await {
                 Ok(_) => Ok(JsValue::TRUE),
@@ -239,10 +423,10 @@ impl WasmTonkCore {
         let bytes_parsed = Bytes::from(bytes.to_vec());
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
             // Deserialize JsValue to serde_json::Value
             let content_value: serde_json::Value = serde_wasm_bindgen::from_value(content)
-                .map_err(|e| js_error(format!("Invalid content value: {}", e)))?;
+                .map_err(|e| invalid_argument(format!("Invalid content value: {}", e)))?;
             match vfs
                 .create_document_with_bytes(&path, content_value, bytes_parsed)
                 .await
@@ -258,7 +442,7 @@ impl WasmTonkCore {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             match vfs.find_document(&path).await {
                 Ok(Some(handle)) => {
@@ -278,16 +462,35 @@ impl WasmTonkCore {
         })
     }
 
+    /// Read many documents at once, resolving to an object keyed by path
+    ///
+    /// Paths with no document are left out of the result.
+    #[wasm_bindgen(js_name = readMany)]
+    pub fn read_many(&self, paths: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let paths: Vec<String> = serde_wasm_bindgen::from_value(paths)
+                .map_err(|e| invalid_argument(format!("Invalid paths: {}", e)))?;
+
+            let tonk = tonk.lock().await;
+
+            match tonk.read_many(&paths).await {
+                Ok(nodes) => to_js_value(&nodes),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
     #[wasm_bindgen(js_name = setFile)]
     pub fn set_file(&self, path: String, content: JsValue) -> Promise {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             // Deserialize JsValue to serde_json::Value
             let content_value: serde_json::Value = serde_wasm_bindgen::from_value(content)
-                .map_err(|e| js_error(format!("Invalid content value: {}", e)))?;
+                .map_err(|e| invalid_argument(format!("Invalid content value: {}", e)))?;
 
             match vfs.set_document(&path, content_value).await {
                 Ok(updated) => Ok(JsValue::from_bool(updated)),
@@ -302,11 +505,11 @@ impl WasmTonkCore {
         let bytes_parsed = Bytes::from(bytes.to_vec());
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             // Deserialize JsValue to serde_json::Value
             let content_value: serde_json::Value = serde_wasm_bindgen::from_value(content)
-                .map_err(|e| js_error(format!("Invalid content value: {}", e)))?;
+                .map_err(|e| invalid_argument(format!("Invalid content value: {}", e)))?;
 
             match vfs
                 .set_document_with_bytes(&path, content_value, bytes_parsed)
@@ -324,11 +527,11 @@ impl WasmTonkCore {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             // Deserialize JsValue to serde_json::Value
             let content_value: serde_json::Value = serde_wasm_bindgen::from_value(content)
-                .map_err(|e| js_error(format!("Invalid content value: {}", e)))?;
+                .map_err(|e| invalid_argument(format!("Invalid content value: {}", e)))?;
 
             match vfs.update_document(&path, content_value).await {
                 Ok(changed) => Ok(JsValue::from_bool(changed)),
@@ -343,15 +546,15 @@ impl WasmTonkCore {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             // Deserialize the JSON path array
             let json_path_vec: Vec<String> = serde_wasm_bindgen::from_value(json_path)
-                .map_err(|e| js_error(format!("Invalid json_path: {}", e)))?;
+                .map_err(|e| invalid_argument(format!("Invalid json_path: {}", e)))?;
 
             // Deserialize the value
             let value: serde_json::Value = serde_wasm_bindgen::from_value(value)
-                .map_err(|e| js_error(format!("Invalid value: {}", e)))?;
+                .map_err(|e| invalid_argument(format!("Invalid value: {}", e)))?;
 
             match vfs.patch_document(&path, &json_path_vec, value).await {
                 Ok(updated) => Ok(JsValue::from_bool(updated)),
@@ -360,6 +563,59 @@ impl WasmTonkCore {
         })
     }
 
+    /// Deep-merge a JSON merge patch (RFC 7386) into the value at a JSON path
+    #[wasm_bindgen(js_name = mergePatchFile)]
+    pub fn merge_patch_file(&self, path: String, json_path: JsValue, patch: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            let json_path_vec: Vec<String> = serde_wasm_bindgen::from_value(json_path)
+                .map_err(|e| invalid_argument(format!("Invalid json_path: {}", e)))?;
+            let patch: serde_json::Value = serde_wasm_bindgen::from_value(patch)
+                .map_err(|e| invalid_argument(format!("Invalid patch: {}", e)))?;
+
+            match vfs.merge_patch_document(&path, &json_path_vec, patch).await {
+                Ok(updated) => Ok(JsValue::from_bool(updated)),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Get the concurrent values at a JSON path, as `{ actor, value }` objects
+    #[wasm_bindgen(js_name = getConflicts)]
+    pub fn get_conflicts(&self, path: String, json_path: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            let json_path_vec: Vec<String> = serde_wasm_bindgen::from_value(json_path)
+                .map_err(|e| invalid_argument(format!("Invalid json_path: {}", e)))?;
+
+            match vfs.get_conflicts(&path, &json_path_vec).await {
+                Ok(conflicts) => Ok(to_js_value(&conflicts)?),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Get a document's changes, oldest first, as `{ hash, actor, seq, timestamp, message, author }`
+    #[wasm_bindgen(js_name = getHistory)]
+    pub fn get_history(&self, path: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.history(&path).await {
+                Ok(history) => Ok(to_js_value(&history)?),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
     /// Splice text at a specific JSON path within a document
     #[wasm_bindgen(js_name = spliceText)]
     pub fn splice_text(
@@ -373,11 +629,11 @@ impl WasmTonkCore {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             // Deserialize the JSON path array
             let json_path_vec: Vec<String> = serde_wasm_bindgen::from_value(json_path)
-                .map_err(|e| js_error(format!("Invalid json_path: {}", e)))?;
+                .map_err(|e| invalid_argument(format!("Invalid json_path: {}", e)))?;
 
             match vfs
                 .splice_text(&path, &json_path_vec, index, delete_count as isize, &insert)
@@ -394,7 +650,7 @@ impl WasmTonkCore {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             match vfs.remove_document(&path).await {
                 Ok(removed) => Ok(JsValue::from_bool(removed)),
@@ -403,12 +659,89 @@ impl WasmTonkCore {
         })
     }
 
+    /// Delete a directory, and with `recursive` everything below it
+    ///
+    /// Rejects with `DIRECTORY_NOT_EMPTY` if the directory has children and
+    /// `recursive` is false. Resolves to false if nothing exists at `path`.
+    #[wasm_bindgen(js_name = deleteDirectory)]
+    pub fn delete_directory(&self, path: String, recursive: bool) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.remove_directory(&path, recursive).await {
+                Ok(removed) => Ok(JsValue::from_bool(removed)),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Soft-delete a file or directory, resolving to its path inside the trash
+    #[wasm_bindgen(js_name = moveToTrash)]
+    pub fn move_to_trash(&self, path: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.move_to_trash(&path).await {
+                Ok(trash_path) => Ok(JsValue::from_str(&tonk.namespace_path(&vfs, &trash_path))),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    #[wasm_bindgen(js_name = listTrash)]
+    pub fn list_trash(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let vfs = tonk.vfs();
+
+            match vfs.list_trash().await {
+                Ok(entries) => Ok(to_js_value(&entries)?),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    #[wasm_bindgen(js_name = restoreFromTrash)]
+    pub fn restore_from_trash(&self, path: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.restore_from_trash(&path).await {
+                Ok(restored) => Ok(JsValue::from_str(&tonk.namespace_path(&vfs, &restored))),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Permanently remove trashed items older than `older_than_ms`, or everything if omitted
+    #[wasm_bindgen(js_name = emptyTrash)]
+    pub fn empty_trash(&self, older_than_ms: Option<f64>) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let vfs = tonk.vfs();
+
+            let older_than = older_than_ms.map(|ms| chrono::Duration::milliseconds(ms as i64));
+            match vfs.empty_trash(older_than).await {
+                Ok(removed) => Ok(JsValue::from_f64(removed as f64)),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
     #[wasm_bindgen(js_name = createDirectory)]
     pub fn create_directory(&self, path: String) -> Promise {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             match vfs.create_directory(&path).await {
                 Ok(_) => Ok(JsValue::TRUE),
@@ -417,12 +750,57 @@ impl WasmTonkCore {
         })
     }
 
+    /// Create a symbolic link at `path` pointing to the absolute path `target`
+    #[wasm_bindgen(js_name = createLink)]
+    pub fn create_link(&self, path: String, target: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path, target) = tonk.vfs_for_paths(&path, &target).map_err(js_error)?;
+
+            match vfs.create_link(&path, &target).await {
+                Ok(_) => Ok(JsValue::TRUE),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Read the target of the symbolic link at `path`
+    #[wasm_bindgen(js_name = readLink)]
+    pub fn read_link(&self, path: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.read_link(&path).await {
+                Ok(target) => Ok(JsValue::from_str(&tonk.namespace_path(&vfs, &target))),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Follow every symbolic link in `path`, resolving to the path it leads to
+    #[wasm_bindgen(js_name = resolvePath)]
+    pub fn resolve_path(&self, path: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.resolve(&path).await {
+                Ok(resolved) => Ok(JsValue::from_str(&tonk.namespace_path(&vfs, &resolved))),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
     #[wasm_bindgen(js_name = listDirectory)]
     pub fn list_directory(&self, path: String) -> Promise {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             match vfs.list_directory(&path).await {
                 Ok(nodes) => to_js_value(&nodes),
@@ -431,12 +809,35 @@ impl WasmTonkCore {
         })
     }
 
+    /// List one page of a directory, with optional `{ offset, limit, sortBy, order }`
+    #[wasm_bindgen(js_name = listDirectoryPaged)]
+    pub fn list_directory_paged(&self, path: String, options: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let options = if options.is_undefined() || options.is_null() {
+                ListOptions::default()
+            } else {
+                serde_wasm_bindgen::from_value(options)
+                    .map_err(|e| invalid_argument(format!("Invalid list options: {}", e)))?
+            };
+
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.list_directory_paged(&path, options).await {
+                Ok(page) => to_js_value(&page),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
     #[wasm_bindgen(js_name = rename)]
     pub fn rename(&self, from_path: String, to_path: String) -> Promise {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, from_path, to_path) =
+                tonk.vfs_for_paths(&from_path, &to_path).map_err(js_error)?;
 
             match vfs.move_document(&from_path, &to_path).await {
                 Ok(moved) => Ok(JsValue::from_bool(moved)),
@@ -445,12 +846,40 @@ impl WasmTonkCore {
         })
     }
 
+    /// Copy a document or directory tree, with optional `{ conflict, dryRun }`
+    ///
+    /// `conflict` is one of `"skip"`, `"overwrite"` (the default), `"rename"` or
+    /// `"fail"`. Resolves to the operations performed, or planned in a dry run.
+    /// Paths below a mount point are copied within the mounted space, so both
+    /// paths have to be in the same space.
+    #[wasm_bindgen(js_name = copy)]
+    pub fn copy(&self, from_path: String, to_path: String, options: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let options = copy_options(options)?;
+            let tonk = tonk.lock().await;
+            let (vfs, from_path, to_path) =
+                tonk.vfs_for_paths(&from_path, &to_path).map_err(js_error)?;
+
+            match vfs.copy(&from_path, &to_path, options).await {
+                Ok(mut operations) => {
+                    for operation in &mut operations {
+                        operation.source = tonk.namespace_path(&vfs, &operation.source);
+                        operation.path = tonk.namespace_path(&vfs, &operation.path);
+                    }
+                    to_js_value(&operations)
+                }
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
     #[wasm_bindgen(js_name = exists)]
     pub fn exists(&self, path: String) -> Promise {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             match vfs.exists(&path).await {
                 Ok(exists) => Ok(JsValue::from_bool(exists)),
@@ -464,7 +893,7 @@ impl WasmTonkCore {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             match vfs.metadata(&path).await {
                 Ok(ref_node) => Ok(to_js_value(&ref_node)?),
@@ -473,79 +902,625 @@ impl WasmTonkCore {
         })
     }
 
-    #[wasm_bindgen(js_name = watchDocument)]
-    pub fn watch_document(&self, path: String, callback: Function) -> Promise {
+    /// Set an extended attribute on a file or directory (`null` removes it)
+    #[wasm_bindgen(js_name = setMetadata)]
+    pub fn set_metadata(&self, path: String, key: String, value: JsValue) -> Promise {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
-
-            match vfs.watch_document(&path).await {
-                Ok(Some(watcher)) => {
-                    // Get the document ID before moving the watcher
-                    let document_id = watcher.document_id().to_string();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
-                    // Create abort handle for the watcher task
-                    let (abort_handle, abort_registration) =
-                        futures::future::AbortHandle::new_pair();
-
-                    // Create a channel for communication between the watcher and callback
-                    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<serde_json::Value>();
+            let value: serde_json::Value = serde_wasm_bindgen::from_value(value)
+                .map_err(|e| invalid_argument(format!("Invalid metadata value: {}", e)))?;
 
-                    // Spawn a task to receive document updates and call the JS callback
-                    spawn_local(async move {
-                        while let Some(json_value) = rx.recv().await {
-                            if let Ok(js_value) = to_js_value(&json_value) {
-                                let _ = callback.call1(&JsValue::null(), &js_value);
-                            }
-                        }
-                    });
+            match vfs.set_metadata(&path, &key, value).await {
+                Ok(changed) => Ok(JsValue::from_bool(changed)),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
 
-                    // Spawn the watcher task
-                    spawn_local(async move {
-                        let abortable = futures::future::Abortable::new(
-                            watcher.on_change(move |doc| {
-                                // Convert document to JSON value
-                                let auto_serde = AutoSerde::from(&*doc);
-                                if let Ok(json_value) = serde_json::to_value(&auto_serde) {
-                                    // Send the JSON value through the channel
-                                    let _ = tx.send(json_value);
-                                }
-                            }),
-                            abort_registration,
-                        );
-                        let _ = abortable.await;
-                    });
+    /// Get a single extended attribute, resolving to `undefined` if unset
+    #[wasm_bindgen(js_name = getMetadataValue)]
+    pub fn get_metadata_value(&self, path: String, key: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
-                    Ok(JsValue::from(WasmDocumentWatcher {
-                        document_id,
-                        abort_handle: Arc::new(Mutex::new(Some(abort_handle))),
-                    }))
-                }
-                Ok(None) => Err(js_error("Document not found at the specified path")),
+            match vfs.get_metadata(&path, &key).await {
+                Ok(Some(value)) => Ok(to_js_value(&value)?),
+                Ok(None) => Ok(JsValue::undefined()),
                 Err(e) => Err(js_error(e)),
             }
         })
     }
 
-    #[wasm_bindgen(js_name = isConnected)]
-    pub fn is_connected(&self) -> Promise {
+    #[wasm_bindgen(js_name = removeMetadata)]
+    pub fn remove_metadata(&self, path: String, key: String) -> Promise {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let result = tonk.is_connected().await;
-            Ok(JsValue::from_bool(result))
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.remove_metadata(&path, &key).await {
+                Ok(removed) => Ok(JsValue::from_bool(removed)),
+                Err(e) => Err(js_error(e)),
+            }
         })
     }
 
-    #[wasm_bindgen(js_name = getConnectionState)]
-    pub fn get_connection_state(&self) -> Promise {
+    #[wasm_bindgen(js_name = listMetadata)]
+    pub fn list_metadata(&self, path: String) -> Promise {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let state = tonk.connection_state().await;
-            let state_str = match state {
-                crate::ConnectionState::Disconnected => "disconnected",
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.list_metadata(&path).await {
+                Ok(metadata) => Ok(to_js_value(&metadata)?),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    #[wasm_bindgen(js_name = getStats)]
+    pub fn get_stats(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let vfs = tonk.vfs();
+
+            match vfs.stats().await {
+                Ok(stats) => Ok(to_js_value(&stats)?),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Read the space profile, resolving to null if none has been set
+    #[wasm_bindgen(js_name = getProfile)]
+    pub fn get_profile(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+
+            match tonk.profile().await {
+                Ok(Some(profile)) => to_js_value(&profile),
+                Ok(None) => Ok(JsValue::NULL),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Create or replace the space profile from
+    /// `{ name, description, icon, iconType, createdBy }`
+    #[wasm_bindgen(js_name = setProfile)]
+    pub fn set_profile(&self, profile: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let profile: SpaceProfile = serde_wasm_bindgen::from_value(profile)
+                .map_err(|e| invalid_argument(format!("Invalid profile: {}", e)))?;
+
+            let tonk = tonk.lock().await;
+            match tonk.set_profile(&profile).await {
+                Ok(()) => Ok(JsValue::UNDEFINED),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Event channel capacity, overflow policy and how many events were dropped
+    #[wasm_bindgen(js_name = getEventStats)]
+    pub fn get_event_stats(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            to_js_value(&tonk.vfs().event_stats())
+        })
+    }
+
+    /// Upgrade every node to the current layout version, returning how many changed
+    #[wasm_bindgen(js_name = upgradeSchema)]
+    pub fn upgrade_schema(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let vfs = tonk.vfs();
+
+            match vfs.upgrade_schema().await {
+                Ok(count) => Ok(JsValue::from(count as u32)),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Reject writes to documents at or below `prefix` that don't match a JSON Schema
+    #[wasm_bindgen(js_name = registerValidator)]
+    pub fn register_validator(&self, prefix: String, schema: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let schema: serde_json::Value = serde_wasm_bindgen::from_value(schema)
+                .map_err(|e| invalid_argument(format!("Invalid schema: {}", e)))?;
+            let schema = JsonSchema::new(schema).map_err(js_error)?;
+
+            let tonk = tonk.lock().await;
+            let (vfs, prefix) = tonk.vfs_for_path(&prefix);
+            vfs.register_validator(&prefix, schema);
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Remove the validators registered for `prefix`, returning how many there were
+    #[wasm_bindgen(js_name = removeValidators)]
+    pub fn remove_validators(&self, prefix: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, prefix) = tonk.vfs_for_path(&prefix);
+            Ok(JsValue::from(vfs.remove_validators(&prefix) as u32))
+        })
+    }
+
+    /// Import a ZIP archive below `destPrefix`, returning the paths of the files written
+    #[wasm_bindgen(js_name = importZip)]
+    pub fn import_zip(&self, data: &[u8], dest_prefix: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        let data = data.to_vec();
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, dest_prefix) = tonk.vfs_for_path(&dest_prefix);
+            match vfs.import_zip(&dest_prefix, &data).await {
+                Ok(paths) => {
+                    let paths: Vec<String> = paths
+                        .iter()
+                        .map(|path| tonk.namespace_path(&vfs, path))
+                        .collect();
+                    to_js_value(&paths)
+                }
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Import a ZIP archive below `destPrefix`, with optional `{ conflict, dryRun }`
+    ///
+    /// Resolves to the operations performed, or planned in a dry run.
+    #[wasm_bindgen(js_name = importZipWithOptions)]
+    pub fn import_zip_with_options(
+        &self,
+        data: &[u8],
+        dest_prefix: String,
+        options: JsValue,
+    ) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        let data = data.to_vec();
+        future_to_promise(async move {
+            let options = copy_options(options)?;
+            let tonk = tonk.lock().await;
+            let (vfs, dest_prefix) = tonk.vfs_for_path(&dest_prefix);
+            match vfs
+                .import_zip_with_options(&dest_prefix, &data, options)
+                .await
+            {
+                Ok(mut operations) => {
+                    for operation in &mut operations {
+                        operation.path = tonk.namespace_path(&vfs, &operation.path);
+                    }
+                    to_js_value(&operations)
+                }
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Mount another space at `mountPoint`
+    ///
+    /// `source` is either a space's root document ID or the bytes of a bundle.
+    /// Resolves to the root document ID of the mounted tree.
+    #[wasm_bindgen(js_name = mountSpace)]
+    pub fn mount_space(&self, source: JsValue, mount_point: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        let source = if let Some(space_id) = source.as_string() {
+            space_id
+                .parse()
+                .map(MountSource::Space)
+                .map_err(|_| invalid_argument(format!("Invalid space ID: {}", space_id)))
+        } else if source.is_instance_of::<Uint8Array>() {
+            Ok(MountSource::Bundle(Uint8Array::from(source).to_vec()))
+        } else {
+            Err(invalid_argument(
+                "Mount source must be a space ID or bundle bytes",
+            ))
+        };
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            match tonk.mount_space(source?, &mount_point).await {
+                Ok(vfs) => Ok(JsValue::from_str(&vfs.root_id().to_string())),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Detach the space mounted at `mountPoint`, resolving to whether one was
+    #[wasm_bindgen(js_name = unmountSpace)]
+    pub fn unmount_space(&self, mount_point: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            match tonk.unmount_space(&mount_point).await {
+                Ok(unmounted) => Ok(JsValue::from_bool(unmounted)),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Sync the space mounted at `mountPoint` with its own relay
+    #[wasm_bindgen(js_name = connectMount)]
+    pub fn connect_mount(&self, mount_point: String, url: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            match tonk.connect_mount(&mount_point, &url).await {
+                Ok(_) => Ok(JsValue::undefined()),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Delete documents in storage that no tree reaches, resolving to a report
+    ///
+    /// With `dryRun` nothing is deleted and the report says what would be reclaimed.
+    /// Documents younger than `graceMs` (an hour by default) are left for a later pass.
+    /// Rejects for IndexedDB storage without a namespace, which other spaces may share.
+    #[wasm_bindgen(js_name = gc)]
+    pub fn gc(&self, dry_run: bool, grace_ms: Option<f64>) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        let grace = grace_ms.map_or(crate::DEFAULT_GC_GRACE, |ms| {
+            std::time::Duration::from_millis(ms.max(0.0) as u64)
+        });
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            match tonk.gc(dry_run, grace).await {
+                Ok(report) => to_js_value(&report),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Rewrite the document at `path` as a single snapshot, resolving to a report
+    #[wasm_bindgen(js_name = compact)]
+    pub fn compact(&self, path: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            match tonk.compact(&path).await {
+                Ok(report) => to_js_value(&report),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Compact every stored document kept in at least `minChunks` chunks
+    #[wasm_bindgen(js_name = compactAll)]
+    pub fn compact_all(&self, min_chunks: u32) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            match tonk.compact_all(min_chunks as usize).await {
+                Ok(report) => to_js_value(&report),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Compact documents in the background, with optional `{ intervalMs, minChunks }`
+    #[wasm_bindgen(js_name = startCompaction)]
+    pub fn start_compaction(&self, policy: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let policy: CompactionPolicy = if policy.is_undefined() || policy.is_null() {
+                CompactionPolicy::default()
+            } else {
+                serde_wasm_bindgen::from_value(policy)
+                    .map_err(|e| invalid_argument(format!("Invalid compaction policy: {}", e)))?
+            };
+            tonk.lock().await.start_compaction(policy);
+            Ok(JsValue::undefined())
+        })
+    }
+
+    #[wasm_bindgen(js_name = stopCompaction)]
+    pub fn stop_compaction(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            tonk.lock().await.stop_compaction();
+            Ok(JsValue::undefined())
+        })
+    }
+
+    #[wasm_bindgen(js_name = compactionStats)]
+    pub fn compaction_stats(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            to_js_value(&tonk.compaction_stats())
+        })
+    }
+
+    /// Record VFS events in storage, keeping an optional `{ maxEntries }` of them
+    #[wasm_bindgen(js_name = startJournal)]
+    pub fn start_journal(&self, policy: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let policy: JournalPolicy = if policy.is_undefined() || policy.is_null() {
+                JournalPolicy::default()
+            } else {
+                serde_wasm_bindgen::from_value(policy)
+                    .map_err(|e| invalid_argument(format!("Invalid journal policy: {}", e)))?
+            };
+            tonk.lock().await.start_journal(policy);
+            Ok(JsValue::undefined())
+        })
+    }
+
+    #[wasm_bindgen(js_name = stopJournal)]
+    pub fn stop_journal(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            tonk.lock().await.stop_journal();
+            Ok(JsValue::undefined())
+        })
+    }
+
+    /// Journal entries `{ cursor, op, path, docId, heads, timestamp }` after `cursor`
+    ///
+    /// An entry with op `gap` and a `missed` count means events were dropped.
+    #[wasm_bindgen(js_name = journalSince)]
+    pub fn journal_since(&self, cursor: f64) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            to_js_value(&tonk.journal_since(cursor as u64).await)
+        })
+    }
+
+    /// Paths referencing a document ID, including those in mounted spaces
+    #[wasm_bindgen(js_name = pathsForDocument)]
+    pub fn paths_for_document(&self, doc_id: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let doc_id = doc_id
+                .parse()
+                .map_err(|_| invalid_argument(format!("Invalid document ID: {}", doc_id)))?;
+            let tonk = tonk.lock().await;
+            match tonk.paths_for_document(&doc_id).await {
+                Ok(paths) => to_js_value(&paths),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Set the display order of a directory's children
+    #[wasm_bindgen(js_name = setOrder)]
+    pub fn set_order(&self, path: String, names: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            let names: Vec<String> = serde_wasm_bindgen::from_value(names)
+                .map_err(|e| invalid_argument(format!("Invalid order: {}", e)))?;
+
+            match vfs.set_order(&path, names).await {
+                Ok(changed) => Ok(JsValue::from_bool(changed)),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    #[wasm_bindgen(js_name = getOrder)]
+    pub fn get_order(&self, path: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.get_order(&path).await {
+                Ok(order) => Ok(to_js_value(&order)?),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Watch a document, calling back with its content after each change
+    ///
+    /// An optional `{ coalesceMs, maxPerSecond }` throttle collapses bursts of
+    /// changes into one callback and caps how often the callback runs.
+    #[wasm_bindgen(js_name = watchDocument)]
+    pub fn watch_document(&self, path: String, callback: Function, throttle: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let throttle = throttle_from_js(throttle)?;
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.watch_document(&path).await {
+                Ok(Some(watcher)) => {
+                    let watcher = watcher.with_throttle(throttle);
+                    // Get the document ID before moving the watcher
+                    let document_id = watcher.document_id().to_string();
+
+                    // Create abort handle for the watcher task
+                    let (abort_handle, abort_registration) =
+                        futures::future::AbortHandle::new_pair();
+
+                    // Create a channel for communication between the watcher and callback
+                    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<serde_json::Value>();
+
+                    // Spawn a task to receive document updates and call the JS callback
+                    spawn_local(async move {
+                        while let Some(json_value) = rx.recv().await {
+                            if let Ok(js_value) = to_js_value(&json_value) {
+                                let _ = callback.call1(&JsValue::null(), &js_value);
+                            }
+                        }
+                    });
+
+                    // Spawn the watcher task
+                    spawn_local(async move {
+                        let abortable = futures::future::Abortable::new(
+                            watcher.on_change(move |doc| {
+                                // Convert document to JSON value
+                                let auto_serde = AutoSerde::from(&*doc);
+                                if let Ok(json_value) = serde_json::to_value(&auto_serde) {
+                                    // Send the JSON value through the channel
+                                    let _ = tx.send(json_value);
+                                }
+                            }),
+                            abort_registration,
+                        );
+                        let _ = abortable.await;
+                    });
+
+                    Ok(JsValue::from(WasmDocumentWatcher {
+                        document_id,
+                        abort_handle: Arc::new(Mutex::new(Some(abort_handle))),
+                    }))
+                }
+                Ok(None) => Err(js_error(VfsError::PathNotFound(path.to_string()))),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Watch a document, calling back with `{ path, kind, value }` patches for each change
+    ///
+    /// `path` is the list of keys and indices from the content root, and `kind`
+    /// is `added`, `removed` or `changed`.
+    #[wasm_bindgen(js_name = watchPatches)]
+    pub fn watch_patches(&self, path: String, callback: Function) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.watch_document(&path).await {
+                Ok(Some(watcher)) => {
+                    let document_id = watcher.document_id().to_string();
+                    let (abort_handle, abort_registration) =
+                        futures::future::AbortHandle::new_pair();
+                    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+                    spawn_local(async move {
+                        while let Some(patches) = rx.recv().await {
+                            if let Ok(js_value) = to_js_value(&patches) {
+                                let _ = callback.call1(&JsValue::null(), &js_value);
+                            }
+                        }
+                    });
+
+                    spawn_local(async move {
+                        let abortable = futures::future::Abortable::new(
+                            watcher.on_patches(move |patches| {
+                                let _ = tx.send(patches);
+                            }),
+                            abort_registration,
+                        );
+                        let _ = abortable.await;
+                    });
+
+                    Ok(JsValue::from(WasmDocumentWatcher {
+                        document_id,
+                        abort_handle: Arc::new(Mutex::new(Some(abort_handle))),
+                    }))
+                }
+                Ok(None) => Err(js_error(VfsError::PathNotFound(path.to_string()))),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Watch several paths, calling back once per burst of changes with the affected paths
+    #[wasm_bindgen(js_name = watchBatched)]
+    pub fn watch_batched(&self, paths: JsValue, window_ms: f64, callback: Function) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+
+            let paths: Vec<String> = serde_wasm_bindgen::from_value(paths)
+                .map_err(|e| invalid_argument(format!("Invalid paths: {}", e)))?;
+            let window = std::time::Duration::from_millis(window_ms.max(0.0) as u64);
+
+            // A batch watches one tree, so the paths all have to be in the same space
+            let first = paths.first().map(String::as_str).unwrap_or("/");
+            let (vfs, _) = tonk.vfs_for_path(first);
+            let mut tree_paths = Vec::with_capacity(paths.len());
+            for path in &paths {
+                let (_, _, tree_path) = tonk
+                    .vfs_for_paths(first, path)
+                    .map_err(|e| invalid_argument(format!("Invalid paths: {}", e)))?;
+                tree_paths.push(tree_path);
+            }
+            let namespace_paths: HashMap<String, String> =
+                tree_paths.iter().cloned().zip(paths).collect();
+
+            match vfs.watch_batched(&tree_paths, window).await {
+                Ok(watcher) => {
+                    let (abort_handle, abort_registration) =
+                        futures::future::AbortHandle::new_pair();
+                    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Vec<String>>();
+
+                    // Spawn a task to deliver batches to the JS callback
+                    spawn_local(async move {
+                        while let Some(changed) = rx.recv().await {
+                            let changed: Vec<&String> = changed
+                                .iter()
+                                .map(|path| namespace_paths.get(path).unwrap_or(path))
+                                .collect();
+                            if let Ok(js_value) = to_js_value(&changed) {
+                                let _ = callback.call1(&JsValue::null(), &js_value);
+                            }
+                        }
+                    });
+
+                    spawn_local(async move {
+                        let abortable = futures::future::Abortable::new(
+                            watcher.on_changes(move |changed| {
+                                let _ = tx.send(changed);
+                            }),
+                            abort_registration,
+                        );
+                        let _ = abortable.await;
+                    });
+
+                    Ok(JsValue::from(WasmBatchWatcher {
+                        abort_handle: Arc::new(Mutex::new(Some(abort_handle))),
+                    }))
+                }
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    #[wasm_bindgen(js_name = isConnected)]
+    pub fn is_connected(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let result = tonk.is_connected().await;
+            Ok(JsValue::from_bool(result))
+        })
+    }
+
+    #[wasm_bindgen(js_name = getConnectionState)]
+    pub fn get_connection_state(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let state = tonk.connection_state().await;
+            let state_str = match state {
+                crate::ConnectionState::Disconnected => "disconnected",
                 crate::ConnectionState::Connecting => "connecting",
                 crate::ConnectionState::Open => "open",
                 crate::ConnectionState::Connected => "connected",
@@ -553,7 +1528,147 @@ impl WasmTonkCore {
                     return Ok(JsValue::from_str(&format!("failed:{}", msg)));
                 }
             };
-            Ok(JsValue::from_str(state_str))
+            Ok(JsValue::from_str(state_str))
+        })
+    }
+
+    /// Snapshot of sync activity:
+    /// `{ connections, docsPending, bytesInFlight, messagesSent, ..., idleMs }`
+    #[wasm_bindgen(js_name = syncStatus)]
+    pub fn sync_status(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            to_js_value(&tonk.sync_status())
+        })
+    }
+
+    /// Writes waiting to reach a peer:
+    /// `{ pendingChanges, pendingPaths, lastSyncAt, reconnectAttempts }`
+    #[wasm_bindgen(js_name = outboxStatus)]
+    pub fn outbox_status(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            to_js_value(&tonk.outbox_status())
+        })
+    }
+
+    /// Browser storage used by the origin: `{ usage, quota, persisted, evicted }`
+    ///
+    /// `evicted` is set when the stored replica was gone when this instance started,
+    /// in which case documents must be synced again from a relay.
+    #[wasm_bindgen(js_name = storageEstimate)]
+    pub fn storage_estimate(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let estimate = tonk.storage_estimate().await.map_err(js_error)?;
+            to_js_value(&estimate)
+        })
+    }
+
+    /// How the local replica was found to have lost its documents, or `null`:
+    /// `{ reason: "rootMissing", rootId }` or
+    /// `{ reason: "rootMismatch", localRoot, relayRoot }`
+    #[wasm_bindgen(js_name = storageEviction)]
+    pub fn storage_eviction(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            to_js_value(&tonk.storage_eviction())
+        })
+    }
+
+    /// Subscribe to evictions of the local replica as an async iterator,
+    /// starting with one found before subscribing
+    ///
+    /// Sync the space again from the relay when one arrives.
+    #[wasm_bindgen(js_name = subscribeEviction)]
+    pub fn subscribe_eviction(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let rx = tonk.subscribe_eviction();
+            WasmEventIterator::new(EventSource::Eviction(rx)).into_js()
+        })
+    }
+
+    /// Compare the root of the relay's manifest with this instance's root
+    ///
+    /// Browsers can't read the root a relay names when accepting a connection,
+    /// so call this with the root of the relay's `/.manifest.tonk`. Resolves to
+    /// whether they match; if not, an eviction is reported to subscribers.
+    #[wasm_bindgen(js_name = checkRelayRoot)]
+    pub fn check_relay_root(&self, relay_root: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            Ok(JsValue::from_bool(tonk.check_relay_root(&relay_root)))
+        })
+    }
+
+    /// Ask the browser to exempt stored documents from eviction
+    ///
+    /// Resolves to whether storage is now persistent; browsers may refuse, or prompt
+    /// the user, so call this after a user gesture.
+    #[wasm_bindgen(js_name = requestPersistentStorage)]
+    pub fn request_persistent_storage(&self) -> Promise {
+        future_to_promise(async move {
+            let persisted = crate::browser_storage::request_persistence()
+                .await
+                .map_err(js_error)?;
+            Ok(JsValue::from_bool(persisted))
+        })
+    }
+
+    /// Subscribe to outbox flushes as an async iterator of `{ flushedChanges, syncedAt }`
+    ///
+    /// A flush is reported each time the connected peers have acknowledged
+    /// pending writes, including those restored from before a reload.
+    #[wasm_bindgen(js_name = subscribeOutbox)]
+    pub fn subscribe_outbox(&self) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let rx = tonk.subscribe_outbox();
+            WasmEventIterator::new(EventSource::Outbox(rx)).into_js()
+        })
+    }
+
+    /// Set how dropped connections are retried:
+    /// `{ initialDelayMs, maxDelayMs, maxAttempts }`, or `null` to stop retrying
+    #[wasm_bindgen(js_name = setReconnectPolicy)]
+    pub fn set_reconnect_policy(&self, policy: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let policy = if policy.is_undefined() || policy.is_null() {
+                None
+            } else {
+                Some(
+                    serde_wasm_bindgen::from_value::<ReconnectPolicy>(policy).map_err(|e| {
+                        invalid_argument(format!("Invalid reconnect policy: {}", e))
+                    })?,
+                )
+            };
+            let tonk = tonk.lock().await;
+            tonk.set_reconnect_policy(policy);
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Resolve once every document is in sync with the connected peers, rejecting
+    /// after `timeoutMs`
+    #[wasm_bindgen(js_name = waitForSync)]
+    pub fn wait_for_sync(&self, timeout_ms: f64) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            // Wait without holding the lock so other calls can run meanwhile
+            let tonk = tonk.lock().await.clone();
+            tonk.wait_for_sync(std::time::Duration::from_millis(timeout_ms.max(0.0) as u64))
+                .await
+                .map_err(js_error)?;
+            Ok(JsValue::UNDEFINED)
         })
     }
 
@@ -562,7 +1677,7 @@ impl WasmTonkCore {
         let tonk = Arc::clone(&self.tonk);
         future_to_promise(async move {
             let tonk = tonk.lock().await;
-            let vfs = tonk.vfs();
+            let (vfs, path) = tonk.vfs_for_path(&path);
 
             match vfs.watch_directory(&path).await {
                 Ok(Some(watcher)) => {
@@ -608,7 +1723,64 @@ impl WasmTonkCore {
                         abort_handle: Arc::new(Mutex::new(Some(abort_handle))),
                     }))
                 }
-                Ok(None) => Err(js_error("Directory not found at the specified path")),
+                Ok(None) => Err(js_error(VfsError::PathNotFound(path.to_string()))),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Subscribe to VFS events as an async iterator
+    ///
+    /// Events are buffered up to the VFS event channel's capacity. A consumer that
+    /// falls further behind gets a `{ type: "lagged", missed }` event with the number
+    /// it missed. The count is also sent as `skipped`, its earlier name, which is
+    /// deprecated.
+    /// An optional `{ coalesceMs, maxPerSecond }` throttle merges events of the
+    /// same kind for the same path within a burst and caps the event rate.
+    #[wasm_bindgen(js_name = subscribeEvents)]
+    pub fn subscribe_events(&self, throttle: JsValue) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let throttle = throttle_from_js(throttle)?;
+            let tonk = tonk.lock().await;
+            let rx = tonk.vfs().subscribe_events_throttled(throttle);
+            WasmEventIterator::new(EventSource::Vfs(rx)).into_js()
+        })
+    }
+
+    /// Watch a document as an async iterator of its content
+    ///
+    /// Changes made while the consumer is busy are coalesced, so each step yields the
+    /// latest state of the document rather than every intermediate one.
+    #[wasm_bindgen(js_name = watchDocumentIter)]
+    pub fn watch_document_iter(&self, path: String) -> Promise {
+        let tonk = Arc::clone(&self.tonk);
+        future_to_promise(async move {
+            let tonk = tonk.lock().await;
+            let (vfs, path) = tonk.vfs_for_path(&path);
+
+            match vfs.watch_document(&path).await {
+                Ok(Some(watcher)) => {
+                    let (abort_handle, abort_registration) =
+                        futures::future::AbortHandle::new_pair();
+                    let (tx, rx) = watch::channel(None);
+
+                    spawn_local(async move {
+                        let abortable = futures::future::Abortable::new(
+                            watcher.on_change(move |doc| {
+                                let auto_serde = AutoSerde::from(&*doc);
+                                if let Ok(json_value) = serde_json::to_value(&auto_serde) {
+                                    let _ = tx.send(Some(json_value));
+                                }
+                            }),
+                            abort_registration,
+                        );
+                        let _ = abortable.await;
+                    });
+
+                    WasmEventIterator::new(EventSource::Document { rx, abort_handle }).into_js()
+                }
+                Ok(None) => Err(js_error(VfsError::PathNotFound(path.to_string()))),
                 Err(e) => Err(js_error(e)),
             }
         })
@@ -640,7 +1812,10 @@ impl WasmBundle {
             let bundle = bundle.lock().await;
             match bundle.root_id() {
                 Ok(root_id) => Ok(JsValue::from_str(&root_id)),
-                Err(e) => Err(js_error(format!("Error retrieving bundle root ID: {}", e))),
+                Err(e) => Err(js_error(anyhow::anyhow!(
+                    "Error retrieving bundle root ID: {}",
+                    e
+                ))),
             }
         })
     }
@@ -706,6 +1881,65 @@ impl WasmBundle {
         })
     }
 
+    /// `"zip"` for a v1 bundle or `"container"` for a v2 container
+    #[wasm_bindgen(js_name = format)]
+    pub fn format(&self) -> Promise {
+        let bundle = Arc::clone(&self.bundle);
+        future_to_promise(async move { to_js_value(&bundle.lock().await.format()) })
+    }
+
+    /// Rewrite the bundle as a v2 container, returning its bytes
+    #[wasm_bindgen(js_name = toContainer)]
+    pub fn to_container(&self) -> Promise {
+        let bundle = Arc::clone(&self.bundle);
+        future_to_promise(async move {
+            let mut bundle = bundle.lock().await;
+            match bundle.write_container(Cursor::new(Vec::new())) {
+                Ok(container) => Ok(JsValue::from(Uint8Array::from(
+                    container.into_inner().as_slice(),
+                ))),
+                Err(e) => Err(js_error(anyhow::anyhow!("Failed to convert bundle: {}", e))),
+            }
+        })
+    }
+
+    /// Check entry checksums, the manifest and every stored document
+    #[wasm_bindgen(js_name = verify)]
+    pub fn verify(&self) -> Promise {
+        let bundle = Arc::clone(&self.bundle);
+        future_to_promise(async move {
+            let mut bundle = bundle.lock().await;
+            match bundle.verify() {
+                Ok(report) => to_js_value(&report),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
+    /// Compare entries, manifest fields and document heads with another bundle
+    #[wasm_bindgen(js_name = diff)]
+    pub fn diff(&self, other: &WasmBundle) -> Promise {
+        let bundle = Arc::clone(&self.bundle);
+        let other = Arc::clone(&other.bundle);
+        future_to_promise(async move {
+            // Copy the other bundle first, so diffing a bundle with itself can't deadlock
+            let other_bytes = match other.lock().await.to_bytes() {
+                Ok(bytes) => bytes,
+                Err(e) => return Err(js_error(e)),
+            };
+            let mut other = match Bundle::from_source_unverified(Cursor::new(other_bytes)) {
+                Ok(bundle) => bundle,
+                Err(e) => return Err(js_error(e)),
+            };
+
+            let mut bundle = bundle.lock().await;
+            match bundle.diff(&mut other) {
+                Ok(diff) => to_js_value(&diff),
+                Err(e) => Err(js_error(e)),
+            }
+        })
+    }
+
     #[wasm_bindgen(js_name = getManifest)]
     pub fn get_manifest(&self) -> Promise {
         let bundle = Arc::clone(&self.bundle);
@@ -730,7 +1964,7 @@ impl WasmBundle {
                     Ok(config) => config,
                     Err(e) => {
                         console_error!("Failed to parse bundle config: {}", e);
-                        return Err(JsValue::from_str(&format!("Invalid bundle config: {}", e)));
+                        return Err(invalid_argument(format!("Invalid bundle config: {}", e)));
                     }
                 }
             };
@@ -795,6 +2029,238 @@ impl WasmDocumentWatcher {
     }
 }
 
+#[wasm_bindgen]
+pub struct WasmBatchWatcher {
+    abort_handle: Arc<Mutex<Option<futures::future::AbortHandle>>>,
+}
+
+#[wasm_bindgen]
+impl WasmBatchWatcher {
+    #[wasm_bindgen(js_name = stop)]
+    pub fn stop(&self) -> Promise {
+        let abort_handle = Arc::clone(&self.abort_handle);
+        future_to_promise(async move {
+            if let Some(handle) = abort_handle.lock().await.take() {
+                handle.abort();
+            }
+
+            Ok(JsValue::undefined())
+        })
+    }
+}
+
+/// Where a `WasmEventIterator` gets its values from
+enum EventSource {
+    Vfs(ThrottledEvents),
+    Outbox(broadcast::Receiver<OutboxFlush>),
+    Eviction(watch::Receiver<Option<StorageEviction>>),
+    Document {
+        rx: watch::Receiver<Option<serde_json::Value>>,
+        abort_handle: futures::future::AbortHandle,
+    },
+}
+
+impl EventSource {
+    /// Wait for the next value, or `None` once the source has ended
+    async fn next(&mut self) -> Option<serde_json::Value> {
+        match self {
+            EventSource::Vfs(events) => events.next().await.map(|event| vfs_event_to_json(&event)),
+            EventSource::Outbox(rx) => match rx.recv().await {
+                Ok(flush) => serde_json::to_value(&flush).ok(),
+                Err(broadcast::error::RecvError::Lagged(missed)) => Some(lagged_json(missed)),
+                Err(broadcast::error::RecvError::Closed) => None,
+            },
+            EventSource::Eviction(rx) => loop {
+                rx.changed().await.ok()?;
+                if let Some(eviction) = rx.borrow_and_update().clone() {
+                    break serde_json::to_value(&eviction).ok();
+                }
+            },
+            EventSource::Document { rx, .. } => {
+                rx.changed().await.ok()?;
+                rx.borrow_and_update().clone()
+            }
+        }
+    }
+}
+
+impl Drop for EventSource {
+    fn drop(&mut self) {
+        if let EventSource::Document { abort_handle, .. } = self {
+            abort_handle.abort();
+        }
+    }
+}
+
+fn vfs_event_to_json(event: &VfsEvent) -> serde_json::Value {
+    use serde_json::json;
+
+    match event {
+        VfsEvent::DocumentCreated { path, doc_id } => {
+            json!({ "type": "documentCreated", "path": path, "docId": doc_id.to_string() })
+        }
+        VfsEvent::DocumentUpdated {
+            path,
+            doc_id,
+            changed_paths,
+        } => json!({
+            "type": "documentUpdated",
+            "path": path,
+            "docId": doc_id.to_string(),
+            "changedPaths": changed_paths,
+        }),
+        VfsEvent::DocumentDeleted { path } => json!({ "type": "documentDeleted", "path": path }),
+        VfsEvent::DirectoryCreated { path, doc_id } => {
+            json!({ "type": "directoryCreated", "path": path, "docId": doc_id.to_string() })
+        }
+        VfsEvent::ConflictDetected {
+            path,
+            doc_id,
+            json_paths,
+        } => json!({
+            "type": "conflictDetected",
+            "path": path,
+            "docId": doc_id.to_string(),
+            "jsonPaths": json_paths,
+        }),
+        VfsEvent::Lagged { missed } => lagged_json(*missed),
+    }
+}
+
+/// A `lagged` event, with the count under both its current and deprecated names
+fn lagged_json(missed: u64) -> serde_json::Value {
+    serde_json::json!({ "type": "lagged", "missed": missed, "skipped": missed })
+}
+
+struct EventIteratorState {
+    source: Mutex<Option<EventSource>>,
+    closed: watch::Sender<bool>,
+}
+
+impl EventIteratorState {
+    async fn next(&self) -> Option<serde_json::Value> {
+        let mut closed = self.closed.subscribe();
+        let mut source = self.source.lock().await;
+
+        let value = match source.as_mut() {
+            Some(events) => tokio::select! {
+                value = events.next() => value,
+                _ = closed.wait_for(|closed| *closed) => None,
+            },
+            None => None,
+        };
+        if value.is_none() {
+            *source = None;
+        }
+        value
+    }
+
+    async fn close(&self) {
+        // Wake a pending `next` so it releases the source
+        self.closed.send_replace(true);
+        *self.source.lock().await = None;
+    }
+}
+
+/// Async iterator over VFS events or document states
+///
+/// Implements the JS async iterator protocol, so it can be consumed with
+/// `for await`. Values are produced only as they are requested, and breaking out
+/// of the loop (or calling `return()`) stops the underlying watcher.
+#[wasm_bindgen]
+pub struct WasmEventIterator {
+    state: Arc<EventIteratorState>,
+}
+
+impl WasmEventIterator {
+    fn new(source: EventSource) -> Self {
+        Self {
+            state: Arc::new(EventIteratorState {
+                source: Mutex::new(Some(source)),
+                closed: watch::Sender::new(false),
+            }),
+        }
+    }
+
+    /// Convert to a JS object that is also its own `Symbol.asyncIterator`
+    fn into_js(self) -> std::result::Result<JsValue, JsValue> {
+        let iterator = JsValue::from(self);
+        Reflect::set(
+            &iterator,
+            &Symbol::async_iterator(),
+            &Function::new_no_args("return this"),
+        )?;
+        Ok(iterator)
+    }
+}
+
+fn iterator_result(value: Option<serde_json::Value>) -> std::result::Result<JsValue, JsValue> {
+    let result = Object::new();
+    Reflect::set(
+        &result,
+        &JsValue::from_str("done"),
+        &JsValue::from_bool(value.is_none()),
+    )?;
+    if let Some(value) = value {
+        Reflect::set(&result, &JsValue::from_str("value"), &to_js_value(&value)?)?;
+    }
+    Ok(result.into())
+}
+
+#[wasm_bindgen]
+impl WasmEventIterator {
+    #[wasm_bindgen(js_name = next)]
+    pub fn next(&self) -> Promise {
+        let state = Arc::clone(&self.state);
+        future_to_promise(async move { iterator_result(state.next().await) })
+    }
+
+    #[wasm_bindgen(js_name = return)]
+    pub fn close(&self) -> Promise {
+        let state = Arc::clone(&self.state);
+        future_to_promise(async move {
+            state.close().await;
+            iterator_result(None)
+        })
+    }
+
+    /// Expose the values as a `ReadableStream`, pulled only as the reader consumes them
+    #[wasm_bindgen(js_name = toReadableStream)]
+    pub fn to_readable_stream(&self) -> std::result::Result<ReadableStream, JsValue> {
+        let pull_state = Arc::clone(&self.state);
+        let pull = Closure::<dyn FnMut(ReadableStreamDefaultController) -> Promise>::new(
+            move |controller: ReadableStreamDefaultController| {
+                let state = Arc::clone(&pull_state);
+                future_to_promise(async move {
+                    match state.next().await {
+                        Some(value) => controller.enqueue_with_chunk(&to_js_value(&value)?)?,
+                        None => controller.close()?,
+                    }
+                    Ok(JsValue::undefined())
+                })
+            },
+        );
+
+        let cancel_state = Arc::clone(&self.state);
+        let cancel = Closure::<dyn FnMut(JsValue) -> Promise>::new(move |_reason: JsValue| {
+            let state = Arc::clone(&cancel_state);
+            future_to_promise(async move {
+                state.close().await;
+                Ok(JsValue::undefined())
+            })
+        });
+
+        let source = Object::new();
+        Reflect::set(&source, &JsValue::from_str("pull"), &pull.into_js_value())?;
+        Reflect::set(
+            &source,
+            &JsValue::from_str("cancel"),
+            &cancel.into_js_value(),
+        )?;
+        ReadableStream::new_with_underlying_source(&source)
+    }
+}
+
 #[wasm_bindgen]
 pub fn create_tonk() -> Promise {
     WasmTonkCore::new()
@@ -861,6 +2327,44 @@ pub fn create_tonk_with_config(
     })
 }
 
+/// Create a TonkCore whose changes are authored as a device of the space's operator
+///
+/// `secret_key` is the operator's 32-byte Ed25519 secret key; their DID is its `did:key`.
+#[wasm_bindgen]
+pub fn create_tonk_with_identity(
+    secret_key: Uint8Array,
+    device: String,
+    use_indexed_db: bool,
+    namespace: Option<String>,
+) -> Promise {
+    future_to_promise(async move {
+        let secret_key: [u8; 32] = secret_key
+            .to_vec()
+            .try_into()
+            .map_err(|_| invalid_argument("Secret key must be 32 bytes"))?;
+        let storage_config = if use_indexed_db {
+            StorageConfig::IndexedDB { namespace }
+        } else {
+            StorageConfig::InMemory
+        };
+
+        match TonkCore::builder()
+            .with_identity(SigningKey::from_bytes(&secret_key), device)
+            .with_storage(storage_config)
+            .build()
+            .await
+        {
+            Ok(tonk) => Ok(JsValue::from(WasmTonkCore {
+                tonk: Arc::new(Mutex::new(tonk)),
+            })),
+            Err(e) => {
+                console_error!("TonkCore creation failed: {}", e);
+                Err(js_error(e))
+            }
+        }
+    })
+}
+
 #[wasm_bindgen]
 pub fn create_bundle_from_bytes(data: Uint8Array) -> std::result::Result<WasmBundle, JsValue> {
     WasmBundle::from_bytes(data)
@@ -907,7 +2411,7 @@ pub fn create_tonk_from_bundle_with_storage(
             }
             Err(e) => {
                 console_error!("Failed to get bundle bytes: {:?}", e);
-                Err(js_error("Failed to get bundle bytes"))
+                Err(js_error(anyhow::anyhow!("Failed to get bundle bytes")))
             }
         }
     })
@@ -948,3 +2452,126 @@ pub fn create_tonk_from_bytes_with_storage(
         }
     })
 }
+
+#[wasm_bindgen]
+pub fn create_tonk_from_bytes_with_limits(
+    data: Uint8Array,
+    limits: JsValue,
+    on_progress: Option<Function>,
+) -> Promise {
+    future_to_promise(async move {
+        let builder = import_builder(limits, on_progress)?;
+
+        let bytes = data.to_vec();
+        match builder.from_bytes(bytes).await {
+            Ok(tonk) => Ok(JsValue::from(WasmTonkCore {
+                tonk: Arc::new(Mutex::new(tonk)),
+            })),
+            Err(e) => {
+                console_error!(
+                    "Failed to load TonkCore from bytes (heap: {:?} bytes): {}",
+                    current_heap_bytes(),
+                    e
+                );
+                Err(js_error(e))
+            }
+        }
+    })
+}
+
+/// Load a bundle from a `ReadableStream` (such as a fetch response body), importing
+/// entries as they arrive instead of waiting for the whole download
+#[wasm_bindgen]
+pub fn create_tonk_from_stream(
+    stream: ReadableStream,
+    limits: JsValue,
+    on_progress: Option<Function>,
+) -> Promise {
+    future_to_promise(async move {
+        let builder = import_builder(limits, on_progress)?;
+
+        let reader: ReadableStreamDefaultReader = stream.get_reader().unchecked_into();
+        let chunks = futures::stream::unfold(Some(reader), |reader| async move {
+            let reader = reader?;
+            let result = match JsFuture::from(reader.read()).await {
+                Ok(result) => result,
+                Err(e) => {
+                    return Some((
+                        Err(VfsError::Other(anyhow::anyhow!(
+                            "Failed to read bundle stream: {:?}",
+                            e
+                        ))),
+                        None,
+                    ))
+                }
+            };
+
+            let done = js_sys::Reflect::get(&result, &JsValue::from_str("done"))
+                .map(|done| done.is_truthy())
+                .unwrap_or(true);
+            if done {
+                reader.release_lock();
+                return None;
+            }
+
+            let value = js_sys::Reflect::get(&result, &JsValue::from_str("value"))
+                .unwrap_or(JsValue::UNDEFINED);
+            let chunk = Uint8Array::new(&value).to_vec();
+            Some((Ok(chunk), Some(reader)))
+        });
+
+        match builder.from_byte_stream(Box::pin(chunks)).await {
+            Ok(tonk) => Ok(JsValue::from(WasmTonkCore {
+                tonk: Arc::new(Mutex::new(tonk)),
+            })),
+            Err(e) => {
+                console_error!(
+                    "Failed to load TonkCore from stream (heap: {:?} bytes): {}",
+                    current_heap_bytes(),
+                    e
+                );
+                Err(js_error(e))
+            }
+        }
+    })
+}
+
+/// Build a `TonkCoreBuilder` from JS import limits and an optional progress callback
+fn import_builder(
+    limits: JsValue,
+    on_progress: Option<Function>,
+) -> std::result::Result<TonkCoreBuilder, JsValue> {
+    let limits = if limits.is_undefined() || limits.is_null() {
+        ImportLimits::default()
+    } else {
+        match serde_wasm_bindgen::from_value::<ImportLimits>(limits) {
+            Ok(limits) => limits,
+            Err(e) => {
+                console_error!("Failed to parse import limits: {}", e);
+                return Err(invalid_argument(format!("Invalid import limits: {}", e)));
+            }
+        }
+    };
+
+    let mut builder = TonkCore::builder().with_import_limits(limits);
+    if let Some(callback) = on_progress {
+        builder = builder.with_import_progress(Rc::new(move |progress: &ImportProgress| {
+            if let Ok(js_value) = to_js_value(progress) {
+                let _ = callback.call1(&JsValue::null(), &js_value);
+            }
+        }));
+    }
+    Ok(builder)
+}
+
+/// Describe the features compiled into this build, for feature detection
+#[wasm_bindgen]
+pub fn get_capabilities() -> std::result::Result<JsValue, JsValue> {
+    to_js_value(&TonkCore::capabilities())
+}
+
+/// Current size of the wasm heap in bytes
+#[wasm_bindgen]
+pub fn get_heap_bytes() -> Option<f64> {
+    current_heap_bytes().map(|bytes| bytes as f64)
+}
@@ -0,0 +1,322 @@
+//! Calling a `WasmTonkCore` across a `MessagePort`
+//!
+//! Apps that keep TonkCore in a web worker serve it with `serve_message_port` in
+//! the worker and talk to it from the main thread through a `WasmTonkClient`.
+//! Requests are `{ id, method, args }` messages naming a `WasmTonkCore` method
+//! by its JS name; responses are `{ id, result }` or `{ id, error }`, where
+//! `error` is `{ message, code, context }` and is rethrown as a `TonkError`.
+//!
+//! Only methods whose arguments and results survive structured cloning are
+//! available, so watchers and event iterators stay on the worker side; they
+//! are listed in `LOCAL_ONLY`.
+
+use super::{coded_error, error, invalid_argument, js_error, WasmTonkCore};
+use crate::error::ErrorCode;
+use js_sys::{Array, Function, Object, Promise, Reflect, Uint8Array};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{MessageEvent, MessagePort};
+
+/// `WasmTonkCore` methods that can't be called across a message port
+///
+/// These take callbacks or return async iterators, neither of which survives
+/// structured cloning, or need the main thread.
+const LOCAL_ONLY: &[&str] = &[
+    "watchDocument",
+    "watchPatches",
+    "watchBatched",
+    "watchDirectory",
+    "watchDocumentIter",
+    "subscribeEvents",
+    "subscribeOutbox",
+    "subscribeEviction",
+    // `navigator.storage.persist()` isn't available in workers
+    "requestPersistentStorage",
+];
+
+fn get(value: &JsValue, key: &str) -> JsValue {
+    Reflect::get(value, &JsValue::from_str(key)).unwrap_or(JsValue::UNDEFINED)
+}
+
+fn string_arg(args: &Array, index: u32) -> Result<String, JsValue> {
+    args.get(index)
+        .as_string()
+        .ok_or_else(|| invalid_argument(format!("Argument {} must be a string", index)))
+}
+
+fn number_arg(args: &Array, index: u32) -> Result<f64, JsValue> {
+    args.get(index)
+        .as_f64()
+        .ok_or_else(|| invalid_argument(format!("Argument {} must be a number", index)))
+}
+
+fn bool_arg(args: &Array, index: u32) -> Result<bool, JsValue> {
+    args.get(index)
+        .as_bool()
+        .ok_or_else(|| invalid_argument(format!("Argument {} must be a boolean", index)))
+}
+
+/// Copy of an error as `{ message, code, context }`, which structured cloning keeps intact
+fn error_fields(error: &JsValue) -> Result<JsValue, JsValue> {
+    let message = match get(error, "message").as_string() {
+        Some(message) => message,
+        None => error.as_string().unwrap_or_else(|| format!("{:?}", error)),
+    };
+    let fields = Object::new();
+    Reflect::set(
+        &fields,
+        &JsValue::from_str("message"),
+        &JsValue::from_str(&message),
+    )?;
+    Reflect::set(&fields, &JsValue::from_str("code"), &get(error, "code"))?;
+    Reflect::set(
+        &fields,
+        &JsValue::from_str("context"),
+        &get(error, "context"),
+    )?;
+    Ok(fields.into())
+}
+
+/// Rebuild an error sent with `error_fields`
+fn error_from_fields(fields: &JsValue) -> JsValue {
+    let message = get(fields, "message").as_string().unwrap_or_default();
+    let code = get(fields, "code")
+        .as_string()
+        .unwrap_or_else(|| ErrorCode::Other.as_str().to_string());
+    let context = match get(fields, "context") {
+        context if context.is_object() => context,
+        _ => Object::new().into(),
+    };
+    coded_error(&code, &message, &context)
+}
+
+fn response(id: &JsValue, key: &str, value: &JsValue) -> Result<JsValue, JsValue> {
+    let message = Object::new();
+    Reflect::set(&message, &JsValue::from_str("id"), id)?;
+    Reflect::set(&message, &JsValue::from_str(key), value)?;
+    Ok(message.into())
+}
+
+/// Call the `WasmTonkCore` method with JS name `method`
+fn dispatch(tonk: &WasmTonkCore, method: &str, args: &Array) -> Result<Promise, JsValue> {
+    let s = |index| string_arg(args, index);
+    let n = |index| number_arg(args, index);
+    let b = |index| bool_arg(args, index);
+    let v = |index| args.get(index);
+    let bytes = |index| Uint8Array::new(&args.get(index)).to_vec();
+
+    Ok(match method {
+        "getPeerId" => tonk.get_peer_id(),
+        "connectWebsocket" => tonk.connect_websocket(s(0)?),
+        "connectWebsocketWithShare" => tonk.connect_websocket_with_share(s(0)?, s(1)?),
+        "connectWebsocketWithToken" => tonk.connect_websocket_with_token(s(0)?, s(1)?),
+        "disconnect" => tonk.disconnect(),
+        "isConnected" => tonk.is_connected(),
+        "getConnectionState" => tonk.get_connection_state(),
+        "syncStatus" => tonk.sync_status(),
+        "waitForSync" => tonk.wait_for_sync(n(0)?),
+        "outboxStatus" => tonk.outbox_status(),
+        "setReconnectPolicy" => tonk.set_reconnect_policy(v(0)),
+        "checkRelayRoot" => tonk.check_relay_root(s(0)?),
+        "storageEstimate" => tonk.storage_estimate(),
+        "storageEviction" => tonk.storage_eviction(),
+        "forkToBytes" => tonk.fork_to_bytes(v(0)),
+        "toBytes" => tonk.to_bytes(v(0)),
+        "createFile" => tonk.create_file(s(0)?, v(1)),
+        "createFileWithBytes" => tonk.create_file_with_bytes(s(0)?, v(1), &bytes(2)),
+        "readFile" => tonk.read_file(s(0)?),
+        "readMany" => tonk.read_many(v(0)),
+        "setFile" => tonk.set_file(s(0)?, v(1)),
+        "setFileWithBytes" => tonk.set_file_with_bytes(s(0)?, v(1), &bytes(2)),
+        "updateFile" => tonk.update_file(s(0)?, v(1)),
+        "patchFile" => tonk.patch_file(s(0)?, v(1), v(2)),
+        "mergePatchFile" => tonk.merge_patch_file(s(0)?, v(1), v(2)),
+        "getConflicts" => tonk.get_conflicts(s(0)?, v(1)),
+        "getHistory" => tonk.get_history(s(0)?),
+        "spliceText" => tonk.splice_text(
+            s(0)?,
+            v(1),
+            v(2).as_f64().unwrap_or(0.0) as usize,
+            v(3).as_f64().unwrap_or(0.0) as i32,
+            s(4)?,
+        ),
+        "deleteFile" => tonk.delete_file(s(0)?),
+        "deleteDirectory" => tonk.delete_directory(s(0)?, b(1)?),
+        "moveToTrash" => tonk.move_to_trash(s(0)?),
+        "listTrash" => tonk.list_trash(),
+        "restoreFromTrash" => tonk.restore_from_trash(s(0)?),
+        "emptyTrash" => tonk.empty_trash(v(0).as_f64()),
+        "createDirectory" => tonk.create_directory(s(0)?),
+        "createLink" => tonk.create_link(s(0)?, s(1)?),
+        "readLink" => tonk.read_link(s(0)?),
+        "resolvePath" => tonk.resolve_path(s(0)?),
+        "listDirectory" => tonk.list_directory(s(0)?),
+        "listDirectoryPaged" => tonk.list_directory_paged(s(0)?, v(1)),
+        "rename" => tonk.rename(s(0)?, s(1)?),
+        "copy" => tonk.copy(s(0)?, s(1)?, v(2)),
+        "exists" => tonk.exists(s(0)?),
+        "getMetadata" => tonk.get_metadata(s(0)?),
+        "setMetadata" => tonk.set_metadata(s(0)?, s(1)?, v(2)),
+        "getMetadataValue" => tonk.get_metadata_value(s(0)?, s(1)?),
+        "removeMetadata" => tonk.remove_metadata(s(0)?, s(1)?),
+        "listMetadata" => tonk.list_metadata(s(0)?),
+        "getStats" => tonk.get_stats(),
+        "getProfile" => tonk.get_profile(),
+        "setProfile" => tonk.set_profile(v(0)),
+        "getEventStats" => tonk.get_event_stats(),
+        "upgradeSchema" => tonk.upgrade_schema(),
+        "registerValidator" => tonk.register_validator(s(0)?, v(1)),
+        "removeValidators" => tonk.remove_validators(s(0)?),
+        "importZip" => tonk.import_zip(&bytes(0), s(1)?),
+        "importZipWithOptions" => tonk.import_zip_with_options(&bytes(0), s(1)?, v(2)),
+        "mountSpace" => tonk.mount_space(v(0), s(1)?),
+        "unmountSpace" => tonk.unmount_space(s(0)?),
+        "connectMount" => tonk.connect_mount(s(0)?, s(1)?),
+        "gc" => tonk.gc(b(0)?, v(1).as_f64()),
+        "compact" => tonk.compact(s(0)?),
+        "compactAll" => tonk.compact_all(n(0)? as u32),
+        "startCompaction" => tonk.start_compaction(v(0)),
+        "stopCompaction" => tonk.stop_compaction(),
+        "compactionStats" => tonk.compaction_stats(),
+        "startJournal" => tonk.start_journal(v(0)),
+        "stopJournal" => tonk.stop_journal(),
+        "journalSince" => tonk.journal_since(n(0)?),
+        "pathsForDocument" => tonk.paths_for_document(s(0)?),
+        "setOrder" => tonk.set_order(s(0)?, v(1)),
+        "getOrder" => tonk.get_order(s(0)?),
+        _ if LOCAL_ONLY.contains(&method) => {
+            return Err(invalid_argument(format!(
+                "{} can't be called across a message port",
+                method
+            )))
+        }
+        _ => return Err(invalid_argument(format!("Unsupported method: {}", method))),
+    })
+}
+
+/// Answer requests from a `WasmTonkClient` on the other end of `port`
+///
+/// Requests are handled until the port is closed.
+#[wasm_bindgen]
+pub fn serve_message_port(tonk: &WasmTonkCore, port: MessagePort) {
+    let tonk = WasmTonkCore {
+        tonk: Arc::clone(&tonk.tonk),
+    };
+    let reply_port = port.clone();
+
+    let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+        let request = event.data();
+        let id = get(&request, "id");
+        let method = get(&request, "method").as_string().unwrap_or_default();
+        let args = Array::from(&get(&request, "args"));
+
+        let call = dispatch(&tonk, &method, &args);
+        let port = reply_port.clone();
+        spawn_local(async move {
+            let result = match call {
+                Ok(promise) => JsFuture::from(promise).await,
+                Err(e) => Err(e),
+            };
+            let message = match result {
+                Ok(value) => response(&id, "result", &value),
+                Err(e) => error_fields(&e).and_then(|error| response(&id, "error", &error)),
+            };
+            if let Err(e) = message.and_then(|message| port.post_message(&message)) {
+                console_error!("Failed to reply to {}: {:?}", method, e);
+            }
+        });
+    });
+
+    port.set_onmessage(Some(on_message.into_js_value().unchecked_ref()));
+}
+
+type Pending = Rc<RefCell<HashMap<u32, (Function, Function)>>>;
+
+/// Proxy for a `WasmTonkCore` served on the other end of a `MessagePort`
+#[wasm_bindgen]
+pub struct WasmTonkClient {
+    port: MessagePort,
+    pending: Pending,
+    next_id: Cell<u32>,
+}
+
+#[wasm_bindgen]
+impl WasmTonkClient {
+    #[wasm_bindgen(constructor)]
+    pub fn new(port: MessagePort) -> WasmTonkClient {
+        let pending: Pending = Rc::new(RefCell::new(HashMap::new()));
+
+        let responses = Rc::clone(&pending);
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let message = event.data();
+            let Some(id) = get(&message, "id").as_f64() else {
+                return;
+            };
+            let Some((resolve, reject)) = responses.borrow_mut().remove(&(id as u32)) else {
+                return;
+            };
+
+            let error = get(&message, "error");
+            let _ = if error.is_undefined() {
+                resolve.call1(&JsValue::null(), &get(&message, "result"))
+            } else {
+                reject.call1(&JsValue::null(), &error_from_fields(&error))
+            };
+        });
+        port.set_onmessage(Some(on_message.into_js_value().unchecked_ref()));
+
+        WasmTonkClient {
+            port,
+            pending,
+            next_id: Cell::new(0),
+        }
+    }
+
+    /// Call a `WasmTonkCore` method by its JS name, e.g. `call("readFile", ["/a.txt"])`
+    #[wasm_bindgen(js_name = call)]
+    pub fn call(&self, method: String, args: Array) -> Promise {
+        let id = self.next_id.get();
+        self.next_id.set(id.wrapping_add(1));
+
+        let mut on_promise = |resolve: Function, reject: Function| {
+            let request = Object::new();
+            let sent = Reflect::set(&request, &JsValue::from_str("id"), &JsValue::from(id))
+                .and_then(|_| {
+                    Reflect::set(
+                        &request,
+                        &JsValue::from_str("method"),
+                        &JsValue::from_str(&method),
+                    )
+                })
+                .and_then(|_| Reflect::set(&request, &JsValue::from_str("args"), &args))
+                .and_then(|_| self.port.post_message(&request));
+
+            match sent {
+                Ok(()) => {
+                    self.pending.borrow_mut().insert(id, (resolve, reject));
+                }
+                Err(e) => {
+                    let _ = reject.call1(&JsValue::null(), &e);
+                }
+            }
+        };
+
+        Promise::new(&mut on_promise)
+    }
+
+    /// Close the port, rejecting calls that are still waiting for a response
+    #[wasm_bindgen(js_name = close)]
+    pub fn close(&self) {
+        self.port.close();
+        for (_, (_, reject)) in self.pending.borrow_mut().drain() {
+            let _ = reject.call1(
+                &JsValue::null(),
+                &js_error(anyhow::anyhow!("Message port closed")),
+            );
+        }
+    }
+}
@@ -1,25 +1,259 @@
+use crate::capabilities::PeerInfo;
 use crate::error::Result;
 #[cfg(not(target_arch = "wasm32"))]
 use crate::error::VfsError;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::eviction::EvictionMonitor;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::sync_status::SyncTracker;
 use samod::{ConnDirection, ConnFinishedReason, Repo};
 use std::sync::Arc;
 #[cfg(not(target_arch = "wasm32"))]
 use tokio_tungstenite::connect_async;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio_tungstenite::tungstenite::{
+    client::IntoClientRequest,
+    error::{Error as WsError, ProtocolError, SubProtocolError},
+    http::{HeaderMap, HeaderValue},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod framing;
+#[cfg(not(target_arch = "wasm32"))]
+mod monitor;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use framing::{
+    ChunkedSocket, FramingConfig, CHUNKED_PROTOCOL, DEFAULT_MAX_FRAME_SIZE,
+    DEFAULT_MAX_MESSAGE_SIZE,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use monitor::MonitoredSocket;
+
+/// Add a share link token to a relay URL, so the relay serves the connection read-only
+pub fn share_url(url: &str, token: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{url}{separator}share={}", encode_query_value(token))
+}
+
+/// Add a sync token to a relay URL, for relays that require one to connect
+pub fn token_url(url: &str, token: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{url}{separator}token={}", encode_query_value(token))
+}
+
+/// Query parameters a client describes itself with: name, version, platform and features
+pub const CLIENT_PARAMS: [&str; 4] = [
+    "clientName",
+    "clientVersion",
+    "clientPlatform",
+    "clientFeatures",
+];
+
+/// Response headers a relay describes itself with, in the order of [`CLIENT_PARAMS`]
+pub const RELAY_HEADERS: [&str; 4] = [
+    "x-tonk-relay-name",
+    "x-tonk-relay-version",
+    "x-tonk-relay-platform",
+    "x-tonk-relay-features",
+];
+
+/// Response header a relay names the root document of the space it hosts with
+pub const RELAY_ROOT_HEADER: &str = "x-tonk-relay-root";
+
+/// The values of `info` in the order of [`CLIENT_PARAMS`], features comma-separated
+pub fn peer_fields(info: &PeerInfo) -> [String; 4] {
+    [
+        info.name.clone(),
+        info.version.clone(),
+        info.platform.clone(),
+        info.features.join(","),
+    ]
+}
+
+/// Rebuild peer info from fields in the order of [`CLIENT_PARAMS`]
+///
+/// Returns `None` if the peer didn't send its name, as peers other than
+/// tonk-core and the relay don't.
+pub fn parse_peer_fields(fields: [Option<&str>; 4]) -> Option<PeerInfo> {
+    let [name, version, platform, features] = fields;
+    Some(PeerInfo {
+        name: name.filter(|name| !name.is_empty())?.to_string(),
+        version: version.unwrap_or_default().to_string(),
+        platform: platform.unwrap_or_default().to_string(),
+        features: features
+            .unwrap_or_default()
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+/// Add this build's [`PeerInfo`] to a relay URL, so the relay can tell which client connected
+pub fn client_url(url: &str) -> String {
+    let mut url = url.to_string();
+    for (param, value) in CLIENT_PARAMS.iter().zip(peer_fields(&PeerInfo::current())) {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        url = format!("{url}{separator}{param}={}", encode_query_value(&value));
+    }
+    url
+}
+
+/// Percent-encode everything but unreserved characters
+fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// What the relay said about itself when accepting a connection
+#[cfg(not(target_arch = "wasm32"))]
+fn relay_info(headers: &HeaderMap) -> Option<PeerInfo> {
+    parse_peer_fields(RELAY_HEADERS.map(|name| headers.get(name)?.to_str().ok()))
+}
 
 #[cfg(not(target_arch = "wasm32"))]
 pub async fn connect(samod: Arc<Repo>, url: &str) -> Result<ConnFinishedReason> {
-    let (ws_stream, _) = connect_async(url)
-        .await
-        .map_err(|e| VfsError::WebSocketError(format!("Failed to connect to {url}: {e}")))?;
+    connect_with_framing(samod, url, None).await
+}
+
+/// Connect to a peer, chunking large sync messages if the peer supports it
+///
+/// With `framing` set, the connection offers [`CHUNKED_PROTOCOL`] and falls back
+/// to whole messages if the peer doesn't select it.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn connect_with_framing(
+    samod: Arc<Repo>,
+    url: &str,
+    framing: Option<&FramingConfig>,
+) -> Result<ConnFinishedReason> {
+    connect_tracked(samod, url, framing, Arc::default(), None).await
+}
+
+/// Connect to a peer, recording the connection's traffic in `tracker`
+///
+/// With `eviction` set, the root the relay hosts is compared with the local one.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn connect_tracked(
+    samod: Arc<Repo>,
+    url: &str,
+    framing: Option<&FramingConfig>,
+    tracker: Arc<SyncTracker>,
+    eviction: Option<&EvictionMonitor>,
+) -> Result<ConnFinishedReason> {
+    let accepted = |headers: &HeaderMap| {
+        let relay_root = headers.get(RELAY_ROOT_HEADER).and_then(|v| v.to_str().ok());
+        if let (Some(eviction), Some(relay_root)) = (eviction, relay_root) {
+            eviction.check_relay_root(relay_root);
+        }
+        relay_info(headers)
+    };
+
+    let connect_error =
+        |e: WsError| VfsError::WebSocketError(format!("Failed to connect to {url}: {e}"));
+
+    let request_url = client_url(url);
+    if let Some(framing) = framing {
+        let mut request = request_url
+            .as_str()
+            .into_client_request()
+            .map_err(connect_error)?;
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(CHUNKED_PROTOCOL),
+        );
+
+        match connect_async(request).await {
+            Ok((ws_stream, response)) => {
+                let relay = accepted(response.headers());
+                let socket = MonitoredSocket::new(
+                    ChunkedSocket::new(ws_stream, framing.clone()),
+                    Arc::clone(&tracker),
+                    url,
+                    relay,
+                );
+                return Ok(samod
+                    .connect_tungstenite(socket, ConnDirection::Outgoing)
+                    .await);
+            }
+            Err(WsError::Protocol(ProtocolError::SecWebSocketSubProtocolError(
+                SubProtocolError::NoSubProtocol,
+            ))) => {
+                tracing::debug!("{url} doesn't support chunked framing, sending whole messages");
+            }
+            Err(e) => return Err(connect_error(e)),
+        }
+    }
+
+    let (ws_stream, response) = connect_async(request_url).await.map_err(connect_error)?;
+    let relay = accepted(response.headers());
+
+    let socket = MonitoredSocket::new(ws_stream, tracker, url, relay);
 
     Ok(samod
-        .connect_tungstenite(ws_stream, ConnDirection::Outgoing)
+        .connect_tungstenite(socket, ConnDirection::Outgoing)
         .await)
 }
 
 #[cfg(target_arch = "wasm32")]
 pub async fn connect_wasm(samod: Arc<Repo>, url: &str) -> Result<ConnFinishedReason> {
     Ok(samod
-        .connect_wasm_websocket(url, ConnDirection::Outgoing)
+        .connect_wasm_websocket(&client_url(url), ConnDirection::Outgoing)
         .await)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_url_round_trip() {
+        let url = client_url(&share_url("ws://relay.example/sync", "token"));
+        assert!(url.starts_with("ws://relay.example/sync?share=token&clientName=tonk-core&"));
+
+        let query = url.split_once('?').unwrap().1;
+        // Feature lists are comma-separated, which is encoded
+        assert!(query.contains("clientFeatures=") && !query.contains(','));
+
+        // Decoded the way the relay's query extractor does
+        let params: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(param, _)| param == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(param("share"), Some("token"));
+        let info = parse_peer_fields(CLIENT_PARAMS.map(param)).unwrap();
+        assert_eq!(info, PeerInfo::current());
+        assert!(info.has_feature("mounts"));
+
+        assert_eq!(parse_peer_fields([None, Some("1.0"), None, None]), None);
+    }
+
+    #[test]
+    fn test_tokens_are_encoded() {
+        let token = "a&b=c#d+e f";
+        let url = token_url(&share_url("ws://relay.example/sync?x=1", token), token);
+        assert!(!url.contains('#') && !url.contains(' '));
+
+        let query = url.split_once('?').unwrap().1;
+        let params: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        assert_eq!(
+            params,
+            [("x", "1"), ("share", token), ("token", token)]
+                .map(|(param, value)| (param.to_string(), value.to_string()))
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use bytes::{Bytes, BytesMut};
+use futures::{ready, Sink, Stream};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio_tungstenite::tungstenite::{Error, Message};
+
+/// WebSocket subprotocol under which binary messages are sent as chunked frames
+pub const CHUNKED_PROTOCOL: &str = "tonk-chunked.v1";
+
+/// Default largest payload sent in one frame
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Default largest message reassembled from frames
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Frame holding a whole message
+const WHOLE: u8 = 0;
+/// Frame holding part of a message, with more to follow
+const MORE: u8 = 1;
+/// Frame holding the final part of a message
+const LAST: u8 = 2;
+
+/// How sync messages are split into WebSocket frames
+///
+/// Large messages, such as document snapshots, are sent as a run of frames no
+/// bigger than `max_frame_size` so a slow link isn't tied up by one huge write.
+/// Each side chunks what it sends by its own limit and reassembles whatever it
+/// receives, up to `max_message_size`.
+#[derive(Debug, Clone)]
+pub struct FramingConfig {
+    pub max_frame_size: usize,
+    pub max_message_size: usize,
+}
+
+impl Default for FramingConfig {
+    fn default() -> Self {
+        Self {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+impl FramingConfig {
+    /// Set the largest payload sent in one frame
+    pub fn with_max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// Set the largest message accepted from the peer
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+}
+
+fn invalid(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+/// Split a message into frames of at most `max_frame_size` payload bytes
+fn split_message(data: &[u8], max_frame_size: usize) -> Vec<Bytes> {
+    let frame = |flag: u8, payload: &[u8]| {
+        let mut frame = BytesMut::with_capacity(payload.len() + 1);
+        frame.extend_from_slice(&[flag]);
+        frame.extend_from_slice(payload);
+        frame.freeze()
+    };
+
+    let max_frame_size = max_frame_size.max(1);
+    if data.len() <= max_frame_size {
+        return vec![frame(WHOLE, data)];
+    }
+
+    let count = data.len().div_ceil(max_frame_size);
+    data.chunks(max_frame_size)
+        .enumerate()
+        .map(|(i, chunk)| frame(if i + 1 == count { LAST } else { MORE }, chunk))
+        .collect()
+}
+
+/// Joins frames back into messages
+struct Reassembler {
+    partial: Option<BytesMut>,
+    max_message_size: usize,
+}
+
+impl Reassembler {
+    fn new(max_message_size: usize) -> Self {
+        Self {
+            partial: None,
+            max_message_size,
+        }
+    }
+
+    /// Add a frame, returning the message it completes, if any
+    fn push(&mut self, frame: &[u8]) -> std::io::Result<Option<Bytes>> {
+        let (&flag, payload) = frame
+            .split_first()
+            .ok_or_else(|| invalid("Empty sync frame"))?;
+
+        match flag {
+            WHOLE if self.partial.is_some() => {
+                Err(invalid("Whole message received inside a chunked one"))
+            }
+            WHOLE => Ok(Some(Bytes::copy_from_slice(payload))),
+            MORE | LAST => {
+                let partial = self.partial.get_or_insert_with(BytesMut::new);
+                if partial.len() + payload.len() > self.max_message_size {
+                    self.partial = None;
+                    return Err(invalid(format!(
+                        "Sync message exceeds the {} byte limit",
+                        self.max_message_size
+                    )));
+                }
+                partial.extend_from_slice(payload);
+
+                if flag == LAST {
+                    Ok(self.partial.take().map(BytesMut::freeze))
+                } else {
+                    Ok(None)
+                }
+            }
+            _ => Err(invalid(format!("Unknown sync frame type {}", flag))),
+        }
+    }
+}
+
+/// Wraps a WebSocket so binary messages are chunked on send and reassembled on receive
+///
+/// Both ends must use it; connections opt in by selecting [`CHUNKED_PROTOCOL`]
+/// during the handshake. Text and control messages pass through unchanged.
+pub struct ChunkedSocket<S> {
+    inner: S,
+    max_frame_size: usize,
+    reassembler: Reassembler,
+    /// Frames of the last message sent, waiting for the inner sink
+    outgoing: VecDeque<Message>,
+}
+
+impl<S> ChunkedSocket<S> {
+    pub fn new(inner: S, config: FramingConfig) -> Self {
+        Self {
+            inner,
+            max_frame_size: config.max_frame_size,
+            reassembler: Reassembler::new(config.max_message_size),
+            outgoing: VecDeque::new(),
+        }
+    }
+}
+
+impl<S> ChunkedSocket<S>
+where
+    S: Sink<Message, Error = Error> + Unpin,
+{
+    /// Hand queued frames to the inner sink
+    fn poll_send_outgoing(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        while !self.outgoing.is_empty() {
+            ready!(Pin::new(&mut self.inner).poll_ready(cx))?;
+            if let Some(frame) = self.outgoing.pop_front() {
+                Pin::new(&mut self.inner).start_send(frame)?;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> Stream for ChunkedSocket<S>
+where
+    S: Stream<Item = Result<Message, Error>> + Unpin,
+{
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(Message::Binary(frame))) => match this.reassembler.push(&frame) {
+                    Ok(Some(message)) => return Poll::Ready(Some(Ok(Message::Binary(message)))),
+                    Ok(None) => continue,
+                    Err(e) => return Poll::Ready(Some(Err(Error::Io(e)))),
+                },
+                other => return Poll::Ready(other),
+            }
+        }
+    }
+}
+
+impl<S> Sink<Message> for ChunkedSocket<S>
+where
+    S: Sink<Message, Error = Error> + Unpin,
+{
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.poll_send_outgoing(cx))?;
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Error> {
+        match item {
+            Message::Binary(data) => {
+                let frames = split_message(&data, self.max_frame_size);
+                self.outgoing
+                    .extend(frames.into_iter().map(Message::Binary));
+                Ok(())
+            }
+            other => Pin::new(&mut self.inner).start_send(other),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.poll_send_outgoing(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.poll_send_outgoing(cx))?;
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let data: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        let frames = split_message(&data, 4096);
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|frame| frame.len() <= 4097));
+
+        let mut reassembler = Reassembler::new(DEFAULT_MAX_MESSAGE_SIZE);
+        assert!(reassembler.push(&frames[0]).unwrap().is_none());
+        assert!(reassembler.push(&frames[1]).unwrap().is_none());
+        assert_eq!(
+            reassembler.push(&frames[2]).unwrap().unwrap(),
+            Bytes::from(data)
+        );
+
+        let small = split_message(b"hello", 4096);
+        assert_eq!(small.len(), 1);
+        assert_eq!(
+            reassembler.push(&small[0]).unwrap().unwrap(),
+            Bytes::from_static(b"hello")
+        );
+    }
+
+    #[test]
+    fn test_reassembly_limits() {
+        let frames = split_message(&[0; 100], 10);
+        let mut reassembler = Reassembler::new(50);
+        let result: Result<Vec<_>, _> = frames.iter().map(|f| reassembler.push(f)).collect();
+        assert!(result.is_err());
+
+        assert!(Reassembler::new(50).push(&[]).is_err());
+        assert!(Reassembler::new(50).push(&[9, 1, 2]).is_err());
+    }
+}
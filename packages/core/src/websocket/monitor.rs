@@ -0,0 +1,96 @@
+use crate::capabilities::PeerInfo;
+use crate::sync_status::SyncTracker;
+use futures::{ready, Sink, Stream};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio_tungstenite::tungstenite::{Error, Message};
+
+/// Wraps a WebSocket to record its sync traffic in a [`SyncTracker`]
+///
+/// The connection counts as open, and is listed as a connection to `url`, for
+/// as long as the wrapper lives.
+pub(crate) struct MonitoredSocket<S> {
+    inner: S,
+    tracker: Arc<SyncTracker>,
+    connection_id: u64,
+    /// Bytes sent since the last flush
+    unflushed: u64,
+}
+
+impl<S> MonitoredSocket<S> {
+    pub(crate) fn new(
+        inner: S,
+        tracker: Arc<SyncTracker>,
+        url: &str,
+        peer: Option<PeerInfo>,
+    ) -> Self {
+        tracker.connection_opened();
+        let connection_id = tracker.register(url, peer);
+        Self {
+            inner,
+            tracker,
+            connection_id,
+            unflushed: 0,
+        }
+    }
+
+    fn mark_flushed(&mut self) {
+        self.tracker.flushed(self.unflushed);
+        self.unflushed = 0;
+    }
+}
+
+impl<S> Drop for MonitoredSocket<S> {
+    fn drop(&mut self) {
+        self.mark_flushed();
+        self.tracker.unregister(self.connection_id);
+        self.tracker.connection_closed();
+    }
+}
+
+impl<S> Stream for MonitoredSocket<S>
+where
+    S: Stream<Item = Result<Message, Error>> + Unpin,
+{
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = ready!(Pin::new(&mut self.inner).poll_next(cx));
+        if let Some(Ok(message)) = &item {
+            self.tracker.received(message.len() as u64);
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl<S> Sink<Message> for MonitoredSocket<S>
+where
+    S: Sink<Message, Error = Error> + Unpin,
+{
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Error> {
+        let len = item.len() as u64;
+        Pin::new(&mut self.inner).start_send(item)?;
+        self.unflushed += len;
+        self.tracker.sent(len);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+        self.mark_flushed();
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(Pin::new(&mut self.inner).poll_close(cx))?;
+        self.mark_flushed();
+        Poll::Ready(Ok(()))
+    }
+}
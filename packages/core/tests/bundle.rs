@@ -733,6 +733,43 @@ async fn test_bundle_partial_write_recovery() {
     assert!(result.is_err(), "Loading partial bundle should fail");
 }
 
+#[tokio::test]
+async fn test_bundle_file_locking() {
+    use tempfile::NamedTempFile;
+    use tonk_core::error::VfsError;
+
+    let tonk = TonkCore::new().await.unwrap();
+    let temp_file = NamedTempFile::new().unwrap();
+    tonk.to_file(temp_file.path()).await.unwrap();
+
+    // A writer keeps everyone else out
+    let writer = Bundle::from_file(temp_file.path()).unwrap();
+    assert!(!writer.is_read_only());
+    let error = Bundle::from_file(temp_file.path()).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<VfsError>(),
+        Some(VfsError::BundleLocked(_))
+    ));
+    assert!(matches!(
+        TonkCore::from_file(temp_file.path()).await,
+        Err(VfsError::BundleLocked(_))
+    ));
+    drop(writer);
+
+    // Readers share the file, and further opens fall back to read-only
+    let reader = Bundle::from_file_read_only(temp_file.path()).unwrap();
+    let fallback = Bundle::from_file(temp_file.path()).unwrap();
+    assert!(fallback.is_read_only());
+    TonkCore::from_file(temp_file.path()).await.unwrap();
+    assert!(matches!(
+        tonk.to_file(temp_file.path()).await,
+        Err(VfsError::BundleLocked(_))
+    ));
+
+    drop((reader, fallback));
+    tonk.to_file(temp_file.path()).await.unwrap();
+}
+
 // ============ Sync Integration Tests ============
 
 #[tokio::test]
@@ -1332,6 +1369,52 @@ async fn test_load_bundle_from_blank_bundle_tonk_file() {
         .expect("Should be able to check existence"));
 }
 
+#[tokio::test]
+async fn test_deterministic_export_is_reproducible() {
+    use tonk_core::bundle::BundleConfig;
+
+    let tonk = TonkCore::new().await.unwrap();
+    for i in 0..5 {
+        tonk.vfs()
+            .create_document(&format!("/docs/file{}.txt", i), format!("content {}", i))
+            .await
+            .unwrap();
+    }
+
+    let config = BundleConfig {
+        deterministic: true,
+        ..Default::default()
+    };
+    let first = tonk.to_bytes(Some(config.clone())).await.unwrap();
+    sleep(Duration::from_millis(1100)).await;
+    let second = tonk.to_bytes(Some(config.clone())).await.unwrap();
+    assert_eq!(first, second);
+
+    // Loading the bundle and exporting it again gives the same bytes
+    let reloaded = TonkCore::from_bytes(first.clone()).await.unwrap();
+    assert_eq!(reloaded.to_bytes(Some(config)).await.unwrap(), first);
+
+    let bundle = Bundle::from_bytes(first).unwrap();
+    let x_tonk = &bundle.manifest().x_vendor.as_ref().unwrap()["xTonk"];
+    assert_eq!(x_tonk["createdAt"], "1980-01-01T00:00:00+00:00");
+
+    // A provided timestamp is kept
+    let created_at = chrono::DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+    let bytes = tonk
+        .to_bytes(Some(BundleConfig {
+            deterministic: true,
+            created_at: Some(created_at),
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+    let bundle = Bundle::from_bytes(bytes).unwrap();
+    let x_tonk = &bundle.manifest().x_vendor.as_ref().unwrap()["xTonk"];
+    assert_eq!(x_tonk["createdAt"], created_at.to_rfc3339());
+}
+
 #[tokio::test]
 #[ignore] // Run with: cargo test --package tonk-core --test bundle generate_blank_tonk -- --ignored --nocapture
 async fn generate_blank_tonk() {
@@ -224,3 +224,54 @@ async fn test_bundle_with_network_uris() {
     // Verify new peer ID is generated
     assert_ne!(tonk.peer_id(), tonk2.peer_id());
 }
+
+// ============ Performance Tests ============
+
+#[tokio::test]
+#[ignore] // This test is slow, run with --ignored flag
+async fn test_move_large_directory_scaling() {
+    // Moving a directory should cost about the same per entry whatever its size
+    let sizes = [250, 500, 1000, 2000];
+    let mut per_entry = Vec::new();
+
+    for files in sizes {
+        let tonk = TonkCore::new().await.unwrap();
+        let vfs = tonk.vfs();
+        for i in 0..files {
+            let path = format!("/big/dir{}/file_{:04}.txt", i % 20, i);
+            vfs.create_document(&path, format!("File {}", i))
+                .await
+                .unwrap();
+        }
+
+        let start = std::time::Instant::now();
+        assert!(vfs.move_document("/big", "/moved").await.unwrap());
+        let elapsed = start.elapsed();
+        println!(
+            "Moved {} files in {:?} ({:?} per file)",
+            files,
+            elapsed,
+            elapsed / files
+        );
+        per_entry.push(elapsed.as_secs_f64() / files as f64);
+
+        assert!(!vfs.exists("/big").await.unwrap());
+        assert!(vfs
+            .exists(&format!(
+                "/moved/dir{}/file_{:04}.txt",
+                (files - 1) % 20,
+                files - 1
+            ))
+            .await
+            .unwrap());
+    }
+
+    // Quadratic moves take 8x as long per file at the largest size as at the smallest
+    let (smallest, largest) = (per_entry[0], per_entry[per_entry.len() - 1]);
+    assert!(
+        largest < smallest * 4.0,
+        "Per-file move time grew from {:.3}ms to {:.3}ms",
+        smallest * 1000.0,
+        largest * 1000.0
+    );
+}
@@ -0,0 +1,150 @@
+//! Tests for reading and upgrading every historical layout of VFS node documents
+
+use automerge::{transaction::Transactable, ObjType, ReadDoc};
+use samod::DocHandle;
+use serde_json::json;
+use tonk_core::vfs::backend::AutomergeHelpers;
+use tonk_core::{TonkCore, NODE_SCHEMA_VERSION};
+
+/// Rewrite a freshly created document into the unversioned layout
+fn strip_version(handle: &DocHandle) {
+    handle.with_document(|doc| {
+        let mut tx = doc.transaction();
+        tx.delete(automerge::ROOT, "version").unwrap();
+        tx.commit();
+    });
+}
+
+/// Rewrite a freshly created document into the oldest layout, with JSON-string content
+fn make_legacy_string_content(handle: &DocHandle, content: &serde_json::Value) {
+    handle.with_document(|doc| {
+        let mut tx = doc.transaction();
+        tx.delete(automerge::ROOT, "version").unwrap();
+        tx.put(automerge::ROOT, "content", content.to_string())
+            .unwrap();
+        tx.commit();
+    });
+}
+
+#[tokio::test]
+async fn test_new_nodes_are_versioned() {
+    let tonk = TonkCore::new().await.unwrap();
+    let vfs = tonk.vfs();
+
+    let dir = vfs.create_directory("/notes").await.unwrap();
+    let doc = vfs
+        .create_document("/notes/a.json", json!({"title": "a"}))
+        .await
+        .unwrap();
+
+    let doc_node = AutomergeHelpers::read_document::<serde_json::Value>(&doc).unwrap();
+    let dir_node = AutomergeHelpers::read_directory(&dir).unwrap();
+    assert_eq!(doc_node.version, NODE_SCHEMA_VERSION);
+    assert_eq!(dir_node.version, NODE_SCHEMA_VERSION);
+}
+
+#[tokio::test]
+async fn test_read_unversioned_native_node() {
+    let tonk = TonkCore::new().await.unwrap();
+    let vfs = tonk.vfs();
+
+    let handle = vfs
+        .create_document("/old.json", json!({"count": 3}))
+        .await
+        .unwrap();
+    strip_version(&handle);
+
+    let node = AutomergeHelpers::read_document::<serde_json::Value>(&handle).unwrap();
+    assert_eq!(node.version, 0);
+    assert_eq!(node.content, json!({"count": 3}));
+}
+
+#[tokio::test]
+async fn test_read_legacy_string_content_node() {
+    let tonk = TonkCore::new().await.unwrap();
+    let vfs = tonk.vfs();
+
+    let content = json!({"items": [1, 2, 3]});
+    let handle = vfs
+        .create_document("/legacy.json", json!({}))
+        .await
+        .unwrap();
+    make_legacy_string_content(&handle, &content);
+
+    let node = AutomergeHelpers::read_document::<serde_json::Value>(&handle).unwrap();
+    assert_eq!(node.version, 0);
+    assert_eq!(node.content, content);
+}
+
+#[tokio::test]
+async fn test_read_future_node_ignores_unknown_fields() {
+    let tonk = TonkCore::new().await.unwrap();
+    let vfs = tonk.vfs();
+
+    let handle = vfs
+        .create_document("/future.json", json!({"kept": true}))
+        .await
+        .unwrap();
+    handle.with_document(|doc| {
+        let mut tx = doc.transaction();
+        tx.put(automerge::ROOT, "version", (NODE_SCHEMA_VERSION + 1) as i64)
+            .unwrap();
+        let extra = tx
+            .put_object(automerge::ROOT, "signatures", ObjType::List)
+            .unwrap();
+        tx.insert(&extra, 0, "sig").unwrap();
+        tx.commit();
+    });
+
+    let node = AutomergeHelpers::read_document::<serde_json::Value>(&handle).unwrap();
+    assert_eq!(node.version, NODE_SCHEMA_VERSION + 1);
+    assert_eq!(node.content, json!({"kept": true}));
+
+    // Newer nodes are never rewritten by an upgrade
+    assert!(!AutomergeHelpers::upgrade_node(&handle).unwrap());
+}
+
+#[tokio::test]
+async fn test_upgrade_schema() {
+    let tonk = TonkCore::new().await.unwrap();
+    let vfs = tonk.vfs();
+
+    let dir = vfs.create_directory("/docs").await.unwrap();
+    strip_version(&dir);
+
+    let content = json!({"name": "legacy", "tags": ["a", "b"]});
+    let legacy = vfs
+        .create_document("/docs/legacy.json", json!({}))
+        .await
+        .unwrap();
+    make_legacy_string_content(&legacy, &content);
+
+    let unversioned = vfs
+        .create_document("/docs/native.json", json!({"n": 1}))
+        .await
+        .unwrap();
+    strip_version(&unversioned);
+
+    vfs.create_document("/current.json", json!({"n": 2}))
+        .await
+        .unwrap();
+
+    assert_eq!(vfs.upgrade_schema().await.unwrap(), 3);
+    assert_eq!(vfs.upgrade_schema().await.unwrap(), 0);
+
+    let node = AutomergeHelpers::read_document::<serde_json::Value>(&legacy).unwrap();
+    assert_eq!(node.version, NODE_SCHEMA_VERSION);
+    assert_eq!(node.content, content);
+    legacy.with_document(|doc| {
+        let (value, _) = doc.get(automerge::ROOT, "content").unwrap().unwrap();
+        assert!(value.is_object(), "content should be stored natively");
+    });
+
+    let node = AutomergeHelpers::read_document::<serde_json::Value>(&unversioned).unwrap();
+    assert_eq!(node.version, NODE_SCHEMA_VERSION);
+    assert_eq!(node.content, json!({"n": 1}));
+    assert_eq!(
+        AutomergeHelpers::read_directory(&dir).unwrap().version,
+        NODE_SCHEMA_VERSION
+    );
+}
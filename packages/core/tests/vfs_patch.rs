@@ -1,6 +1,8 @@
-//! Tests for native Automerge storage, patch_document, and splice_text functionality
+//! Tests for native Automerge storage, patch_document, merge_patch_document, and splice_text
+//! functionality
 
 use serde_json::json;
+use tonk_core::vfs::backend::AutomergeHelpers;
 use tonk_core::TonkCore;
 
 // ============================================================================
@@ -226,6 +228,127 @@ async fn test_patch_overwrites_value_type() {
     assert!(updated, "Patch should allow changing value type");
 }
 
+// ============================================================================
+// Merge Patch Tests
+// ============================================================================
+
+async fn read_content(vfs: &tonk_core::VirtualFileSystem, path: &str) -> serde_json::Value {
+    let handle = vfs.find_document(path).await.unwrap().unwrap();
+    AutomergeHelpers::read_document::<serde_json::Value>(&handle)
+        .unwrap()
+        .content
+}
+
+#[tokio::test]
+async fn test_merge_patch_deep_merges_objects() {
+    let tonk = TonkCore::new().await.unwrap();
+    let vfs = tonk.vfs();
+
+    vfs.create_document(
+        "/profile.json",
+        json!({
+            "name": "Ada",
+            "address": { "city": "London", "zip": "N1" },
+            "tags": ["a", "b"]
+        }),
+    )
+    .await
+    .unwrap();
+
+    let changed = vfs
+        .merge_patch_document(
+            "/profile.json",
+            &[],
+            json!({
+                "address": { "zip": null, "country": "UK" },
+                "tags": ["c"],
+                "email": "ada@example.com"
+            }),
+        )
+        .await
+        .unwrap();
+    assert!(changed);
+
+    assert_eq!(
+        read_content(&vfs, "/profile.json").await,
+        json!({
+            "name": "Ada",
+            "address": { "city": "London", "country": "UK" },
+            "tags": ["c"],
+            "email": "ada@example.com"
+        })
+    );
+
+    // Re-applying an already merged patch is a no-op
+    let changed = vfs
+        .merge_patch_document(
+            "/profile.json",
+            &[],
+            json!({ "name": "Ada", "missing": null }),
+        )
+        .await
+        .unwrap();
+    assert!(!changed);
+}
+
+#[tokio::test]
+async fn test_merge_patch_at_nested_path() {
+    let tonk = TonkCore::new().await.unwrap();
+    let vfs = tonk.vfs();
+
+    vfs.create_document("/config.json", json!({ "settings": { "theme": "light" } }))
+        .await
+        .unwrap();
+
+    let path = vec!["settings".to_string()];
+    vfs.merge_patch_document(
+        "/config.json",
+        &path,
+        json!({ "fontSize": 14, "editor": { "tabs": 2, "wrap": null } }),
+    )
+    .await
+    .unwrap();
+
+    // Nulls inside newly created objects are dropped rather than stored
+    assert_eq!(
+        read_content(&vfs, "/config.json").await,
+        json!({ "settings": { "theme": "light", "fontSize": 14, "editor": { "tabs": 2 } } })
+    );
+
+    // A non-object patch replaces the target, and null removes it
+    vfs.merge_patch_document("/config.json", &path, json!("reset"))
+        .await
+        .unwrap();
+    assert_eq!(
+        read_content(&vfs, "/config.json").await,
+        json!({ "settings": "reset" })
+    );
+    let removed = vfs
+        .merge_patch_document("/config.json", &path, json!(null))
+        .await
+        .unwrap();
+    assert!(removed);
+    assert_eq!(read_content(&vfs, "/config.json").await, json!({}));
+
+    // Removing what isn't there changes nothing, and the content itself can't be removed
+    let removed = vfs
+        .merge_patch_document("/config.json", &path, json!(null))
+        .await
+        .unwrap();
+    assert!(!removed);
+    assert!(vfs
+        .merge_patch_document("/config.json", &[], json!(null))
+        .await
+        .is_err());
+    assert_eq!(read_content(&vfs, "/config.json").await, json!({}));
+
+    let missing = vfs
+        .merge_patch_document("/missing.json", &path, json!({}))
+        .await
+        .unwrap();
+    assert!(!missing);
+}
+
 // ============================================================================
 // Splice Text Tests
 // ============================================================================
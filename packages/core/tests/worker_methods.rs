@@ -0,0 +1,72 @@
+//! Checks that the worker proxy covers every method `WasmTonkCore` exports
+//!
+//! The wasm bindings only build for wasm32, so this reads their source: each
+//! instance method has to be either dispatched in `src/wasm/worker.rs` or
+//! listed there as `LOCAL_ONLY`.
+
+const BINDINGS: &str = include_str!("../src/wasm.rs");
+const WORKER: &str = include_str!("../src/wasm/worker.rs");
+
+/// JS names of the instance methods in `impl WasmTonkCore`
+fn exported_methods() -> Vec<String> {
+    let start = BINDINGS.find("impl WasmTonkCore {").unwrap();
+    let end = start + BINDINGS[start..].find("\n}\n").unwrap();
+    let mut rest = &BINDINGS[start..end];
+
+    let mut methods = Vec::new();
+    while let Some(at) = rest.find("\n    pub fn ") {
+        // Attributes sit between the end of the previous method and this one
+        let attributes = &rest[rest[..at].rfind("\n    }").unwrap_or(0)..at];
+        let signature = &rest[at + "\n    pub fn ".len()..];
+        let name = &signature[..signature.find('(').unwrap()];
+        if signature[..signature.find(')').unwrap()].contains("&self") {
+            methods.push(match attributes.split("js_name = ").nth(1) {
+                Some(js_name) => js_name[..js_name.find(')').unwrap()].to_string(),
+                None => name.to_string(),
+            });
+        }
+        rest = signature;
+    }
+    methods
+}
+
+/// Method names in `source` that are match arms (`"name" =>`) or list items (`"name",`)
+fn quoted_names(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix('"'))
+        .filter_map(|line| line.split_once('"'))
+        .filter(|(name, _)| name.chars().all(|c| c.is_ascii_alphanumeric()))
+        .filter(|(_, rest)| rest.starts_with(" =>") || *rest == ",")
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+#[test]
+fn test_worker_covers_every_exported_method() {
+    let local_start = WORKER.find("const LOCAL_ONLY").unwrap();
+    let local_end = local_start + WORKER[local_start..].find("];").unwrap();
+    let local_only = quoted_names(&WORKER[local_start..local_end]);
+
+    let dispatch_start = WORKER.find("fn dispatch(").unwrap();
+    let dispatch_end = dispatch_start + WORKER[dispatch_start..].find("\n}\n").unwrap();
+    let dispatched = quoted_names(&WORKER[dispatch_start..dispatch_end]);
+
+    let exported = exported_methods();
+    assert!(exported.len() > 50, "found only {:?}", exported);
+
+    for method in &exported {
+        assert!(
+            dispatched.contains(method) != local_only.contains(method),
+            "{} must be either dispatched by the worker or listed as LOCAL_ONLY",
+            method
+        );
+    }
+    for method in dispatched.iter().chain(&local_only) {
+        assert!(
+            exported.contains(method),
+            "{} is named in worker.rs but isn't a WasmTonkCore method",
+            method
+        );
+    }
+}
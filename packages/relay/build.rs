@@ -7,6 +7,9 @@ fn main() {
     let out_dir = env::var("OUT_DIR").unwrap();
     let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
 
+    #[cfg(feature = "grpc")]
+    compile_protos();
+
     println!("cargo:rerun-if-changed=scripts/extract-wasm.js");
     println!("cargo:rerun-if-changed=package.json");
 
@@ -31,3 +34,17 @@ fn main() {
         output.stdout.len()
     );
 }
+
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    println!("cargo:rerun-if-changed=proto/relay.proto");
+
+    // Use the bundled protoc so builds don't depend on a system install
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("Failed to find bundled protoc");
+    env::set_var("PROTOC", protoc);
+
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/relay.proto"], &["proto"])
+        .expect("Failed to compile proto/relay.proto");
+}
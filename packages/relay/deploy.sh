@@ -26,6 +26,16 @@ echo "🔨 Rebuilding core-js..."
 cd ../core-js
 bun run build
 
+echo "⚙️  Configuring relay environment..."
+# The relay has no S3 bucket unless one is set; keep the one it used to default to.
+# Values from an EnvironmentFile in relay.service take precedence over this.
+if [ ! -f /etc/systemd/system/relay.service.d/s3.conf ]; then
+  sudo mkdir -p /etc/systemd/system/relay.service.d
+  printf '[Service]\nEnvironment=S3_BUCKET_NAME=host-web-bundle-storage\n' |
+    sudo tee /etc/systemd/system/relay.service.d/s3.conf >/dev/null
+  sudo systemctl daemon-reload
+fi
+
 echo "🔄 Restarting relay service..."
 sudo systemctl restart relay.service
 
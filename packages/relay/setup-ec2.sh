@@ -69,6 +69,11 @@ bun run build
 
 echo "⚙️  Setting up systemd service..."
 sudo cp /home/ec2-user/tonk/packages/relay/relay.service /etc/systemd/system/
+# The relay has no S3 bucket unless one is set; bundle uploads, snapshots and share links need it.
+# Values from an EnvironmentFile in relay.service take precedence over this.
+sudo mkdir -p /etc/systemd/system/relay.service.d
+printf '[Service]\nEnvironment=S3_BUCKET_NAME=%s\n' "${S3_BUCKET_NAME:-host-web-bundle-storage}" |
+  sudo tee /etc/systemd/system/relay.service.d/s3.conf >/dev/null
 sudo systemctl daemon-reload
 sudo systemctl enable relay.service
 sudo systemctl start relay.service
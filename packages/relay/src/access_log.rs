@@ -0,0 +1,578 @@
+use crate::error::{RelayError, Result};
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tonk_core::PeerInfo;
+
+/// Where sync connection access entries are written
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessLogConfig {
+    /// File entries are appended to as JSON lines; without one they are logged
+    /// as tracing events with the `access` target
+    pub path: Option<PathBuf>,
+    /// Size at which the file is rotated; `0` never rotates
+    pub max_bytes: u64,
+    /// Rotated files kept, from `<path>.1` (newest) to `<path>.<max_files>`
+    pub max_files: usize,
+    /// Addresses or CIDR ranges of proxies whose `X-Forwarded-For` is believed,
+    /// such as a load balancer and, when clustering, the other replicas
+    pub trusted_proxies: Vec<String>,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_bytes: 100 * 1024 * 1024,
+            max_files: 5,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl AccessLogConfig {
+    /// Parse `trusted_proxies`, where a bare address is a range of one
+    pub fn trusted_proxies(&self) -> Result<Vec<IpNet>> {
+        self.trusted_proxies
+            .iter()
+            .map(|proxy| {
+                proxy
+                    .parse::<IpNet>()
+                    .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| {
+                        RelayError::Config(format!(
+                            "`access_log.trusted_proxies` has an invalid address '{}'",
+                            proxy
+                        ))
+                    })
+            })
+            .collect()
+    }
+}
+
+/// What happened to a sync connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AccessEvent {
+    Connect,
+    Disconnect,
+    /// The connection was refused before the WebSocket opened
+    Rejected,
+}
+
+/// How a sync connection was authorized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuthOutcome {
+    /// The relay requires no credentials, so the connection has full access
+    Open,
+    /// The relay's sync token was presented, so the connection has full access
+    Token,
+    /// A valid share link was presented, so the connection is read-only
+    Share,
+    Denied,
+}
+
+/// Kind of WebSocket a connection opened
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Channel {
+    #[default]
+    Sync,
+    /// A WebRTC signaling room, at `/signal/{space_id}`
+    Signal,
+}
+
+/// One line of the access log
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessEntry {
+    /// Unix time in milliseconds
+    pub timestamp: u64,
+    pub event: AccessEvent,
+    pub channel: Channel,
+    pub connection_id: String,
+    /// Root document ID of the space the connection syncs
+    pub space_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_addr: Option<String>,
+    /// Peer ID the client announced when joining, once it has
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    /// Software the client said it runs when connecting, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<PeerInfo>,
+    pub auth: AuthOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share_id: Option<String>,
+    /// Sync message bytes received from the peer, on disconnect
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_in: Option<u64>,
+    /// Sync message bytes sent to the peer, on disconnect
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_out: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Why the connection ended or was rejected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl AccessEntry {
+    pub fn new(
+        event: AccessEvent,
+        connection_id: String,
+        space_id: String,
+        auth: AuthOutcome,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            event,
+            channel: Channel::default(),
+            connection_id,
+            space_id,
+            remote_addr: None,
+            peer_id: None,
+            client: None,
+            auth,
+            share_id: None,
+            bytes_in: None,
+            bytes_out: None,
+            duration_ms: None,
+            reason: None,
+        }
+    }
+
+    /// A new entry for the same connection, keeping who it was and how it authorized
+    pub fn later(&self, event: AccessEvent) -> Self {
+        Self {
+            channel: self.channel,
+            remote_addr: self.remote_addr.clone(),
+            peer_id: self.peer_id.clone(),
+            client: self.client.clone(),
+            share_id: self.share_id.clone(),
+            ..Self::new(
+                event,
+                self.connection_id.clone(),
+                self.space_id.clone(),
+                self.auth,
+            )
+        }
+    }
+}
+
+/// Traffic of one sync connection, shared between its socket and its access entries
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    pub peer_id: Mutex<Option<String>>,
+}
+
+impl ConnectionStats {
+    pub fn peer_id(&self) -> Option<String> {
+        self.peer_id.lock().unwrap().clone()
+    }
+
+    /// Fill in the traffic fields of a disconnect entry
+    pub fn fill(&self, entry: &mut AccessEntry) {
+        entry.peer_id = self.peer_id();
+        entry.bytes_in = Some(self.bytes_in.load(Ordering::Relaxed));
+        entry.bytes_out = Some(self.bytes_out.load(Ordering::Relaxed));
+    }
+}
+
+/// Entries waiting for the writer thread before further ones are dropped
+const QUEUE_LEN: usize = 4096;
+
+enum Command {
+    Write(Vec<u8>),
+    /// Reply once every entry queued before this one is written
+    #[cfg(test)]
+    Flush(mpsc::Sender<()>),
+}
+
+/// The log file and the rotation settings, owned by the writer thread
+struct LogFile {
+    file: File,
+    len: u64,
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl LogFile {
+    fn append(&mut self, line: &[u8]) -> std::io::Result<()> {
+        if self.max_bytes > 0 && self.len > 0 && self.len + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.len += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shift `<path>.N` to `<path>.N+1`, dropping the oldest, and start a new file
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                match std::fs::rename(rotated(&self.path, n), rotated(&self.path, n + 1)) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            std::fs::rename(&self.path, rotated(&self.path, 1))?;
+        }
+
+        self.file = open(&self.path)?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// Queue of entries for the thread that writes and rotates the log file
+///
+/// Writing and renaming files blocks, so it's kept off the async runtime.
+struct Writer {
+    queue: Option<SyncSender<Command>>,
+    thread: Option<JoinHandle<()>>,
+    /// Entries dropped because the queue was full, since the last write
+    dropped: Arc<AtomicU64>,
+}
+
+impl Writer {
+    fn spawn(mut log: LogFile) -> std::io::Result<Self> {
+        let (queue, commands) = mpsc::sync_channel(QUEUE_LEN);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let missed = Arc::clone(&dropped);
+        let thread = std::thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                for command in commands {
+                    match command {
+                        Command::Write(line) => {
+                            let missed = missed.swap(0, Ordering::Relaxed);
+                            if missed > 0 {
+                                tracing::warn!("Dropped {} access log entries", missed);
+                            }
+                            if let Err(e) = log.append(&line) {
+                                tracing::warn!("Failed to write access log: {}", e);
+                            }
+                        }
+                        #[cfg(test)]
+                        Command::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })?;
+        Ok(Self {
+            queue: Some(queue),
+            thread: Some(thread),
+            dropped,
+        })
+    }
+
+    fn send(&self, line: Vec<u8>) {
+        let Some(queue) = &self.queue else {
+            return;
+        };
+        match queue.try_send(Command::Write(line)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    tracing::warn!("Access log writer is behind; dropping entries");
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                tracing::warn!("Access log writer has stopped");
+            }
+        }
+    }
+}
+
+impl Drop for Writer {
+    /// Write out the queued entries before the log goes away
+    fn drop(&mut self) {
+        self.queue.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Records who opened sync connections to which space, and what they exchanged
+pub struct AccessLog {
+    trusted_proxies: Vec<IpNet>,
+    writer: Option<Writer>,
+}
+
+impl AccessLog {
+    pub fn new(config: &AccessLogConfig) -> Result<Self> {
+        let writer = match &config.path {
+            Some(path) => {
+                let file = open(path)?;
+                let len = file.metadata()?.len();
+                Some(Writer::spawn(LogFile {
+                    file,
+                    len,
+                    path: path.clone(),
+                    max_bytes: config.max_bytes,
+                    max_files: config.max_files,
+                })?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            trusted_proxies: config.trusted_proxies()?,
+            writer,
+        })
+    }
+
+    /// Address of the client behind a connection from `peer`, as logged
+    ///
+    /// When `peer` is a trusted proxy, `X-Forwarded-For` is read from the right,
+    /// skipping the addresses of trusted proxies. The first other address is the
+    /// client's, so a client can't choose the address it's logged under by
+    /// sending the header itself.
+    pub fn client_addr(&self, peer: SocketAddr, headers: &HeaderMap) -> String {
+        let trusted = |ip: &IpAddr| self.trusted_proxies.iter().any(|net| net.contains(ip));
+        if !trusted(&peer.ip()) {
+            return peer.to_string();
+        }
+
+        let forwarded: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        let mut client = peer.to_string();
+        for hop in forwarded.into_iter().rev() {
+            match hop.parse::<IpAddr>() {
+                Ok(ip) if trusted(&ip) => client = ip.to_string(),
+                Ok(ip) => return ip.to_string(),
+                Err(_) => break,
+            }
+        }
+        client
+    }
+
+    pub fn record(&self, entry: &AccessEntry) {
+        let Some(writer) = &self.writer else {
+            let client = entry.client.as_ref().map(ToString::to_string);
+            tracing::info!(
+                target: "access",
+                event = ?entry.event,
+                channel = ?entry.channel,
+                connection_id = %entry.connection_id,
+                space_id = %entry.space_id,
+                remote_addr = ?entry.remote_addr,
+                peer_id = ?entry.peer_id,
+                client = ?client,
+                auth = ?entry.auth,
+                share_id = ?entry.share_id,
+                bytes_in = ?entry.bytes_in,
+                bytes_out = ?entry.bytes_out,
+                duration_ms = ?entry.duration_ms,
+                reason = ?entry.reason,
+            );
+            return;
+        };
+
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!("Failed to serialize access log entry: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        writer.send(line);
+    }
+
+    /// Wait until the entries recorded so far are written
+    #[cfg(test)]
+    fn flush(&self) {
+        let Some(queue) = self.writer.as_ref().and_then(|w| w.queue.as_ref()) else {
+            return;
+        };
+        let (done, written) = mpsc::channel();
+        if queue.send(Command::Flush(done)).is_ok() {
+            let _ = written.recv();
+        }
+    }
+}
+
+fn open(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(n: usize) -> AccessEntry {
+        AccessEntry::new(
+            AccessEvent::Connect,
+            format!("connection-{:03}", n),
+            "space".to_string(),
+            AuthOutcome::Open,
+        )
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .map(|text| text.lines().map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_rotation_keeps_max_files() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        let line_len = serde_json::to_vec(&entry(0)).unwrap().len() as u64 + 1;
+        let log = AccessLog::new(&AccessLogConfig {
+            path: Some(path.clone()),
+            max_bytes: line_len * 3,
+            max_files: 2,
+            ..AccessLogConfig::default()
+        })
+        .unwrap();
+
+        for n in 0..10 {
+            log.record(&entry(n));
+        }
+        log.flush();
+
+        // Three entries fit in each file, so the current file holds the last
+        // one and the two rotated files the six before it
+        assert!(!rotated(&path, 3).exists());
+        let ids = |path: &Path| -> Vec<String> {
+            lines(path)
+                .iter()
+                .map(|line| {
+                    let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                    value["connectionId"].as_str().unwrap().to_string()
+                })
+                .collect()
+        };
+        assert_eq!(ids(&path), vec!["connection-009"]);
+        assert_eq!(
+            ids(&rotated(&path, 1)),
+            vec!["connection-006", "connection-007", "connection-008"]
+        );
+        assert_eq!(
+            ids(&rotated(&path, 2)),
+            vec!["connection-003", "connection-004", "connection-005"]
+        );
+    }
+
+    #[test]
+    fn test_rotation_appends_to_an_existing_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        let line_len = serde_json::to_vec(&entry(0)).unwrap().len() as u64 + 1;
+        let config = AccessLogConfig {
+            path: Some(path.clone()),
+            max_bytes: line_len * 2,
+            max_files: 1,
+            ..AccessLogConfig::default()
+        };
+
+        // The size of the file left by an earlier run counts towards rotation
+        AccessLog::new(&config).unwrap().record(&entry(0));
+        let log = AccessLog::new(&config).unwrap();
+        log.record(&entry(1));
+        log.record(&entry(2));
+        log.flush();
+
+        assert_eq!(lines(&rotated(&path, 1)).len(), 2);
+        assert_eq!(lines(&path).len(), 1);
+    }
+
+    #[test]
+    fn test_without_kept_files_rotation_starts_over() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        let log = AccessLog::new(&AccessLogConfig {
+            path: Some(path.clone()),
+            max_bytes: 1,
+            max_files: 0,
+            ..AccessLogConfig::default()
+        })
+        .unwrap();
+
+        for n in 0..3 {
+            log.record(&entry(n));
+        }
+        log.flush();
+
+        assert!(!rotated(&path, 1).exists());
+        assert_eq!(lines(&path).len(), 1);
+    }
+
+    #[test]
+    fn test_forwarded_for_is_read_from_trusted_proxies_only() {
+        let log = AccessLog::new(&AccessLogConfig {
+            trusted_proxies: vec!["10.0.0.0/8".to_string(), "192.0.2.1".to_string()],
+            ..AccessLogConfig::default()
+        })
+        .unwrap();
+        let headers = |forwarded: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-forwarded-for", forwarded.parse().unwrap());
+            headers
+        };
+        let peer = |addr: &str| addr.parse::<SocketAddr>().unwrap();
+
+        // A client connecting directly can't claim another address
+        assert_eq!(
+            log.client_addr(peer("203.0.113.7:5000"), &headers("198.51.100.1")),
+            "203.0.113.7:5000"
+        );
+        // Behind a load balancer and a replica, the client is the first
+        // untrusted hop from the right, even if it sent a header of its own
+        assert_eq!(
+            log.client_addr(
+                peer("10.0.0.2:5000"),
+                &headers("198.51.100.1, 203.0.113.7, 192.0.2.1")
+            ),
+            "203.0.113.7"
+        );
+        assert_eq!(
+            log.client_addr(peer("10.0.0.2:5000"), &HeaderMap::new()),
+            "10.0.0.2:5000"
+        );
+
+        assert!(AccessLogConfig {
+            trusted_proxies: vec!["not-an-address".to_string()],
+            ..AccessLogConfig::default()
+        }
+        .trusted_proxies()
+        .is_err());
+    }
+}
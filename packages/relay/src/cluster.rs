@@ -0,0 +1,595 @@
+use crate::error::{RelayError, Result};
+use crate::storage::ObjectStore;
+use axum::extract::ws::{close_code, CloseFrame};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+const NODES_PREFIX: &str = "cluster/nodes/";
+const OWNERS_PREFIX: &str = "cluster/owners/";
+const PROBES_PREFIX: &str = "cluster/probes/";
+
+/// Resolves once this replica no longer owns a space
+pub type Lost = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Close frame sent to connections to a space this replica no longer owns
+pub fn moved_frame() -> CloseFrame {
+    CloseFrame {
+        code: close_code::AGAIN,
+        reason: "Space moved to another relay".into(),
+    }
+}
+
+/// Clustering of relay replicas that share an S3 bucket
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClusterConfig {
+    pub enabled: bool,
+    /// Name of this replica; a random one is chosen at startup if unset
+    pub node_id: Option<String>,
+    /// Base URL other replicas proxy connections to, such as `http://10.0.0.1:8081`
+    ///
+    /// Replicas proxy to each other over plain WebSockets, so this must be an
+    /// `http://` or `ws://` URL on a private network.
+    pub advertise_url: Option<String>,
+    /// Seconds a replica owns a space without renewing it before another may take over
+    pub lease_secs: u64,
+    /// Seconds between heartbeats, which also renew owned spaces
+    pub heartbeat_secs: u64,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            node_id: None,
+            advertise_url: None,
+            lease_secs: 15,
+            heartbeat_secs: 5,
+        }
+    }
+}
+
+/// A replica, as recorded by its latest heartbeat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfo {
+    pub node_id: String,
+    pub advertise_url: String,
+    /// Unix time in milliseconds of the heartbeat
+    pub heartbeat_at: u64,
+}
+
+/// Which replica owns a space, stored under `cluster/owners/{space}.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Ownership {
+    node_id: String,
+    /// Unix time in milliseconds after which another replica may take over
+    expires_at: u64,
+}
+
+/// A space this replica owns
+struct Owned {
+    /// ETag of the ownership record
+    e_tag: String,
+    /// When the record was last written, before the write was sent
+    renewed_at: Instant,
+}
+
+/// Where requests for a space should be served
+#[derive(Debug, Clone)]
+pub enum Route {
+    /// This replica owns the space
+    Local,
+    /// Another replica owns it
+    Remote(NodeInfo),
+}
+
+/// Cluster membership and space ownership, as reported by the admin API
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterStatus {
+    pub node_id: String,
+    /// Replicas whose last heartbeat is within the lease, this one included
+    pub members: Vec<NodeInfo>,
+    /// Spaces this replica owns
+    pub owned_spaces: Vec<String>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Routes each space to a single replica, with takeover when its owner goes quiet
+///
+/// Replicas coordinate through objects in the shared S3 bucket, using
+/// conditional writes so that only one of them can claim or take over a space.
+/// Each replica writes a heartbeat under `cluster/nodes/` and renews the spaces
+/// it owns under `cluster/owners/`. A space whose owner hasn't renewed it within
+/// the lease is taken over by the next replica asked to serve it. A replica
+/// stops treating a space as its own once its lease runs out, so connections to
+/// it can be closed before another replica takes over.
+///
+/// The bucket must honour `If-Match` and `If-None-Match` on writes, which
+/// [`Cluster::check_conditional_writes`] verifies at startup.
+pub struct Cluster {
+    node_id: String,
+    advertise_url: String,
+    lease: Duration,
+    heartbeat: Duration,
+    objects: ObjectStore,
+    owned: Mutex<HashMap<String, Owned>>,
+    /// Bumped whenever a space is given up, waking [`Cluster::lost`] futures
+    releases: watch::Sender<u64>,
+    /// Members seen at the last heartbeat
+    members: Mutex<Vec<NodeInfo>>,
+}
+
+impl Cluster {
+    pub fn new(config: &ClusterConfig, objects: ObjectStore) -> Result<Self> {
+        let advertise_url = config
+            .advertise_url
+            .clone()
+            .ok_or_else(|| RelayError::Config("`cluster.advertise_url` is required".into()))?;
+        if !["http://", "ws://"]
+            .iter()
+            .any(|scheme| advertise_url.starts_with(scheme))
+        {
+            return Err(RelayError::Config(
+                "`cluster.advertise_url` must be an http:// or ws:// URL".into(),
+            ));
+        }
+        Ok(Self {
+            node_id: config
+                .node_id
+                .clone()
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            advertise_url: advertise_url.trim_end_matches('/').to_string(),
+            lease: Duration::from_secs(config.lease_secs),
+            heartbeat: Duration::from_secs(config.heartbeat_secs),
+            objects,
+            owned: Mutex::default(),
+            releases: watch::Sender::new(0),
+            members: Mutex::default(),
+        })
+    }
+
+    fn ownership_key(space: &str) -> String {
+        format!("{}{}.json", OWNERS_PREFIX, space)
+    }
+
+    fn node_key(node_id: &str) -> String {
+        format!("{}{}.json", NODES_PREFIX, node_id)
+    }
+
+    fn ownership(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&Ownership {
+            node_id: self.node_id.clone(),
+            expires_at: now_ms() + self.lease.as_millis() as u64,
+        })?)
+    }
+
+    /// Find the replica that owns `space`, claiming it if it's unowned or its owner has gone quiet
+    pub async fn route(&self, space: &str) -> Result<Route> {
+        if self
+            .owned
+            .lock()
+            .unwrap()
+            .get(space)
+            .is_some_and(|owned| owned.renewed_at.elapsed() < self.lease)
+        {
+            return Ok(Route::Local);
+        }
+
+        let key = Self::ownership_key(space);
+        // A lost race means someone else just claimed it, so one re-read settles it
+        for _ in 0..2 {
+            let current = self.objects.get_object_tagged(&key).await?;
+            let e_tag = match &current {
+                Some((data, e_tag)) => {
+                    let owner: Ownership = serde_json::from_slice(data)?;
+                    if owner.node_id != self.node_id && owner.expires_at > now_ms() {
+                        self.release(space);
+                        return Ok(Route::Remote(self.member(&owner.node_id).await?));
+                    }
+                    if owner.node_id != self.node_id {
+                        tracing::warn!(
+                            "Taking over space {} from unresponsive node {}",
+                            space,
+                            owner.node_id
+                        );
+                    }
+                    Some(e_tag.as_str())
+                }
+                None => None,
+            };
+
+            let renewed_at = Instant::now();
+            if let Some(e_tag) = self
+                .objects
+                .put_object_if(&key, self.ownership()?, e_tag)
+                .await?
+            {
+                let mut owned = self.owned.lock().unwrap();
+                if !owned.contains_key(space) {
+                    tracing::info!("Node {} now owns space {}", self.node_id, space);
+                }
+                owned.insert(space.to_string(), Owned { e_tag, renewed_at });
+                return Ok(Route::Local);
+            }
+        }
+
+        self.release(space);
+        Err(RelayError::S3(format!(
+            "Ownership of space {} is contended",
+            space
+        )))
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Whether this replica currently owns `space`
+    pub fn owns(&self, space: &str) -> bool {
+        self.owned.lock().unwrap().contains_key(space)
+    }
+
+    /// Stop treating `space` as owned, waking anything waiting on [`Cluster::lost`]
+    fn release(&self, space: &str) -> Option<Owned> {
+        let released = self.owned.lock().unwrap().remove(space);
+        if released.is_some() {
+            self.releases.send_modify(|count| *count += 1);
+        }
+        released
+    }
+
+    /// Resolve once this replica no longer owns `space`, to close its connections
+    pub fn lost(self: &Arc<Self>, space: &str) -> Lost {
+        let cluster = Arc::clone(self);
+        let space = space.to_string();
+        let mut releases = self.releases.subscribe();
+        Box::pin(async move {
+            while cluster.owns(&space) {
+                if releases.changed().await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            }
+        })
+    }
+
+    /// Look up a member by ID, from the last heartbeat or else from the bucket
+    async fn member(&self, node_id: &str) -> Result<NodeInfo> {
+        if let Some(node) = self
+            .members
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|node| node.node_id == node_id)
+        {
+            return Ok(node.clone());
+        }
+
+        let data = self
+            .objects
+            .get_object(&Self::node_key(node_id))
+            .await?
+            .ok_or_else(|| RelayError::NotFound(format!("Cluster node {} not found", node_id)))?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    /// Write a heartbeat, renew owned spaces and refresh the member list
+    async fn beat(&self) -> Result<()> {
+        let node = NodeInfo {
+            node_id: self.node_id.clone(),
+            advertise_url: self.advertise_url.clone(),
+            heartbeat_at: now_ms(),
+        };
+        self.objects
+            .put_object(&Self::node_key(&self.node_id), serde_json::to_vec(&node)?)
+            .await?;
+
+        let owned: Vec<(String, String)> = self
+            .owned
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(space, owned)| (space.clone(), owned.e_tag.clone()))
+            .collect();
+        for (space, e_tag) in owned {
+            let renewed_at = Instant::now();
+            let renewed = self
+                .objects
+                .put_object_if(
+                    &Self::ownership_key(&space),
+                    self.ownership()?,
+                    Some(&e_tag),
+                )
+                .await;
+            match renewed {
+                Ok(Some(e_tag)) => {
+                    if let Some(owned) = self.owned.lock().unwrap().get_mut(&space) {
+                        *owned = Owned { e_tag, renewed_at };
+                    }
+                }
+                Ok(None) => {
+                    tracing::warn!("Node {} lost ownership of space {}", self.node_id, space);
+                    self.release(&space);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to renew space {}: {}", space, e);
+                    let expired = self
+                        .owned
+                        .lock()
+                        .unwrap()
+                        .get(&space)
+                        .is_some_and(|owned| owned.renewed_at.elapsed() >= self.lease);
+                    if expired {
+                        tracing::warn!(
+                            "Node {} gave up space {} after its lease ran out",
+                            self.node_id,
+                            space
+                        );
+                        self.release(&space);
+                    }
+                }
+            }
+        }
+
+        let cutoff = now_ms().saturating_sub(self.lease.as_millis() as u64);
+        let mut members = Vec::new();
+        for key in self.objects.list_keys(NODES_PREFIX).await? {
+            if let Some(data) = self.objects.get_object(&key).await? {
+                match serde_json::from_slice::<NodeInfo>(&data) {
+                    Ok(node) if node.heartbeat_at >= cutoff => members.push(node),
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("Ignoring unreadable cluster node {}: {}", key, e),
+                }
+            }
+        }
+        members.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        *self.members.lock().unwrap() = members;
+        Ok(())
+    }
+
+    /// Heartbeat until the task is aborted
+    pub async fn run(self: Arc<Self>) {
+        tracing::info!(
+            "Joining cluster as node {} at {}",
+            self.node_id,
+            self.advertise_url
+        );
+        let mut interval = tokio::time::interval(self.heartbeat);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.beat().await {
+                tracing::error!("Cluster heartbeat failed: {}", e);
+            }
+        }
+    }
+
+    /// Give up owned spaces and remove this replica's heartbeat, so others take over at once
+    ///
+    /// A space is only released if its record is still the one this replica
+    /// wrote, so a replica that already took it over keeps it.
+    pub async fn leave(&self) {
+        let spaces: Vec<String> = self.owned.lock().unwrap().keys().cloned().collect();
+        for space in spaces {
+            let Some(owned) = self.release(&space) else {
+                continue;
+            };
+            match self
+                .objects
+                .delete_object_if(&Self::ownership_key(&space), &owned.e_tag)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => tracing::debug!("Space {} was already taken over", space),
+                Err(e) => tracing::warn!("Failed to release space {}: {}", space, e),
+            }
+        }
+        if let Err(e) = self
+            .objects
+            .delete_object(&Self::node_key(&self.node_id))
+            .await
+        {
+            tracing::warn!("Failed to remove cluster node {}: {}", self.node_id, e);
+        }
+    }
+
+    /// Check that the bucket honours conditional writes, without which two
+    /// replicas could both claim a space
+    ///
+    /// Some S3-compatible stores accept `If-Match` and `If-None-Match` but
+    /// ignore them, so this writes a probe object and checks that conflicting
+    /// writes are refused.
+    pub async fn check_conditional_writes(&self) -> Result<()> {
+        let key = format!("{}{}.json", PROBES_PREFIX, self.node_id);
+        self.objects.delete_object(&key).await?;
+
+        let created = self
+            .objects
+            .put_object_if(&key, b"{}".to_vec(), None)
+            .await?;
+        let duplicate = self
+            .objects
+            .put_object_if(&key, b"{}".to_vec(), None)
+            .await?;
+        let stale = self
+            .objects
+            .put_object_if(&key, b"{}".to_vec(), Some("\"stale\""))
+            .await?;
+        self.objects.delete_object(&key).await?;
+
+        if created.is_none() || duplicate.is_some() || stale.is_some() {
+            return Err(RelayError::Config(
+                "The S3 bucket ignores conditional writes, which clustering requires".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn status(&self) -> ClusterStatus {
+        let mut owned_spaces: Vec<String> = self.owned.lock().unwrap().keys().cloned().collect();
+        owned_spaces.sort();
+        ClusterStatus {
+            node_id: self.node_id.clone(),
+            members: self.members.lock().unwrap().clone(),
+            owned_spaces,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(node_id: &str, objects: &ObjectStore, lease: Duration) -> Arc<Cluster> {
+        let config = ClusterConfig {
+            enabled: true,
+            node_id: Some(node_id.to_string()),
+            advertise_url: Some(format!("http://{}:8081/", node_id)),
+            ..ClusterConfig::default()
+        };
+        let mut cluster = Cluster::new(&config, objects.clone()).unwrap();
+        cluster.lease = lease;
+        Arc::new(cluster)
+    }
+
+    fn remote_owner(route: Route) -> String {
+        match route {
+            Route::Remote(owner) => owner.node_id,
+            Route::Local => panic!("expected another replica to own the space"),
+        }
+    }
+
+    #[test]
+    fn test_advertise_url_must_be_plain_http() {
+        let config = ClusterConfig {
+            enabled: true,
+            advertise_url: Some("https://relay.example.com".to_string()),
+            ..ClusterConfig::default()
+        };
+        assert!(Cluster::new(&config, ObjectStore::in_memory()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_routes_to_the_owner() {
+        let objects = ObjectStore::in_memory();
+        let a = node("a", &objects, Duration::from_secs(15));
+        let b = node("b", &objects, Duration::from_secs(15));
+        a.beat().await.unwrap();
+
+        assert!(matches!(a.route("space").await.unwrap(), Route::Local));
+        assert!(matches!(a.route("space").await.unwrap(), Route::Local));
+        assert_eq!(remote_owner(b.route("space").await.unwrap()), "a");
+        assert!(!b.owns("space"));
+
+        let owner = match b.route("space").await.unwrap() {
+            Route::Remote(owner) => owner,
+            Route::Local => unreachable!(),
+        };
+        assert_eq!(owner.advertise_url, "http://a:8081");
+    }
+
+    #[tokio::test]
+    async fn test_expired_lease_is_taken_over() {
+        let objects = ObjectStore::in_memory();
+        let a = node("a", &objects, Duration::from_millis(50));
+        let b = node("b", &objects, Duration::from_millis(50));
+        a.beat().await.unwrap();
+        b.beat().await.unwrap();
+        assert!(matches!(a.route("space").await.unwrap(), Route::Local));
+        let lost = a.lost("space");
+
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert!(matches!(b.route("space").await.unwrap(), Route::Local));
+
+        // Once its lease has run out, a replica checks the record before serving
+        assert_eq!(remote_owner(a.route("space").await.unwrap()), "b");
+        tokio::time::timeout(Duration::from_secs(1), lost)
+            .await
+            .expect("connections to a lost space should close");
+    }
+
+    #[tokio::test]
+    async fn test_renewal_notices_takeover() {
+        let objects = ObjectStore::in_memory();
+        let a = node("a", &objects, Duration::from_millis(50));
+        let b = node("b", &objects, Duration::from_millis(50));
+        assert!(matches!(a.route("space").await.unwrap(), Route::Local));
+        let lost = a.lost("space");
+
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert!(matches!(b.route("space").await.unwrap(), Route::Local));
+        a.beat().await.unwrap();
+
+        assert!(!a.owns("space"));
+        tokio::time::timeout(Duration::from_secs(1), lost)
+            .await
+            .expect("connections to a lost space should close");
+        assert!(b.owns("space"));
+    }
+
+    #[tokio::test]
+    async fn test_own_expired_lease_is_renewed() {
+        let objects = ObjectStore::in_memory();
+        let a = node("a", &objects, Duration::from_millis(50));
+        assert!(matches!(a.route("space").await.unwrap(), Route::Local));
+
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert!(matches!(a.route("space").await.unwrap(), Route::Local));
+        assert!(a.owns("space"));
+    }
+
+    #[tokio::test]
+    async fn test_leave_keeps_claims_taken_over() {
+        let objects = ObjectStore::in_memory();
+        let a = node("a", &objects, Duration::from_millis(50));
+        let b = node("b", &objects, Duration::from_millis(50));
+        assert!(matches!(a.route("taken").await.unwrap(), Route::Local));
+        assert!(matches!(a.route("kept").await.unwrap(), Route::Local));
+        let lost = a.lost("kept");
+
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert!(matches!(b.route("taken").await.unwrap(), Route::Local));
+        a.leave().await;
+
+        assert!(objects
+            .get_object(&Cluster::ownership_key("kept"))
+            .await
+            .unwrap()
+            .is_none());
+        let record = objects
+            .get_object(&Cluster::ownership_key("taken"))
+            .await
+            .unwrap()
+            .expect("the new owner's claim should survive");
+        let owner: Ownership = serde_json::from_slice(&record).unwrap();
+        assert_eq!(owner.node_id, "b");
+        tokio::time::timeout(Duration::from_secs(1), lost)
+            .await
+            .expect("connections should close on leaving");
+    }
+
+    #[tokio::test]
+    async fn test_conditional_writes_are_checked() {
+        let a = node("a", &ObjectStore::in_memory(), Duration::from_secs(15));
+        a.check_conditional_writes().await.unwrap();
+
+        let a = node(
+            "a",
+            &ObjectStore::in_memory_unconditional(),
+            Duration::from_secs(15),
+        );
+        assert!(a.check_conditional_writes().await.is_err());
+    }
+}
@@ -0,0 +1,579 @@
+use crate::access_log::AccessLogConfig;
+use crate::cluster::ClusterConfig;
+use crate::error::{RelayError, Result};
+use crate::snapshot::SnapshotConfig;
+use serde::{Deserialize, Deserializer};
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tonk_core::bundle::integrity::{parse_public_key, VerifyingKey};
+
+/// Where automerge documents are kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Filesystem,
+    /// S3 under `documents/`, with the storage directory as a write-through cache
+    S3,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("filesystem") {
+            Ok(Self::Filesystem)
+        } else if s.eq_ignore_ascii_case("s3") {
+            Ok(Self::S3)
+        } else {
+            Err("expected `filesystem` or `s3`".to_string())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Directory documents are stored in, or cached in with the S3 backend
+    pub dir: PathBuf,
+    pub backend: StorageBackend,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("automerge-repo-data"),
+            backend: StorageBackend::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    /// Bucket for bundles, snapshots, share links, clustering and, with the S3
+    /// backend, documents; all of those are unavailable when unset
+    pub bucket: Option<String>,
+    pub region: String,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            bucket: None,
+            region: "eu-north-1".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Seconds a WebSocket connection may stay silent before it is closed; `0` disables
+    pub idle_timeout_secs: u64,
+    /// Largest frame sent to clients that negotiate chunked sync messages
+    pub max_frame_bytes: usize,
+    /// Largest sync message reassembled from a chunked client's frames
+    pub max_message_bytes: usize,
+    /// Idle seconds before TCP keepalive probes are sent; off when unset
+    pub tcp_keepalive_secs: Option<u64>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_secs: 120,
+            max_frame_bytes: tonk_core::websocket::DEFAULT_MAX_FRAME_SIZE,
+            // What axum accepts in a single WebSocket message
+            max_message_bytes: 64 * 1024 * 1024,
+            tcp_keepalive_secs: None,
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        (self.idle_timeout_secs > 0).then(|| Duration::from_secs(self.idle_timeout_secs))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Bearer token required by admin endpoints; they are disabled when unset
+    pub admin_token: Option<String>,
+    /// Token sync connections must present unless they use a share link;
+    /// anyone can connect and write when unset
+    pub sync_token: Option<String>,
+    /// Key share links are signed with; share links are disabled when unset.
+    /// Requires `sync_token`, since links would otherwise grant nothing
+    pub share_secret: Option<String>,
+    /// Serve the hosted space's `/app` to anyone even when `sync_token` is set,
+    /// which browsers need as they can't add the token to asset requests
+    pub public_app: bool,
+    /// Base64 Ed25519 public keys; when any are set, the hosted bundle and
+    /// uploaded bundles must be signed by one of them
+    pub trusted_bundle_keys: Vec<String>,
+}
+
+impl AuthConfig {
+    /// Parse `trusted_bundle_keys`
+    pub fn trusted_keys(&self) -> Result<Vec<VerifyingKey>> {
+        self.trusted_bundle_keys
+            .iter()
+            .map(|key| parse_public_key(key).map_err(|e| invalid("auth.trusted_bundle_keys", e)))
+            .collect()
+    }
+}
+
+/// Relay configuration
+///
+/// Built from defaults, then the TOML file given with `--config`, then
+/// environment variables, then positional arguments, each overriding the last.
+/// See `relay.example.toml` for the file format.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Port serving HTTP, and WebSocket unless `ws_port` is set
+    pub port: u16,
+    /// Separate port serving only WebSocket sync connections
+    pub ws_port: Option<u16>,
+    /// Port for the gRPC interface, with the `grpc` feature
+    pub grpc_port: Option<u16>,
+    /// Addresses to bind, e.g. `0.0.0.0` and `::` for dual-stack
+    pub bind: Vec<IpAddr>,
+    /// Set `SO_REUSEPORT` so several relay processes can share a port
+    pub reuse_port: bool,
+    /// The `.tonk` bundle to host
+    pub bundle: PathBuf,
+    /// Log filter, such as `info` or `tonk_relay=debug`; `RUST_LOG` takes precedence
+    pub log_level: String,
+    pub storage: StorageConfig,
+    pub s3: S3Config,
+    pub limits: LimitsConfig,
+    pub auth: AuthConfig,
+    pub snapshots: SnapshotConfig,
+    pub cluster: ClusterConfig,
+    pub access_log: AccessLogConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 8081,
+            ws_port: None,
+            grpc_port: None,
+            bind: vec![IpAddr::from([127, 0, 0, 1])],
+            reuse_port: false,
+            bundle: PathBuf::new(),
+            log_level: "info".to_string(),
+            storage: StorageConfig::default(),
+            s3: S3Config::default(),
+            limits: LimitsConfig::default(),
+            auth: AuthConfig::default(),
+            snapshots: SnapshotConfig::default(),
+            cluster: ClusterConfig::default(),
+            access_log: AccessLogConfig::default(),
+        }
+    }
+}
+
+fn invalid(field: &str, problem: impl Display) -> RelayError {
+    RelayError::Config(format!("`{}` {}", field, problem))
+}
+
+/// Parse the environment variable `name`, if it is set
+fn env<T>(name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    std::env::var(name)
+        .ok()
+        .map(|value| {
+            value
+                .parse::<T>()
+                .map_err(|e| RelayError::Config(format!("Invalid {} '{}': {}", name, value, e)))
+        })
+        .transpose()
+}
+
+/// Deserialize a number of seconds into a duration
+pub(crate) fn secs<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Duration>, D::Error> {
+    Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+}
+
+impl Config {
+    /// Build the configuration from the command line and environment
+    ///
+    /// Usage: `tonk-relay [--config relay.toml] [port] [bundle-path] [storage-dir]`
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config_path = None;
+        let mut positional = Vec::new();
+
+        let mut args = args.into_iter().skip(1);
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                let path = args
+                    .next()
+                    .ok_or_else(|| RelayError::Config("--config requires a path".to_string()))?;
+                config_path = Some(PathBuf::from(path));
+            } else if let Some(path) = arg.strip_prefix("--config=") {
+                config_path = Some(PathBuf::from(path));
+            } else if arg.starts_with("--") {
+                return Err(RelayError::Config(format!("Unknown option {}", arg)));
+            } else {
+                positional.push(arg);
+            }
+        }
+
+        let mut config = match config_path {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.apply_args(&positional)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Read a TOML configuration file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| RelayError::Config(format!("Failed to read {}: {}", path.display(), e)))?;
+        toml::from_str(&text).map_err(|e| RelayError::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Override settings from the environment
+    ///
+    /// - `HOST`: comma-separated addresses for `bind`
+    /// - `WS_PORT`, `GRPC_PORT`, `SO_REUSEPORT`: `ws_port`, `grpc_port`, `reuse_port`
+    /// - `DOCUMENT_STORAGE`: `storage.backend`, `filesystem` or `s3` in any case
+    /// - `S3_BUCKET_NAME`, `AWS_REGION`: `s3.bucket`, `s3.region`
+    /// - `IDLE_TIMEOUT_SECS`, `MAX_FRAME_BYTES`, `MAX_MESSAGE_BYTES`, `TCP_KEEPALIVE_SECS`:
+    ///   `limits.*`
+    /// - `ADMIN_TOKEN`, `SYNC_TOKEN`, `SHARE_SECRET`: `auth.admin_token`, `auth.sync_token`,
+    ///   `auth.share_secret`
+    /// - `PUBLIC_APP`: `auth.public_app`
+    /// - `TRUSTED_BUNDLE_KEYS`: comma-separated `auth.trusted_bundle_keys`
+    /// - `SNAPSHOT_INTERVAL_SECS`, `SNAPSHOT_KEEP_LAST`, `SNAPSHOT_KEEP_DAILY`,
+    ///   `SNAPSHOT_KEEP_WEEKLY`: `snapshots.*`
+    /// - `CLUSTER_ENABLED`, `CLUSTER_NODE_ID`, `CLUSTER_ADVERTISE_URL`: `cluster.*`
+    /// - `ACCESS_LOG_PATH`, `ACCESS_LOG_MAX_BYTES`, `ACCESS_LOG_MAX_FILES`: `access_log.*`
+    /// - `ACCESS_LOG_TRUSTED_PROXIES`: comma-separated `access_log.trusted_proxies`
+    pub fn apply_env(&mut self) -> Result<()> {
+        if let Ok(hosts) = std::env::var("HOST") {
+            self.bind = hosts
+                .split(',')
+                .map(|host| {
+                    host.trim()
+                        .parse::<IpAddr>()
+                        .map_err(|e| RelayError::Config(format!("Invalid HOST '{}': {}", host, e)))
+                })
+                .collect::<Result<Vec<_>>>()?;
+        }
+        if let Some(port) = env("WS_PORT")? {
+            self.ws_port = Some(port);
+        }
+        if let Some(port) = env("GRPC_PORT")? {
+            self.grpc_port = Some(port);
+        }
+        if let Ok(reuse_port) = std::env::var("SO_REUSEPORT") {
+            self.reuse_port = reuse_port.eq_ignore_ascii_case("true") || reuse_port == "1";
+        }
+
+        if let Some(backend) = env("DOCUMENT_STORAGE")? {
+            self.storage.backend = backend;
+        }
+        if let Ok(bucket) = std::env::var("S3_BUCKET_NAME") {
+            self.s3.bucket = Some(bucket);
+        }
+        if let Ok(region) = std::env::var("AWS_REGION") {
+            self.s3.region = region;
+        }
+
+        if let Some(secs) = env("IDLE_TIMEOUT_SECS")? {
+            self.limits.idle_timeout_secs = secs;
+        }
+        if let Some(bytes) = env("MAX_FRAME_BYTES")? {
+            self.limits.max_frame_bytes = bytes;
+        }
+        if let Some(bytes) = env("MAX_MESSAGE_BYTES")? {
+            self.limits.max_message_bytes = bytes;
+        }
+        if let Some(secs) = env("TCP_KEEPALIVE_SECS")? {
+            self.limits.tcp_keepalive_secs = Some(secs);
+        }
+
+        if let Ok(token) = std::env::var("ADMIN_TOKEN") {
+            self.auth.admin_token = Some(token);
+        }
+        if let Ok(token) = std::env::var("SYNC_TOKEN") {
+            self.auth.sync_token = Some(token);
+        }
+        if let Ok(secret) = std::env::var("SHARE_SECRET") {
+            self.auth.share_secret = Some(secret);
+        }
+        if let Ok(public_app) = std::env::var("PUBLIC_APP") {
+            self.auth.public_app = public_app.eq_ignore_ascii_case("true") || public_app == "1";
+        }
+        if let Ok(keys) = std::env::var("TRUSTED_BUNDLE_KEYS") {
+            self.auth.trusted_bundle_keys = keys
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        if let Some(secs) = env("SNAPSHOT_INTERVAL_SECS")? {
+            self.snapshots.interval = Some(Duration::from_secs(secs));
+        }
+        if let Some(count) = env("SNAPSHOT_KEEP_LAST")? {
+            self.snapshots.keep_last = count;
+        }
+        if let Some(count) = env("SNAPSHOT_KEEP_DAILY")? {
+            self.snapshots.keep_daily = count;
+        }
+        if let Some(count) = env("SNAPSHOT_KEEP_WEEKLY")? {
+            self.snapshots.keep_weekly = count;
+        }
+
+        if let Ok(enabled) = std::env::var("CLUSTER_ENABLED") {
+            self.cluster.enabled = enabled.eq_ignore_ascii_case("true") || enabled == "1";
+        }
+        if let Ok(node_id) = std::env::var("CLUSTER_NODE_ID") {
+            self.cluster.node_id = Some(node_id);
+        }
+        if let Ok(url) = std::env::var("CLUSTER_ADVERTISE_URL") {
+            self.cluster.advertise_url = Some(url);
+        }
+
+        if let Ok(path) = std::env::var("ACCESS_LOG_PATH") {
+            self.access_log.path = Some(PathBuf::from(path));
+        }
+        if let Some(bytes) = env("ACCESS_LOG_MAX_BYTES")? {
+            self.access_log.max_bytes = bytes;
+        }
+        if let Some(count) = env("ACCESS_LOG_MAX_FILES")? {
+            self.access_log.max_files = count;
+        }
+        if let Ok(proxies) = std::env::var("ACCESS_LOG_TRUSTED_PROXIES") {
+            self.access_log.trusted_proxies = proxies
+                .split(',')
+                .map(str::trim)
+                .filter(|proxy| !proxy.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        Ok(())
+    }
+
+    /// Override settings from the positional arguments `[port] [bundle-path] [storage-dir]`
+    fn apply_args(&mut self, args: &[String]) -> Result<()> {
+        if args.len() > 3 {
+            return Err(RelayError::Config(format!(
+                "Unexpected argument {}",
+                args[3]
+            )));
+        }
+        if let Some(port) = args.first() {
+            self.port = port
+                .parse()
+                .map_err(|e| RelayError::Config(format!("Invalid port '{}': {}", port, e)))?;
+        }
+        if let Some(bundle) = args.get(1) {
+            self.bundle = PathBuf::from(bundle);
+        }
+        if let Some(dir) = args.get(2) {
+            self.storage.dir = PathBuf::from(dir);
+        }
+        Ok(())
+    }
+
+    /// Check the settings are usable, naming the first offending field
+    pub fn validate(&self) -> Result<()> {
+        if self.bundle.as_os_str().is_empty() {
+            return Err(invalid(
+                "bundle",
+                "is required, in the config file or as the second argument",
+            ));
+        }
+        if !self.bundle.exists() {
+            return Err(invalid(
+                "bundle",
+                format!("file not found: {}", self.bundle.display()),
+            ));
+        }
+
+        if self.bind.is_empty() {
+            return Err(invalid("bind", "must list at least one address"));
+        }
+        if self.ws_port == Some(self.port) {
+            return Err(invalid("ws_port", "must differ from `port`"));
+        }
+        if self.grpc_port == Some(self.port)
+            || self.grpc_port.is_some_and(|p| Some(p) == self.ws_port)
+        {
+            return Err(invalid(
+                "grpc_port",
+                "must differ from `port` and `ws_port`",
+            ));
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log_level) {
+            return Err(invalid("log_level", e));
+        }
+
+        if self.storage.dir.as_os_str().is_empty() {
+            return Err(invalid("storage.dir", "must not be empty"));
+        }
+        if self.s3.bucket.as_deref().is_some_and(str::is_empty) {
+            return Err(invalid("s3.bucket", "must not be empty"));
+        }
+        if self.s3.bucket.is_none() && self.storage.backend == StorageBackend::S3 {
+            return Err(invalid(
+                "s3.bucket",
+                "is required with the `s3` storage backend",
+            ));
+        }
+        if self.s3.region.is_empty() {
+            return Err(invalid("s3.region", "must not be empty"));
+        }
+
+        if self.limits.max_frame_bytes == 0 {
+            return Err(invalid("limits.max_frame_bytes", "must be positive"));
+        }
+        if self.limits.max_message_bytes == 0 {
+            return Err(invalid("limits.max_message_bytes", "must be positive"));
+        }
+        if self.limits.tcp_keepalive_secs == Some(0) {
+            return Err(invalid("limits.tcp_keepalive_secs", "must be positive"));
+        }
+
+        if self.auth.admin_token.as_deref().is_some_and(str::is_empty) {
+            return Err(invalid("auth.admin_token", "must not be empty"));
+        }
+        if self.auth.sync_token.as_deref().is_some_and(str::is_empty) {
+            return Err(invalid("auth.sync_token", "must not be empty"));
+        }
+        if self.auth.share_secret.as_deref().is_some_and(str::is_empty) {
+            return Err(invalid("auth.share_secret", "must not be empty"));
+        }
+        if self.auth.share_secret.is_some() && self.s3.bucket.is_none() {
+            return Err(invalid(
+                "s3.bucket",
+                "is required when share links are enabled",
+            ));
+        }
+        if self.auth.share_secret.is_some() && self.auth.sync_token.is_none() {
+            return Err(invalid(
+                "auth.sync_token",
+                "is required when share links are enabled",
+            ));
+        }
+        self.auth.trusted_keys()?;
+
+        if self.snapshots.interval == Some(Duration::ZERO) {
+            return Err(invalid("snapshots.interval_secs", "must be positive"));
+        }
+
+        if self
+            .access_log
+            .path
+            .as_ref()
+            .is_some_and(|path| path.as_os_str().is_empty())
+        {
+            return Err(invalid("access_log.path", "must not be empty"));
+        }
+        self.access_log.trusted_proxies()?;
+
+        if self.cluster.enabled {
+            if self.s3.bucket.is_none() {
+                return Err(invalid(
+                    "s3.bucket",
+                    "is required when clustering is enabled",
+                ));
+            }
+            // A replica taking over a space must see the documents its last owner wrote
+            if self.storage.backend != StorageBackend::S3 {
+                return Err(invalid(
+                    "storage.backend",
+                    "must be `s3` when clustering is enabled",
+                ));
+            }
+            if self.cluster.advertise_url.is_none() {
+                return Err(invalid(
+                    "cluster.advertise_url",
+                    "is required when clustering is enabled",
+                ));
+            }
+            if self.cluster.node_id.as_deref().is_some_and(str::is_empty) {
+                return Err(invalid("cluster.node_id", "must not be empty"));
+            }
+            if self.cluster.heartbeat_secs == 0 {
+                return Err(invalid("cluster.heartbeat_secs", "must be positive"));
+            }
+            if self.cluster.lease_secs <= self.cluster.heartbeat_secs {
+                return Err(invalid(
+                    "cluster.lease_secs",
+                    "must be longer than `cluster.heartbeat_secs`",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clustered(dir: &Path) -> Config {
+        let bundle = dir.join("space.tonk");
+        std::fs::write(&bundle, b"").unwrap();
+        Config {
+            bundle,
+            s3: S3Config {
+                bucket: Some("bucket".to_string()),
+                ..S3Config::default()
+            },
+            storage: StorageConfig {
+                backend: StorageBackend::S3,
+                ..StorageConfig::default()
+            },
+            cluster: ClusterConfig {
+                enabled: true,
+                advertise_url: Some("http://10.0.0.1:8081".to_string()),
+                ..ClusterConfig::default()
+            },
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_clustering_requires_s3_document_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = clustered(dir.path());
+        config.validate().unwrap();
+
+        config.storage.backend = StorageBackend::Filesystem;
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("storage.backend"), "{}", error);
+    }
+
+    #[test]
+    fn test_storage_backend_from_str() {
+        assert_eq!("s3".parse(), Ok(StorageBackend::S3));
+        assert_eq!("S3".parse(), Ok(StorageBackend::S3));
+        assert_eq!("Filesystem".parse(), Ok(StorageBackend::Filesystem));
+        assert!("s4".parse::<StorageBackend>().is_err());
+        assert!("".parse::<StorageBackend>().is_err());
+    }
+}
@@ -29,6 +29,15 @@ pub enum RelayError {
     #[error("Not found: {0}")]
     NotFound(String),
 
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
     #[error("{0}")]
     Other(String),
 }
@@ -0,0 +1,264 @@
+use crate::error::{RelayError, Result};
+use crate::server::token_matches;
+use automerge::{AutoSerde, Automerge};
+use futures::{Stream, StreamExt};
+use samod::{DocHandle, DocumentId, Repo};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::service::Interceptor;
+use tonic::{Request, Response, Status};
+use tonk_core::vfs::backend::AutomergeHelpers;
+use tonk_core::VirtualFileSystem;
+
+/// Types and service traits generated from `proto/relay.proto`
+pub mod proto {
+    tonic::include_proto!("tonk.relay.v1");
+}
+
+use proto::relay_server::{Relay, RelayServer as RelayGrpcServer};
+use proto::write_document_request::Change;
+use proto::{
+    Document, ExportBundleRequest, ExportBundleResponse, ReadDocumentRequest,
+    SubscribeDocumentRequest, WriteDocumentRequest, WriteDocumentResponse,
+};
+
+/// gRPC service giving backend integrations typed access to the relay's documents
+pub struct RelayService {
+    repo: Arc<Repo>,
+}
+
+impl RelayService {
+    pub fn new(repo: Arc<Repo>) -> Self {
+        Self { repo }
+    }
+
+    async fn find(&self, document_id: &str) -> std::result::Result<DocHandle, Status> {
+        let id = document_id
+            .parse::<DocumentId>()
+            .map_err(|e| Status::invalid_argument(format!("Invalid document ID: {}", e)))?;
+
+        self.repo
+            .find(id)
+            .await
+            .map_err(|_| Status::unavailable("Relay is shutting down"))?
+            .ok_or_else(|| Status::not_found(format!("Document not found: {}", document_id)))
+    }
+}
+
+/// Hex-encoded heads of a document
+fn heads(doc: &Automerge) -> Vec<String> {
+    doc.get_heads().iter().map(|h| h.to_string()).collect()
+}
+
+/// Read the current state of a document
+#[allow(clippy::result_large_err)]
+fn snapshot(handle: &DocHandle) -> std::result::Result<Document, Status> {
+    handle.with_document(|doc| {
+        let json = serde_json::to_string(&AutoSerde::from(&*doc))
+            .map_err(|e| Status::internal(format!("Failed to render document: {}", e)))?;
+
+        Ok(Document {
+            document_id: handle.document_id().to_string(),
+            heads: heads(doc),
+            json,
+        })
+    })
+}
+
+type DocumentStream = Pin<Box<dyn Stream<Item = std::result::Result<Document, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Relay for RelayService {
+    async fn read_document(
+        &self,
+        request: Request<ReadDocumentRequest>,
+    ) -> std::result::Result<Response<Document>, Status> {
+        let handle = self.find(&request.into_inner().document_id).await?;
+        Ok(Response::new(snapshot(&handle)?))
+    }
+
+    async fn write_document(
+        &self,
+        request: Request<WriteDocumentRequest>,
+    ) -> std::result::Result<Response<WriteDocumentResponse>, Status> {
+        let request = request.into_inner();
+        let handle = self.find(&request.document_id).await?;
+
+        let changed = match request.change {
+            Some(Change::AutomergeChanges(changes)) => handle.with_document(|doc| {
+                let before = doc.get_heads();
+                doc.load_incremental(&changes)
+                    .map_err(|e| Status::invalid_argument(format!("Invalid changes: {}", e)))?;
+                Ok::<_, Status>(doc.get_heads() != before)
+            })?,
+            Some(Change::MergePatch(merge_patch)) => {
+                let patch: serde_json::Value = serde_json::from_str(&merge_patch.patch)
+                    .map_err(|e| Status::invalid_argument(format!("Invalid patch: {}", e)))?;
+                AutomergeHelpers::merge_patch_document(&handle, &merge_patch.path, &patch)
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?
+            }
+            None => return Err(Status::invalid_argument("No change given")),
+        };
+
+        Ok(Response::new(WriteDocumentResponse {
+            changed,
+            heads: handle.with_document(|doc| heads(doc)),
+        }))
+    }
+
+    type SubscribeDocumentStream = DocumentStream;
+
+    async fn subscribe_document(
+        &self,
+        request: Request<SubscribeDocumentRequest>,
+    ) -> std::result::Result<Response<Self::SubscribeDocumentStream>, Status> {
+        let handle = self.find(&request.into_inner().document_id).await?;
+
+        let initial = snapshot(&handle);
+        let updates = handle.changes().map(move |_| snapshot(&handle));
+        let stream = futures::stream::once(async move { initial }).chain(updates);
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn export_bundle(
+        &self,
+        request: Request<ExportBundleRequest>,
+    ) -> std::result::Result<Response<ExportBundleResponse>, Status> {
+        let root_id = request.into_inner().root_id;
+        let handle = self.find(&root_id).await?;
+
+        let vfs =
+            VirtualFileSystem::from_root_id(Arc::clone(&self.repo), handle.document_id().clone())
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+        let bundle = vfs
+            .to_bytes(None)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to export bundle: {}", e)))?;
+
+        Ok(Response::new(ExportBundleResponse { bundle }))
+    }
+}
+
+/// Metadata key a share link token would be presented under
+const SHARE_METADATA: &str = "share";
+
+/// Requires calls to present the relay's sync token, when it has one
+///
+/// The token is checked as a bearer token in the `authorization` metadata,
+/// the same way sync connections present it. Share links only grant read
+/// access to parts of a space, which the RPCs can't be limited to, so calls
+/// presenting one are refused.
+#[derive(Debug, Clone)]
+pub struct SyncTokenAuth {
+    sync_token: Option<String>,
+}
+
+impl SyncTokenAuth {
+    /// Check calls against `sync_token`, failing if clients couldn't present it
+    /// in metadata, as the interface would then be unusable rather than protected
+    pub fn new(sync_token: Option<String>) -> Result<Self> {
+        if let Some(token) = &sync_token {
+            AsciiMetadataValue::try_from(format!("Bearer {}", token)).map_err(|_| {
+                RelayError::Config(
+                    "`auth.sync_token` must be printable ASCII to serve gRPC".to_string(),
+                )
+            })?;
+        }
+        Ok(Self { sync_token })
+    }
+}
+
+impl Interceptor for SyncTokenAuth {
+    fn call(&mut self, request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        if request.metadata().contains_key(SHARE_METADATA) {
+            return Err(Status::permission_denied(
+                "Share links can't be used over gRPC",
+            ));
+        }
+        let Some(expected) = self.sync_token.as_deref() else {
+            return Ok(request);
+        };
+        let presented = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if !token_matches(presented, expected) {
+            return Err(Status::unauthenticated("Invalid sync token"));
+        }
+        Ok(request)
+    }
+}
+
+/// Serve the gRPC interface on its own port until the server fails
+pub async fn serve(repo: Arc<Repo>, addr: SocketAddr, auth: SyncTokenAuth) -> Result<()> {
+    tracing::info!("gRPC server listening on {}", addr);
+
+    tonic::transport::Server::builder()
+        .add_service(RelayGrpcServer::with_interceptor(
+            RelayService::new(repo),
+            auth,
+        ))
+        .serve(addr)
+        .await
+        .map_err(|e| RelayError::Other(format!("gRPC server error: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(
+        auth: &SyncTokenAuth,
+        metadata: &[(&'static str, &str)],
+    ) -> std::result::Result<(), Status> {
+        let mut request = Request::new(());
+        for (key, value) in metadata {
+            request.metadata_mut().insert(*key, value.parse().unwrap());
+        }
+        auth.clone().call(request).map(|_| ())
+    }
+
+    #[test]
+    fn test_calls_without_the_sync_token_are_rejected() {
+        let auth = SyncTokenAuth::new(Some("secret".to_string())).unwrap();
+
+        let missing = call(&auth, &[]).unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::Unauthenticated);
+        let wrong = call(&auth, &[("authorization", "Bearer guess")]).unwrap_err();
+        assert_eq!(wrong.code(), tonic::Code::Unauthenticated);
+        let unprefixed = call(&auth, &[("authorization", "secret")]).unwrap_err();
+        assert_eq!(unprefixed.code(), tonic::Code::Unauthenticated);
+
+        assert!(call(&auth, &[("authorization", "Bearer secret")]).is_ok());
+    }
+
+    #[test]
+    fn test_share_links_are_rejected() {
+        let open = SyncTokenAuth::new(None).unwrap();
+        assert!(call(&open, &[]).is_ok());
+        let share = call(&open, &[(SHARE_METADATA, "token")]).unwrap_err();
+        assert_eq!(share.code(), tonic::Code::PermissionDenied);
+
+        let auth = SyncTokenAuth::new(Some("secret".to_string())).unwrap();
+        let share = call(
+            &auth,
+            &[
+                ("authorization", "Bearer secret"),
+                (SHARE_METADATA, "token"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(share.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[test]
+    fn test_tokens_that_cant_be_presented_are_refused() {
+        assert!(SyncTokenAuth::new(Some("line\nbreak".to_string())).is_err());
+        assert!(SyncTokenAuth::new(Some("café".to_string())).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use crate::server::AppState;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Time a single readiness check may take before it counts as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// File written and removed in the storage directory to check it is writable
+const PROBE_FILE: &str = ".readyz-probe";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Fail,
+    /// The checked dependency isn't configured
+    Skipped,
+}
+
+/// Outcome of a single readiness check
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckResult {
+    pub status: CheckStatus,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of all readiness checks
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub status: CheckStatus,
+    pub checks: BTreeMap<&'static str, CheckResult>,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.status == CheckStatus::Ok
+    }
+}
+
+/// Check that the relay can serve the hosted space
+///
+/// - `bundle`: the repo has the bundle's root document
+/// - `storage`: the document storage directory is writable
+/// - `s3`: the S3 bucket is reachable with the configured credentials; skipped
+///   when no bucket is configured
+pub async fn readiness(state: &AppState) -> Readiness {
+    let (bundle, storage, s3) = tokio::join!(
+        run_check(check_bundle(state)),
+        run_check(check_storage(state)),
+        async {
+            match &state.s3_storage {
+                Some(s3_storage) => run_check(s3_storage.check_access()).await,
+                None => CheckResult {
+                    status: CheckStatus::Skipped,
+                    duration_ms: 0,
+                    error: None,
+                },
+            }
+        },
+    );
+
+    let checks = BTreeMap::from([("bundle", bundle), ("storage", storage), ("s3", s3)]);
+    let status = if checks.values().any(|c| c.status == CheckStatus::Fail) {
+        CheckStatus::Fail
+    } else {
+        CheckStatus::Ok
+    };
+
+    Readiness { status, checks }
+}
+
+async fn run_check<F, E>(check: F) -> CheckResult
+where
+    F: Future<Output = Result<(), E>>,
+    E: std::fmt::Display,
+{
+    let start = Instant::now();
+    let error = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("Timed out after {:?}", CHECK_TIMEOUT)),
+    };
+
+    CheckResult {
+        status: if error.is_none() {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Fail
+        },
+        duration_ms: start.elapsed().as_millis() as u64,
+        error,
+    }
+}
+
+async fn check_bundle(state: &AppState) -> Result<(), String> {
+    let root_id = state.bundle_storage.root_id().await;
+    let doc_id = root_id
+        .parse()
+        .map_err(|e| format!("Invalid root ID {}: {}", root_id, e))?;
+
+    match state.repo.find(doc_id).await {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(format!("Root document {} not found", root_id)),
+        Err(e) => Err(format!("Repo stopped: {}", e)),
+    }
+}
+
+async fn check_storage(state: &AppState) -> std::io::Result<()> {
+    // Samod creates the directory on first write, so a fresh relay may not have it yet
+    tokio::fs::create_dir_all(&state.storage_dir).await?;
+    let probe = state.storage_dir.join(PROBE_FILE);
+    tokio::fs::write(&probe, b"ok").await?;
+    tokio::fs::remove_file(&probe).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, StorageConfig};
+    use crate::server::RelayServer;
+    use std::sync::Arc;
+    use tonk_core::TonkCore;
+
+    #[tokio::test]
+    async fn test_readiness_skips_s3_without_a_bucket() {
+        let tonk = TonkCore::new().await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("space.tonk");
+        std::fs::write(&bundle, tonk.to_bytes(None).await.unwrap()).unwrap();
+        let config = Config {
+            bundle,
+            storage: StorageConfig {
+                dir: dir.path().join("data"),
+                ..StorageConfig::default()
+            },
+            ..Config::default()
+        };
+        config.validate().unwrap();
+
+        let server = RelayServer::create(tonk.samod(), &config, Arc::default())
+            .await
+            .unwrap();
+        assert!(server.state.s3_storage.is_none());
+
+        let readiness = readiness(&server.state).await;
+        assert_eq!(readiness.checks["s3"].status, CheckStatus::Skipped);
+        assert_eq!(readiness.checks["bundle"].status, CheckStatus::Ok);
+        assert_eq!(readiness.checks["storage"].status, CheckStatus::Ok);
+        assert!(readiness.is_ready());
+    }
+}
@@ -0,0 +1,435 @@
+use crate::cluster::{moved_frame, Lost};
+use axum::extract::ws::{Message, WebSocket};
+use futures::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tonk_core::lease::{LeaseReply, LeaseRequest, MAX_LEASE_TTL};
+use uuid::Uuid;
+
+/// Maximum size of a lease message in bytes
+const MAX_MESSAGE_BYTES: usize = 16 * 1024;
+
+/// Maximum number of leases one connection may hold at once
+const MAX_LEASES_PER_HOLDER: usize = 256;
+
+/// Messages queued for a holder before further ones are dropped
+const HOLDER_QUEUE: usize = 64;
+
+struct Held {
+    holder: Uuid,
+    expires_at: Instant,
+    /// Queue of the holder's connection, for telling it the lease was lost
+    notify: mpsc::Sender<String>,
+}
+
+/// Arbitrates advisory leases on paths within a space
+///
+/// Clients open a WebSocket to `/lease/{space_id}` and send [`LeaseRequest`]s
+/// as JSON, each answered with a [`LeaseReply`] carrying the request's `id`.
+/// Only one holder has the lease on a path at a time, so a connection that
+/// already holds a path is denied it too, like any other. Leases live only
+/// in memory and end when released, when their holder disconnects, or when
+/// they expire unrenewed, in which case the holder is sent `lost`. Nothing is
+/// written to the space's documents.
+#[derive(Default)]
+pub struct LeaseHub {
+    /// Leases by space, then path
+    leases: Mutex<HashMap<String, HashMap<String, Held>>>,
+}
+
+fn encode(reply: &LeaseReply) -> String {
+    serde_json::to_string(reply).unwrap_or_default()
+}
+
+impl LeaseHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of leases currently held across all spaces
+    pub fn lease_count(&self) -> usize {
+        self.leases.lock().unwrap().values().map(HashMap::len).sum()
+    }
+
+    /// Answer one request from `holder`
+    fn respond(
+        &self,
+        space: &str,
+        holder: Uuid,
+        notify: &mpsc::Sender<String>,
+        text: &str,
+    ) -> LeaseReply {
+        if text.len() > MAX_MESSAGE_BYTES {
+            return LeaseReply::Error {
+                id: None,
+                message: format!("Message exceeds {} bytes", MAX_MESSAGE_BYTES),
+            };
+        }
+        let request: LeaseRequest = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => {
+                return LeaseReply::Error {
+                    id: None,
+                    message: format!("Invalid request: {}", e),
+                }
+            }
+        };
+
+        if let LeaseRequest::Acquire { id, ttl_ms: 0, .. }
+        | LeaseRequest::Renew { id, ttl_ms: 0, .. } = request
+        {
+            return LeaseReply::Error {
+                id: Some(id),
+                message: "Lease TTL must be positive".to_string(),
+            };
+        }
+
+        let now = Instant::now();
+        let mut spaces = self.leases.lock().unwrap();
+        let leases = spaces.entry(space.to_string()).or_default();
+
+        let reply = match request {
+            LeaseRequest::Acquire { id, path, ttl_ms } => {
+                match leases.get(&path) {
+                    Some(held) if held.expires_at > now => {
+                        return LeaseReply::Denied {
+                            id,
+                            expires_in_ms: (held.expires_at - now).as_millis() as u64,
+                            path,
+                        };
+                    }
+                    Some(held) if held.holder != holder => {
+                        // Expired but not yet swept; its holder hears it is lost
+                        let lost = LeaseReply::Lost { path: path.clone() };
+                        let _ = held.notify.try_send(encode(&lost));
+                    }
+                    _ => {}
+                }
+
+                let already_held = leases.get(&path).is_some_and(|held| held.holder == holder);
+                let count = leases.values().filter(|held| held.holder == holder).count();
+                if !already_held && count >= MAX_LEASES_PER_HOLDER {
+                    return LeaseReply::Error {
+                        id: Some(id),
+                        message: format!("Connection holds {} leases already", count),
+                    };
+                }
+
+                let ttl = Duration::from_millis(ttl_ms).min(MAX_LEASE_TTL);
+                leases.insert(
+                    path.clone(),
+                    Held {
+                        holder,
+                        expires_at: now + ttl,
+                        notify: notify.clone(),
+                    },
+                );
+                LeaseReply::Granted {
+                    id,
+                    path,
+                    ttl_ms: ttl.as_millis() as u64,
+                }
+            }
+            LeaseRequest::Renew { id, path, ttl_ms } => match leases.get_mut(&path) {
+                Some(held) if held.holder == holder => {
+                    let ttl = Duration::from_millis(ttl_ms).min(MAX_LEASE_TTL);
+                    held.expires_at = now + ttl;
+                    LeaseReply::Granted {
+                        id,
+                        path,
+                        ttl_ms: ttl.as_millis() as u64,
+                    }
+                }
+                Some(held) if held.expires_at > now => LeaseReply::Denied {
+                    id,
+                    expires_in_ms: (held.expires_at - now).as_millis() as u64,
+                    path,
+                },
+                _ => LeaseReply::Error {
+                    id: Some(id),
+                    message: format!("No lease held on {}", path),
+                },
+            },
+            LeaseRequest::Release { id, path } => {
+                if leases.get(&path).is_some_and(|held| held.holder == holder) {
+                    leases.remove(&path);
+                }
+                LeaseReply::Released { id, path }
+            }
+        };
+
+        if leases.is_empty() {
+            spaces.remove(space);
+        }
+        reply
+    }
+
+    /// When the next lease held by `holder` expires
+    fn next_expiry(&self, space: &str, holder: Uuid) -> Option<Instant> {
+        let spaces = self.leases.lock().unwrap();
+        spaces
+            .get(space)?
+            .values()
+            .filter(|held| held.holder == holder)
+            .map(|held| held.expires_at)
+            .min()
+    }
+
+    /// Drop the expired leases of `holder`, returning their paths
+    fn expire(&self, space: &str, holder: Uuid) -> Vec<String> {
+        let now = Instant::now();
+        self.remove_where(space, |held| {
+            held.holder == holder && held.expires_at <= now
+        })
+    }
+
+    /// Drop every lease of `holder`
+    fn release_all(&self, space: &str, holder: Uuid) {
+        self.remove_where(space, |held| held.holder == holder);
+    }
+
+    fn remove_where(&self, space: &str, remove: impl Fn(&Held) -> bool) -> Vec<String> {
+        let mut spaces = self.leases.lock().unwrap();
+        let Some(leases) = spaces.get_mut(space) else {
+            return Vec::new();
+        };
+
+        let paths: Vec<String> = leases
+            .iter()
+            .filter(|(_, held)| remove(held))
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &paths {
+            leases.remove(path);
+        }
+        if leases.is_empty() {
+            spaces.remove(space);
+        }
+        paths
+    }
+
+    /// Serve a client's lease connection until it disconnects
+    ///
+    /// The connection is closed early if `lost` resolves, once another replica
+    /// takes over the space.
+    pub async fn handle(&self, socket: WebSocket, space: String, lost: Option<Lost>) {
+        let (mut sink, mut stream) = socket.split();
+        let (tx, mut rx) = mpsc::channel::<String>(HOLDER_QUEUE);
+        let holder = Uuid::new_v4();
+        tracing::debug!("Lease client {} connected to space {}", holder, space);
+
+        let send = async {
+            while let Some(text) = rx.recv().await {
+                if sink.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        let receive = async {
+            loop {
+                let next_expiry = self.next_expiry(&space, holder);
+                let expired = async {
+                    match next_expiry {
+                        Some(at) => tokio::time::sleep_until(at).await,
+                        None => std::future::pending().await,
+                    }
+                };
+
+                tokio::select! {
+                    message = stream.next() => {
+                        let text = match message {
+                            Some(Ok(Message::Text(text))) => text,
+                            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                            Some(Ok(_)) => continue,
+                        };
+                        let reply = self.respond(&space, holder, &tx, &text);
+                        let _ = tx.try_send(encode(&reply));
+                    }
+                    _ = expired => {
+                        for path in self.expire(&space, holder) {
+                            let _ = tx.try_send(encode(&LeaseReply::Lost { path }));
+                        }
+                    }
+                }
+            }
+        };
+
+        let lost = async {
+            match lost {
+                Some(lost) => lost.await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let moved = tokio::select! {
+            _ = send => false,
+            _ = receive => false,
+            _ = lost => true,
+        };
+        if moved {
+            let _ = sink.send(Message::Close(Some(moved_frame()))).await;
+        }
+
+        self.release_all(&space, holder);
+        tracing::debug!("Lease client {} left space {}", holder, space);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACE: &str = "space";
+
+    /// A holder with its own queue, as each lease connection has
+    struct Holder {
+        id: Uuid,
+        notify: mpsc::Sender<String>,
+        queue: mpsc::Receiver<String>,
+    }
+
+    impl Holder {
+        fn new() -> Self {
+            let (notify, queue) = mpsc::channel(HOLDER_QUEUE);
+            Self {
+                id: Uuid::new_v4(),
+                notify,
+                queue,
+            }
+        }
+
+        fn send(&self, hub: &LeaseHub, request: LeaseRequest) -> LeaseReply {
+            hub.respond(
+                SPACE,
+                self.id,
+                &self.notify,
+                &serde_json::to_string(&request).unwrap(),
+            )
+        }
+
+        fn acquire(&self, hub: &LeaseHub, path: &str, ttl_ms: u64) -> LeaseReply {
+            self.send(
+                hub,
+                LeaseRequest::Acquire {
+                    id: 1,
+                    path: path.to_string(),
+                    ttl_ms,
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn test_one_holder_per_path() {
+        let hub = LeaseHub::new();
+        let (a, b) = (Holder::new(), Holder::new());
+
+        assert!(matches!(
+            a.acquire(&hub, "/job", 30_000),
+            LeaseReply::Granted { ttl_ms: 30_000, .. }
+        ));
+        assert!(matches!(
+            b.acquire(&hub, "/job", 30_000),
+            LeaseReply::Denied { .. }
+        ));
+        // A second acquire on the same connection doesn't share the lease either
+        assert!(matches!(
+            a.acquire(&hub, "/job", 30_000),
+            LeaseReply::Denied { .. }
+        ));
+        assert!(matches!(
+            b.acquire(&hub, "/other", 30_000),
+            LeaseReply::Granted { .. }
+        ));
+        assert_eq!(hub.lease_count(), 2);
+
+        // Only the holder can renew or release
+        let renew = |holder: &Holder| {
+            holder.send(
+                &hub,
+                LeaseRequest::Renew {
+                    id: 2,
+                    path: "/job".to_string(),
+                    ttl_ms: 1_000_000,
+                },
+            )
+        };
+        assert!(matches!(renew(&b), LeaseReply::Denied { .. }));
+        assert!(matches!(
+            renew(&a),
+            LeaseReply::Granted { ttl_ms, .. } if ttl_ms == MAX_LEASE_TTL.as_millis() as u64
+        ));
+        let release = LeaseRequest::Release {
+            id: 3,
+            path: "/job".to_string(),
+        };
+        b.send(&hub, release.clone());
+        assert_eq!(hub.lease_count(), 2);
+        a.send(&hub, release);
+        assert!(matches!(
+            b.acquire(&hub, "/job", 30_000),
+            LeaseReply::Granted { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_expired_lease_is_taken_over() {
+        let hub = LeaseHub::new();
+        let (mut a, b) = (Holder::new(), Holder::new());
+        a.acquire(&hub, "/job", 50);
+        assert!(hub.next_expiry(SPACE, a.id).is_some());
+        assert!(hub.next_expiry(SPACE, b.id).is_none());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(matches!(
+            b.acquire(&hub, "/job", 50),
+            LeaseReply::Granted { .. }
+        ));
+        let lost: LeaseReply = serde_json::from_str(&a.queue.try_recv().unwrap()).unwrap();
+        assert_eq!(
+            lost,
+            LeaseReply::Lost {
+                path: "/job".to_string()
+            }
+        );
+        assert!(hub.expire(SPACE, a.id).is_empty());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(hub.expire(SPACE, b.id), vec!["/job".to_string()]);
+        assert_eq!(hub.lease_count(), 0);
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let hub = LeaseHub::new();
+        let a = Holder::new();
+        assert!(matches!(
+            a.acquire(&hub, "/job", 0),
+            LeaseReply::Error { id: Some(1), .. }
+        ));
+        assert!(matches!(
+            hub.respond(SPACE, a.id, &a.notify, "not json"),
+            LeaseReply::Error { id: None, .. }
+        ));
+        let oversized = "x".repeat(MAX_MESSAGE_BYTES + 1);
+        assert!(matches!(
+            hub.respond(SPACE, a.id, &a.notify, &oversized),
+            LeaseReply::Error { id: None, .. }
+        ));
+
+        for n in 0..MAX_LEASES_PER_HOLDER {
+            a.acquire(&hub, &format!("/{}", n), 30_000);
+        }
+        assert!(matches!(
+            a.acquire(&hub, "/one-more", 30_000),
+            LeaseReply::Error { .. }
+        ));
+
+        hub.release_all(SPACE, a.id);
+        assert_eq!(hub.lease_count(), 0);
+    }
+}
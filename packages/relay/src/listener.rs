@@ -0,0 +1,81 @@
+use crate::config::Config;
+use crate::error::Result;
+use socket2::{Domain, Protocol, SockAddr, Socket, TcpKeepalive, Type};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// Addresses and socket options the relay listens with
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    /// Hosts to bind, e.g. `0.0.0.0` and `::` for dual-stack
+    pub hosts: Vec<IpAddr>,
+    /// Port serving HTTP, and WebSocket unless `ws_port` is set
+    pub http_port: u16,
+    /// Separate port serving only WebSocket sync connections
+    pub ws_port: Option<u16>,
+    /// Idle time before TCP keepalive probes are sent on connections
+    pub keepalive: Option<Duration>,
+    /// Set `SO_REUSEPORT` so several relay processes can share a port
+    pub reuse_port: bool,
+}
+
+impl ListenerConfig {
+    /// Take the addresses and socket options from the relay configuration
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            hosts: config.bind.clone(),
+            http_port: config.port,
+            ws_port: config.ws_port,
+            keepalive: config.limits.tcp_keepalive_secs.map(Duration::from_secs),
+            reuse_port: config.reuse_port,
+        }
+    }
+
+    /// Addresses serving HTTP
+    pub fn http_addrs(&self) -> Vec<SocketAddr> {
+        self.addrs(self.http_port)
+    }
+
+    /// Addresses serving only WebSocket connections, if a separate port is configured
+    pub fn ws_addrs(&self) -> Vec<SocketAddr> {
+        self.ws_port
+            .map(|port| self.addrs(port))
+            .unwrap_or_default()
+    }
+
+    fn addrs(&self, port: u16) -> Vec<SocketAddr> {
+        self.hosts
+            .iter()
+            .map(|host| SocketAddr::new(*host, port))
+            .collect()
+    }
+
+    /// Bind a listener on `addr` with the configured socket options
+    ///
+    /// IPv6 sockets are bound IPv6-only so `::` and `0.0.0.0` can be bound side
+    /// by side on the same port. Accepted connections inherit the keepalive setting.
+    pub fn bind(&self, addr: SocketAddr) -> Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+        if addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        socket.set_reuse_address(true)?;
+        if self.reuse_port {
+            #[cfg(unix)]
+            socket.set_reuse_port(true)?;
+            #[cfg(not(unix))]
+            tracing::warn!("SO_REUSEPORT is not supported on this platform, ignoring");
+        }
+        if let Some(keepalive) = self.keepalive {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(keepalive))?;
+        }
+
+        socket.set_nonblocking(true)?;
+        socket.bind(&SockAddr::from(addr))?;
+        socket.listen(1024)?;
+
+        Ok(TcpListener::from_std(socket.into())?)
+    }
+}
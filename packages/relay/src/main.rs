@@ -1,91 +1,102 @@
+mod access_log;
+mod cluster;
+mod config;
 mod error;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod health;
+mod leases;
+mod listener;
 mod network;
 mod server;
+mod share;
+mod signaling;
+mod site;
+mod snapshot;
 mod storage;
 
+use config::{Config, StorageBackend};
 use error::Result;
+use listener::ListenerConfig;
 use samod::storage::TokioFilesystemStorage;
 use samod::RepoBuilder;
 use server::RelayServer;
+#[cfg(feature = "grpc")]
 use std::net::SocketAddr;
-use std::path::PathBuf;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
+use storage::{S3DocumentStorage, S3Storage};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let config = Config::from_args(std::env::args())?;
+
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(&config.log_level)),
         )
         .init();
 
-    let args: Vec<String> = std::env::args().collect();
-
-    let port = args
-        .get(1)
-        .and_then(|s: &String| s.parse::<u16>().ok())
-        .unwrap_or(8081);
-
-    let bundle_path: PathBuf = args
-        .get(2)
-        .map(PathBuf::from)
-        .ok_or_else(|| error::RelayError::Other("Bundle path is required".to_string()))?;
-
-    let storage_dir: PathBuf = args
-        .get(3)
-        .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from("automerge-repo-data"));
-
-    if !bundle_path.exists() {
-        return Err(error::RelayError::NotFound(format!(
-            "Bundle file not found: {}",
-            bundle_path.display()
-        )));
-    }
-
     tracing::info!("Starting Tonk Relay Server");
-    tracing::info!("Port: {}", port);
-    tracing::info!("Bundle: {}", bundle_path.display());
-    tracing::info!("Storage: {}", storage_dir.display());
-
-    let s3_config = (
-        std::env::var("S3_BUCKET_NAME").unwrap_or_else(|_| "host-web-bundle-storage".to_string()),
-        (std::env::var("AWS_REGION").unwrap_or_else(|_| "eu-north-1".to_string())),
-    );
-
-    let filesystem_storage = TokioFilesystemStorage::new(storage_dir.clone());
+    tracing::info!("Port: {}", config.port);
+    tracing::info!("Bundle: {}", config.bundle.display());
+    tracing::info!("Storage: {}", config.storage.dir.display());
 
     let runtime = tokio::runtime::Handle::current();
-    let repo = RepoBuilder::new(runtime)
-        .with_storage(filesystem_storage)
-        .load()
-        .await;
+    let repo = match (config.storage.backend, &config.s3.bucket) {
+        (StorageBackend::S3, Some(bucket)) => {
+            tracing::info!(
+                "Document storage: s3://{}, cached in {}",
+                bucket,
+                config.storage.dir.display()
+            );
+            let s3_storage = S3Storage::new(bucket.clone(), config.s3.region.clone()).await?;
+            RepoBuilder::new(runtime)
+                .with_storage(S3DocumentStorage::new(
+                    s3_storage,
+                    config.storage.dir.clone(),
+                ))
+                .load()
+                .await
+        }
+        // Config validation rejects the S3 backend without a bucket
+        _ => {
+            let filesystem_storage = TokioFilesystemStorage::new(config.storage.dir.clone());
+            RepoBuilder::new(runtime)
+                .with_storage(filesystem_storage)
+                .load()
+                .await
+        }
+    };
 
     let repo = Arc::new(repo);
 
     let connection_count = Arc::new(AtomicUsize::new(0));
 
-    let server_addr: SocketAddr = format!(
-        "{}:{}",
-        std::env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
-        port
-    )
-    .parse()
-    .expect("Invalid server address");
+    let listener_config = ListenerConfig::from_config(&config);
+
+    #[cfg(feature = "grpc")]
+    let grpc_handle = match config.grpc_port {
+        Some(grpc_port) => {
+            let grpc_addr = SocketAddr::new(listener_config.hosts[0], grpc_port);
+            let grpc_repo = Arc::clone(&repo);
+            let grpc_auth = grpc::SyncTokenAuth::new(config.auth.sync_token.clone())?;
+            Some(tokio::spawn(async move {
+                if let Err(e) = grpc::serve(grpc_repo, grpc_addr, grpc_auth).await {
+                    tracing::error!("gRPC server error: {}", e);
+                }
+            }))
+        }
+        None => None,
+    };
 
-    let relay_server: RelayServer = RelayServer::create(
-        Arc::clone(&repo),
-        bundle_path.clone(),
-        bundle_path.clone(),
-        s3_config,
-        Arc::clone(&connection_count),
-    )
-    .await?;
+    let relay_server: RelayServer =
+        RelayServer::create(Arc::clone(&repo), &config, Arc::clone(&connection_count)).await?;
 
+    let state = Arc::clone(&relay_server.state);
     let server_handle = tokio::spawn(async move {
-        if let Err(e) = relay_server.run(server_addr).await {
+        if let Err(e) = relay_server.run(listener_config).await {
             tracing::error!("Server error: {}", e);
         }
     });
@@ -94,6 +105,13 @@ async fn main() -> Result<()> {
     tracing::info!("Shutting down gracefully...");
 
     server_handle.abort();
+    if let Some(cluster) = &state.cluster {
+        cluster.leave().await;
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_handle) = grpc_handle {
+        grpc_handle.abort();
+    }
 
     Ok(())
 }
@@ -1,3 +1,12 @@
+pub mod clients;
+pub mod metered;
+pub mod proxy;
+pub mod read_only;
+pub mod reaper;
 pub mod websocket_server;
 
+pub use clients::ConnectedClients;
+pub use metered::MeteredSocket;
+pub use read_only::ReadOnlySocket;
+pub use reaper::IdleReaper;
 pub use websocket_server::handle_websocket_connection;
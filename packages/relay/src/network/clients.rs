@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tonk_core::PeerInfo;
+
+/// Key for connections whose client didn't say what it runs
+const UNKNOWN_CLIENT: &str = "unknown";
+
+/// Key for connections from clients other than [`KNOWN_CLIENTS`]
+const OTHER_CLIENT: &str = "other";
+
+/// Clients counted under their own name and version; any other name counts as `other`
+const KNOWN_CLIENTS: [&str; 2] = ["tonk-core", "tonk-relay"];
+
+/// Longest name, version, platform or feature kept from what a client reports
+const MAX_FIELD_LEN: usize = 64;
+
+/// Most features kept from what a client reports
+const MAX_FEATURES: usize = 32;
+
+/// Keep only what a client reported that fits in logs and metrics
+///
+/// Fields longer than [`MAX_FIELD_LEN`] or with characters other than ASCII
+/// letters, digits, `.`, `_`, `+` and `-` are dropped. A client whose name is
+/// dropped counts as one that didn't say what it runs.
+pub(crate) fn sanitize(client: PeerInfo) -> Option<PeerInfo> {
+    let valid = |field: &str| {
+        field.len() <= MAX_FIELD_LEN
+            && field
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"._+-".contains(&b))
+    };
+    let keep = |field: String| if valid(&field) { field } else { String::new() };
+
+    if !valid(&client.name) {
+        return None;
+    }
+    Some(PeerInfo {
+        name: client.name,
+        version: keep(client.version),
+        platform: keep(client.platform),
+        features: client
+            .features
+            .into_iter()
+            .filter(|feature| valid(feature))
+            .take(MAX_FEATURES)
+            .collect(),
+    })
+}
+
+/// Open sync connections, counted by the client name and version they reported
+#[derive(Debug, Default)]
+pub struct ConnectedClients {
+    counts: Mutex<BTreeMap<String, usize>>,
+}
+
+impl ConnectedClients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open connections keyed by `name/version`, `other` or `unknown`
+    pub fn counts(&self) -> BTreeMap<String, usize> {
+        self.counts.lock().unwrap().clone()
+    }
+
+    pub(crate) fn opened(&self, client: Option<&PeerInfo>) {
+        *self.counts.lock().unwrap().entry(key(client)).or_default() += 1;
+    }
+
+    pub(crate) fn closed(&self, client: Option<&PeerInfo>) {
+        let mut counts = self.counts.lock().unwrap();
+        let key = key(client);
+        if let Some(count) = counts.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&key);
+            }
+        }
+    }
+}
+
+fn key(client: Option<&PeerInfo>) -> String {
+    match client {
+        None => UNKNOWN_CLIENT.to_string(),
+        Some(client) if !KNOWN_CLIENTS.contains(&client.name.as_str()) => OTHER_CLIENT.to_string(),
+        Some(client) => format!("{}/{}", client.name, client.version),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(name: &str, version: &str) -> PeerInfo {
+        PeerInfo {
+            name: name.to_string(),
+            version: version.to_string(),
+            platform: "native".to_string(),
+            features: vec!["mounts".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_sanitize_drops_oversized_and_unexpected_fields() {
+        assert_eq!(
+            sanitize(client("tonk-core", "0.1.0")),
+            Some(client("tonk-core", "0.1.0"))
+        );
+        assert_eq!(sanitize(client("tonk core", "0.1.0")), None);
+        assert_eq!(sanitize(client(&"a".repeat(MAX_FIELD_LEN + 1), "1")), None);
+
+        let mut reported = client("tonk-core", "0.1.0\n[forged]");
+        reported.features = (0..100).map(|i| format!("f{i}")).collect();
+        reported.features.insert(0, "bad feature".to_string());
+        let kept = sanitize(reported).unwrap();
+        assert_eq!(kept.version, "");
+        assert_eq!(kept.features.len(), MAX_FEATURES);
+        assert_eq!(kept.features[0], "f0");
+    }
+
+    #[test]
+    fn test_unknown_names_are_counted_as_other() {
+        let clients = ConnectedClients::new();
+        clients.opened(Some(&client("tonk-core", "0.1.0")));
+        clients.opened(Some(&client("custom-app", "1.0")));
+        clients.opened(Some(&client("another-app", "2.0")));
+        clients.opened(None);
+
+        let counts = clients.counts();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts["tonk-core/0.1.0"], 1);
+        assert_eq!(counts[OTHER_CLIENT], 2);
+        assert_eq!(counts[UNKNOWN_CLIENT], 1);
+
+        clients.closed(Some(&client("custom-app", "1.0")));
+        clients.closed(Some(&client("another-app", "2.0")));
+        assert!(!clients.counts().contains_key(OTHER_CLIENT));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::access_log::ConnectionStats;
+use crate::share::MessageFields;
+use futures::{ready, Sink, Stream};
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio_tungstenite::tungstenite::{Error, Message};
+
+/// Wraps a sync connection to count its traffic and learn the peer's ID
+///
+/// The peer ID is read from the `join` message the peer opens the connection with.
+pub struct MeteredSocket<S> {
+    inner: S,
+    stats: Arc<ConnectionStats>,
+    joined: bool,
+}
+
+impl<S> MeteredSocket<S> {
+    pub fn new(inner: S, stats: Arc<ConnectionStats>) -> Self {
+        Self {
+            inner,
+            stats,
+            joined: false,
+        }
+    }
+}
+
+impl<S> Stream for MeteredSocket<S>
+where
+    S: Stream<Item = Result<Message, Error>> + Unpin,
+{
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = ready!(Pin::new(&mut self.inner).poll_next(cx));
+        if let Some(Ok(message)) = &item {
+            self.stats
+                .bytes_in
+                .fetch_add(message.len() as u64, Ordering::Relaxed);
+            if let (false, Message::Binary(data)) = (self.joined, message) {
+                if let Some(fields) = MessageFields::parse(data) {
+                    if let (Some("join"), Some(sender_id)) = (fields.message_type, fields.sender_id)
+                    {
+                        *self.stats.peer_id.lock().unwrap() = Some(sender_id.to_string());
+                        self.joined = true;
+                    }
+                }
+            }
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl<S> Sink<Message> for MeteredSocket<S>
+where
+    S: Sink<Message, Error = Error> + Unpin,
+{
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Error> {
+        let len = item.len() as u64;
+        Pin::new(&mut self.inner).start_send(item)?;
+        self.stats.bytes_out.fetch_add(len, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
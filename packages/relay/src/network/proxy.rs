@@ -0,0 +1,320 @@
+use crate::cluster::NodeInfo;
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use futures::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+
+/// Header naming the replica a connection was proxied through, so a replica
+/// never proxies a connection that another one already proxied
+pub const PROXIED_BY: &str = "x-tonk-proxied-by";
+
+/// Request headers passed on to the owner
+const FORWARDED_HEADERS: [HeaderName; 2] = [header::AUTHORIZATION, header::SEC_WEBSOCKET_PROTOCOL];
+
+/// WebSocket URL of `path` on `owner`
+fn owner_url(owner: &NodeInfo, uri: &Uri) -> String {
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    let base = owner.advertise_url.strip_prefix("http://").map_or_else(
+        || owner.advertise_url.clone(),
+        |rest| format!("ws://{}", rest),
+    );
+    format!("{}{}", base, path)
+}
+
+/// Open the same WebSocket on `owner` and relay messages between it and the client
+///
+/// The client's credentials and subprotocols are passed on, with its address
+/// appended to `X-Forwarded-For`. The owner's choice of subprotocol and its
+/// `x-tonk-*` headers are passed back, so the client can't tell the
+/// connection was proxied.
+pub async fn proxy_to_owner(
+    ws: WebSocketUpgrade,
+    owner: &NodeInfo,
+    node_id: &str,
+    uri: &Uri,
+    headers: &HeaderMap,
+    remote_addr: SocketAddr,
+) -> Response {
+    let url = owner_url(owner, uri);
+    let mut request = match url.as_str().into_client_request() {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::error!("Invalid URL for cluster node {}: {}", owner.node_id, e);
+            return StatusCode::BAD_GATEWAY.into_response();
+        }
+    };
+
+    let forwarded_for = match headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
+        Some(earlier) => format!("{}, {}", earlier, remote_addr.ip()),
+        None => remote_addr.ip().to_string(),
+    };
+    let upstream_headers = request.headers_mut();
+    for name in FORWARDED_HEADERS {
+        if let Some(value) = headers.get(&name) {
+            upstream_headers.insert(name, value.clone());
+        }
+    }
+    if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+        upstream_headers.insert("x-forwarded-for", value);
+    }
+    if let Ok(value) = HeaderValue::from_str(node_id) {
+        upstream_headers.insert(PROXIED_BY, value);
+    }
+
+    let (upstream, upstream_response) = match tokio_tungstenite::connect_async(request).await {
+        Ok(connected) => connected,
+        Err(tungstenite::Error::Http(response)) => {
+            // Pass on refusals such as a bad token as they are
+            let status =
+                StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+            let body = response.into_body().unwrap_or_default();
+            return (status, body).into_response();
+        }
+        Err(e) => {
+            tracing::error!("Failed to proxy to cluster node {}: {}", owner.node_id, e);
+            return StatusCode::BAD_GATEWAY.into_response();
+        }
+    };
+    tracing::debug!("Proxying connection for {} to {}", uri.path(), url);
+
+    let protocol = upstream_response
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let ws = match protocol {
+        Some(protocol) => ws.protocols([protocol]),
+        None => ws,
+    };
+
+    let mut response = ws
+        .on_upgrade(move |socket| relay_messages(socket, upstream))
+        .into_response();
+    for (name, value) in upstream_response.headers() {
+        if name.as_str().starts_with("x-tonk-") {
+            if let Ok(name) = HeaderName::from_bytes(name.as_str().as_bytes()) {
+                if let Ok(value) = HeaderValue::from_bytes(value.as_bytes()) {
+                    response.headers_mut().insert(name, value);
+                }
+            }
+        }
+    }
+    response
+}
+
+type Upstream =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Pass messages both ways until either side closes
+async fn relay_messages(client: WebSocket, upstream: Upstream) {
+    let (mut client_sink, mut client_stream) = client.split();
+    let (mut upstream_sink, mut upstream_stream) = upstream.split();
+
+    let to_upstream = async {
+        while let Some(Ok(message)) = client_stream.next().await {
+            let closing = matches!(message, Message::Close(_));
+            if upstream_sink.send(to_tungstenite(message)).await.is_err() || closing {
+                break;
+            }
+        }
+        let _ = upstream_sink.close().await;
+    };
+
+    let to_client = async {
+        while let Some(Ok(message)) = upstream_stream.next().await {
+            let Some(message) = from_tungstenite(message) else {
+                continue;
+            };
+            let closing = matches!(message, Message::Close(_));
+            if client_sink.send(message).await.is_err() || closing {
+                break;
+            }
+        }
+        let _ = client_sink.close().await;
+    };
+
+    tokio::select! {
+        _ = to_upstream => {}
+        _ = to_client => {}
+    }
+}
+
+fn to_tungstenite(message: Message) -> tungstenite::Message {
+    match message {
+        Message::Binary(data) => tungstenite::Message::Binary(data),
+        Message::Text(text) => tungstenite::Message::Text(text.as_str().into()),
+        Message::Ping(data) => tungstenite::Message::Ping(data),
+        Message::Pong(data) => tungstenite::Message::Pong(data),
+        Message::Close(frame) => {
+            tungstenite::Message::Close(frame.map(|f| tungstenite::protocol::CloseFrame {
+                code: f.code.into(),
+                reason: f.reason.as_str().into(),
+            }))
+        }
+    }
+}
+
+fn from_tungstenite(message: tungstenite::Message) -> Option<Message> {
+    Some(match message {
+        tungstenite::Message::Binary(data) => Message::Binary(data),
+        tungstenite::Message::Text(text) => Message::Text(text.as_str().into()),
+        tungstenite::Message::Ping(data) => Message::Ping(data),
+        tungstenite::Message::Pong(data) => Message::Pong(data),
+        tungstenite::Message::Close(frame) => Message::Close(frame.map(|f| CloseFrame {
+            code: f.code.into(),
+            reason: f.reason.as_str().into(),
+        })),
+        tungstenite::Message::Frame(_) => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::ConnectInfo;
+    use axum::routing::get;
+    use axum::Router;
+
+    async fn serve(router: Router) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+        addr
+    }
+
+    fn node(addr: SocketAddr) -> NodeInfo {
+        NodeInfo {
+            node_id: "owner".to_string(),
+            advertise_url: format!("http://{}", addr),
+            heartbeat_at: 0,
+        }
+    }
+
+    /// A replica that proxies `/sync` to `owner`
+    fn proxy(node_id: &'static str, owner: NodeInfo) -> Router {
+        Router::new().route(
+            "/sync",
+            get(
+                move |ws: WebSocketUpgrade,
+                      uri: Uri,
+                      headers: HeaderMap,
+                      ConnectInfo(remote_addr): ConnectInfo<SocketAddr>| async move {
+                    proxy_to_owner(ws, &owner, node_id, &uri, &headers, remote_addr).await
+                },
+            ),
+        )
+    }
+
+    /// Echoes messages after reporting the headers a connection arrived with
+    async fn owner(ws: WebSocketUpgrade, headers: HeaderMap) -> Response {
+        let seen = ["authorization", "x-forwarded-for", PROXIED_BY]
+            .map(|name| {
+                headers
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("")
+            })
+            .join("|");
+        let mut response = ws
+            .protocols(["chunked"])
+            .on_upgrade(|mut socket| async move {
+                let _ = socket.send(Message::Text(seen.into())).await;
+                while let Some(Ok(message)) = socket.next().await {
+                    if socket.send(message).await.is_err() {
+                        break;
+                    }
+                }
+            })
+            .into_response();
+        response
+            .headers_mut()
+            .insert("x-tonk-relay-name", HeaderValue::from_static("owner"));
+        response
+    }
+
+    #[tokio::test]
+    async fn test_proxies_to_owner() {
+        let owner_addr = serve(Router::new().route("/sync", get(owner))).await;
+        let node = node(owner_addr);
+        let proxy_addr = serve(proxy("proxy", node)).await;
+
+        let mut request = format!("ws://{}/sync", proxy_addr)
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert("authorization", HeaderValue::from_static("Bearer secret"));
+        request.headers_mut().insert(
+            "sec-websocket-protocol",
+            HeaderValue::from_static("chunked"),
+        );
+        let (mut client, response) = tokio_tungstenite::connect_async(request).await.unwrap();
+        assert_eq!(
+            response.headers().get("sec-websocket-protocol").unwrap(),
+            "chunked"
+        );
+        assert_eq!(
+            response.headers().get("x-tonk-relay-name").unwrap(),
+            "owner"
+        );
+
+        let seen = client.next().await.unwrap().unwrap();
+        assert_eq!(seen.to_text().unwrap(), "Bearer secret|127.0.0.1|proxy");
+
+        client
+            .send(tungstenite::Message::Binary(vec![1, 2, 3].into()))
+            .await
+            .unwrap();
+        let echoed = client.next().await.unwrap().unwrap();
+        assert_eq!(echoed.into_data().as_ref(), &[1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_passes_on_refusals() {
+        let owner_addr = serve(Router::new().route(
+            "/sync",
+            get(|| async { (StatusCode::UNAUTHORIZED, "Invalid sync token") }),
+        ))
+        .await;
+        let node = node(owner_addr);
+        let proxy_addr = serve(proxy("proxy", node)).await;
+
+        match tokio_tungstenite::connect_async(format!("ws://{}/sync", proxy_addr)).await {
+            Err(tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), 401);
+            }
+            other => panic!("expected the owner's refusal, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_owner_url() {
+        let owner = NodeInfo {
+            node_id: "a".to_string(),
+            advertise_url: "http://10.0.0.1:8081".to_string(),
+            heartbeat_at: 0,
+        };
+        let uri: Uri = "/sync?token=secret".parse().unwrap();
+        assert_eq!(
+            owner_url(&owner, &uri),
+            "ws://10.0.0.1:8081/sync?token=secret"
+        );
+
+        let owner = NodeInfo {
+            advertise_url: "ws://10.0.0.1:8081".to_string(),
+            ..owner
+        };
+        assert_eq!(
+            owner_url(&owner, &"/".parse().unwrap()),
+            "ws://10.0.0.1:8081/"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use crate::share::{MessageFields, ShareScope};
+use futures::stream::{self, BoxStream};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio_tungstenite::tungstenite::{Error, Message};
+
+type BoxSink = Pin<Box<dyn Sink<Message, Error = Error> + Send>>;
+
+/// What to do with a message a share link connection sent
+enum Inbound {
+    Forward(Message),
+    Drop,
+    /// The peer tried to write, so the connection ends
+    Violation,
+}
+
+/// A sync connection opened with a share link, which may only read
+///
+/// The connection ends as soon as the peer sends changes. Ephemeral messages,
+/// which peers broadcast to each other through the relay, are dropped in both
+/// directions, and with a path prefix so are messages about documents outside it.
+pub struct ReadOnlySocket {
+    sink: BoxSink,
+    stream: BoxStream<'static, Result<Message, Error>>,
+}
+
+impl ReadOnlySocket {
+    pub fn new<S>(socket: S, scope: Arc<ShareScope>) -> Self
+    where
+        S: Stream<Item = Result<Message, Error>> + Sink<Message, Error = Error> + Send + 'static,
+    {
+        let (sink, stream) = socket.split();
+
+        let outbound_scope = Arc::clone(&scope);
+        let sink = sink.with_flat_map(move |message: Message| {
+            let forward = match &message {
+                Message::Binary(data) => MessageFields::parse(data).is_none_or(|fields| {
+                    fields.message_type != Some("ephemeral")
+                        && fields
+                            .document_id
+                            .is_none_or(|doc_id| outbound_scope.allows(doc_id))
+                }),
+                _ => true,
+            };
+            stream::iter(forward.then_some(Ok(message)))
+        });
+
+        let stream = stream
+            .map(move |message| message.map(|message| inbound(&scope, message)))
+            .take_while(|inbound| std::future::ready(!matches!(inbound, Ok(Inbound::Violation))))
+            .filter_map(|inbound| {
+                std::future::ready(match inbound {
+                    Ok(Inbound::Forward(message)) => Some(Ok(message)),
+                    Ok(_) => None,
+                    Err(e) => Some(Err(e)),
+                })
+            });
+
+        Self {
+            sink: Box::pin(sink),
+            stream: stream.boxed(),
+        }
+    }
+}
+
+fn inbound(scope: &ShareScope, message: Message) -> Inbound {
+    let Message::Binary(data) = &message else {
+        return Inbound::Forward(message);
+    };
+    let share = &scope.claims.id;
+    let Some(fields) = MessageFields::parse(data) else {
+        tracing::warn!("Share link {} sent an unreadable message", share);
+        return Inbound::Violation;
+    };
+    if fields.writes() {
+        tracing::warn!(
+            "Share link {} tried to write to document {}",
+            share,
+            fields.document_id.unwrap_or("?")
+        );
+        return Inbound::Violation;
+    }
+    if fields.message_type == Some("ephemeral") {
+        return Inbound::Drop;
+    }
+    match fields.document_id {
+        Some(doc_id) if !scope.allows(doc_id) => {
+            tracing::debug!(
+                "Share link {} asked for document {} outside its path prefix",
+                share,
+                doc_id
+            );
+            Inbound::Drop
+        }
+        _ => Inbound::Forward(message),
+    }
+}
+
+impl Stream for ReadOnlySocket {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.poll_next_unpin(cx)
+    }
+}
+
+impl Sink<Message> for ReadOnlySocket {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.sink.as_mut().poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Error> {
+        self.sink.as_mut().start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.sink.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.sink.as_mut().poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::share::tests::{claims, doc_id, message, space, sync_messages};
+    use futures::channel::mpsc;
+    use samod::DocHandle;
+
+    /// One end of an in-memory WebSocket
+    struct Pipe {
+        incoming: mpsc::UnboundedReceiver<Message>,
+        outgoing: mpsc::UnboundedSender<Message>,
+    }
+
+    impl Stream for Pipe {
+        type Item = Result<Message, Error>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            self.incoming
+                .poll_next_unpin(cx)
+                .map(|message| message.map(Ok))
+        }
+    }
+
+    impl Sink<Message> for Pipe {
+        type Error = Error;
+
+        fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(self: Pin<&mut Self>, item: Message) -> Result<(), Error> {
+            self.outgoing
+                .unbounded_send(item)
+                .map_err(|_| Error::ConnectionClosed)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// A read-only socket limited to `/docs` of the space at `root`, the sender
+    /// of messages from its peer, and the receiver of messages sent to it
+    fn socket(
+        root: DocHandle,
+    ) -> (
+        ReadOnlySocket,
+        mpsc::UnboundedSender<Message>,
+        mpsc::UnboundedReceiver<Message>,
+    ) {
+        let scope = ShareScope::new(claims(root.document_id().to_string(), Some("/docs")), root);
+        let (peer, incoming) = mpsc::unbounded();
+        let (outgoing, sent) = mpsc::unbounded();
+        let socket = ReadOnlySocket::new(Pipe { incoming, outgoing }, Arc::new(scope));
+        (socket, peer, sent)
+    }
+
+    fn binary(data: Vec<u8>) -> Message {
+        Message::Binary(data.into())
+    }
+
+    #[tokio::test]
+    async fn test_inbound_messages_are_filtered() {
+        let (_tonk, root) = space().await;
+        let (mut socket, peer, _sent) = socket(root.clone());
+        let (request, changes) = sync_messages();
+        let inside = doc_id(&root, "/docs/a.txt");
+        let outside = doc_id(&root, "/other/c.txt");
+
+        let forwarded = binary(message("request", Some(&inside), Some(&request)));
+        peer.unbounded_send(binary(message("request", Some(&outside), Some(&request))))
+            .unwrap();
+        peer.unbounded_send(binary(message(
+            "ephemeral",
+            Some(&inside),
+            Some(b"presence"),
+        )))
+        .unwrap();
+        peer.unbounded_send(forwarded.clone()).unwrap();
+        assert_eq!(socket.next().await.unwrap().unwrap(), forwarded);
+
+        // Sending changes ends the connection, dropping anything after them
+        peer.unbounded_send(binary(message("sync", Some(&inside), Some(&changes))))
+            .unwrap();
+        peer.unbounded_send(forwarded).unwrap();
+        assert!(socket.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_paths_outside_the_prefix_cant_be_read() {
+        let (_tonk, root) = space().await;
+        let (mut socket, peer, mut sent) = socket(root.clone());
+        let (request, _) = sync_messages();
+        let root_id = root.document_id().to_string();
+        let inside = doc_id(&root, "/docs/a.txt");
+
+        // Requests for the root, whose path index names every path, are dropped
+        let forwarded = binary(message("request", Some(&inside), Some(&request)));
+        peer.unbounded_send(binary(message("request", Some(&root_id), Some(&request))))
+            .unwrap();
+        peer.unbounded_send(forwarded.clone()).unwrap();
+        assert_eq!(socket.next().await.unwrap().unwrap(), forwarded);
+
+        // And nothing about it is sent
+        socket
+            .send(binary(message("sync", Some(&root_id), Some(&request))))
+            .await
+            .unwrap();
+        socket.close().await.unwrap();
+        drop(socket);
+        assert_eq!(sent.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_messages_end_the_connection() {
+        let (_tonk, root) = space().await;
+        let (mut socket, peer, _sent) = socket(root);
+        peer.unbounded_send(binary(b"not cbor".to_vec())).unwrap();
+        assert!(socket.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_outbound_messages_are_filtered() {
+        let (_tonk, root) = space().await;
+        let (mut socket, _peer, mut sent) = socket(root.clone());
+        let (request, _) = sync_messages();
+        let inside = doc_id(&root, "/docs/sub/b.txt");
+        let outside = doc_id(&root, "/other/c.txt");
+
+        let delivered = binary(message("sync", Some(&inside), Some(&request)));
+        socket
+            .send(binary(message("sync", Some(&outside), Some(&request))))
+            .await
+            .unwrap();
+        socket
+            .send(binary(message(
+                "ephemeral",
+                Some(&inside),
+                Some(b"presence"),
+            )))
+            .await
+            .unwrap();
+        socket.send(delivered.clone()).await.unwrap();
+        socket.close().await.unwrap();
+        drop(socket);
+
+        assert_eq!(sent.next().await, Some(delivered));
+        assert_eq!(sent.next().await, None);
+    }
+}
@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Closes WebSocket connections whose peer has gone silent
+///
+/// Clients that vanish without a TCP FIN (sleeping laptops, dropped mobile
+/// networks) otherwise hold their connection open indefinitely. A connection
+/// that receives nothing for half the timeout is pinged; if the peer still
+/// hasn't answered when the timeout runs out, the connection is closed.
+#[derive(Debug)]
+pub struct IdleReaper {
+    /// Silence after which a connection is closed; never closed if unset
+    pub timeout: Option<Duration>,
+    unresponsive: AtomicUsize,
+    reaped: AtomicUsize,
+}
+
+impl IdleReaper {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            unresponsive: AtomicUsize::new(0),
+            reaped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Connections that have been pinged and not answered yet
+    pub fn unresponsive(&self) -> usize {
+        self.unresponsive.load(Ordering::Relaxed)
+    }
+
+    /// Connections closed for being silent since the relay started
+    pub fn reaped(&self) -> usize {
+        self.reaped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn mark_unresponsive(&self) {
+        self.unresponsive.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn clear_unresponsive(&self) {
+        self.unresponsive.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn mark_reaped(&self) {
+        self.reaped.fetch_add(1, Ordering::Relaxed);
+    }
+}
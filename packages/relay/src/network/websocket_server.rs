@@ -1,24 +1,189 @@
-use axum::extract::ws::{Message, WebSocket};
+use super::{ConnectedClients, IdleReaper, MeteredSocket, ReadOnlySocket};
+use crate::access_log::{AccessEntry, AccessEvent, AccessLog, ConnectionStats};
+use crate::cluster::{moved_frame, Lost};
+use crate::share::ShareScope;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
 use futures::stream::{SplitSink, SplitStream};
-use futures::{Sink, Stream, StreamExt};
+use futures::{Future, Sink, SinkExt, Stream, StreamExt};
 use samod::{ConnDirection, Repo};
 use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
 use tokio_tungstenite::tungstenite;
+use tonk_core::websocket::{ChunkedSocket, FramingConfig, CHUNKED_PROTOCOL};
+
+/// Tracks how long a connection has been silent, pinging and then closing it
+struct IdleTimer {
+    reaper: Arc<IdleReaper>,
+    /// Silence before a ping is sent, and again before the connection is closed
+    interval: Duration,
+    deadline: Pin<Box<Sleep>>,
+    pinged: bool,
+    /// Control frame waiting for the sink, and whether the connection ends once it is sent
+    outgoing: Option<(Message, bool)>,
+    closed: bool,
+}
+
+impl IdleTimer {
+    fn new(reaper: Arc<IdleReaper>, timeout: Duration) -> Self {
+        let interval = timeout / 2;
+        Self {
+            reaper,
+            interval,
+            deadline: Box::pin(tokio::time::sleep(interval)),
+            pinged: false,
+            outgoing: None,
+            closed: false,
+        }
+    }
+
+    /// Record that the peer sent something
+    fn activity(&mut self) {
+        self.deadline.as_mut().reset(Instant::now() + self.interval);
+        if self.pinged {
+            self.pinged = false;
+            self.reaper.clear_unresponsive();
+        }
+    }
+
+    /// Drive pings and closing while waiting for the peer
+    ///
+    /// Returns `Ready` once the connection has been closed for being silent.
+    fn poll_idle(
+        &mut self,
+        sink: &mut SplitSink<WebSocket, Message>,
+        cx: &mut Context<'_>,
+    ) -> Poll<()> {
+        loop {
+            if self.closed {
+                // Best effort: the peer may well be gone already
+                return sink.poll_flush_unpin(cx).map(|_| ());
+            }
+
+            if let Some((message, ends)) = self.outgoing.take() {
+                match sink.poll_ready_unpin(cx) {
+                    Poll::Pending => {
+                        self.outgoing = Some((message, ends));
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(Err(_)) => {
+                        self.closed = true;
+                        return Poll::Ready(());
+                    }
+                    Poll::Ready(Ok(())) => {
+                        if sink.start_send_unpin(message).is_err() || ends {
+                            self.closed = true;
+                            continue;
+                        }
+                        let _ = sink.poll_flush_unpin(cx);
+                    }
+                }
+            }
+
+            if self.deadline.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.deadline.as_mut().reset(Instant::now() + self.interval);
+
+            if self.pinged {
+                self.reaper.mark_reaped();
+                let frame = CloseFrame {
+                    code: close_code::AWAY,
+                    reason: "Idle timeout".into(),
+                };
+                self.outgoing = Some((Message::Close(Some(frame)), true));
+            } else {
+                self.pinged = true;
+                self.reaper.mark_unresponsive();
+                self.outgoing = Some((Message::Ping(Default::default()), false));
+            }
+        }
+    }
+}
+
+impl Drop for IdleTimer {
+    fn drop(&mut self) {
+        if self.pinged {
+            self.reaper.clear_unresponsive();
+        }
+    }
+}
+
+/// Closes a connection once this replica no longer owns its space
+struct Eviction {
+    lost: Lost,
+    /// Whether the space was lost and the close frame is being sent
+    closing: bool,
+    sent: bool,
+}
+
+impl Eviction {
+    fn new(lost: Lost) -> Self {
+        Self {
+            lost,
+            closing: false,
+            sent: false,
+        }
+    }
+
+    /// Returns `Ready` once the close frame has been sent and the connection should end
+    fn poll_evicted(
+        &mut self,
+        sink: &mut SplitSink<WebSocket, Message>,
+        cx: &mut Context<'_>,
+    ) -> Poll<()> {
+        if !self.closing {
+            if self.lost.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.closing = true;
+        }
+        if !self.sent {
+            match sink.poll_ready_unpin(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(())) => {
+                    let _ = sink.start_send_unpin(Message::Close(Some(moved_frame())));
+                }
+                Poll::Ready(Err(_)) => {}
+            }
+            self.sent = true;
+        }
+        // Best effort: the peer may well be gone already
+        sink.poll_flush_unpin(cx).map(|_| ())
+    }
+}
 
 struct WebSocketAdapter {
     sink: SplitSink<WebSocket, Message>,
     stream: SplitStream<WebSocket>,
+    idle: Option<IdleTimer>,
+    eviction: Option<Eviction>,
 }
 
 impl Stream for WebSocketAdapter {
     type Item = Result<tungstenite::Message, tungstenite::Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match Pin::new(&mut self.stream).poll_next(cx) {
+        let this = &mut *self;
+        if this.idle.as_ref().is_some_and(|idle| idle.closed) {
+            // Finish sending the close frame before ending the stream
+            return this.sink.poll_flush_unpin(cx).map(|_| None);
+        }
+        if let Some(eviction) = &mut this.eviction {
+            let evicted = eviction.poll_evicted(&mut this.sink, cx);
+            if eviction.closing {
+                return evicted.map(|()| None);
+            }
+        }
+
+        match Pin::new(&mut this.stream).poll_next(cx) {
             Poll::Ready(Some(Ok(msg))) => {
+                if let Some(idle) = &mut this.idle {
+                    idle.activity();
+                }
                 let tungstenite_msg = match msg {
                     Message::Binary(data) => tungstenite::Message::Binary(data),
                     Message::Text(text) => tungstenite::Message::Text(text.to_string().into()),
@@ -38,7 +203,10 @@ impl Stream for WebSocketAdapter {
                 std::io::Error::other(e.to_string()),
             )))),
             Poll::Ready(None) => Poll::Ready(None),
-            Poll::Pending => Poll::Pending,
+            Poll::Pending => match &mut this.idle {
+                Some(idle) => idle.poll_idle(&mut this.sink, cx).map(|()| None),
+                None => Poll::Pending,
+            },
         }
     }
 }
@@ -89,27 +257,99 @@ impl Sink<tungstenite::Message> for WebSocketAdapter {
     }
 }
 
+/// Serve a sync connection until it ends, recording it in the access log
+///
+/// `connect` is the connection's access entry, logged once the connection starts.
+/// When clustering, `lost` resolves once another replica takes over the space,
+/// closing the connection so the client reconnects to it.
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_websocket_connection(
     axum_socket: WebSocket,
     repo: Arc<Repo>,
     connection_count: Arc<AtomicUsize>,
+    clients: Arc<ConnectedClients>,
+    reaper: Arc<IdleReaper>,
+    framing: FramingConfig,
+    share: Option<Arc<ShareScope>>,
+    access_log: Arc<AccessLog>,
+    connect: AccessEntry,
+    lost: Option<Lost>,
 ) {
-    let connection_id = uuid::Uuid::new_v4();
+    let connection_id = connect.connection_id.clone();
+    let start = Instant::now();
     connection_count.fetch_add(1, Ordering::Relaxed);
+    clients.opened(connect.client.as_ref());
     let count = connection_count.load(Ordering::Relaxed);
     tracing::info!(
-        "[{}] WebSocket connected. Total connections: {}",
+        "[{}] WebSocket connected from {}. Total connections: {}",
         connection_id,
+        connect
+            .client
+            .as_ref()
+            .map_or_else(|| "an unknown client".to_string(), ToString::to_string),
         count
     );
+    access_log.record(&connect);
 
+    let chunked = axum_socket
+        .protocol()
+        .is_some_and(|protocol| protocol == CHUNKED_PROTOCOL);
     let (sink, stream) = axum_socket.split();
-    let adapter = WebSocketAdapter { sink, stream };
+    let idle = reaper
+        .timeout
+        .map(|timeout| IdleTimer::new(Arc::clone(&reaper), timeout));
+    let adapter = WebSocketAdapter {
+        sink,
+        stream,
+        idle,
+        eviction: lost.map(Eviction::new),
+    };
+    let stats = Arc::new(ConnectionStats::default());
 
-    tracing::debug!("[{}] Starting samod connection", connection_id);
-    let finish_reason = repo
-        .connect_tungstenite(adapter, ConnDirection::Incoming)
-        .await;
+    tracing::debug!(
+        "[{}] Starting samod connection (chunked: {})",
+        connection_id,
+        chunked
+    );
+    if let Some(share) = &share {
+        tracing::info!(
+            "[{}] Read-only connection with share link {}",
+            connection_id,
+            share.claims.id
+        );
+    }
+    let finish_reason = match (chunked, share) {
+        (true, Some(share)) => {
+            let socket = ChunkedSocket::new(adapter, framing);
+            repo.connect_tungstenite(
+                ReadOnlySocket::new(MeteredSocket::new(socket, Arc::clone(&stats)), share),
+                ConnDirection::Incoming,
+            )
+            .await
+        }
+        (true, None) => {
+            let socket = ChunkedSocket::new(adapter, framing);
+            repo.connect_tungstenite(
+                MeteredSocket::new(socket, Arc::clone(&stats)),
+                ConnDirection::Incoming,
+            )
+            .await
+        }
+        (false, Some(share)) => {
+            repo.connect_tungstenite(
+                ReadOnlySocket::new(MeteredSocket::new(adapter, Arc::clone(&stats)), share),
+                ConnDirection::Incoming,
+            )
+            .await
+        }
+        (false, None) => {
+            repo.connect_tungstenite(
+                MeteredSocket::new(adapter, Arc::clone(&stats)),
+                ConnDirection::Incoming,
+            )
+            .await
+        }
+    };
 
     tracing::info!(
         "[{}] Connection finished with reason: {:?}",
@@ -118,10 +358,17 @@ pub async fn handle_websocket_connection(
     );
 
     connection_count.fetch_sub(1, Ordering::Relaxed);
+    clients.closed(connect.client.as_ref());
     let count = connection_count.load(Ordering::Relaxed);
     tracing::info!(
         "[{}] WebSocket disconnected. Total connections: {}",
         connection_id,
         count
     );
+
+    let mut disconnect = connect.later(AccessEvent::Disconnect);
+    stats.fill(&mut disconnect);
+    disconnect.duration_ms = Some(start.elapsed().as_millis() as u64);
+    disconnect.reason = Some(format!("{:?}", finish_reason));
+    access_log.record(&disconnect);
 }
@@ -1,26 +1,44 @@
+use crate::access_log::{AccessEntry, AccessEvent, AccessLog, AuthOutcome, Channel};
+use crate::cluster::{Cluster, Lost, Route};
+use crate::config::Config;
 use crate::error::{RelayError, Result};
-use crate::network::handle_websocket_connection;
-use crate::storage::{BundleStorageAdapter, S3Storage};
+use crate::health;
+use crate::leases::LeaseHub;
+use crate::listener::ListenerConfig;
+use crate::network::proxy::{proxy_to_owner, PROXIED_BY};
+use crate::network::{clients, handle_websocket_connection, ConnectedClients, IdleReaper};
+use crate::share::{normalize_prefix, ShareScope, ShareTokens};
+use crate::signaling::SignalingHub;
+use crate::site;
+use crate::snapshot::SnapshotScheduler;
+use crate::storage::{slim_manifest, BundleStorageAdapter, ObjectStore, S3Storage};
 use axum::extract::ws::{rejection::WebSocketUpgradeRejection, WebSocket, WebSocketUpgrade};
-use axum::http::HeaderMap;
+use axum::http::{HeaderMap, HeaderName, Uri};
 use axum::{
     body::Bytes,
-    extract::{Path, State},
+    extract::{ConnectInfo, Path, Query, State},
     http::{header, HeaderValue, StatusCode},
-    response::{IntoResponse, Response},
-    routing::{get, post},
+    response::{IntoResponse, Redirect, Response},
+    routing::{delete, get, post},
     Json, Router,
 };
-use samod::Repo;
+use samod::{DocumentId, Repo};
+use serde::Deserialize;
 use serde_json::json;
-use std::io::Read;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+use tonk_core::bundle::{Bundle, BundlePath, VerifyingKey};
+use tonk_core::error::VfsError;
+use tonk_core::websocket::{
+    parse_peer_fields, peer_fields, FramingConfig, CHUNKED_PROTOCOL, RELAY_HEADERS,
+    RELAY_ROOT_HEADER,
+};
+use tonk_core::{PeerInfo, VirtualFileSystem};
 use tower_http::cors::{Any, CorsLayer};
-use zip::ZipArchive;
 
 /// Embedded WASM binary from @tonk/core npm module
 const WASM_BYTES: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/tonk_core_bg.wasm"));
@@ -30,8 +48,31 @@ pub struct AppState {
     pub bundle_storage: Arc<BundleStorageAdapter>,
     pub s3_storage: Option<Arc<S3Storage>>,
     pub connection_count: Arc<AtomicUsize>,
+    /// Open sync connections by the client software that opened them
+    pub clients: Arc<ConnectedClients>,
+    pub reaper: Arc<IdleReaper>,
+    /// Framing for sync connections that negotiate chunked messages
+    pub framing: FramingConfig,
     pub start_time: SystemTime,
     pub blank_tonk_path: PathBuf,
+    /// Directory the repo stores documents in
+    pub storage_dir: PathBuf,
+    pub snapshots: Option<Arc<SnapshotScheduler>>,
+    pub signaling: Arc<SignalingHub>,
+    pub leases: Arc<LeaseHub>,
+    /// Bearer token required by admin endpoints; they are disabled when unset
+    pub admin_token: Option<String>,
+    /// Token sync connections without a share link must present; open to anyone when unset
+    pub sync_token: Option<String>,
+    /// Whether the hosted space's `/app` is served without the sync token
+    pub public_app: bool,
+    /// Space ownership among replicas, when clustering is enabled
+    pub cluster: Option<Arc<Cluster>>,
+    /// Read-only share links, when a share secret is configured
+    pub shares: Option<Arc<ShareTokens>>,
+    pub access_log: Arc<AccessLog>,
+    /// Keys uploaded bundles must be signed by, if any
+    pub trusted_keys: Vec<VerifyingKey>,
 }
 
 pub struct RelayServer {
@@ -41,22 +82,76 @@ pub struct RelayServer {
 impl RelayServer {
     pub async fn create(
         repo: Arc<Repo>,
-        bundle_path: PathBuf,
-        blank_tonk_path: PathBuf,
-        s3_config: (String, String),
+        config: &Config,
         connection_count: Arc<AtomicUsize>,
     ) -> Result<Self> {
-        let bundle_bytes = std::fs::read(&bundle_path)?;
-        let bundle_storage = Arc::new(BundleStorageAdapter::from_bundle(bundle_bytes).await?);
-        let s3_storage = Some(Arc::new(S3Storage::new(s3_config.0, s3_config.1).await?));
+        let bundle_bytes = std::fs::read(&config.bundle)?;
+        let trusted_keys = config.auth.trusted_keys()?;
+        let bundle_storage =
+            Arc::new(BundleStorageAdapter::from_bundle(bundle_bytes, &trusted_keys).await?);
+        let s3_storage = match &config.s3.bucket {
+            Some(bucket) => Some(Arc::new(
+                S3Storage::new(bucket.clone(), config.s3.region.clone()).await?,
+            )),
+            None => None,
+        };
+
+        let snapshots = match &s3_storage {
+            Some(s3_storage) => {
+                let root_id =
+                    bundle_storage.root_id().await.parse().map_err(|e| {
+                        RelayError::InvalidManifest(format!("Invalid root ID: {}", e))
+                    })?;
+                Some(Arc::new(SnapshotScheduler::new(
+                    Arc::clone(&repo),
+                    root_id,
+                    Arc::clone(s3_storage),
+                    config.snapshots.clone(),
+                )))
+            }
+            None => None,
+        };
+
+        let cluster = match &s3_storage {
+            Some(s3_storage) if config.cluster.enabled => {
+                let cluster =
+                    Cluster::new(&config.cluster, ObjectStore::S3(Arc::clone(s3_storage)))?;
+                cluster.check_conditional_writes().await?;
+                Some(Arc::new(cluster))
+            }
+            _ => None,
+        };
+
+        let shares = match (&s3_storage, &config.auth.share_secret) {
+            (Some(s3_storage), Some(secret)) => {
+                Some(Arc::new(ShareTokens::new(secret, Arc::clone(s3_storage))))
+            }
+            _ => None,
+        };
 
         let state = Arc::new(AppState {
             repo: Arc::clone(&repo),
             bundle_storage,
             s3_storage,
             connection_count,
+            clients: Arc::new(ConnectedClients::new()),
+            reaper: Arc::new(IdleReaper::new(config.limits.idle_timeout())),
+            framing: FramingConfig::default()
+                .with_max_frame_size(config.limits.max_frame_bytes)
+                .with_max_message_size(config.limits.max_message_bytes),
             start_time: SystemTime::now(),
-            blank_tonk_path,
+            blank_tonk_path: config.bundle.clone(),
+            storage_dir: config.storage.dir.clone(),
+            snapshots,
+            signaling: Arc::new(SignalingHub::new()),
+            leases: Arc::new(LeaseHub::new()),
+            admin_token: config.auth.admin_token.clone(),
+            sync_token: config.auth.sync_token.clone(),
+            public_app: config.auth.public_app,
+            cluster,
+            shares,
+            access_log: Arc::new(AccessLog::new(&config.access_log)?),
+            trusted_keys,
         });
 
         Ok(Self { state })
@@ -72,39 +167,194 @@ impl RelayServer {
             .route("/api/bundles/{id}/manifest", get(download_bundle_manifest))
             .route("/api/blank-tonk", get(serve_blank_tonk))
             .route("/metrics", get(metrics))
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .route("/api/admin/snapshot", post(trigger_snapshot))
+            .route("/api/admin/cluster", get(cluster_status))
+            .route("/api/admin/documents/{doc_id}/paths", get(document_paths))
+            .route("/api/admin/shares", get(list_shares).post(create_share))
+            .route("/api/admin/shares/{id}", delete(revoke_share))
+            .route("/signal/{space_id}", get(signaling_handler))
+            .route("/lease/{space_id}", get(lease_handler))
             .layer(
                 CorsLayer::new()
                     .allow_origin(Any)
                     .allow_methods(Any)
                     .allow_headers(Any),
             )
+            // Added after the CORS layer so other origins can't read a space's site
+            .route("/{space_id}/app", get(app_redirect_handler))
+            .route("/{space_id}/app/", get(app_index_handler))
+            .route("/{space_id}/app/{*path}", get(app_asset_handler))
+            .with_state(state)
+    }
+
+    /// Router for a WebSocket-only port
+    pub fn ws_router(state: Arc<AppState>) -> Router {
+        Router::new()
+            .route("/", get(websocket_handler))
             .with_state(state)
     }
 
-    pub async fn run(self, http_addr: SocketAddr) -> Result<()> {
+    pub async fn run(self, listener_config: ListenerConfig) -> Result<()> {
         let app = Self::router(Arc::clone(&self.state));
 
-        let listener = tokio::net::TcpListener::bind(http_addr).await?;
+        let snapshot_task = self
+            .state
+            .snapshots
+            .as_ref()
+            .map(|snapshots| tokio::spawn(Arc::clone(snapshots).run()));
+        let cluster_task = self
+            .state
+            .cluster
+            .as_ref()
+            .map(|cluster| tokio::spawn(Arc::clone(cluster).run()));
+        let mut servers = Vec::new();
 
-        tracing::info!(
-            "Unified server (HTTP + WebSocket) listening on {}",
-            http_addr
-        );
+        for addr in listener_config.http_addrs() {
+            let listener = listener_config.bind(addr)?;
+            if listener_config.ws_port.is_some() {
+                tracing::info!("HTTP server listening on {}", addr);
+            } else {
+                tracing::info!("Unified server (HTTP + WebSocket) listening on {}", addr);
+            }
+            servers.push(serve(listener, app.clone()));
+        }
 
-        axum::serve(listener, app)
-            .await
-            .map_err(|e| RelayError::Other(format!("HTTP server error: {}", e)))?;
+        let ws_app = Self::ws_router(Arc::clone(&self.state));
+        for addr in listener_config.ws_addrs() {
+            let listener = listener_config.bind(addr)?;
+            tracing::info!("WebSocket server listening on {}", addr);
+            servers.push(serve(listener, ws_app.clone()));
+        }
+
+        let result = futures::future::try_join_all(servers).await;
+        if let Some(snapshot_task) = snapshot_task {
+            snapshot_task.abort();
+        }
+        if let Some(cluster_task) = cluster_task {
+            cluster_task.abort();
+        }
+        result?;
 
         Ok(())
     }
 }
 
+async fn serve(listener: tokio::net::TcpListener, app: Router) -> Result<()> {
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .map_err(|e| RelayError::Other(format!("HTTP server error: {}", e)))
+}
+
 async fn health_check() -> impl IntoResponse {
     "👍 Tonk relay server is running"
 }
 
+/// Proxy a WebSocket to the replica that owns `space_id`, unless clustering
+/// is off or this one owns it, in which case the upgrade is handed back
+///
+/// A connection another replica already proxied is refused rather than
+/// proxied again, as the two replicas disagree about the owner until one of
+/// their leases runs out.
+async fn route_to_owner(
+    state: &AppState,
+    space_id: &str,
+    ws: WebSocketUpgrade,
+    uri: &Uri,
+    headers: &HeaderMap,
+    remote_addr: SocketAddr,
+) -> std::result::Result<WebSocketUpgrade, Response> {
+    let Some(cluster) = state.cluster.as_ref() else {
+        return Ok(ws);
+    };
+    match cluster.route(space_id).await {
+        Ok(Route::Local) => Ok(ws),
+        Ok(Route::Remote(owner)) if headers.contains_key(PROXIED_BY) => {
+            tracing::warn!(
+                "Refusing connection for space {} proxied to this node, which node {} owns",
+                space_id,
+                owner.node_id
+            );
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Space is moving between relays",
+            )
+                .into_response())
+        }
+        Ok(Route::Remote(owner)) => {
+            Err(proxy_to_owner(ws, &owner, cluster.node_id(), uri, headers, remote_addr).await)
+        }
+        Err(e) => {
+            tracing::error!("Failed to route space {}: {}", space_id, e);
+            Err(e.into_response())
+        }
+    }
+}
+
+/// Resolves once another replica takes over `space_id`, when clustering
+fn space_lost(state: &AppState, space_id: &str) -> Option<Lost> {
+    state.cluster.as_ref().map(|cluster| cluster.lost(space_id))
+}
+
+/// Query parameters of a sync connection
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SyncQuery {
+    /// Share link token, making the connection read-only
+    share: Option<String>,
+    /// Sync token, for clients that can't set an `Authorization` header
+    token: Option<String>,
+    /// What the client runs, sent by tonk-core as `websocket::CLIENT_PARAMS`
+    client_name: Option<String>,
+    client_version: Option<String>,
+    client_platform: Option<String>,
+    client_features: Option<String>,
+}
+
+impl SyncQuery {
+    /// What the client reported, with anything unfit for logs and metrics dropped
+    fn client(&self) -> Option<PeerInfo> {
+        parse_peer_fields([
+            self.client_name.as_deref(),
+            self.client_version.as_deref(),
+            self.client_platform.as_deref(),
+            self.client_features.as_deref(),
+        ])
+        .and_then(clients::sanitize)
+    }
+}
+
+/// How the relay describes itself to clients, in the headers of accepted sync connections
+fn relay_info(state: &AppState) -> PeerInfo {
+    let mut features = vec!["chunkedFraming", "leases", "signaling"];
+    if state.shares.is_some() {
+        features.push("shareLinks");
+    }
+    if state.cluster.is_some() {
+        features.push("cluster");
+    }
+    if cfg!(feature = "grpc") {
+        features.push("grpc");
+    }
+    features.sort_unstable();
+
+    PeerInfo {
+        name: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        platform: "native".to_string(),
+        features: features.into_iter().map(str::to_string).collect(),
+    }
+}
+
 async fn root_handler(
     headers: HeaderMap,
+    uri: Uri,
+    Query(query): Query<SyncQuery>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
     ws: std::result::Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
     State(state): State<Arc<AppState>>,
 ) -> Response {
@@ -115,9 +365,7 @@ async fn root_handler(
         .unwrap_or(false)
     {
         match ws {
-            Ok(ws) => ws
-                .on_upgrade(move |socket| handle_websocket(socket, state))
-                .into_response(),
+            Ok(ws) => upgrade_sync(ws, &uri, &headers, query, remote_addr, state).await,
             Err(_) => {
                 (StatusCode::BAD_REQUEST, "Invalid WebSocket upgrade request").into_response()
             }
@@ -127,7 +375,309 @@ async fn root_handler(
     }
 }
 
-async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
+async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(query): Query<SyncQuery>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    upgrade_sync(ws, &uri, &headers, query, remote_addr, state).await
+}
+
+/// Accept a sync connection to the hosted space, unless another replica owns it
+/// or the credentials it presented are refused
+async fn upgrade_sync(
+    ws: WebSocketUpgrade,
+    uri: &Uri,
+    headers: &HeaderMap,
+    query: SyncQuery,
+    remote_addr: SocketAddr,
+    state: Arc<AppState>,
+) -> Response {
+    let space_id = state.bundle_storage.root_id().await;
+    let ws = match route_to_owner(&state, &space_id, ws, uri, headers, remote_addr).await {
+        Ok(ws) => ws,
+        Err(response) => return response,
+    };
+    let lost = space_lost(&state, &space_id);
+
+    let connection_id = uuid::Uuid::new_v4().to_string();
+    let client = query.client();
+    let (auth, share) = match authorize_sync(&state, &space_id, headers, &query).await {
+        Ok(authorized) => authorized,
+        Err(e) => {
+            let mut rejected = AccessEntry::new(
+                AccessEvent::Rejected,
+                connection_id,
+                space_id,
+                AuthOutcome::Denied,
+            );
+            rejected.client = client;
+            return reject(&state, rejected, remote_addr, headers, e);
+        }
+    };
+
+    let root = HeaderValue::from_str(&space_id).ok();
+    let mut connect = AccessEntry::new(AccessEvent::Connect, connection_id, space_id, auth);
+    connect.remote_addr = Some(state.access_log.client_addr(remote_addr, headers));
+    connect.client = client;
+    connect.share_id = share.as_ref().map(|share| share.claims.id.clone());
+
+    let relay = relay_info(&state);
+    let mut response = ws
+        .protocols([CHUNKED_PROTOCOL])
+        .on_upgrade(move |socket| handle_websocket(socket, state, share, connect, lost))
+        .into_response();
+    for (name, value) in RELAY_HEADERS.iter().zip(peer_fields(&relay)) {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(name), value);
+        }
+    }
+    // Lets clients tell a replica that lost its storage from the space it synced
+    if let Some(root) = root {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(RELAY_ROOT_HEADER), root);
+    }
+    response
+}
+
+/// Record a connection refused for `error` in the access log and answer with it
+fn reject(
+    state: &AppState,
+    mut rejected: AccessEntry,
+    remote_addr: SocketAddr,
+    headers: &HeaderMap,
+    error: RelayError,
+) -> Response {
+    rejected.remote_addr = Some(state.access_log.client_addr(remote_addr, headers));
+    rejected.reason = Some(error.to_string());
+    state.access_log.record(&rejected);
+    error.into_response()
+}
+
+/// Check the credentials a sync connection presented
+///
+/// A share link makes the connection read-only. Otherwise, if the relay has a
+/// sync token, the connection must present it as a bearer token or the
+/// `token` query parameter.
+async fn authorize_sync(
+    state: &AppState,
+    space_id: &str,
+    headers: &HeaderMap,
+    query: &SyncQuery,
+) -> Result<(AuthOutcome, Option<Arc<ShareScope>>)> {
+    if let Some(token) = query.share.as_deref() {
+        let share = share_scope(state, space_id, token).await?;
+        return Ok((AuthOutcome::Share, Some(share)));
+    }
+    let Some(expected) = state.sync_token.as_deref() else {
+        return Ok((AuthOutcome::Open, None));
+    };
+    let presented = query.token.as_deref().or_else(|| {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+    });
+    if !token_matches(presented, expected) {
+        return Err(RelayError::Unauthorized("Invalid sync token".to_string()));
+    }
+    Ok((AuthOutcome::Token, None))
+}
+
+/// Whether a presented token is `expected`, compared in constant time
+pub(crate) fn token_matches(presented: Option<&str>, expected: &str) -> bool {
+    presented.is_some_and(|presented| bool::from(presented.as_bytes().ct_eq(expected.as_bytes())))
+}
+
+/// Check a share token for the space a sync connection connects to
+async fn share_scope(state: &AppState, space_id: &str, token: &str) -> Result<Arc<ShareScope>> {
+    let shares = state
+        .shares
+        .as_ref()
+        .ok_or_else(|| RelayError::Unauthorized("Share links are disabled".to_string()))?;
+    let claims = shares.verify(token).await?;
+    if claims.space_id != space_id {
+        return Err(RelayError::Unauthorized(
+            "Share link is for another space".to_string(),
+        ));
+    }
+    let root = state
+        .repo
+        .find(hosted_root_id(state).await?)
+        .await
+        .map_err(|e| RelayError::Other(e.to_string()))?
+        .ok_or_else(|| RelayError::NotFound(format!("Space {} not found", space_id)))?;
+    Ok(Arc::new(ShareScope::new(claims, root)))
+}
+
+/// Refuse spaces other than the hosted one before anything is done for them
+///
+/// Routing claims ownership of a space in the cluster, so it's only done for
+/// the one space a relay serves.
+async fn check_hosted_space(state: &AppState, space_id: &str) -> Result<()> {
+    if space_id != state.bundle_storage.root_id().await {
+        return Err(RelayError::NotFound(format!(
+            "Space {} not found",
+            space_id
+        )));
+    }
+    Ok(())
+}
+
+/// Accept a signaling connection presenting the credentials a sync connection
+/// with full access would
+///
+/// Peers that meet by signaling sync directly, out of reach of the relay's
+/// read-only enforcement, so share links can't join either.
+async fn signaling_handler(
+    ws: WebSocketUpgrade,
+    uri: Uri,
+    headers: HeaderMap,
+    Path(space_id): Path<String>,
+    Query(query): Query<SyncQuery>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if let Err(e) = check_hosted_space(&state, &space_id).await {
+        return e.into_response();
+    }
+
+    let connection_id = uuid::Uuid::new_v4().to_string();
+    let auth = match authorize_sync(&state, &space_id, &headers, &query).await {
+        Ok((AuthOutcome::Share, _)) => Err(RelayError::Unauthorized(
+            "Share links can't join signaling".to_string(),
+        )),
+        Ok((auth, _)) => Ok(auth),
+        Err(e) => Err(e),
+    };
+    let auth = match auth {
+        Ok(auth) => auth,
+        Err(e) => {
+            let mut rejected = AccessEntry::new(
+                AccessEvent::Rejected,
+                connection_id,
+                space_id,
+                AuthOutcome::Denied,
+            );
+            rejected.channel = Channel::Signal;
+            rejected.client = query.client();
+            return reject(&state, rejected, remote_addr, &headers, e);
+        }
+    };
+
+    let ws = match route_to_owner(&state, &space_id, ws, &uri, &headers, remote_addr).await {
+        Ok(ws) => ws,
+        Err(response) => return response,
+    };
+    let lost = space_lost(&state, &space_id);
+
+    let mut connect = AccessEntry::new(AccessEvent::Connect, connection_id, space_id.clone(), auth);
+    connect.channel = Channel::Signal;
+    connect.remote_addr = Some(state.access_log.client_addr(remote_addr, &headers));
+    connect.client = query.client();
+    ws.on_upgrade(move |socket| async move {
+        let start = std::time::Instant::now();
+        state.access_log.record(&connect);
+        state.signaling.handle(socket, space_id, lost).await;
+        let mut disconnect = connect.later(AccessEvent::Disconnect);
+        disconnect.duration_ms = Some(start.elapsed().as_millis() as u64);
+        state.access_log.record(&disconnect);
+    })
+    .into_response()
+}
+
+/// Accept a lease connection presenting the credentials a sync connection
+/// with full access would
+///
+/// Share links only grant read access, so they can't take leases either.
+async fn lease_handler(
+    ws: WebSocketUpgrade,
+    uri: Uri,
+    headers: HeaderMap,
+    Path(space_id): Path<String>,
+    Query(query): Query<SyncQuery>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if let Err(e) = check_hosted_space(&state, &space_id).await {
+        return e.into_response();
+    }
+    match authorize_sync(&state, &space_id, &headers, &query).await {
+        Ok((AuthOutcome::Share, _)) => {
+            return RelayError::Unauthorized("Share links can't take leases".to_string())
+                .into_response();
+        }
+        Ok(_) => {}
+        Err(e) => return e.into_response(),
+    }
+    let ws = match route_to_owner(&state, &space_id, ws, &uri, &headers, remote_addr).await {
+        Ok(ws) => ws,
+        Err(response) => return response,
+    };
+    let lost = space_lost(&state, &space_id);
+    ws.on_upgrade(move |socket| async move { state.leases.handle(socket, space_id, lost).await })
+        .into_response()
+}
+
+/// Redirect to the trailing-slash URL so relative asset links resolve inside `/app/`
+async fn app_redirect_handler(Path(space_id): Path<String>) -> Redirect {
+    Redirect::permanent(&format!("/{}/app/", space_id))
+}
+
+/// Check a request for the hosted space's site presents the sync token,
+/// unless the site is public
+///
+/// Share links may be limited to part of the space, so they don't open it.
+async fn authorize_app(
+    state: &AppState,
+    space_id: &str,
+    headers: &HeaderMap,
+    query: &SyncQuery,
+) -> Result<()> {
+    if state.public_app {
+        return Ok(());
+    }
+    match authorize_sync(state, space_id, headers, query).await? {
+        (AuthOutcome::Share, _) => Err(RelayError::Unauthorized(
+            "Share links can't open the app".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+async fn app_index_handler(
+    Path(space_id): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<SyncQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response> {
+    authorize_app(&state, &space_id, &headers, &query).await?;
+    site::serve(&state, &space_id, "", &headers).await
+}
+
+async fn app_asset_handler(
+    Path((space_id, path)): Path<(String, String)>,
+    headers: HeaderMap,
+    Query(query): Query<SyncQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response> {
+    authorize_app(&state, &space_id, &headers, &query).await?;
+    site::serve(&state, &space_id, &path, &headers).await
+}
+
+async fn handle_websocket(
+    socket: WebSocket,
+    state: Arc<AppState>,
+    share: Option<Arc<ShareScope>>,
+    connect: AccessEntry,
+    lost: Option<Lost>,
+) {
     let start = std::time::Instant::now();
     tracing::info!("WebSocket handler started");
 
@@ -135,6 +685,13 @@ async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
         socket,
         Arc::clone(&state.repo),
         Arc::clone(&state.connection_count),
+        Arc::clone(&state.clients),
+        Arc::clone(&state.reaper),
+        state.framing.clone(),
+        share,
+        Arc::clone(&state.access_log),
+        connect,
+        lost,
     )
     .await;
 
@@ -195,27 +752,17 @@ async fn upload_bundle(
         return Err(RelayError::Bundle("Invalid bundle data".to_string()));
     }
 
-    let cursor = std::io::Cursor::new(body.as_ref());
-    let mut archive = ZipArchive::new(cursor)
-        .map_err(|e| RelayError::Bundle(format!("Invalid bundle: {}", e)))?;
-
-    let mut manifest_file = archive
-        .by_name("manifest.json")
-        .map_err(|_| RelayError::Bundle("Invalid bundle: manifest.json not found".to_string()))?;
-
-    let mut manifest_content = String::new();
-    manifest_file.read_to_string(&mut manifest_content)?;
-
-    let manifest: serde_json::Value = serde_json::from_str(&manifest_content)?;
-
-    let bundle_id = manifest
-        .get("rootId")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| {
-            RelayError::Bundle("Invalid bundle: rootId not found in manifest".to_string())
-        })?;
+    // Either bundle format is accepted: a ZIP archive or a v2 container. Only its
+    // content index is checked here; entries are checked as they're read back.
+    let bundle = if state.trusted_keys.is_empty() {
+        Bundle::from_source_unverified(std::io::Cursor::new(body.to_vec()))
+    } else {
+        Bundle::from_source_trusting(std::io::Cursor::new(body.to_vec()), &state.trusted_keys)
+    }
+    .map_err(|e| RelayError::Bundle(format!("Invalid bundle: {:#}", e)))?;
+    let bundle_id = bundle.manifest().root_id.clone();
 
-    s3_storage.upload_bundle(bundle_id, body.to_vec()).await?;
+    s3_storage.upload_bundle(&bundle_id, body.to_vec()).await?;
 
     Ok(Json(json!({
         "id": bundle_id,
@@ -262,9 +809,8 @@ async fn download_bundle_manifest(
 
     let bundle_data = s3_storage.download_bundle(&bundle_id).await?;
 
-    let cursor = std::io::Cursor::new(&bundle_data);
-    let mut archive = ZipArchive::new(cursor)
-        .map_err(|e| RelayError::Bundle(format!("Failed to open bundle: {}", e)))?;
+    let mut bundle = Bundle::from_source_unverified(std::io::Cursor::new(bundle_data))
+        .map_err(|e| RelayError::Bundle(format!("Failed to open bundle: {:#}", e)))?;
 
     let root_id_prefix = bundle_id.chars().take(2).collect::<String>();
     let storage_folder_prefix = format!("storage/{}", root_id_prefix);
@@ -273,28 +819,36 @@ async fn download_bundle_manifest(
     use zip::write::SimpleFileOptions;
     use zip::ZipWriter;
 
+    // The manifest bundle is always a ZIP, whichever format the stored bundle has
     let mut zip_data = Vec::new();
     let mut zip_writer = ZipWriter::new(std::io::Cursor::new(&mut zip_data));
 
-    if let Ok(mut manifest_file) = archive.by_name("manifest.json") {
-        let mut manifest_content = Vec::new();
-        manifest_file.read_to_end(&mut manifest_content)?;
-        zip_writer.start_file("manifest.json", SimpleFileOptions::default())?;
-        zip_writer.write_all(&manifest_content)?;
-    }
-
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        let file_name = file.name().to_string();
-
-        if !file.is_dir()
-            && file_name != "manifest.json"
-            && file_name.starts_with(&storage_folder_prefix)
-        {
-            let mut content = Vec::new();
-            file.read_to_end(&mut content)?;
-            zip_writer.start_file(&file_name, SimpleFileOptions::default())?;
-            zip_writer.write_all(&content)?;
+    // The content index is cut down to the entries copied, so the result verifies
+    let paths: Vec<String> = bundle
+        .prefix_entries(&BundlePath::from(storage_folder_prefix.as_str()))
+        .into_iter()
+        .map(|entry| entry.path)
+        .collect();
+    let checksums = bundle
+        .manifest()
+        .integrity
+        .iter()
+        .flat_map(|integrity| {
+            paths.iter().filter_map(|path| {
+                let checksum = integrity.entries.get(path)?;
+                Some((path.clone(), checksum.clone()))
+            })
+        })
+        .collect();
+    let manifest = slim_manifest(bundle.manifest(), checksums);
+    zip_writer.start_file("manifest.json", SimpleFileOptions::default())?;
+    zip_writer.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+
+    // Entries are streamed across so only one chunk of each is held at a time
+    for path in paths {
+        if let Some(mut reader) = bundle.get_reader(&BundlePath::from(path.as_str()))? {
+            zip_writer.start_file(path, SimpleFileOptions::default())?;
+            std::io::copy(&mut reader, &mut zip_writer)?;
         }
     }
 
@@ -353,6 +907,13 @@ async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
             "total": sys.total_memory(),
         },
         "connections": state.connection_count.load(Ordering::Relaxed),
+        "clients": state.clients.counts(),
+        "signalingPeers": state.signaling.peer_count(),
+        "leases": state.leases.lease_count(),
+        "zombies": {
+            "unresponsive": state.reaper.unresponsive(),
+            "reaped": state.reaper.reaped(),
+        },
         "uptime": uptime,
         "process": {
             "pid": std::process::id(),
@@ -360,13 +921,212 @@ async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     }))
 }
 
+/// Liveness: the process is up and serving requests
+async fn healthz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(json!({
+        "status": "ok",
+        "uptime": state.start_time.elapsed().unwrap_or_default().as_secs(),
+    }))
+}
+
+/// Readiness: the hosted space and its storage are usable
+async fn readyz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let readiness = health::readiness(&state).await;
+    let status = if readiness.is_ready() {
+        StatusCode::OK
+    } else {
+        tracing::warn!("Readiness check failed: {:?}", readiness.checks);
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness))
+}
+
+/// Check the request carries the admin bearer token
+fn authorize_admin(state: &AppState, headers: &HeaderMap) -> Result<()> {
+    let token = state
+        .admin_token
+        .as_deref()
+        .ok_or_else(|| RelayError::NotFound("Admin endpoints are disabled".to_string()))?;
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if !token_matches(presented, token) {
+        return Err(RelayError::Unauthorized("Invalid admin token".to_string()));
+    }
+    Ok(())
+}
+
+async fn trigger_snapshot(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    authorize_admin(&state, &headers)?;
+
+    let snapshots = state
+        .snapshots
+        .as_ref()
+        .ok_or_else(|| RelayError::S3("S3 storage not configured".to_string()))?;
+    let key = snapshots.snapshot().await?;
+
+    Ok(Json(json!({
+        "key": key,
+        "message": "Snapshot uploaded successfully"
+    })))
+}
+
+async fn cluster_status(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    authorize_admin(&state, &headers)?;
+
+    let cluster = state
+        .cluster
+        .as_ref()
+        .ok_or_else(|| RelayError::NotFound("Clustering is not enabled".to_string()))?;
+    Ok(Json(cluster.status()))
+}
+
+/// Resolve a document ID to the paths referencing it in the hosted space
+async fn document_paths(
+    State(state): State<Arc<AppState>>,
+    Path(doc_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    authorize_admin(&state, &headers)?;
+
+    let doc_id: DocumentId = doc_id
+        .parse()
+        .map_err(|_| RelayError::NotFound(format!("No such document: {}", doc_id)))?;
+    let paths = hosted_vfs(&state)
+        .await?
+        .paths_for_document(&doc_id)
+        .await
+        .map_err(|e| RelayError::Other(e.to_string()))?;
+
+    Ok(Json(json!({
+        "docId": doc_id.to_string(),
+        "paths": paths,
+    })))
+}
+
+/// Root document ID of the hosted space
+async fn hosted_root_id(state: &AppState) -> Result<DocumentId> {
+    state
+        .bundle_storage
+        .root_id()
+        .await
+        .parse()
+        .map_err(|e| RelayError::InvalidManifest(format!("Invalid root ID: {}", e)))
+}
+
+/// The hosted space's file tree, opened so that reading it never writes to it
+async fn hosted_vfs(state: &AppState) -> Result<VirtualFileSystem> {
+    let vfs =
+        VirtualFileSystem::from_root_id(Arc::clone(&state.repo), hosted_root_id(state).await?)
+            .await
+            .map_err(|e| RelayError::Other(e.to_string()))?;
+    Ok(vfs.with_index_upkeep(false))
+}
+
+fn share_tokens(state: &AppState) -> Result<&ShareTokens> {
+    state
+        .shares
+        .as_deref()
+        .ok_or_else(|| RelayError::NotFound("Share links are disabled".to_string()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct CreateShare {
+    /// Directory to limit the link to
+    path_prefix: Option<String>,
+    /// Seconds until the link expires; it never does if unset
+    expires_in_secs: Option<u64>,
+}
+
+/// Create a read-only share link to the hosted space
+async fn create_share(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<CreateShare>,
+) -> Result<impl IntoResponse> {
+    authorize_admin(&state, &headers)?;
+
+    let path_prefix = match request.path_prefix.as_deref() {
+        Some(prefix) => normalize_prefix(prefix)?,
+        None => None,
+    };
+    // A link limited to a prefix isn't served the root, so it starts from the
+    // prefix's own document
+    let document_id = match path_prefix.as_deref() {
+        Some(prefix) => Some(
+            hosted_vfs(&state)
+                .await?
+                .metadata(prefix)
+                .await
+                .map_err(|e| match e {
+                    VfsError::PathNotFound(_) => {
+                        RelayError::NotFound(format!("Nothing at {}", prefix))
+                    }
+                    e => RelayError::Other(e.to_string()),
+                })?
+                .pointer
+                .to_string(),
+        ),
+        None => None,
+    };
+    let space_id = state.bundle_storage.root_id().await;
+    let (token, share) = share_tokens(&state)?
+        .issue(space_id, path_prefix, request.expires_in_secs)
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "token": token,
+            "share": share,
+            "documentId": document_id,
+        })),
+    ))
+}
+
+async fn list_shares(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    authorize_admin(&state, &headers)?;
+
+    Ok(Json(share_tokens(&state)?.list().await?))
+}
+
+async fn revoke_share(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    authorize_admin(&state, &headers)?;
+
+    if !share_tokens(&state)?.revoke(&id).await? {
+        return Err(RelayError::NotFound(format!("No such share link: {}", id)));
+    }
+    Ok(Json(json!({
+        "id": id,
+        "message": "Share link revoked"
+    })))
+}
+
 impl IntoResponse for RelayError {
     fn into_response(self) -> Response {
         let (status, error_message) = match self {
             RelayError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            RelayError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
             RelayError::S3(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
             RelayError::Bundle(msg) => (StatusCode::BAD_REQUEST, msg),
             RelayError::InvalidManifest(msg) => (StatusCode::BAD_REQUEST, msg),
+            RelayError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
         };
 
@@ -377,3 +1137,158 @@ impl IntoResponse for RelayError {
         (status, body).into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, StorageConfig};
+    use tonk_core::{StorageEviction, TonkCore};
+
+    /// Serve a relay hosting a space with one document, returning its URL and bundle
+    async fn serve_relay(dir: &std::path::Path) -> (String, Vec<u8>) {
+        serve_relay_with(dir, |_| {}).await
+    }
+
+    /// [`serve_relay`] with the config changed by `configure`
+    async fn serve_relay_with(
+        dir: &std::path::Path,
+        configure: impl FnOnce(&mut Config),
+    ) -> (String, Vec<u8>) {
+        let tonk = TonkCore::new().await.unwrap();
+        tonk.vfs()
+            .create_document("/notes.txt", "hello".to_string())
+            .await
+            .unwrap();
+        let bundle_bytes = tonk.to_bytes(None).await.unwrap();
+        let bundle = dir.join("space.tonk");
+        std::fs::write(&bundle, &bundle_bytes).unwrap();
+        let mut config = Config {
+            bundle,
+            storage: StorageConfig {
+                dir: dir.join("data"),
+                ..StorageConfig::default()
+            },
+            ..Config::default()
+        };
+        configure(&mut config);
+        let relay = RelayServer::create(tonk.samod(), &config, Arc::default())
+            .await
+            .unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = RelayServer::router(relay.state);
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+        (format!("ws://{}/", addr), bundle_bytes)
+    }
+
+    /// Connect `tonk` in the background and wait for the relay to accept it
+    async fn connect(tonk: &TonkCore, url: &str) {
+        let client = tonk.clone();
+        let url = url.to_string();
+        tokio::spawn(async move { client.connect_websocket(&url).await });
+        tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            while tonk.connections().iter().all(|c| c.peer().is_none()) {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clients_compare_their_root_with_the_relays() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, bundle) = serve_relay(dir.path()).await;
+
+        let loaded = TonkCore::from_bytes(bundle).await.unwrap();
+        connect(&loaded, &url).await;
+        assert_eq!(loaded.storage_eviction(), None);
+
+        let connections = loaded.connections();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].url(), url);
+        assert_eq!(connections[0].peer().unwrap().name, "tonk-relay");
+
+        // A replica that started empty, as one whose storage was wiped does
+        let fresh = TonkCore::new().await.unwrap();
+        let mut evictions = fresh.subscribe_eviction();
+        connect(&fresh, &url).await;
+        evictions.changed().await.unwrap();
+        assert!(matches!(
+            &*evictions.borrow(),
+            Some(StorageEviction::RootMismatch { local_root, relay_root })
+                if *local_root == fresh.vfs().root_id().to_string()
+                    && *relay_root == loaded.vfs().root_id().to_string()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_lease_channel_requires_the_sync_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, bundle) = serve_relay_with(dir.path(), |config| {
+            config.auth.sync_token = Some("secret".to_string())
+        })
+        .await;
+        let tonk = TonkCore::from_bytes(bundle).await.unwrap();
+        let lease_url = format!("{}lease/{}", url, tonk.vfs().root_id());
+
+        match tokio_tungstenite::connect_async(&lease_url).await {
+            Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED)
+            }
+            other => panic!("expected a refusal, got {:?}", other.map(|_| ())),
+        }
+
+        // The token the client synced with is presented on the lease channel too
+        connect(&tonk, &tonk_core::websocket::token_url(&url, "secret")).await;
+        let lease = tonk
+            .acquire_lease("/scheduler", std::time::Duration::from_secs(30))
+            .await
+            .unwrap();
+        assert!(lease.is_held());
+    }
+
+    #[tokio::test]
+    async fn test_signaling_requires_the_sync_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, bundle) = serve_relay_with(dir.path(), |config| {
+            config.auth.sync_token = Some("secret".to_string())
+        })
+        .await;
+        let tonk = TonkCore::from_bytes(bundle).await.unwrap();
+        let signal_url = format!("{}signal/{}", url, tonk.vfs().root_id());
+
+        match tokio_tungstenite::connect_async(&signal_url).await {
+            Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED)
+            }
+            other => panic!("expected a refusal, got {:?}", other.map(|_| ())),
+        }
+
+        let authorized = tonk_core::websocket::token_url(&signal_url, "secret");
+        tokio_tungstenite::connect_async(&authorized).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lease_and_signaling_refuse_other_spaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, _) = serve_relay(dir.path()).await;
+        let other = TonkCore::new().await.unwrap().vfs().root_id();
+
+        for channel in ["lease", "signal"] {
+            match tokio_tungstenite::connect_async(format!("{}{}/{}", url, channel, other)).await {
+                Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+                    assert_eq!(response.status(), StatusCode::NOT_FOUND)
+                }
+                other => panic!("expected a refusal, got {:?}", other.map(|_| ())),
+            }
+        }
+    }
+}
@@ -0,0 +1,666 @@
+use crate::error::{RelayError, Result};
+use crate::storage::{ObjectStore, S3Storage};
+use automerge::ChangeHash;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use samod::DocHandle;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tonk_core::vfs::backend::AutomergeHelpers;
+
+const SHARES_PREFIX: &str = "shares/";
+
+/// What a share link grants, signed into its token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareClaims {
+    pub id: String,
+    /// Root document ID of the shared space
+    pub space_id: String,
+    /// Directory the link is limited to; the whole space if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    /// Unix time in milliseconds the link was created
+    pub created_at: u64,
+    /// Unix time in milliseconds after which the link no longer works
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Normalize a path prefix to `/a/b` form, with `None` for the whole space
+pub fn normalize_prefix(prefix: &str) -> Result<Option<String>> {
+    if !prefix.starts_with('/') {
+        return Err(RelayError::BadRequest(format!(
+            "Path prefix must be absolute: {}",
+            prefix
+        )));
+    }
+    let prefix = prefix.trim_end_matches('/');
+    Ok((!prefix.is_empty()).then(|| prefix.to_string()))
+}
+
+/// Issues, checks and revokes read-only share links
+///
+/// A token is the base64url-encoded claims and their HMAC-SHA256 under the
+/// relay's share secret, joined by a `.`. Each issued link is also recorded
+/// under `shares/` in the S3 bucket, and a token only works while its record
+/// exists, so deleting the record revokes it on every replica.
+pub struct ShareTokens {
+    secret: Vec<u8>,
+    records: ObjectStore,
+}
+
+impl ShareTokens {
+    pub fn new(secret: &str, s3_storage: Arc<S3Storage>) -> Self {
+        Self {
+            secret: secret.as_bytes().to_vec(),
+            records: ObjectStore::S3(s3_storage),
+        }
+    }
+
+    /// Keep link records in memory rather than S3
+    #[cfg(test)]
+    fn in_memory(secret: &str) -> Self {
+        Self {
+            secret: secret.as_bytes().to_vec(),
+            records: ObjectStore::in_memory(),
+        }
+    }
+
+    fn record_key(id: &str) -> String {
+        format!("{}{}.json", SHARES_PREFIX, id)
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+
+    /// Create a link to `space_id`, returning its token and claims
+    pub async fn issue(
+        &self,
+        space_id: String,
+        path_prefix: Option<String>,
+        expires_in_secs: Option<u64>,
+    ) -> Result<(String, ShareClaims)> {
+        let created_at = now_ms();
+        let claims = ShareClaims {
+            id: uuid::Uuid::new_v4().to_string(),
+            space_id,
+            path_prefix,
+            created_at,
+            expires_at: expires_in_secs
+                .map(|secs| created_at.saturating_add(secs.saturating_mul(1000))),
+        };
+        let payload = serde_json::to_vec(&claims)?;
+        self.records
+            .put_object(&Self::record_key(&claims.id), payload.clone())
+            .await?;
+
+        let mut mac = self.mac();
+        mac.update(&payload);
+        let token = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(&payload),
+            URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+        );
+        Ok((token, claims))
+    }
+
+    /// Check a token's signature, expiry and that it hasn't been revoked
+    pub async fn verify(&self, token: &str) -> Result<ShareClaims> {
+        let claims = self.decode(token, now_ms())?;
+        if self
+            .records
+            .get_object(&Self::record_key(&claims.id))
+            .await?
+            .is_none()
+        {
+            return Err(RelayError::Unauthorized(
+                "Share link has been revoked".to_string(),
+            ));
+        }
+        Ok(claims)
+    }
+
+    /// Read a token's claims, checking its signature and that it hasn't expired by `now`
+    fn decode(&self, token: &str, now: u64) -> Result<ShareClaims> {
+        let invalid = || RelayError::Unauthorized("Invalid share token".to_string());
+        let (payload, signature) = token.split_once('.').ok_or_else(invalid)?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+
+        let mut mac = self.mac();
+        mac.update(&payload);
+        mac.verify_slice(&signature).map_err(|_| invalid())?;
+
+        let claims: ShareClaims = serde_json::from_slice(&payload).map_err(|_| invalid())?;
+        if claims
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= now)
+        {
+            return Err(RelayError::Unauthorized(
+                "Share link has expired".to_string(),
+            ));
+        }
+        Ok(claims)
+    }
+
+    /// Links that haven't been revoked, oldest first
+    pub async fn list(&self) -> Result<Vec<ShareClaims>> {
+        let mut shares = Vec::new();
+        for key in self.records.list_keys(SHARES_PREFIX).await? {
+            if let Some(data) = self.records.get_object(&key).await? {
+                match serde_json::from_slice::<ShareClaims>(&data) {
+                    Ok(claims) => shares.push(claims),
+                    Err(e) => tracing::warn!("Ignoring unreadable share link {}: {}", key, e),
+                }
+            }
+        }
+        shares.sort_by_key(|claims| claims.created_at);
+        Ok(shares)
+    }
+
+    /// Revoke a link, returning `false` if there was no such link
+    pub async fn revoke(&self, id: &str) -> Result<bool> {
+        let key = Self::record_key(id);
+        if self.records.get_object(&key).await?.is_none() {
+            return Ok(false);
+        }
+        self.records.delete_object(&key).await?;
+        Ok(true)
+    }
+}
+
+/// Whether `path` is at or below `prefix`
+fn in_scope(prefix: &str, path: &str) -> bool {
+    path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Documents within a share link's path prefix
+struct Allowed {
+    /// Heads of the path index the set was read at
+    heads: Vec<ChangeHash>,
+    doc_ids: HashSet<String>,
+}
+
+/// The documents a sync connection opened with a share link may read
+///
+/// Paths are looked up in the shared space's path index, read straight from
+/// its root document so that checking a connection's scope never writes to it.
+pub struct ShareScope {
+    pub claims: ShareClaims,
+    /// Root document of the shared space, which holds its path index
+    root: DocHandle,
+    allowed: Mutex<Option<Allowed>>,
+}
+
+impl ShareScope {
+    pub fn new(claims: ShareClaims, root: DocHandle) -> Self {
+        Self {
+            claims,
+            root,
+            allowed: Mutex::default(),
+        }
+    }
+
+    /// Whether the link grants access to `doc_id`
+    ///
+    /// With a path prefix, that's only documents at or below the prefix. The
+    /// root and the directories above the prefix aren't served, since the path
+    /// index and directory listings they hold name the rest of the space.
+    /// Documents are checked against the current path index, so moving a
+    /// document out of the prefix takes it out of scope.
+    pub fn allows(&self, doc_id: &str) -> bool {
+        let Some(prefix) = &self.claims.path_prefix else {
+            return true;
+        };
+
+        let heads = self.root.with_document(|doc| doc.get_heads());
+        let mut allowed = self.allowed.lock().unwrap();
+        if allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.heads != heads)
+        {
+            let index = match AutomergeHelpers::read_path_index_native(&self.root) {
+                Ok(index) => index,
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to read the path index of space {}: {}",
+                        self.claims.space_id,
+                        e
+                    );
+                    return false;
+                }
+            };
+            let doc_ids = index
+                .paths
+                .into_iter()
+                .filter(|(path, _)| in_scope(prefix, path))
+                .map(|(_, entry)| entry.doc_id)
+                .collect();
+            *allowed = Some(Allowed { heads, doc_ids });
+        }
+        allowed
+            .as_ref()
+            .is_some_and(|allowed| allowed.doc_ids.contains(doc_id))
+    }
+}
+
+/// The fields of a sync protocol message that the relay looks at
+#[derive(Debug, Default)]
+pub struct MessageFields<'a> {
+    pub message_type: Option<&'a str>,
+    pub document_id: Option<&'a str>,
+    /// Peer ID of the sender, announced in `join` messages
+    pub sender_id: Option<&'a str>,
+    /// Automerge sync message carried by `sync` and `request` messages
+    pub data: Option<&'a [u8]>,
+}
+
+impl<'a> MessageFields<'a> {
+    /// Read the top-level fields of a CBOR-encoded sync protocol message
+    ///
+    /// Returns `None` if the message isn't a CBOR map, or uses indefinite
+    /// lengths, which peers don't send.
+    pub fn parse(message: &'a [u8]) -> Option<Self> {
+        let mut reader = CborReader {
+            bytes: message,
+            pos: 0,
+        };
+        let (major, len) = reader.head()?;
+        if major != 5 {
+            return None;
+        }
+        let mut fields = Self::default();
+        for _ in 0..len {
+            match reader.text()? {
+                "type" => fields.message_type = Some(reader.text()?),
+                "documentId" => fields.document_id = Some(reader.text()?),
+                "senderId" => fields.sender_id = Some(reader.text()?),
+                "data" => fields.data = Some(reader.bytes()?),
+                _ => reader.skip()?,
+            }
+        }
+        Some(fields)
+    }
+
+    /// Whether the message would write to the document it is about
+    ///
+    /// Unreadable sync messages count as writes.
+    pub fn writes(&self) -> bool {
+        match (self.message_type, self.data) {
+            (Some("sync" | "request"), Some(data)) => !automerge::sync::Message::decode(data)
+                .is_ok_and(|message| message.changes.is_empty()),
+            (Some("sync" | "request"), None) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Just enough of a CBOR decoder to find fields in a map
+struct CborReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CborReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(len)?;
+        let slice = self.bytes.get(self.pos..end)?;
+        self.pos = end;
+        Some(slice)
+    }
+
+    /// Read an item's major type and argument
+    fn head(&mut self) -> Option<(u8, u64)> {
+        let initial = *self.take(1)?.first()?;
+        let major = initial >> 5;
+        let argument = match initial & 0x1f {
+            info @ 0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().ok()?) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().ok()?) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().ok()?),
+            _ => return None,
+        };
+        Some((major, argument))
+    }
+
+    fn text(&mut self) -> Option<&'a str> {
+        match self.head()? {
+            (3, len) => std::str::from_utf8(self.take(usize::try_from(len).ok()?)?).ok(),
+            _ => None,
+        }
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        match self.head()? {
+            (2, len) => self.take(usize::try_from(len).ok()?),
+            _ => None,
+        }
+    }
+
+    fn skip(&mut self) -> Option<()> {
+        match self.head()? {
+            (0 | 1 | 7, _) => {}
+            (2 | 3, len) => {
+                self.take(usize::try_from(len).ok()?)?;
+            }
+            (4, len) => {
+                for _ in 0..len {
+                    self.skip()?;
+                }
+            }
+            (5, len) => {
+                for _ in 0..len.checked_mul(2)? {
+                    self.skip()?;
+                }
+            }
+            (6, _) => self.skip()?,
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use automerge::sync::SyncDoc;
+    use automerge::transaction::Transactable;
+    use tonk_core::TonkCore;
+
+    /// Encode a CBOR item head
+    fn head(major: u8, len: usize) -> Vec<u8> {
+        match len {
+            0..=23 => vec![major << 5 | len as u8],
+            24..=0xff => vec![major << 5 | 24, len as u8],
+            _ => {
+                let mut head = vec![major << 5 | 25];
+                head.extend_from_slice(&(len as u16).to_be_bytes());
+                head
+            }
+        }
+    }
+
+    fn text(value: &str) -> Vec<u8> {
+        [head(3, value.len()), value.as_bytes().to_vec()].concat()
+    }
+
+    /// Encode a sync protocol message as a CBOR map
+    pub(crate) fn message(
+        message_type: &str,
+        document_id: Option<&str>,
+        data: Option<&[u8]>,
+    ) -> Vec<u8> {
+        let mut fields = vec![(text("type"), text(message_type))];
+        if let Some(document_id) = document_id {
+            fields.push((text("documentId"), text(document_id)));
+        }
+        if let Some(data) = data {
+            fields.push((text("data"), [head(2, data.len()), data.to_vec()].concat()));
+        }
+        let mut encoded = head(5, fields.len());
+        for (key, value) in fields {
+            encoded.extend(key);
+            encoded.extend(value);
+        }
+        encoded
+    }
+
+    /// Automerge sync messages, the first carrying no changes and the second some
+    pub(crate) fn sync_messages() -> (Vec<u8>, Vec<u8>) {
+        let mut writer = automerge::AutoCommit::new();
+        writer.put(automerge::ROOT, "key", "value").unwrap();
+        let mut reader = automerge::AutoCommit::new();
+        let mut writer_state = automerge::sync::State::new();
+        let mut reader_state = automerge::sync::State::new();
+
+        let request = reader
+            .sync()
+            .generate_sync_message(&mut reader_state)
+            .unwrap();
+        writer
+            .sync()
+            .receive_sync_message(&mut writer_state, request.clone())
+            .unwrap();
+        let changes = writer
+            .sync()
+            .generate_sync_message(&mut writer_state)
+            .unwrap();
+        assert!(!changes.changes.is_empty());
+        (request.encode(), changes.encode())
+    }
+
+    /// A space with `/docs/a.txt`, `/docs/sub/b.txt` and `/other/c.txt`
+    pub(crate) async fn space() -> (TonkCore, DocHandle) {
+        let tonk = TonkCore::new().await.unwrap();
+        for path in ["/docs/a.txt", "/docs/sub/b.txt", "/other/c.txt"] {
+            tonk.vfs()
+                .create_document(path, path.to_string())
+                .await
+                .unwrap();
+        }
+        let root = tonk
+            .samod()
+            .find(tonk.vfs().root_id())
+            .await
+            .unwrap()
+            .unwrap();
+        (tonk, root)
+    }
+
+    pub(crate) fn claims(space_id: String, path_prefix: Option<&str>) -> ShareClaims {
+        ShareClaims {
+            id: "share".to_string(),
+            space_id,
+            path_prefix: path_prefix.map(str::to_string),
+            created_at: 0,
+            expires_at: None,
+        }
+    }
+
+    /// Document ID at `path` in the space
+    pub(crate) fn doc_id(root: &DocHandle, path: &str) -> String {
+        AutomergeHelpers::get_path_entry(root, path)
+            .unwrap()
+            .unwrap()
+            .doc_id
+    }
+
+    #[tokio::test]
+    async fn test_tokens_verify_until_revoked() {
+        let tokens = ShareTokens::in_memory("secret");
+        let (token, claims) = tokens
+            .issue("space".to_string(), Some("/docs".to_string()), None)
+            .await
+            .unwrap();
+
+        let verified = tokens.verify(&token).await.unwrap();
+        assert_eq!(verified.id, claims.id);
+        assert_eq!(verified.space_id, "space");
+        assert_eq!(verified.path_prefix.as_deref(), Some("/docs"));
+        assert_eq!(tokens.list().await.unwrap().len(), 1);
+
+        assert!(tokens.revoke(&claims.id).await.unwrap());
+        assert!(tokens.verify(&token).await.is_err());
+        assert!(tokens.list().await.unwrap().is_empty());
+        assert!(!tokens.revoke(&claims.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_tokens_reject_forgery() {
+        let tokens = ShareTokens::in_memory("secret");
+        let (token, _) = tokens.issue("space".to_string(), None, None).await.unwrap();
+
+        // Signed under another secret
+        let other = ShareTokens::in_memory("other secret");
+        assert!(other.decode(&token, 0).is_err());
+
+        // Claims swapped for another space, keeping the signature
+        let (payload, signature) = token.split_once('.').unwrap();
+        let mut claims: ShareClaims =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+        claims.space_id = "another space".to_string();
+        let forged = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap()),
+            signature
+        );
+        assert!(tokens.decode(&forged, 0).is_err());
+
+        for malformed in ["", "no-separator", "!!.!!", &format!("{}.", payload)] {
+            assert!(tokens.decode(malformed, 0).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tokens_expire() {
+        let tokens = ShareTokens::in_memory("secret");
+        let (token, claims) = tokens
+            .issue("space".to_string(), None, Some(60))
+            .await
+            .unwrap();
+        let expires_at = claims.expires_at.unwrap();
+        assert_eq!(expires_at, claims.created_at + 60_000);
+
+        assert!(tokens.decode(&token, expires_at - 1).is_ok());
+        assert!(tokens.decode(&token, expires_at).is_err());
+
+        // Expiry too far off to represent is as late as it can be
+        let (token, claims) = tokens
+            .issue("space".to_string(), None, Some(u64::MAX))
+            .await
+            .unwrap();
+        assert_eq!(claims.expires_at, Some(u64::MAX));
+        assert!(tokens.decode(&token, claims.created_at).is_ok());
+    }
+
+    #[test]
+    fn test_message_fields_parse() {
+        let (request, _) = sync_messages();
+        let encoded = message("request", Some("doc"), Some(&request));
+        let fields = MessageFields::parse(&encoded).unwrap();
+        assert_eq!(fields.message_type, Some("request"));
+        assert_eq!(fields.document_id, Some("doc"));
+        assert_eq!(fields.data, Some(request.as_slice()));
+
+        // Fields the relay doesn't look at are skipped, whatever their type
+        let mut encoded = head(5, 4);
+        encoded.extend(text("targetId"));
+        encoded.extend([head(4, 2), vec![0x01], head(5, 1), text("a"), vec![0xf5]].concat());
+        encoded.extend(text("count"));
+        encoded.extend([0x19, 0x12, 0x34]);
+        encoded.extend(text("senderId"));
+        encoded.extend(text("peer"));
+        encoded.extend(text("type"));
+        encoded.extend(text("join"));
+        let fields = MessageFields::parse(&encoded).unwrap();
+        assert_eq!(fields.message_type, Some("join"));
+        assert_eq!(fields.sender_id, Some("peer"));
+        assert_eq!(fields.document_id, None);
+
+        // Not a map, truncated, or of indefinite length
+        assert!(MessageFields::parse(&text("type")).is_none());
+        let encoded = message("sync", Some("doc"), None);
+        assert!(MessageFields::parse(&encoded[..encoded.len() - 1]).is_none());
+        assert!(MessageFields::parse(&[0xbf, 0xff]).is_none());
+        assert!(MessageFields::parse(&[]).is_none());
+    }
+
+    #[test]
+    fn test_message_fields_writes() {
+        let (request, changes) = sync_messages();
+        let writes = |encoded: Vec<u8>| MessageFields::parse(&encoded).unwrap().writes();
+
+        assert!(!writes(message("request", Some("doc"), Some(&request))));
+        assert!(!writes(message("sync", Some("doc"), Some(&request))));
+        assert!(writes(message("sync", Some("doc"), Some(&changes))));
+        assert!(writes(message("request", Some("doc"), Some(&changes))));
+        // Sync messages that can't be read count as writes
+        assert!(writes(message("sync", Some("doc"), None)));
+        assert!(writes(message("sync", Some("doc"), Some(b"garbage"))));
+        assert!(!writes(message(
+            "ephemeral",
+            Some("doc"),
+            Some(b"presence")
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_scope_follows_path_prefix() {
+        let (tonk, root) = space().await;
+        let root_id = root.document_id().to_string();
+        let scope = ShareScope::new(claims(root_id.clone(), Some("/docs")), root.clone());
+
+        assert!(scope.allows(&doc_id(&root, "/docs")));
+        assert!(scope.allows(&doc_id(&root, "/docs/a.txt")));
+        assert!(scope.allows(&doc_id(&root, "/docs/sub/b.txt")));
+        assert!(!scope.allows(&doc_id(&root, "/other")));
+        assert!(!scope.allows(&doc_id(&root, "/other/c.txt")));
+        assert!(!scope.allows("unknown"));
+        // The root holds the whole path index, so it isn't served either
+        assert!(!scope.allows(&root_id));
+
+        // A sibling sharing the prefix's name isn't below it
+        tonk.vfs()
+            .create_document("/docs2.txt", "docs2".to_string())
+            .await
+            .unwrap();
+        assert!(!scope.allows(&doc_id(&root, "/docs2.txt")));
+
+        // Without a prefix, the whole space is shared
+        let scope = ShareScope::new(claims(root_id.clone(), None), root.clone());
+        assert!(scope.allows(&root_id));
+        assert!(scope.allows(&doc_id(&root, "/other/c.txt")));
+    }
+
+    #[tokio::test]
+    async fn test_scope_follows_moves() {
+        let (tonk, root) = space().await;
+        let scope = ShareScope::new(
+            claims(root.document_id().to_string(), Some("/docs")),
+            root.clone(),
+        );
+        let a = doc_id(&root, "/docs/a.txt");
+        let c = doc_id(&root, "/other/c.txt");
+        assert!(scope.allows(&a));
+        assert!(!scope.allows(&c));
+
+        tonk.vfs()
+            .move_document("/docs/a.txt", "/other/a.txt")
+            .await
+            .unwrap();
+        tonk.vfs()
+            .move_document("/other/c.txt", "/docs/c.txt")
+            .await
+            .unwrap();
+        assert!(!scope.allows(&a));
+        assert!(scope.allows(&c));
+    }
+
+    #[test]
+    fn test_in_scope() {
+        assert!(in_scope("/docs", "/docs"));
+        assert!(in_scope("/docs", "/docs/a"));
+        assert!(!in_scope("/docs", "/"));
+        assert!(!in_scope("/docs/sub", "/docs"));
+        assert!(!in_scope("/docs", "/docsx"));
+        assert!(!in_scope("/docs/sub", "/docs/other"));
+        assert!(!in_scope("/docs", "/other/docs"));
+    }
+}
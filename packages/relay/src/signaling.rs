@@ -0,0 +1,163 @@
+use crate::cluster::{moved_frame, Lost};
+use axum::extract::ws::{Message, WebSocket};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// Maximum number of peers in a single room
+const MAX_ROOM_PEERS: usize = 64;
+
+/// Maximum size of a signaling message in bytes
+const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// Messages queued for a peer before further ones are dropped
+const PEER_QUEUE: usize = 64;
+
+/// Relays WebRTC session descriptions and ICE candidates between browsers
+///
+/// Peers join a room named after a space id by opening a WebSocket to
+/// `/signal/{space_id}`. On joining, a peer is sent
+/// `{"type": "welcome", "peerId", "peers"}` listing the others in the room, who
+/// are sent `{"type": "peer-joined", "peerId"}`; `{"type": "peer-left", "peerId"}`
+/// follows when a peer disconnects. Any other JSON object a peer sends must name
+/// its recipient in `to`, and is forwarded with the sender's id added as `from`.
+/// The relay doesn't interpret offers, answers or candidates.
+#[derive(Default)]
+pub struct SignalingHub {
+    rooms: Mutex<HashMap<String, HashMap<Uuid, mpsc::Sender<String>>>>,
+}
+
+impl SignalingHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of peers currently connected across all rooms
+    pub fn peer_count(&self) -> usize {
+        self.rooms.lock().unwrap().values().map(HashMap::len).sum()
+    }
+
+    /// Add a peer to a room, returning its id and the ids of the peers already there
+    fn join(&self, room: &str, tx: mpsc::Sender<String>) -> Option<(Uuid, Vec<Uuid>)> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let peers = rooms.entry(room.to_string()).or_default();
+        if peers.len() >= MAX_ROOM_PEERS {
+            return None;
+        }
+
+        let peer_id = Uuid::new_v4();
+        let others: Vec<Uuid> = peers.keys().copied().collect();
+        let joined = json!({ "type": "peer-joined", "peerId": peer_id }).to_string();
+        for other in peers.values() {
+            let _ = other.try_send(joined.clone());
+        }
+        peers.insert(peer_id, tx);
+
+        Some((peer_id, others))
+    }
+
+    fn leave(&self, room: &str, peer_id: Uuid) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let Some(peers) = rooms.get_mut(room) else {
+            return;
+        };
+
+        peers.remove(&peer_id);
+        if peers.is_empty() {
+            rooms.remove(room);
+            return;
+        }
+        let left = json!({ "type": "peer-left", "peerId": peer_id }).to_string();
+        for other in peers.values() {
+            let _ = other.try_send(left.clone());
+        }
+    }
+
+    /// Forward a message from `from` to the peer it names in `to`
+    fn forward(&self, room: &str, from: Uuid, text: &str) -> Result<(), String> {
+        if text.len() > MAX_MESSAGE_BYTES {
+            return Err(format!("Message exceeds {} bytes", MAX_MESSAGE_BYTES));
+        }
+
+        let mut message: Value =
+            serde_json::from_str(text).map_err(|e| format!("Invalid message: {}", e))?;
+        let to = message
+            .get("to")
+            .and_then(Value::as_str)
+            .and_then(|to| Uuid::parse_str(to).ok())
+            .ok_or_else(|| "Message has no valid 'to' peer".to_string())?;
+        message["from"] = json!(from);
+
+        let rooms = self.rooms.lock().unwrap();
+        let recipient = rooms
+            .get(room)
+            .and_then(|peers| peers.get(&to))
+            .ok_or_else(|| format!("Peer {} is not in this room", to))?;
+        recipient
+            .try_send(message.to_string())
+            .map_err(|_| format!("Peer {} is not keeping up", to))
+    }
+
+    /// Serve a peer's signaling connection until it disconnects
+    ///
+    /// The connection is closed early if `lost` resolves, once another replica
+    /// takes over the space.
+    pub async fn handle(&self, socket: WebSocket, room: String, lost: Option<Lost>) {
+        let (mut sink, mut stream) = socket.split();
+        let (tx, mut rx) = mpsc::channel::<String>(PEER_QUEUE);
+
+        let Some((peer_id, peers)) = self.join(&room, tx.clone()) else {
+            let error = json!({ "type": "error", "message": "Room is full" });
+            let _ = sink.send(Message::Text(error.to_string().into())).await;
+            return;
+        };
+        tracing::debug!("Signaling peer {} joined room {}", peer_id, room);
+
+        let welcome = json!({ "type": "welcome", "peerId": peer_id, "peers": peers });
+        let _ = tx.try_send(welcome.to_string());
+
+        let send = async {
+            while let Some(text) = rx.recv().await {
+                if sink.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        let receive = async {
+            while let Some(Ok(message)) = stream.next().await {
+                let text = match message {
+                    Message::Text(text) => text,
+                    Message::Close(_) => break,
+                    _ => continue,
+                };
+                if let Err(e) = self.forward(&room, peer_id, &text) {
+                    let error = json!({ "type": "error", "message": e });
+                    let _ = tx.try_send(error.to_string());
+                }
+            }
+        };
+
+        let lost = async {
+            match lost {
+                Some(lost) => lost.await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let moved = tokio::select! {
+            _ = send => false,
+            _ = receive => false,
+            _ = lost => true,
+        };
+        if moved {
+            let _ = sink.send(Message::Close(Some(moved_frame()))).await;
+        }
+
+        self.leave(&room, peer_id);
+        tracing::debug!("Signaling peer {} left room {}", peer_id, room);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::error::{RelayError, Result};
+use crate::server::AppState;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use samod::DocumentId;
+use tonk_core::error::VfsError;
+use tonk_core::vfs::mime::{mime_type, MIME_METADATA_KEY};
+use tonk_core::vfs::{file_data, NodeType};
+use tonk_core::VirtualFileSystem;
+
+/// VFS directory a space's frontend assets are served from
+const APP_DIR: &str = "/app";
+
+/// File served for requests that name a directory
+const INDEX_FILE: &str = "index.html";
+
+/// Policy keeping a served app to its own origin: scripts, styles and
+/// connections may only come from the relay, and it can't be framed elsewhere
+const CONTENT_SECURITY_POLICY: &str = "default-src 'self'; img-src 'self' data: blob:; \
+     style-src 'self' 'unsafe-inline'; object-src 'none'; base-uri 'self'; \
+     form-action 'self'; frame-ancestors 'self'";
+
+fn not_found(path: &str) -> RelayError {
+    RelayError::NotFound(format!("No such file: {}", path))
+}
+
+fn vfs_error(path: &str, err: VfsError) -> RelayError {
+    match err {
+        VfsError::PathNotFound(_) | VfsError::DocumentNotFound(_) | VfsError::InvalidPath(_) => {
+            not_found(path)
+        }
+        err => RelayError::Other(format!("Failed to read {}: {}", path, err)),
+    }
+}
+
+/// Whether an `If-None-Match` header matches the current entity tag
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(value) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    value.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+/// Follow the links in `path`, failing unless it still leads below `/app`
+async fn resolve_in_app(vfs: &VirtualFileSystem, path: &str, requested: &str) -> Result<String> {
+    let resolved = vfs
+        .resolve(path)
+        .await
+        .map_err(|e| vfs_error(requested, e))?;
+    match resolved.strip_prefix(APP_DIR) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => Ok(resolved),
+        _ => Err(not_found(requested)),
+    }
+}
+
+/// Serve a file below the hosted space's `/app` directory
+///
+/// Other spaces and documents in the repo aren't served. `path` is relative to `/app`; directories are served through their
+/// `index.html`. Links are followed only while they stay below `/app`. The
+/// content type comes from the document's `mime` metadata, falling back to one
+/// guessed from the file name, and browsers are told not to sniff another. The
+/// entity tag is derived from the document's heads, so it changes with every
+/// edit. Serving never writes to the space.
+pub async fn serve(
+    state: &AppState,
+    space_id: &str,
+    path: &str,
+    headers: &HeaderMap,
+) -> Result<Response> {
+    let no_such_space = || RelayError::NotFound(format!("No such space: {}", space_id));
+    if space_id != state.bundle_storage.root_id().await {
+        return Err(no_such_space());
+    }
+    let root_id: DocumentId = space_id.parse().map_err(|_| no_such_space())?;
+
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(not_found(path)),
+            _ => components.push(component),
+        }
+    }
+    let requested = if components.is_empty() {
+        APP_DIR.to_string()
+    } else {
+        format!("{}/{}", APP_DIR, components.join("/"))
+    };
+
+    let vfs = VirtualFileSystem::from_root_id(state.repo.clone(), root_id)
+        .await
+        .map_err(|e| vfs_error(&requested, e))?
+        .with_index_upkeep(false);
+
+    let mut file_path = resolve_in_app(&vfs, &requested, &requested).await?;
+    let mut node = vfs
+        .metadata(&file_path)
+        .await
+        .map_err(|e| vfs_error(&requested, e))?;
+    if node.node_type == NodeType::Directory {
+        let index_path = format!("{}/{}", file_path.trim_end_matches('/'), INDEX_FILE);
+        file_path = resolve_in_app(&vfs, &index_path, &requested).await?;
+        node = vfs
+            .metadata(&file_path)
+            .await
+            .map_err(|e| vfs_error(&requested, e))?;
+    }
+    if node.node_type != NodeType::Document {
+        return Err(not_found(&requested));
+    }
+
+    let handle = vfs
+        .find_document(&file_path)
+        .await
+        .map_err(|e| vfs_error(&requested, e))?
+        .ok_or_else(|| not_found(&requested))?;
+
+    let heads = handle.with_document(|doc| doc.get_heads());
+    let etag = format!(
+        "\"{}\"",
+        heads
+            .iter()
+            .map(|head| head.to_string())
+            .collect::<Vec<_>>()
+            .join("-")
+    );
+    let content_type = node
+        .metadata
+        .get(MIME_METADATA_KEY)
+        .and_then(|v| v.as_str())
+        .unwrap_or_else(|| mime_type(&file_path))
+        .to_string();
+
+    let mut response_headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, value);
+    }
+    // Documents can change at any time, so caches must revalidate
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    response_headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    response_headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(CONTENT_SECURITY_POLICY),
+    );
+
+    if etag_matches(headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+
+    let body = file_data(&handle).map_err(|e| vfs_error(&requested, e))?;
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&content_type)
+            .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
+    );
+
+    Ok((StatusCode::OK, response_headers, body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, StorageConfig};
+    use crate::server::RelayServer;
+    use std::sync::Arc;
+    use tonk_core::TonkCore;
+
+    /// A relay serving `tonk`'s repo
+    async fn relay(tonk: &TonkCore, dir: &std::path::Path) -> RelayServer {
+        let bundle = dir.join("space.tonk");
+        std::fs::write(&bundle, tonk.to_bytes(None).await.unwrap()).unwrap();
+        let config = Config {
+            bundle,
+            storage: StorageConfig {
+                dir: dir.join("data"),
+                ..StorageConfig::default()
+            },
+            ..Config::default()
+        };
+        RelayServer::create(tonk.samod(), &config, Arc::default())
+            .await
+            .unwrap()
+    }
+
+    async fn body(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_serves_app_files_without_writing() {
+        let tonk = TonkCore::new().await.unwrap();
+        let vfs = tonk.vfs();
+        vfs.create_document("/app/index.html", "<html></html>".to_string())
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let relay = relay(&tonk, dir.path()).await;
+        let space_id = vfs.root_id().to_string();
+        let root = tonk.samod().find(vfs.root_id()).await.unwrap().unwrap();
+        let heads = root.with_document(|doc| doc.get_heads());
+
+        let response = serve(&relay.state, &space_id, "", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers().clone();
+        assert!(headers[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(
+            headers[header::CONTENT_SECURITY_POLICY],
+            CONTENT_SECURITY_POLICY
+        );
+        assert_eq!(body(response).await, b"<html></html>");
+
+        let mut conditional = HeaderMap::new();
+        conditional.insert(header::IF_NONE_MATCH, headers[header::ETAG].clone());
+        let response = serve(&relay.state, &space_id, "index.html", &conditional)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        assert_eq!(root.with_document(|doc| doc.get_heads()), heads);
+    }
+
+    #[tokio::test]
+    async fn test_only_the_hosted_space_is_served() {
+        let tonk = TonkCore::new().await.unwrap();
+        tonk.vfs()
+            .create_document("/app/index.html", "<html></html>".to_string())
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let relay = relay(&tonk, dir.path()).await;
+
+        // Another tree in the same repo, with an app of its own
+        let other = VirtualFileSystem::new(tonk.samod()).await.unwrap();
+        other
+            .create_document("/app/index.html", "<html>other</html>".to_string())
+            .await
+            .unwrap();
+
+        for space_id in [other.root_id().to_string(), "not-an-id".to_string()] {
+            assert!(
+                matches!(
+                    serve(&relay.state, &space_id, "", &HeaderMap::new()).await,
+                    Err(RelayError::NotFound(_))
+                ),
+                "{} was served",
+                space_id
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_links_must_stay_in_app() {
+        let tonk = TonkCore::new().await.unwrap();
+        let vfs = tonk.vfs();
+        vfs.create_document("/app/style.css", "body {}".to_string())
+            .await
+            .unwrap();
+        vfs.create_document("/private/secret.txt", "secret".to_string())
+            .await
+            .unwrap();
+        vfs.create_link("/app/theme.css", "/app/style.css")
+            .await
+            .unwrap();
+        vfs.create_link("/app/leak.txt", "/private/secret.txt")
+            .await
+            .unwrap();
+        vfs.create_link("/app/private", "/private").await.unwrap();
+        vfs.create_link("/app/sibling", "/application")
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let relay = relay(&tonk, dir.path()).await;
+        let space_id = vfs.root_id().to_string();
+
+        let response = serve(&relay.state, &space_id, "theme.css", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(body(response).await, b"body {}");
+
+        for path in [
+            "leak.txt",
+            "private/secret.txt",
+            "sibling",
+            "../private/secret.txt",
+        ] {
+            assert!(
+                matches!(
+                    serve(&relay.state, &space_id, path, &HeaderMap::new()).await,
+                    Err(RelayError::NotFound(_))
+                ),
+                "{} was served",
+                path
+            );
+        }
+    }
+}
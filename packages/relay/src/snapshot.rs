@@ -0,0 +1,174 @@
+use crate::config::secs;
+use crate::error::{RelayError, Result};
+use crate::storage::S3Storage;
+use samod::{DocumentId, Repo};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tonk_core::VirtualFileSystem;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+const WEEK_SECS: u64 = 7 * DAY_SECS;
+
+/// How often the hosted space is snapshotted and which snapshots are kept
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotConfig {
+    /// Time between scheduled snapshots; snapshots are only taken on demand if unset
+    #[serde(rename = "interval_secs", deserialize_with = "secs")]
+    pub interval: Option<Duration>,
+    /// Number of most recent snapshots to keep
+    pub keep_last: usize,
+    /// Number of days for which the newest snapshot of the day is kept
+    pub keep_daily: usize,
+    /// Number of weeks for which the newest snapshot of the week is kept
+    pub keep_weekly: usize,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            interval: None,
+            keep_last: 24,
+            keep_daily: 7,
+            keep_weekly: 4,
+        }
+    }
+}
+
+impl SnapshotConfig {
+    /// Choose which snapshots to keep, given their creation times in seconds
+    ///
+    /// The newest `keep_last` snapshots are kept, plus the newest snapshot of each
+    /// of the most recent `keep_daily` days and `keep_weekly` weeks that have one.
+    pub fn retained(&self, timestamps: &[u64]) -> HashSet<u64> {
+        let mut newest_first = timestamps.to_vec();
+        newest_first.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut keep: HashSet<u64> = newest_first.iter().take(self.keep_last).copied().collect();
+        for (period, count) in [(DAY_SECS, self.keep_daily), (WEEK_SECS, self.keep_weekly)] {
+            let mut periods = HashSet::new();
+            for &timestamp in &newest_first {
+                if periods.len() == count {
+                    break;
+                }
+                if periods.insert(timestamp / period) {
+                    keep.insert(timestamp);
+                }
+            }
+        }
+
+        keep
+    }
+}
+
+/// Exports the hosted space to `.tonk` bundles in S3 and prunes old ones
+pub struct SnapshotScheduler {
+    repo: Arc<Repo>,
+    root_id: DocumentId,
+    s3_storage: Arc<S3Storage>,
+    config: SnapshotConfig,
+}
+
+impl SnapshotScheduler {
+    pub fn new(
+        repo: Arc<Repo>,
+        root_id: DocumentId,
+        s3_storage: Arc<S3Storage>,
+        config: SnapshotConfig,
+    ) -> Self {
+        Self {
+            repo,
+            root_id,
+            s3_storage,
+            config,
+        }
+    }
+
+    fn prefix(&self) -> String {
+        format!("snapshots/{}/", self.root_id)
+    }
+
+    /// Export the space, upload it, and prune snapshots outside the retention policy
+    ///
+    /// Returns the S3 key of the new snapshot.
+    pub async fn snapshot(&self) -> Result<String> {
+        let vfs = VirtualFileSystem::from_root_id(Arc::clone(&self.repo), self.root_id.clone())
+            .await
+            .map_err(|e| RelayError::Bundle(e.to_string()))?;
+        let bundle = vfs
+            .to_bytes(None)
+            .await
+            .map_err(|e| RelayError::Bundle(format!("Failed to export snapshot: {}", e)))?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let key = format!("{}{}.tonk", self.prefix(), timestamp);
+        let size = bundle.len();
+        self.s3_storage.put_object(&key, bundle).await?;
+        tracing::info!("Snapshot uploaded: {} ({} bytes)", key, size);
+
+        let pruned = self.prune().await?;
+        if pruned > 0 {
+            tracing::info!("Pruned {} old snapshots", pruned);
+        }
+
+        Ok(key)
+    }
+
+    /// Delete snapshots outside the retention policy, returning how many were deleted
+    pub async fn prune(&self) -> Result<usize> {
+        let prefix = self.prefix();
+        let snapshots: Vec<(String, u64)> = self
+            .s3_storage
+            .list_keys(&prefix)
+            .await?
+            .into_iter()
+            .filter_map(|key| {
+                let timestamp = key
+                    .strip_prefix(&prefix)?
+                    .strip_suffix(".tonk")?
+                    .parse()
+                    .ok()?;
+                Some((key, timestamp))
+            })
+            .collect();
+
+        let timestamps: Vec<u64> = snapshots.iter().map(|(_, t)| *t).collect();
+        let keep = self.config.retained(&timestamps);
+
+        let mut pruned = 0;
+        for (key, timestamp) in snapshots {
+            if !keep.contains(&timestamp) {
+                self.s3_storage.delete_object(&key).await?;
+                pruned += 1;
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    /// Take a snapshot every interval until the task is aborted
+    ///
+    /// Returns immediately if no interval is configured.
+    pub async fn run(self: Arc<Self>) {
+        let Some(period) = self.config.interval else {
+            return;
+        };
+        tracing::info!("Snapshotting space {} every {:?}", self.root_id, period);
+
+        let mut interval = tokio::time::interval(period);
+        // The first tick completes immediately; skip it so startup doesn't snapshot
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.snapshot().await {
+                tracing::error!("Scheduled snapshot failed: {}", e);
+            }
+        }
+    }
+}
@@ -1,5 +1,9 @@
 pub mod bundle;
+pub mod documents;
+pub mod objects;
 pub mod s3;
 
-pub use bundle::BundleStorageAdapter;
+pub use bundle::{slim_manifest, BundleStorageAdapter};
+pub use documents::S3DocumentStorage;
+pub use objects::ObjectStore;
 pub use s3::S3Storage;
@@ -1,8 +1,10 @@
 use crate::error::{RelayError, Result};
 use samod::storage::StorageKey;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tonk_core::bundle::integrity::sha256_hex;
+use tonk_core::bundle::{Integrity, Manifest, VerifyingKey};
 use tonk_core::{Bundle, BundlePath};
 
 #[derive(Clone)]
@@ -12,8 +14,9 @@ pub struct BundleStorageAdapter {
 }
 
 impl BundleStorageAdapter {
-    pub async fn from_bundle(bundle_bytes: Vec<u8>) -> Result<Self> {
-        let bundle = Bundle::from_bytes(bundle_bytes)
+    /// Host a bundle, which must be signed by one of `trusted_keys` if any are given
+    pub async fn from_bundle(bundle_bytes: Vec<u8>, trusted_keys: &[VerifyingKey]) -> Result<Self> {
+        let bundle = Bundle::from_bytes_trusting(bundle_bytes, trusted_keys)
             .map_err(|e| RelayError::Bundle(format!("Failed to load bundle: {}", e)))?;
 
         let manifest = bundle.manifest();
@@ -29,6 +32,11 @@ impl BundleStorageAdapter {
         })
     }
 
+    /// ID of the root document of the hosted space
+    pub async fn root_id(&self) -> String {
+        self.bundle.read().await.manifest().root_id.clone()
+    }
+
     fn key_to_string(key: &StorageKey) -> String {
         key.into_iter()
             .filter(|s| !s.is_empty())
@@ -76,23 +84,48 @@ impl BundleStorageAdapter {
             .map_err(|e| RelayError::Bundle(format!("Failed to read from bundle: {}", e)))
     }
 
+    /// Bundle holding just the hosted space's root document folder
+    ///
+    /// Documents written since the bundle was loaded replace their stored copies.
     pub async fn create_slim_bundle(&self) -> Result<Vec<u8>> {
         use std::io::Write;
         use zip::write::SimpleFileOptions;
         use zip::ZipWriter;
 
-        let (manifest_json, all_keys) = {
+        let (manifest, all_keys) = {
             let bundle = self.bundle.read().await;
-            let manifest = bundle.manifest();
-            let manifest_json = serde_json::to_string_pretty(manifest)?;
-            let all_keys = bundle.list_keys();
-            (manifest_json, all_keys)
+            (bundle.manifest().clone(), bundle.list_keys())
         };
 
-        let manifest: tonk_core::bundle::Manifest = serde_json::from_str(&manifest_json)?;
         let root_id_prefix = manifest.root_id.chars().take(2).collect::<String>();
         let storage_folder_prefix = format!("storage/{}", root_id_prefix);
 
+        // Work out what's written up front so the manifest can list its checksums
+        let memory_data = self.memory_data.read().await.clone();
+        let mut checksums = BTreeMap::new();
+        let mut from_bundle = Vec::new();
+        for key in all_keys {
+            let key_str = key.to_string();
+            if key_str.starts_with(&storage_folder_prefix) && !memory_data.contains_key(&key_str) {
+                if let Some(checksum) = manifest
+                    .integrity
+                    .as_ref()
+                    .and_then(|integrity| integrity.entries.get(&key_str))
+                {
+                    checksums.insert(key_str.clone(), checksum.clone());
+                }
+                from_bundle.push(key);
+            }
+        }
+        let from_memory: Vec<(&String, &Vec<u8>)> = memory_data
+            .iter()
+            .filter(|(key_str, _)| key_str.starts_with(&storage_folder_prefix))
+            .collect();
+        for (key_str, data) in &from_memory {
+            checksums.insert((*key_str).clone(), sha256_hex(data));
+        }
+        let manifest_json = serde_json::to_string_pretty(&slim_manifest(&manifest, checksums))?;
+
         let mut zip_data = Vec::new();
         {
             let mut zip_writer = ZipWriter::new(std::io::Cursor::new(&mut zip_data));
@@ -100,38 +133,22 @@ impl BundleStorageAdapter {
             zip_writer.start_file("manifest.json", SimpleFileOptions::default())?;
             zip_writer.write_all(manifest_json.as_bytes())?;
 
-            for key in all_keys {
-                let key_str = key.to_string();
-
-                if !key_str.starts_with(&storage_folder_prefix) {
+            // Stream entries from the bundle rather than copying each into memory
+            let mut bundle = self.bundle.write().await;
+            for key in from_bundle {
+                let Some(mut reader) = bundle
+                    .get_reader(&key)
+                    .map_err(|e| RelayError::Bundle(format!("Failed to read key: {}", e)))?
+                else {
                     continue;
-                }
-
-                let memory_data = self.memory_data.read().await;
-                let data = if let Some(updated_data) = memory_data.get(&key_str) {
-                    updated_data.clone()
-                } else {
-                    drop(memory_data);
-                    let mut bundle_write = self.bundle.write().await;
-                    match bundle_write
-                        .get(&key)
-                        .map_err(|e| RelayError::Bundle(format!("Failed to read key: {}", e)))?
-                    {
-                        Some(data) => data,
-                        None => continue,
-                    }
                 };
-
-                zip_writer.start_file(&key_str, SimpleFileOptions::default())?;
-                zip_writer.write_all(&data)?;
+                zip_writer.start_file(key.to_string(), SimpleFileOptions::default())?;
+                std::io::copy(&mut reader, &mut zip_writer)?;
             }
 
-            let memory_data = self.memory_data.read().await;
-            for (key_str, data) in memory_data.iter() {
-                if key_str.starts_with(&storage_folder_prefix) {
-                    zip_writer.start_file(key_str, SimpleFileOptions::default())?;
-                    zip_writer.write_all(data)?;
-                }
+            for (key_str, data) in from_memory {
+                zip_writer.start_file(key_str, SimpleFileOptions::default())?;
+                zip_writer.write_all(data)?;
             }
 
             zip_writer.finish()?;
@@ -141,6 +158,21 @@ impl BundleStorageAdapter {
     }
 }
 
+/// Manifest for a bundle that carries only some of `manifest`'s entries
+///
+/// The content index is cut down to `checksums`, the hex SHA-256 of each entry
+/// written. Its signature covered the full index, so it's dropped; the relay
+/// holds no key to sign the result with.
+pub fn slim_manifest(manifest: &Manifest, checksums: BTreeMap<String, String>) -> Manifest {
+    Manifest {
+        integrity: manifest.integrity.as_ref().map(|_| Integrity {
+            entries: checksums,
+            signature: None,
+        }),
+        ..manifest.clone()
+    }
+}
+
 impl samod::storage::Storage for BundleStorageAdapter {
     fn load(&self, key: StorageKey) -> impl std::future::Future<Output = Option<Vec<u8>>> + Send {
         let key_str = Self::key_to_string(&key);
@@ -237,3 +269,38 @@ impl samod::storage::Storage for BundleStorageAdapter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonk_core::TonkCore;
+
+    #[tokio::test]
+    async fn test_slim_bundle_verifies() {
+        let tonk = TonkCore::new().await.unwrap();
+        for i in 0..8 {
+            tonk.vfs()
+                .create_document(&format!("/doc{i}.txt"), format!("Document {i}"))
+                .await
+                .unwrap();
+        }
+        let bytes = tonk.to_bytes(None).await.unwrap();
+        let full = Bundle::from_bytes(bytes.clone()).unwrap();
+
+        let adapter = BundleStorageAdapter::from_bundle(bytes, &[]).await.unwrap();
+        let slim = adapter.create_slim_bundle().await.unwrap();
+
+        // The slim bundle carries fewer entries, and an index listing just those
+        let mut slim = Bundle::from_bytes(slim).unwrap();
+        assert!(slim.list_keys().len() < full.list_keys().len());
+        let integrity = slim.manifest().integrity.clone().unwrap();
+        assert_eq!(integrity.entries.len(), slim.list_keys().len() - 1);
+        assert!(integrity.signature.is_none());
+        for path in integrity.entries.keys() {
+            assert!(slim
+                .get(&BundlePath::from(path.as_str()))
+                .unwrap()
+                .is_some());
+        }
+    }
+}
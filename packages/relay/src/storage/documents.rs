@@ -0,0 +1,127 @@
+use super::S3Storage;
+use samod::storage::{Storage, StorageKey, TokioFilesystemStorage};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Prefix under which document storage keys are kept in the bucket
+const DOCUMENTS_PREFIX: &str = "documents";
+
+/// Document storage shared between relay replicas through S3
+///
+/// S3 is the source of truth, so a replica can be replaced without losing
+/// documents. Every write also goes to a local filesystem cache, and reads are
+/// served from it when possible. Samod's storage keys are content addressed, so a
+/// cached key never goes stale; only listing has to ask S3, to pick up keys
+/// written by other replicas.
+#[derive(Clone)]
+pub struct S3DocumentStorage {
+    s3: S3Storage,
+    cache: TokioFilesystemStorage,
+}
+
+impl S3DocumentStorage {
+    pub fn new(s3: S3Storage, cache_dir: PathBuf) -> Self {
+        Self {
+            s3,
+            cache: TokioFilesystemStorage::new(cache_dir),
+        }
+    }
+
+    fn object_key(key: &StorageKey) -> String {
+        std::iter::once(DOCUMENTS_PREFIX)
+            .chain(key.into_iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn storage_key(object_key: &str) -> Option<StorageKey> {
+        let parts = object_key
+            .strip_prefix(DOCUMENTS_PREFIX)?
+            .strip_prefix('/')?
+            .split('/');
+        StorageKey::from_parts(parts).ok()
+    }
+
+    async fn load_cached(&self, key: StorageKey) -> Option<Vec<u8>> {
+        if let Some(data) = self.cache.load(key.clone()).await {
+            return Some(data);
+        }
+
+        match self.s3.get_object(&Self::object_key(&key)).await {
+            Ok(Some(data)) => {
+                self.cache.put(key, data.clone()).await;
+                Some(data)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                tracing::error!("Failed to load document data from S3: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Storage for S3DocumentStorage {
+    fn load(&self, key: StorageKey) -> impl std::future::Future<Output = Option<Vec<u8>>> + Send {
+        let storage = self.clone();
+
+        async move { storage.load_cached(key).await }
+    }
+
+    fn load_range(
+        &self,
+        prefix: StorageKey,
+    ) -> impl std::future::Future<Output = HashMap<StorageKey, Vec<u8>>> + Send {
+        let storage = self.clone();
+
+        async move {
+            let object_keys = match storage.s3.list_keys(&Self::object_key(&prefix)).await {
+                Ok(object_keys) => object_keys,
+                Err(e) => {
+                    tracing::error!("Failed to list document data in S3, using cache: {}", e);
+                    return storage.cache.load_range(prefix).await;
+                }
+            };
+
+            let mut result = HashMap::new();
+            for key in object_keys.iter().filter_map(|k| Self::storage_key(k)) {
+                // Listing by string prefix also matches keys whose last component
+                // merely starts with the prefix's, so filter by component too
+                if !prefix.is_prefix_of(&key) {
+                    continue;
+                }
+                if let Some(data) = storage.load_cached(key.clone()).await {
+                    result.insert(key, data);
+                }
+            }
+
+            result
+        }
+    }
+
+    fn put(&self, key: StorageKey, data: Vec<u8>) -> impl std::future::Future<Output = ()> + Send {
+        let storage = self.clone();
+
+        async move {
+            let object_key = Self::object_key(&key);
+            storage.cache.put(key, data.clone()).await;
+
+            if let Err(e) = storage.s3.put_object(&object_key, data).await {
+                tracing::error!("Failed to write document data to S3: {}", e);
+            }
+        }
+    }
+
+    fn delete(&self, key: StorageKey) -> impl std::future::Future<Output = ()> + Send {
+        let storage = self.clone();
+
+        async move {
+            let object_key = Self::object_key(&key);
+            storage.cache.delete(key).await;
+
+            if let Err(e) = storage.s3.delete_object(&object_key).await {
+                tracing::error!("Failed to delete document data from S3: {}", e);
+            }
+        }
+    }
+}
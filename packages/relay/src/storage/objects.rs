@@ -0,0 +1,184 @@
+use crate::error::Result;
+use crate::storage::S3Storage;
+use std::sync::Arc;
+
+/// Small JSON records the relay keeps beside its documents, such as share
+/// links and cluster leases
+#[derive(Clone)]
+pub enum ObjectStore {
+    S3(Arc<S3Storage>),
+    #[cfg(test)]
+    Memory(Arc<memory::MemoryObjects>),
+}
+
+impl ObjectStore {
+    /// Keep objects in memory rather than S3
+    #[cfg(test)]
+    pub(crate) fn in_memory() -> Self {
+        Self::Memory(Arc::new(memory::MemoryObjects::new(true)))
+    }
+
+    /// Keep objects in memory, ignoring the conditions on conditional writes
+    /// the way some S3-compatible stores do
+    #[cfg(test)]
+    pub(crate) fn in_memory_unconditional() -> Self {
+        Self::Memory(Arc::new(memory::MemoryObjects::new(false)))
+    }
+
+    pub async fn put_object(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        match self {
+            Self::S3(s3_storage) => s3_storage.put_object(key, data).await,
+            #[cfg(test)]
+            Self::Memory(objects) => {
+                objects.put(key, data);
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::S3(s3_storage) => s3_storage.get_object(key).await,
+            #[cfg(test)]
+            Self::Memory(objects) => Ok(objects.get(key).map(|(data, _)| data)),
+        }
+    }
+
+    pub async fn get_object_tagged(&self, key: &str) -> Result<Option<(Vec<u8>, String)>> {
+        match self {
+            Self::S3(s3_storage) => s3_storage.get_object_tagged(key).await,
+            #[cfg(test)]
+            Self::Memory(objects) => Ok(objects.get(key)),
+        }
+    }
+
+    /// See [`S3Storage::put_object_if`]
+    pub async fn put_object_if(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        expected: Option<&str>,
+    ) -> Result<Option<String>> {
+        match self {
+            Self::S3(s3_storage) => s3_storage.put_object_if(key, data, expected).await,
+            #[cfg(test)]
+            Self::Memory(objects) => Ok(objects.put_if(key, data, expected)),
+        }
+    }
+
+    pub async fn delete_object(&self, key: &str) -> Result<()> {
+        match self {
+            Self::S3(s3_storage) => s3_storage.delete_object(key).await,
+            #[cfg(test)]
+            Self::Memory(objects) => {
+                objects.delete(key);
+                Ok(())
+            }
+        }
+    }
+
+    /// See [`S3Storage::delete_object_if`]
+    pub async fn delete_object_if(&self, key: &str, expected: &str) -> Result<bool> {
+        match self {
+            Self::S3(s3_storage) => s3_storage.delete_object_if(key, expected).await,
+            #[cfg(test)]
+            Self::Memory(objects) => Ok(objects.delete_if(key, expected)),
+        }
+    }
+
+    pub async fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        match self {
+            Self::S3(s3_storage) => s3_storage.list_keys(prefix).await,
+            #[cfg(test)]
+            Self::Memory(objects) => Ok(objects.keys(prefix)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod memory {
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Objects {
+        by_key: BTreeMap<String, (Vec<u8>, u64)>,
+        /// Last version handed out, standing in for ETags
+        version: u64,
+    }
+
+    impl Objects {
+        fn e_tag(&self, key: &str) -> Option<String> {
+            self.by_key.get(key).map(|(_, version)| version.to_string())
+        }
+
+        fn put(&mut self, key: &str, data: Vec<u8>) -> String {
+            self.version += 1;
+            self.by_key.insert(key.to_string(), (data, self.version));
+            self.version.to_string()
+        }
+    }
+
+    pub(crate) struct MemoryObjects {
+        objects: Mutex<Objects>,
+        /// Whether conditional writes check their condition
+        conditional: bool,
+    }
+
+    impl MemoryObjects {
+        pub(crate) fn new(conditional: bool) -> Self {
+            Self {
+                objects: Mutex::default(),
+                conditional,
+            }
+        }
+
+        pub(crate) fn put(&self, key: &str, data: Vec<u8>) -> String {
+            self.objects.lock().unwrap().put(key, data)
+        }
+
+        pub(crate) fn get(&self, key: &str) -> Option<(Vec<u8>, String)> {
+            self.objects
+                .lock()
+                .unwrap()
+                .by_key
+                .get(key)
+                .map(|(data, version)| (data.clone(), version.to_string()))
+        }
+
+        pub(crate) fn put_if(
+            &self,
+            key: &str,
+            data: Vec<u8>,
+            expected: Option<&str>,
+        ) -> Option<String> {
+            let mut objects = self.objects.lock().unwrap();
+            (!self.conditional || objects.e_tag(key).as_deref() == expected)
+                .then(|| objects.put(key, data))
+        }
+
+        pub(crate) fn delete(&self, key: &str) {
+            self.objects.lock().unwrap().by_key.remove(key);
+        }
+
+        pub(crate) fn delete_if(&self, key: &str, expected: &str) -> bool {
+            let mut objects = self.objects.lock().unwrap();
+            let deleted = !self.conditional || objects.e_tag(key).as_deref() == Some(expected);
+            if deleted {
+                objects.by_key.remove(key);
+            }
+            deleted
+        }
+
+        pub(crate) fn keys(&self, prefix: &str) -> Vec<String> {
+            self.objects
+                .lock()
+                .unwrap()
+                .by_key
+                .keys()
+                .filter(|key| key.starts_with(prefix))
+                .cloned()
+                .collect()
+        }
+    }
+}
@@ -33,15 +33,8 @@ impl S3Storage {
             return true;
         }
 
-        match self
-            .client
-            .list_objects_v2()
-            .bucket(&self.bucket)
-            .max_keys(1)
-            .send()
-            .await
-        {
-            Ok(_) => {
+        match self.check_access().await {
+            Ok(()) => {
                 self.is_available
                     .store(true, std::sync::atomic::Ordering::Relaxed);
                 true
@@ -53,6 +46,18 @@ impl S3Storage {
         }
     }
 
+    /// List the bucket to check it is reachable, bypassing the cached health state
+    pub async fn check_access(&self) -> Result<()> {
+        self.client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .max_keys(1)
+            .send()
+            .await
+            .map_err(|e| RelayError::S3(e.to_string()))?;
+        Ok(())
+    }
+
     pub async fn upload_bundle(&self, bundle_id: &str, data: Vec<u8>) -> Result<()> {
         if !self.health_check().await {
             return Err(RelayError::S3("S3 not available".to_string()));
@@ -177,6 +182,220 @@ impl S3Storage {
             }
         }
     }
+
+    /// Store an object under `key`, replacing any existing one
+    pub async fn put_object(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        if !self.health_check().await {
+            return Err(RelayError::S3("S3 not available".to_string()));
+        }
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(data))
+            .content_type("application/octet-stream")
+            .send()
+            .await
+            .map_err(|e| RelayError::S3(format!("Failed to put object {}: {}", key, e)))?;
+
+        Ok(())
+    }
+
+    /// Fetch the object stored under `key`, or `None` if there isn't one
+    pub async fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if !self.health_check().await {
+            return Err(RelayError::S3("S3 not available".to_string()));
+        }
+
+        let response = match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) => {
+                return Err(RelayError::S3(format!(
+                    "Failed to get object {}: {}",
+                    key, e
+                )))
+            }
+        };
+
+        let data = response
+            .body
+            .collect()
+            .await
+            .map_err(|e| RelayError::S3(format!("Failed to read object {}: {}", key, e)))?;
+
+        Ok(Some(data.to_vec()))
+    }
+
+    /// Fetch the object stored under `key` with its ETag, or `None` if there isn't one
+    pub async fn get_object_tagged(&self, key: &str) -> Result<Option<(Vec<u8>, String)>> {
+        if !self.health_check().await {
+            return Err(RelayError::S3("S3 not available".to_string()));
+        }
+
+        let response = match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) => {
+                return Err(RelayError::S3(format!(
+                    "Failed to get object {}: {}",
+                    key, e
+                )))
+            }
+        };
+
+        let e_tag = response.e_tag().unwrap_or_default().to_string();
+        let data = response
+            .body
+            .collect()
+            .await
+            .map_err(|e| RelayError::S3(format!("Failed to read object {}: {}", key, e)))?;
+
+        Ok(Some((data.to_vec(), e_tag)))
+    }
+
+    /// Store an object under `key` only if the stored one still has ETag `expected`,
+    /// or, when `expected` is `None`, only if there is no object yet
+    ///
+    /// Returns the new ETag, or `None` if another writer got there first.
+    pub async fn put_object_if(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        expected: Option<&str>,
+    ) -> Result<Option<String>> {
+        if !self.health_check().await {
+            return Err(RelayError::S3("S3 not available".to_string()));
+        }
+
+        let request = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(data))
+            .content_type("application/json");
+        let request = match expected {
+            Some(e_tag) => request.if_match(e_tag),
+            None => request.if_none_match("*"),
+        };
+
+        match request.send().await {
+            Ok(response) => Ok(Some(response.e_tag().unwrap_or_default().to_string())),
+            // 412 when the condition fails, 409 when a concurrent conditional write won
+            Err(e)
+                if e.raw_response()
+                    .is_some_and(|r| matches!(r.status().as_u16(), 409 | 412)) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(RelayError::S3(format!(
+                "Failed to put object {}: {}",
+                key, e
+            ))),
+        }
+    }
+
+    /// Delete the object stored under `key`; deleting a missing object succeeds
+    pub async fn delete_object(&self, key: &str) -> Result<()> {
+        if !self.health_check().await {
+            return Err(RelayError::S3("S3 not available".to_string()));
+        }
+
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| RelayError::S3(format!("Failed to delete object {}: {}", key, e)))?;
+
+        Ok(())
+    }
+
+    /// Delete the object stored under `key` only if it still has ETag `expected`
+    ///
+    /// Returns whether it was deleted; `false` means it was replaced or is gone.
+    pub async fn delete_object_if(&self, key: &str, expected: &str) -> Result<bool> {
+        if !self.health_check().await {
+            return Err(RelayError::S3("S3 not available".to_string()));
+        }
+
+        match self
+            .client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .if_match(expected)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e)
+                if e.raw_response()
+                    .is_some_and(|r| matches!(r.status().as_u16(), 404 | 409 | 412)) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(RelayError::S3(format!(
+                "Failed to delete object {}: {}",
+                key, e
+            ))),
+        }
+    }
+
+    /// List the keys of all objects starting with `prefix`
+    pub async fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        if !self.health_check().await {
+            return Err(RelayError::S3("S3 not available".to_string()));
+        }
+
+        let mut keys = Vec::new();
+        let mut continuation_token = None;
+
+        loop {
+            let response = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .map_err(|e| RelayError::S3(format!("Failed to list objects: {}", e)))?;
+
+            keys.extend(
+                response
+                    .contents()
+                    .iter()
+                    .filter_map(|object| object.key().map(str::to_string)),
+            );
+
+            match response.next_continuation_token() {
+                Some(token) if response.is_truncated().unwrap_or(false) => {
+                    continuation_token = Some(token.to_string());
+                }
+                _ => break,
+            }
+        }
+
+        Ok(keys)
+    }
 }
 
 pub struct BundleMetadata {